@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use peachps::{internet::IpAddr, link, network_device, option, transport::icmp};
+
+/// 送信するデータ長(ICMPヘッダと合わせて64バイト)
+const PAYLOAD_SIZE: usize = 56;
+const TTL: u8 = 64;
+const TIMEOUT: Duration = Duration::from_secs(1);
+const INTERVAL: Duration = Duration::from_secs(1);
+
+#[tokio::main]
+pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: ./ping <interface_name> <destination> [count]");
+        std::process::exit(1);
+    }
+    let dst = IpAddr::from(args[2].as_str());
+    let count: u16 = match args.get(3) {
+        Some(count) => count.parse()?,
+        None => 4,
+    };
+
+    let sock = network_device::setup_raw_socket(args[1].clone())?;
+
+    let opt: option::PeachPSOption = option::PeachPSOption::from_yaml("config.yaml");
+
+    eprintln!("MAC: {}", opt.dev_addr);
+    eprintln!("IP: {}", opt.ip_addr);
+
+    let items = peachps::Items::new(opt, sock);
+
+    let stack = items.clone();
+    tokio::spawn(async move { peachps::run(&stack, link::LinkProtocol::Ethernet).await });
+
+    let session = icmp::EchoSession::new(&items)?;
+    println!("PING {} {} data bytes", dst, PAYLOAD_SIZE);
+
+    let mut rtts = Vec::new();
+    for i in 0..count {
+        if i != 0 {
+            tokio::time::sleep(INTERVAL).await;
+        }
+
+        match session.ping(dst, PAYLOAD_SIZE, TTL, TIMEOUT).await {
+            Ok(reply) => {
+                println!(
+                    "{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+                    reply.payload_len + 8,
+                    reply.src_addr,
+                    reply.sequence_number,
+                    reply.ttl,
+                    reply.rtt.as_secs_f64() * 1000.0
+                );
+                rtts.push(reply.rtt);
+            }
+            Err(e) => println!("icmp_seq={}: {}", i + 1, e),
+        }
+    }
+
+    println!("--- {} ping statistics ---", dst);
+    println!(
+        "{} packets transmitted, {} received, {:.0}% packet loss",
+        count,
+        rtts.len(),
+        (count as usize - rtts.len()) as f64 * 100.0 / count.max(1) as f64
+    );
+    if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+        let avg = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+        println!(
+            "rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+            min.as_secs_f64() * 1000.0,
+            avg.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0
+        );
+    }
+
+    Ok(())
+}
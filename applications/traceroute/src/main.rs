@@ -0,0 +1,179 @@
+use std::time::{Duration, Instant};
+
+use peachps::{
+    internet::{IpAddr, SocketAddr},
+    link, network_device, option,
+    transport::{icmp, udp, TransportProtocolError},
+    Items,
+};
+
+const MAX_HOPS: u8 = 30;
+const PROBES_PER_HOP: u16 = 3;
+const TIMEOUT: Duration = Duration::from_secs(3);
+/// UDPプローブの宛先ポートの初期値．プローブ毎に1ずつ増やす
+const BASE_PORT: u16 = 33434;
+/// プローブに載せるデータ長
+const PAYLOAD_SIZE: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProbeMethod {
+    Icmp,
+    Udp,
+}
+
+/// 1つのプローブに対する応答
+struct Hop {
+    src_addr: IpAddr,
+    rtt: Duration,
+    error: Option<TransportProtocolError>,
+}
+
+#[tokio::main]
+pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: ./traceroute <interface_name> <destination> [icmp|udp]");
+        std::process::exit(1);
+    }
+    let dst = IpAddr::from(args[2].as_str());
+    let method = match args.get(3).map(|s| s.as_str()) {
+        Some("udp") => ProbeMethod::Udp,
+        Some("icmp") | None => ProbeMethod::Icmp,
+        Some(s) => {
+            eprintln!("unknown probe method => '{}'", s);
+            std::process::exit(1);
+        }
+    };
+
+    let sock = network_device::setup_raw_socket(args[1].clone())?;
+
+    let opt: option::PeachPSOption = option::PeachPSOption::from_yaml("config.yaml");
+
+    eprintln!("MAC: {}", opt.dev_addr);
+    eprintln!("IP: {}", opt.ip_addr);
+
+    let items = peachps::Items::new(opt, sock);
+
+    let stack = items.clone();
+    tokio::spawn(async move { peachps::run(&stack, link::LinkProtocol::Ethernet).await });
+
+    let session = icmp::EchoSession::new(&items)?;
+    println!(
+        "traceroute to {}, {} hops max, {} byte packets",
+        dst, MAX_HOPS, PAYLOAD_SIZE
+    );
+
+    let mut port = BASE_PORT;
+    for ttl in 1..=MAX_HOPS {
+        print!("{:2} ", ttl);
+        let mut last_addr = None;
+        let mut reached = false;
+
+        for _ in 0..PROBES_PER_HOP {
+            let hop = match method {
+                ProbeMethod::Icmp => probe_icmp(&session, dst, ttl).await?,
+                ProbeMethod::Udp => probe_udp(&items, dst, port, ttl).await?,
+            };
+            port = port.wrapping_add(1);
+
+            let hop = match hop {
+                Some(hop) => hop,
+                None => {
+                    print!(" *");
+                    continue;
+                }
+            };
+            if last_addr != Some(hop.src_addr) {
+                print!(" {}", hop.src_addr);
+                last_addr = Some(hop.src_addr);
+            }
+            print!("  {:.3} ms", hop.rtt.as_secs_f64() * 1000.0);
+
+            match hop.error {
+                // 中継したルータからの応答
+                Some(TransportProtocolError::TimeToLiveExceeded) => {}
+                // 宛先からのエコー応答，またはUDPのポート到達不能
+                None | Some(TransportProtocolError::ConnectionRefused) => reached = true,
+                Some(e) => {
+                    print!(" !{}", annotation(e));
+                    reached = true;
+                }
+            }
+        }
+        println!();
+
+        if reached {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// エコー要求を送る．タイムアウトした場合は `None` を返す
+async fn probe_icmp<ND: network_device::NetworkDevice>(
+    session: &icmp::EchoSession<ND>,
+    dst: IpAddr,
+    ttl: u8,
+) -> Result<Option<Hop>, TransportProtocolError> {
+    match session.probe(dst, PAYLOAD_SIZE, ttl, TIMEOUT).await {
+        Ok(response) => Ok(Some(Hop {
+            src_addr: response.src_addr,
+            rtt: response.rtt,
+            error: response.error,
+        })),
+        Err(TransportProtocolError::RequestTimedOut) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// 使われていないであろうポートへデータグラムを送る．タイムアウトした場合は `None` を返す
+async fn probe_udp<ND: network_device::NetworkDevice>(
+    items: &Items<ND>,
+    dst: IpAddr,
+    port: u16,
+    ttl: u8,
+) -> Result<Option<Hop>, TransportProtocolError> {
+    // ICMPエラーを受け取るために，宛先と接続したソケットを使う
+    let socket = udp::UdpSocket::bind(items, 0)?;
+    socket.connect(SocketAddr::new(dst, port));
+    socket.set_ttl(ttl);
+
+    let sent_at = Instant::now();
+    socket.send(&[0; PAYLOAD_SIZE]).await?;
+
+    let mut buf = [0; 1500];
+    let result = match tokio::time::timeout(TIMEOUT, socket.recv(&mut buf)).await {
+        Ok(result) => result,
+        Err(_) => return Ok(None),
+    };
+    let rtt = sent_at.elapsed();
+
+    match result {
+        // 宛先がデータグラムで応答した
+        Ok(_) => Ok(Some(Hop {
+            src_addr: dst,
+            rtt,
+            error: None,
+        })),
+        Err(e) => match socket.take_error() {
+            Some(error) => Ok(Some(Hop {
+                src_addr: error.reporter,
+                rtt,
+                error: Some(error.error),
+            })),
+            None => Err(e),
+        },
+    }
+}
+
+/// 到達不能の理由を，tracerouteの慣習に従った記号で表す
+fn annotation(e: TransportProtocolError) -> &'static str {
+    match e {
+        TransportProtocolError::NetworkUnreachable => "N",
+        TransportProtocolError::HostUnreachable => "H",
+        TransportProtocolError::ProtocolUnreachable => "P",
+        TransportProtocolError::MessageTooLong => "F",
+        _ => "?",
+    }
+}
@@ -30,6 +30,7 @@ static int try_open_raw_socket();
 static int find_dev_interface_index(struct ifreq *ifr, int dev_fd);
 static int bind_address_to_socket(int dev_fd, struct sockaddr_ll *sock_addr, struct ifreq *ifr);
 static int set_promiscuous_mode(int fd, struct ifreq *ifr);
+static int enable_packet_auxdata(int fd);
 // static int allocate_ip_addr_to_dev();
 
 /*
@@ -90,6 +91,12 @@ int _setup_raw_sock(char *interface_name, RawSocket *raw_sock)
         return -1;
     }
 
+    if (enable_packet_auxdata(raw_sock->fd) == -1)
+    {
+        perror("failed to enable PACKET_AUXDATA");
+        return -1;
+    }
+
     // get physical device's mac address
     memset(&ifr, 0, sizeof(ifr));
     if (set_mac_address(interface_name, raw_sock->mac_addr, &ifr) == -1)
@@ -146,6 +153,17 @@ static int set_promiscuous_mode(int fd, struct ifreq *ifr)
     return 0;
 }
 
+// カーネルが受信フレームから取り除いたVLANタグを，補助データとして受け取る
+static int enable_packet_auxdata(int fd)
+{
+    int one = 1;
+    if (setsockopt(fd, SOL_PACKET, PACKET_AUXDATA, &one, sizeof(one)) == -1)
+    {
+        return -1;
+    }
+    return 0;
+}
+
 static int set_mac_address(char *interface_name, uint8_t *mac_addr, struct ifreq *ifr)
 {
     int fd = socket(AF_INET, SOCK_DGRAM, 0);
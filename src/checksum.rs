@@ -1,6 +1,10 @@
 use std::io::Cursor;
 
-use crate::byteorder_wrapper;
+use crate::{
+    byteorder_wrapper,
+    internet::{ip::IPv4Addr, ipv6::IPv6Addr, IpAddr},
+    transport::TransportProtocol,
+};
 
 /// チェックサムの計算
 /// See also [Header checksum](https://tools.ietf.org/html/rfc791#section-3.1)
@@ -25,8 +29,9 @@ where
         size -= 2;
     }
 
+    // 奇数長の場合，最後の1バイトは上位バイトとして扱う
     if size == 1 {
-        sum += byteorder_wrapper::read_u8(&mut reader, err)? as u32;
+        sum += (byteorder_wrapper::read_u8(&mut reader, err)? as u32) << 8;
     }
 
     loop {
@@ -38,3 +43,115 @@ where
 
     Ok(!(sum as u16))
 }
+
+/// 疑似ヘッダを含めたチェックサムの計算
+/// UDP/TCPはIPヘッダの一部を疑似ヘッダとしてチェックサムの計算対象に含める
+/// See also [RFC768](https://tools.ietf.org/html/rfc768)
+pub fn calculate_checksum_with_pseudo_header<E>(
+    src_addr: IPv4Addr,
+    dst_addr: IPv4Addr,
+    tp: TransportProtocol,
+    segment: &[u8],
+    err: E,
+) -> Result<u16, E>
+where
+    E: std::error::Error + Copy,
+{
+    let mut buf = Vec::with_capacity(12 + segment.len());
+    buf.append(&mut src_addr.to_bytes(err)?);
+    buf.append(&mut dst_addr.to_bytes(err)?);
+    byteorder_wrapper::write_u8(&mut buf, 0, err)?;
+    byteorder_wrapper::write_u8(&mut buf, tp.into(), err)?;
+    byteorder_wrapper::write_u16_as_be(&mut buf, segment.len() as u16, err)?;
+    buf.extend_from_slice(segment);
+
+    calculate_checksum_u16(&buf, buf.len() as u16, err)
+}
+
+/// IPv6の疑似ヘッダを含めたチェックサムの計算
+/// ICMPv6もUDP/TCPと同様に疑似ヘッダを計算対象に含める
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-8.1)
+pub fn calculate_checksum_with_ipv6_pseudo_header<E>(
+    src_addr: IPv6Addr,
+    dst_addr: IPv6Addr,
+    tp: TransportProtocol,
+    segment: &[u8],
+    err: E,
+) -> Result<u16, E>
+where
+    E: std::error::Error + Copy,
+{
+    let mut buf = Vec::with_capacity(40 + segment.len());
+    buf.extend_from_slice(&src_addr.octets());
+    buf.extend_from_slice(&dst_addr.octets());
+    byteorder_wrapper::write_u32_as_be(&mut buf, segment.len() as u32, err)?;
+    buf.extend_from_slice(&[0; 3]);
+    byteorder_wrapper::write_u8(&mut buf, tp.into(), err)?;
+    buf.extend_from_slice(segment);
+
+    calculate_checksum_u16(&buf, buf.len() as u16, err)
+}
+
+/// アドレスファミリに応じた疑似ヘッダを含めたチェックサムの計算．
+/// 送信元と宛先のファミリが異なる場合はIPv6として扱う
+pub fn calculate_checksum_with_ip_pseudo_header<E>(
+    src_addr: IpAddr,
+    dst_addr: IpAddr,
+    tp: TransportProtocol,
+    segment: &[u8],
+    err: E,
+) -> Result<u16, E>
+where
+    E: std::error::Error + Copy,
+{
+    match (src_addr, dst_addr) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            calculate_checksum_with_pseudo_header(src, dst, tp, segment, err)
+        }
+        _ => calculate_checksum_with_ipv6_pseudo_header(
+            src_addr.v6().unwrap_or_default(),
+            dst_addr.v6().unwrap_or_default(),
+            tp,
+            segment,
+            err,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::TransportProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn odd_length_checksum_test() {
+        let err = TransportProtocolError::InvalidChecksum;
+        // 末尾の1バイトは0でパディングした場合と同じ結果になる
+        assert_eq!(
+            calculate_checksum_u16(&[0x12, 0x34, 0x56, 0x00], 4, err).unwrap(),
+            calculate_checksum_u16(&[0x12, 0x34, 0x56], 3, err).unwrap()
+        );
+    }
+
+    #[test]
+    fn pseudo_header_checksum_test() {
+        let err = TransportProtocolError::InvalidChecksum;
+        let src = IPv4Addr::from("192.168.11.1");
+        let dst = IPv4Addr::from("192.168.11.3");
+        let mut datagram = vec![
+            0x30, 0x39, 0x00, 0x35, 0x00, 0x0b, 0x00, 0x00, 0x61, 0x62, 0x63,
+        ];
+        let cksum =
+            calculate_checksum_with_pseudo_header(src, dst, TransportProtocol::UDP, &datagram, err)
+                .unwrap();
+        datagram[6..8].copy_from_slice(&cksum.to_be_bytes());
+
+        // チェックサムを埋めた状態で再計算すると0になる
+        assert_eq!(
+            0,
+            calculate_checksum_with_pseudo_header(src, dst, TransportProtocol::UDP, &datagram, err)
+                .unwrap()
+        );
+    }
+}
@@ -1,5 +1,9 @@
 pub mod arp;
 pub mod ip;
+pub mod ipv6;
+
+mod addr;
+pub use addr::*;
 
 mod protocol;
 pub use protocol::*;
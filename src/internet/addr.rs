@@ -0,0 +1,213 @@
+use crate::internet::{ip::IPv4Addr, ipv6::IPv6Addr};
+
+/// アドレスファミリを問わないIPアドレス．
+/// UDP/TCP/ICMPのAPIや `RxResult` はこの型を通してIPv4/IPv6の両方を扱う
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+pub enum IpAddr {
+    V4(IPv4Addr),
+    V6(IPv6Addr),
+}
+
+/// IPアドレスとポート番号の組
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash, Default)]
+pub struct SocketAddr {
+    pub ip: IpAddr,
+    pub port: u16,
+}
+
+impl IpAddr {
+    /// IPv4アドレスであれば取り出す
+    pub fn v4(&self) -> Option<IPv4Addr> {
+        match self {
+            IpAddr::V4(addr) => Some(*addr),
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    /// IPv6アドレスであれば取り出す
+    pub fn v6(&self) -> Option<IPv6Addr> {
+        match self {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(addr) => Some(*addr),
+        }
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        matches!(self, IpAddr::V4(_))
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self, IpAddr::V6(_))
+    }
+
+    /// 未指定アドレス(0.0.0.0 もしくは ::)かどうか
+    pub fn is_unspecified(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => *addr == IPv4Addr::ANY,
+            IpAddr::V6(addr) => addr.is_unspecified(),
+        }
+    }
+
+    /// マルチキャストアドレスかどうか
+    pub fn is_multicast(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => addr.is_multicast(),
+            IpAddr::V6(addr) => addr.is_multicast(),
+        }
+    }
+}
+
+impl SocketAddr {
+    pub fn new(ip: IpAddr, port: u16) -> Self {
+        Self { ip, port }
+    }
+}
+
+impl Default for IpAddr {
+    fn default() -> Self {
+        IpAddr::V4(Default::default())
+    }
+}
+
+impl std::fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpAddr::V4(addr) => write!(f, "{}", addr),
+            IpAddr::V6(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+/// IPv6アドレスは `[addr]:port` の形式で出力する．
+/// See also [RFC5952](https://tools.ietf.org/html/rfc5952#section-6)
+impl std::fmt::Display for SocketAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ip {
+            IpAddr::V4(addr) => write!(f, "{}:{}", addr, self.port),
+            IpAddr::V6(addr) => write!(f, "[{}]:{}", addr, self.port),
+        }
+    }
+}
+
+impl From<IPv4Addr> for IpAddr {
+    fn from(addr: IPv4Addr) -> Self {
+        IpAddr::V4(addr)
+    }
+}
+
+impl From<IPv6Addr> for IpAddr {
+    fn from(addr: IPv6Addr) -> Self {
+        IpAddr::V6(addr)
+    }
+}
+
+impl From<&str> for IpAddr {
+    fn from(s: &str) -> Self {
+        if s.contains(':') {
+            IpAddr::V6(IPv6Addr::from(s))
+        } else {
+            IpAddr::V4(IPv4Addr::from(s))
+        }
+    }
+}
+
+impl From<(IpAddr, u16)> for SocketAddr {
+    fn from((ip, port): (IpAddr, u16)) -> Self {
+        Self { ip, port }
+    }
+}
+
+impl From<std::net::Ipv4Addr> for IPv4Addr {
+    fn from(addr: std::net::Ipv4Addr) -> Self {
+        Self(u32::from(addr))
+    }
+}
+
+impl From<IPv4Addr> for std::net::Ipv4Addr {
+    fn from(addr: IPv4Addr) -> Self {
+        Self::from(addr.0)
+    }
+}
+
+impl From<std::net::Ipv6Addr> for IPv6Addr {
+    fn from(addr: std::net::Ipv6Addr) -> Self {
+        Self(u128::from(addr))
+    }
+}
+
+impl From<IPv6Addr> for std::net::Ipv6Addr {
+    fn from(addr: IPv6Addr) -> Self {
+        Self::from(addr.0)
+    }
+}
+
+impl From<std::net::IpAddr> for IpAddr {
+    fn from(addr: std::net::IpAddr) -> Self {
+        match addr {
+            std::net::IpAddr::V4(addr) => IpAddr::V4(addr.into()),
+            std::net::IpAddr::V6(addr) => IpAddr::V6(addr.into()),
+        }
+    }
+}
+
+impl From<IpAddr> for std::net::IpAddr {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => std::net::IpAddr::V4(addr.into()),
+            IpAddr::V6(addr) => std::net::IpAddr::V6(addr.into()),
+        }
+    }
+}
+
+impl From<std::net::SocketAddr> for SocketAddr {
+    fn from(addr: std::net::SocketAddr) -> Self {
+        Self::new(addr.ip().into(), addr.port())
+    }
+}
+
+impl From<SocketAddr> for std::net::SocketAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(addr.ip.into(), addr.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_addr_from_str_test() {
+        assert_eq!(
+            IpAddr::V4(IPv4Addr(0xc0a80b1e)),
+            IpAddr::from("192.168.11.30")
+        );
+        assert_eq!(IpAddr::V6(IPv6Addr::LOOPBACK), IpAddr::from("::1"));
+        assert!(IpAddr::from("0.0.0.0").is_unspecified());
+        assert!(IpAddr::from("ff02::1").is_multicast());
+    }
+
+    #[test]
+    fn std_net_conversion_test() {
+        let addr = IpAddr::from("2001:db8::1");
+        let std_addr: std::net::IpAddr = addr.into();
+        assert_eq!("2001:db8::1".parse::<std::net::IpAddr>().unwrap(), std_addr);
+        assert_eq!(addr, IpAddr::from(std_addr));
+
+        let sock = SocketAddr::new(IpAddr::from("192.168.11.30"), 8080);
+        let std_sock: std::net::SocketAddr = sock.into();
+        assert_eq!("192.168.11.30:8080", std_sock.to_string());
+        assert_eq!(sock, SocketAddr::from(std_sock));
+    }
+
+    #[test]
+    fn socket_addr_display_test() {
+        assert_eq!(
+            "[fe80::1]:53",
+            SocketAddr::new(IpAddr::from("fe80::1"), 53).to_string()
+        );
+        assert_eq!(
+            "10.0.0.1:53",
+            SocketAddr::new(IpAddr::from("10.0.0.1"), 53).to_string()
+        );
+    }
+}
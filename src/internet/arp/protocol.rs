@@ -6,6 +6,7 @@ use crate::{
 
 use super::{ARPHeader, Operation};
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn resolve_mac_address<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     dst_ip: ip::IPv4Addr,
@@ -24,6 +25,7 @@ pub async fn resolve_mac_address<'a, ND: network_device::NetworkDevice>(
     Err(InternetProtocolError::CannotResolveMACAddressFrom { unknown_ip: dst_ip })
 }
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn rx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     mut rx_result: RxResult,
@@ -50,11 +52,12 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
         tx_reply(table, &arp_packet_hdr).await?;
     }
 
-    rx_result.src_ip_addr = arp_packet_hdr.src_internet_addr;
+    rx_result.src_ip_addr = arp_packet_hdr.src_internet_addr.into();
 
     Ok((rx_result, rest.to_vec()))
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::needless_lifetimes)]
 pub async fn tx_request<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     target_ip: ip::IPv4Addr,
@@ -81,6 +84,7 @@ pub async fn tx_request<'a, ND: network_device::NetworkDevice>(
     Ok(())
 }
 
+#[allow(clippy::needless_lifetimes)]
 async fn tx_reply<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     receive_arp_packet: &ARPHeader,
@@ -88,6 +92,7 @@ async fn tx_reply<'a, ND: network_device::NetworkDevice>(
     tx(table, Operation::Reply, receive_arp_packet).await
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::needless_lifetimes)]
 async fn tx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     op: Operation,
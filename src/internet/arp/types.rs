@@ -59,6 +59,7 @@ impl Default for ARPHeader {
 impl ARPHeader {
     pub const LENGTH: usize = 28;
 
+    #[allow(clippy::field_reassign_with_default)]
     pub fn new_from_bytes<E: std::error::Error + Copy>(buf: &[u8], err: E) -> Result<Self, E> {
         let mut reader = Cursor::new(buf);
         let mut packet_hdr: Self = Default::default();
@@ -146,6 +147,7 @@ impl From<u16> for Operation {
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<u16> for Operation {
     fn into(self) -> u16 {
         match self {
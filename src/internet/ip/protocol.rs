@@ -5,9 +5,14 @@ use super::{IPHeader, IPv4Addr};
 use crate::{
     checksum,
     internet::{self, arp, InternetProtocol},
-    link, network_device, transport, Items, RxResult,
+    link, network_device,
+    option::PeachPSOption,
+    transport, Items, RxResult,
 };
 
+/// 送信するパケットのデフォルトのTTL
+pub const DEFAULT_TTL: u8 = 0xff;
+
 /// プロトコルの動作モード
 enum ProcessMode {
     /// 自身に向けられたパケットを受理した場合
@@ -29,9 +34,7 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
         eprintln!("{}", ip_packet_hdr);
     }
 
-    let (_, rest) = buf.split_at(ip_packet_hdr.ihl_bytes_from_vhl() as usize);
-
-    let _mode = validate_ip_packet(
+    let mode = validate_ip_packet(
         buf,
         &ip_packet_hdr,
         table.opt.ip_addr,
@@ -39,62 +42,121 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
         buf.len(),
     )?;
 
-    rx_result.src_ip_addr = ip_packet_hdr.src_addr;
+    // 転送は未実装なので，他のホストに向けられたパケットは処理しない．
+    // ただし参加しているマルチキャストグループ宛てであれば受け付ける
+    if let ProcessMode::AnotherHost = mode {
+        let joined = ip_packet_hdr.dst_addr.is_multicast()
+            && table
+                .igmp_groups
+                .lock()
+                .unwrap()
+                .is_member(ip_packet_hdr.dst_addr);
+        if !joined {
+            return Err(InternetProtocolError::Ignore);
+        }
+    }
+
+    let (raw_header, rest) = buf.split_at(ip_packet_hdr.ihl_bytes_from_vhl() as usize);
+
+    rx_result.src_ip_addr = ip_packet_hdr.src_addr.into();
+    rx_result.dst_ip_addr = ip_packet_hdr.dst_addr.into();
+    rx_result.raw_ip_header = raw_header.to_vec();
     rx_result.tp_type = ip_packet_hdr.protocol;
     rx_result.message_len =
         ip_packet_hdr.total_length as usize - ip_packet_hdr.ihl_bytes_from_vhl() as usize;
 
+    // 対応していないプロトコルの場合はProtocol Unreachableを返す．
+    // ICMPv6はIPv6上でしか扱わない
+    if matches!(
+        ip_packet_hdr.protocol,
+        TransportProtocol::UnAssigned | TransportProtocol::ICMPv6
+    ) {
+        let error = transport::icmp::ErrorMessage::DestinationUnreachable(
+            transport::icmp::UnreachableCode::ProtocolUnreachable,
+        );
+        if let Err(e) = transport::icmp::tx_error(table, error, rx_result, rest).await {
+            if table.opt.debug {
+                eprintln!("++++++++ cannot send icmp error: {} ++++++++", e);
+            }
+        }
+        return Err(InternetProtocolError::Ignore);
+    }
+
     Ok((rx_result, rest.to_vec()))
 }
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn tx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     tp: TransportProtocol,
     rx_result: RxResult,
     tp_payload: Vec<u8>,
 ) -> Result<(), InternetProtocolError> {
-    let next_hop = if rx_result.src_ip_addr == IPv4Addr::BLOADCAST {
-        None
-    } else {
-        // TODO: Find route
-        // TODO: use route to determine next hop
-        Some(rx_result.src_ip_addr)
-    };
+    tx_with_ttl(table, tp, rx_result, tp_payload, DEFAULT_TTL).await
+}
+
+/// TTLを指定して送信する
+pub async fn tx_with_ttl<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    tp: TransportProtocol,
+    rx_result: RxResult,
+    tp_payload: Vec<u8>,
+    ttl: u8,
+) -> Result<(), InternetProtocolError> {
+    tx_with_options(table, tp, rx_result, tp_payload, ttl, &[]).await
+}
+
+/// TTLとIPオプションを指定して送信する．
+/// `options` は4オクテットの倍数に揃えておく必要がある
+pub async fn tx_with_options<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    tp: TransportProtocol,
+    rx_result: RxResult,
+    tp_payload: Vec<u8>,
+    ttl: u8,
+    options: &[u8],
+) -> Result<(), InternetProtocolError> {
+    let next_hop = next_hop(&table.opt, rx_result.src_ipv4_addr());
 
     // TODO: segmentation
-    tx_core(table, rx_result, tp, tp_payload, next_hop).await?;
+    tx_core(table, rx_result, tp, tp_payload, next_hop, ttl, options).await?;
 
     Ok(())
 }
 
+#[allow(clippy::needless_lifetimes)]
 async fn tx_core<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     rx_result: RxResult,
     tp: TransportProtocol,
     mut tp_payload: Vec<u8>,
-    _next_hop: Option<IPv4Addr>,
+    next_hop: Option<IPv4Addr>,
+    ttl: u8,
+    options: &[u8],
 ) -> Result<(), InternetProtocolError> {
     let mut ip_packet = Vec::<u8>::new();
 
-    let dst_ip = rx_result.src_ip_addr;
+    let dst_ip = rx_result.src_ipv4_addr();
+    let header_length = IPHeader::LEAST_LENGTH + options.len() as u8;
     let mut packet_hdr = IPHeader {
         version_ihl: IPHeader::VERSION4.checked_shl(4).unwrap()
-            | IPHeader::LEAST_LENGTH.checked_shr(2).unwrap() as u8,
+            | header_length.checked_shr(2).unwrap(),
         type_of_service: 0,
-        total_length: (IPHeader::LEAST_LENGTH as usize + tp_payload.len()) as u16,
+        total_length: (header_length as usize + tp_payload.len()) as u16,
         identification: rand::random::<u16>(),
         flg_offset: 0x0,
-        time_to_live: 0xff,
+        time_to_live: ttl,
         protocol: tp,
         checksum: 0,
         src_addr: table.opt.ip_addr,
         dst_addr: dst_ip,
     };
 
-    let raw_packet_hdr = packet_hdr.to_bytes(InternetProtocolError::CannotConstructPacket)?;
+    let mut raw_packet_hdr = packet_hdr.to_bytes(InternetProtocolError::CannotConstructPacket)?;
+    raw_packet_hdr.extend_from_slice(options);
     packet_hdr.checksum = checksum::calculate_checksum_u16(
         &raw_packet_hdr,
-        IPHeader::LEAST_LENGTH as u16,
+        header_length as u16,
         InternetProtocolError::CannotConstructPacket,
     )?;
     if table.opt.debug {
@@ -103,15 +165,38 @@ async fn tx_core<'a, ND: network_device::NetworkDevice>(
     }
 
     ip_packet.append(&mut packet_hdr.to_bytes(InternetProtocolError::CannotConstructPacket)?);
+    ip_packet.extend_from_slice(options);
     ip_packet.append(&mut tp_payload);
 
-    let dst_mac_addr = table.lookup_arp_table(&rx_result.src_ip_addr);
+    // ブロードキャストの場合はアドレス解決を行わない
+    let next_hop = match next_hop {
+        Some(next_hop) => next_hop,
+        None => {
+            link::ethernet::tx(
+                table,
+                InternetProtocol::IP,
+                link::MacAddress::BLOADCAST,
+                ip_packet,
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    // マルチキャストの場合は宛先アドレスからMACアドレスを求める
+    if next_hop.is_multicast() {
+        let dst_mac_addr = link::ethernet::ipv4_multicast_mac_address(next_hop);
+        link::ethernet::tx(table, InternetProtocol::IP, dst_mac_addr, ip_packet).await?;
+        return Ok(());
+    }
+
+    let dst_mac_addr = table.lookup_arp_table(&next_hop);
     if let Some(dst_mac_addr) = dst_mac_addr {
         link::ethernet::tx(table, InternetProtocol::IP, dst_mac_addr, ip_packet).await?;
         return Ok(());
     }
 
-    let dst_mac_addr = arp::resolve_mac_address(table, dst_ip).await?;
+    let dst_mac_addr = arp::resolve_mac_address(table, next_hop).await?;
 
     link::ethernet::tx(table, InternetProtocol::IP, dst_mac_addr, ip_packet).await?;
 
@@ -133,6 +218,8 @@ fn validate_ip_packet(
     // 実際のバッファサイズより大きければエラーとする
     if raw_packet_len < (packet_hdr.ihl_bytes_from_vhl().into())
         || raw_packet_len < packet_hdr.total_length as usize
+        || packet_hdr.ihl_bytes_from_vhl() < IPHeader::LEAST_LENGTH
+        || packet_hdr.total_length < packet_hdr.ihl_bytes_from_vhl() as u16
     {
         return Err(internet::InternetProtocolError::InvalidPacketLength);
     }
@@ -156,14 +243,57 @@ fn validate_ip_packet(
     }
 
     // ブロードキャストパケットであるかのチェック
-    if ip_addr == ip_addr.to_broadcast(network_mask) || ip_addr == IPv4Addr::BLOADCAST {
+    if packet_hdr.dst_addr == ip_addr.to_broadcast(network_mask)
+        || packet_hdr.dst_addr == IPv4Addr::BLOADCAST
+    {
         return Ok(ProcessMode::Me);
     }
 
     Ok(ProcessMode::AnotherHost)
 }
 
+/// 宛先に対する次ホップを決める．
+/// 同じネットワーク内であれば直接，それ以外はデフォルトゲートウェイへ送信する．
+/// ブロードキャストの場合は `None` を返し，マルチキャストの場合は常に直接送信する
+fn next_hop(opt: &PeachPSOption, dst_ip: IPv4Addr) -> Option<IPv4Addr> {
+    if dst_ip == IPv4Addr::BLOADCAST || dst_ip == opt.ip_addr.to_broadcast(opt.network_mask) {
+        return None;
+    }
+    if dst_ip.is_multicast() {
+        return Some(dst_ip);
+    }
+
+    let on_link = dst_ip.0 & opt.network_mask.0 == opt.ip_addr.0 & opt.network_mask.0;
+    match opt.default_gateway {
+        Some(gateway) if !on_link => Some(gateway),
+        _ => Some(dst_ip),
+    }
+}
+
 #[cfg(test)]
 mod protocol_tests {
-    // use super::*;
+    use super::*;
+
+    #[test]
+    fn next_hop_test() {
+        let mut opt = PeachPSOption {
+            ip_addr: IPv4Addr::from("192.168.11.30"),
+            network_mask: IPv4Addr::from("255.255.255.0"),
+            ..Default::default()
+        };
+
+        let on_link = IPv4Addr::from("192.168.11.1");
+        let off_link = IPv4Addr::from("8.8.8.8");
+        assert_eq!(Some(on_link), next_hop(&opt, on_link));
+        // デフォルトゲートウェイがなければ直接送信する
+        assert_eq!(Some(off_link), next_hop(&opt, off_link));
+        assert_eq!(None, next_hop(&opt, IPv4Addr::from("192.168.11.255")));
+
+        opt.default_gateway = Some(IPv4Addr::from("192.168.11.254"));
+        assert_eq!(Some(on_link), next_hop(&opt, on_link));
+        assert_eq!(opt.default_gateway, next_hop(&opt, off_link));
+
+        let group = IPv4Addr::from("239.1.2.3");
+        assert_eq!(Some(group), next_hop(&opt, group));
+    }
 }
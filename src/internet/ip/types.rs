@@ -45,6 +45,13 @@ pub struct IPv4Addr(pub u32);
 impl IPv4Addr {
     pub const ANY: Self = Self(0);
     pub const BLOADCAST: Self = Self(0xffffffff);
+    /// 全ホストグループ(224.0.0.1)
+    pub const ALL_SYSTEMS: Self = Self(0xe0000001);
+    /// 全ルータグループ(224.0.0.2)
+    pub const ALL_ROUTERS: Self = Self(0xe0000002);
+    /// IGMPv3のレポートの宛先(224.0.0.22)
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4.2.14)
+    pub const ALL_IGMPV3_ROUTERS: Self = Self(0xe0000016);
 
     pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
     where
@@ -63,6 +70,7 @@ impl IPHeader {
     const MORE_FRAGMENTS_FLAG: u16 = 0x2000;
     pub const VERSION4: u8 = 4;
 
+    #[allow(clippy::field_reassign_with_default)]
     pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
     where
         E: std::error::Error + Copy,
@@ -195,10 +203,26 @@ impl IPv4Addr {
         let host_mask = !network_mask.0;
         Self(self.0 | host_mask)
     }
+
+    /// クラスDアドレス(224.0.0.0/4)かどうか
+    pub fn is_multicast(&self) -> bool {
+        self.0 & 0xf0000000 == 0xe0000000
+    }
+
+    /// ループバックアドレス(127.0.0.0/8)かどうか
+    pub fn is_loopback(&self) -> bool {
+        self.0 & 0xff000000 == 0x7f000000
+    }
+
+    /// 将来のために予約されたクラスEアドレス(240.0.0.0/4)かどうか．
+    /// リミテッドブロードキャストアドレスも含む
+    pub fn is_reserved(&self) -> bool {
+        self.0 & 0xf0000000 == 0xf0000000
+    }
 }
 impl From<&str> for IPv4Addr {
     fn from(s: &str) -> Self {
-        let mut iter = s.split(".").map(|v| u32::from_str_radix(v, 10).unwrap());
+        let mut iter = s.split('.').map(|v| v.parse::<u32>().unwrap());
         Self(
             iter.next().unwrap() << 24
                 | iter.next().unwrap() << 16
@@ -221,6 +245,7 @@ impl std::fmt::Display for IPv4Addr {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for IPv4Addr {
     fn default() -> Self {
         Self(0x00)
@@ -0,0 +1,11 @@
+mod types;
+pub use types::*;
+
+mod protocol;
+pub use protocol::*;
+
+mod address;
+pub use address::*;
+
+mod fragment;
+pub use fragment::*;
@@ -0,0 +1,603 @@
+use std::{
+    hash::Hasher,
+    time::{Duration, Instant},
+};
+
+use siphasher::sip::SipHasher24;
+
+use super::IPv6Addr;
+use crate::{
+    link::MacAddress,
+    option::PeachPSOption,
+    transport::icmpv6::{PrefixInformation, RETRANS_TIMER},
+};
+
+/// 重複が見つかった際に安定プライバシーアドレスを生成し直す回数(IDGEN_RETRIES)
+/// See also [RFC7217](https://tools.ietf.org/html/rfc7217#section-6)
+pub const IDGEN_RETRIES: u8 = 3;
+/// 有効期限を短くするルータ広告を受け入れる下限．
+/// 偽のルータ広告によってアドレスを即座に失効させられることを防ぐ
+/// See also [RFC4862](https://tools.ietf.org/html/rfc4862#section-5.5.3)
+const MIN_VALID_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+/// プレフィックス情報の有効期限で，無期限を表す値
+const INFINITE_LIFETIME: u32 = 0xffff_ffff;
+/// インタフェースIDの長さ．自動設定できるのは/64のプレフィックスのみ
+const INTERFACE_ID_LENGTH: u8 = 64;
+/// 自動設定するアドレス数の上限．
+/// 多数のプレフィックスを広告されてもアドレスが際限なく増えないようにする(Linuxの `max_addresses` に相当)
+pub const MAX_AUTOCONF_ADDRESSES: usize = 16;
+
+/// インタフェースIDの生成方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressGenerationMode {
+    /// MACアドレスから修正EUI-64形式で生成する
+    Eui64,
+    /// プレフィックスとMACアドレス，秘密鍵のハッシュから生成する
+    /// See also [RFC7217](https://tools.ietf.org/html/rfc7217#section-5)
+    StablePrivacy,
+}
+
+/// アドレスの状態
+/// See also [RFC4862](https://tools.ietf.org/html/rfc4862#section-2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressState {
+    /// 重複アドレス検出中で，まだ使用できない
+    Tentative,
+    Preferred,
+    /// 推奨期限が切れており，新たな通信の送信元には選ばない
+    Deprecated,
+}
+
+/// アドレスの由来
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressOrigin {
+    LinkLocal,
+    /// 設定ファイルで指定されたもの
+    Manual,
+    /// ルータ広告のプレフィックス情報から自動設定されたもの
+    Autoconf,
+}
+
+/// インタフェースに割り当てられたアドレス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub addr: IPv6Addr,
+    pub state: AddressState,
+    pub origin: AddressOrigin,
+    /// 有効期限．Noneは無期限
+    pub valid_until: Option<Instant>,
+    /// 推奨期限．Noneは無期限
+    pub preferred_until: Option<Instant>,
+    /// 安定プライバシーアドレスの生成に使ったDAD_Counter
+    pub dad_counter: u8,
+    /// 重複アドレス検出で残り送信する近隣要請の数
+    dad_remaining: u32,
+    /// 重複アドレス検出で次に近隣要請を送る(送り終えていれば完了する)時刻
+    dad_next: Instant,
+}
+
+/// タイマ処理の結果，プロトコルスタックが行うべきこと
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AddressTimerEvents {
+    /// 重複アドレス検出の近隣要請を送るアドレス
+    pub dad_solicitations: Vec<IPv6Addr>,
+    /// 重複アドレス検出を終えて使用可能になったアドレス
+    pub assigned: Vec<IPv6Addr>,
+}
+
+/// インタフェースに割り当てられたIPv6アドレスの一覧．
+/// ステートレスアドレス自動設定(SLAAC)によるアドレスの生成と，有効期限の管理を行う
+/// See also [RFC4862](https://tools.ietf.org/html/rfc4862)
+#[derive(Debug, Clone)]
+pub struct AddressTable {
+    addrs: Vec<InterfaceAddress>,
+    dev_addr: MacAddress,
+    mode: AddressGenerationMode,
+    stable_secret: [u8; 16],
+    dad_transmits: u32,
+}
+
+impl AddressTable {
+    /// リンクローカルアドレスと設定されたアドレスを，重複アドレス検出前の状態で登録する
+    pub fn new(opt: &PeachPSOption, now: Instant) -> Self {
+        let mut table = Self {
+            addrs: Vec::new(),
+            dev_addr: opt.dev_addr,
+            mode: opt.ipv6.addr_gen_mode,
+            stable_secret: opt.ipv6.stable_secret,
+            dad_transmits: opt.icmpv6.dad_transmits,
+        };
+
+        let link_local = table.generate(IPv6Addr::LINK_LOCAL_PREFIX, 0);
+        table.add(link_local, AddressOrigin::LinkLocal, None, None, 0, now);
+        for addr in opt.ipv6_addrs.iter() {
+            table.add(*addr, AddressOrigin::Manual, None, None, 0, now);
+        }
+        table
+    }
+
+    /// プレフィックスにインタフェースIDを連結したアドレスを生成する
+    pub fn generate(&self, prefix: IPv6Addr, dad_counter: u8) -> IPv6Addr {
+        let iid = match self.mode {
+            AddressGenerationMode::Eui64 => IPv6Addr::interface_identifier(self.dev_addr),
+            AddressGenerationMode::StablePrivacy => {
+                // F(Prefix, Net_Iface, Network_ID, DAD_Counter, secret_key)
+                // Network_IDは省略する
+                let mut hasher = SipHasher24::new_with_key(&self.stable_secret);
+                hasher.write_u64((prefix.0 >> 64) as u64);
+                hasher.write(&self.dev_addr.0);
+                hasher.write_u8(dad_counter);
+                hasher.finish()
+            }
+        };
+        IPv6Addr((prefix.0 >> 64) << 64 | iid as u128)
+    }
+
+    /// 重複アドレス検出を行う状態でアドレスを登録する．
+    /// 既に登録されていれば，検出をやり直す
+    pub fn add(
+        &mut self,
+        addr: IPv6Addr,
+        origin: AddressOrigin,
+        valid_until: Option<Instant>,
+        preferred_until: Option<Instant>,
+        dad_counter: u8,
+        now: Instant,
+    ) {
+        self.addrs.retain(|entry| entry.addr != addr);
+        self.addrs.push(InterfaceAddress {
+            addr,
+            state: AddressState::Tentative,
+            origin,
+            valid_until,
+            preferred_until,
+            dad_counter,
+            dad_remaining: self.dad_transmits,
+            dad_next: now,
+        });
+    }
+
+    pub fn get(&self, addr: IPv6Addr) -> Option<&InterfaceAddress> {
+        self.addrs.iter().find(|entry| entry.addr == addr)
+    }
+
+    pub fn is_tentative(&self, addr: IPv6Addr) -> bool {
+        self.get(addr)
+            .is_some_and(|entry| entry.state == AddressState::Tentative)
+    }
+
+    /// 使用可能なユニキャストアドレス
+    pub fn unicast_addrs(&self) -> Vec<IPv6Addr> {
+        self.addrs
+            .iter()
+            .filter(|entry| entry.state != AddressState::Tentative)
+            .map(|entry| entry.addr)
+            .collect()
+    }
+
+    /// 参加しているマルチキャストグループ．
+    /// 全ノードマルチキャストアドレスと，各ユニキャストアドレスの要請ノードマルチキャストアドレスからなる．
+    /// 重複アドレス検出中のアドレスについても，他のノードの検出を知るために参加する
+    /// See also [RFC4291](https://tools.ietf.org/html/rfc4291#section-2.8)
+    pub fn multicast_groups(&self) -> Vec<IPv6Addr> {
+        let mut groups = vec![IPv6Addr::ALL_NODES];
+        for entry in self.addrs.iter() {
+            if !groups.contains(&entry.addr.solicited_node()) {
+                groups.push(entry.addr.solicited_node());
+            }
+        }
+        groups
+    }
+
+    /// 宛先アドレスが自身に向けられたものかどうか
+    pub fn is_for_me(&self, dst: IPv6Addr) -> bool {
+        if dst.is_multicast() {
+            return self.multicast_groups().contains(&dst);
+        }
+        self.unicast_addrs().contains(&dst)
+    }
+
+    /// 宛先がリンク上にあるか．
+    /// リンクローカルアドレスか，自身のアドレスと/64のプレフィックスが一致すればリンク上とみなす
+    pub fn is_on_link(&self, dst: IPv6Addr) -> bool {
+        dst.is_link_local()
+            || self
+                .unicast_addrs()
+                .iter()
+                .any(|addr| addr.0 >> 64 == dst.0 >> 64)
+    }
+
+    /// 宛先に送信する際の送信元アドレスを選ぶ．
+    /// リンクローカルな宛先にはリンクローカルアドレスを，それ以外には推奨期限内のアドレスを優先する．
+    /// 使用可能なアドレスがなければ未指定アドレスを返す
+    /// See also [RFC6724](https://tools.ietf.org/html/rfc6724#section-5)
+    pub fn source_address(&self, dst: IPv6Addr) -> IPv6Addr {
+        let find = |pred: &dyn Fn(&InterfaceAddress) -> bool| {
+            self.addrs
+                .iter()
+                .find(|entry| entry.state != AddressState::Tentative && pred(entry))
+                .map(|entry| entry.addr)
+        };
+        let link_local = find(&|entry| entry.addr.is_link_local());
+        // ff02::/16 はリンクローカルスコープのマルチキャスト
+        if dst.is_link_local() || dst.0 >> 112 == 0xff02 {
+            return link_local.unwrap_or(IPv6Addr::UNSPECIFIED);
+        }
+
+        find(&|entry| !entry.addr.is_link_local() && entry.state == AddressState::Preferred)
+            .or_else(|| find(&|entry| !entry.addr.is_link_local()))
+            .or(link_local)
+            .unwrap_or(IPv6Addr::UNSPECIFIED)
+    }
+
+    /// ルータ広告のプレフィックス情報を処理する．
+    /// 新たにアドレスを生成した場合はそれを返す
+    /// See also [RFC4862](https://tools.ietf.org/html/rfc4862#section-5.5.3)
+    pub fn on_prefix_information(
+        &mut self,
+        info: &PrefixInformation,
+        now: Instant,
+    ) -> Option<IPv6Addr> {
+        if !info.autonomous
+            || info.prefix.is_link_local()
+            || info.preferred_lifetime > info.valid_lifetime
+        {
+            return None;
+        }
+
+        let prefix = info.prefix.0 >> 64;
+        let preferred_until = lifetime_to_deadline(info.preferred_lifetime, now);
+        let received_valid = Duration::from_secs(info.valid_lifetime as u64);
+        if let Some(entry) = self
+            .addrs
+            .iter_mut()
+            .find(|entry| entry.origin == AddressOrigin::Autoconf && entry.addr.0 >> 64 == prefix)
+        {
+            entry.preferred_until = preferred_until;
+            if entry.state == AddressState::Deprecated && !is_expired(preferred_until, now) {
+                entry.state = AddressState::Preferred;
+            }
+
+            // 残りの有効期限が2時間以下であれば，ルータ広告で短くすることはできない
+            let remaining = entry
+                .valid_until
+                .map(|valid_until| valid_until.saturating_duration_since(now));
+            if info.valid_lifetime == INFINITE_LIFETIME
+                || received_valid > MIN_VALID_LIFETIME
+                || remaining.is_some_and(|remaining| received_valid > remaining)
+            {
+                entry.valid_until = lifetime_to_deadline(info.valid_lifetime, now);
+            } else if remaining.is_none_or(|remaining| remaining > MIN_VALID_LIFETIME) {
+                entry.valid_until = Some(now + MIN_VALID_LIFETIME);
+            }
+            return None;
+        }
+
+        if info.valid_lifetime == 0 || info.prefix_length != 128 - INTERFACE_ID_LENGTH {
+            return None;
+        }
+        // 上限に達している間は，新しいプレフィックスを無視する
+        let autoconf_addrs = self
+            .addrs
+            .iter()
+            .filter(|entry| entry.origin == AddressOrigin::Autoconf)
+            .count();
+        if autoconf_addrs >= MAX_AUTOCONF_ADDRESSES {
+            return None;
+        }
+        let addr = self.generate(info.prefix, 0);
+        self.add(
+            addr,
+            AddressOrigin::Autoconf,
+            lifetime_to_deadline(info.valid_lifetime, now),
+            preferred_until,
+            0,
+            now,
+        );
+        Some(addr)
+    }
+
+    /// 重複アドレス検出中のアドレスが他のノードと重複していた．
+    /// 安定プライバシーアドレスであれば，DAD_Counterを進めて生成し直したアドレスを返す
+    /// See also [RFC7217](https://tools.ietf.org/html/rfc7217#section-6)
+    pub fn on_duplicate_address(&mut self, addr: IPv6Addr, now: Instant) -> Option<IPv6Addr> {
+        let index = self
+            .addrs
+            .iter()
+            .position(|entry| entry.addr == addr && entry.state == AddressState::Tentative)?;
+        let entry = self.addrs.remove(index);
+
+        let regenerate = self.mode == AddressGenerationMode::StablePrivacy
+            && entry.origin != AddressOrigin::Manual
+            && entry.dad_counter < IDGEN_RETRIES;
+        if !regenerate {
+            return None;
+        }
+        let dad_counter = entry.dad_counter + 1;
+        let addr = self.generate(addr, dad_counter);
+        self.add(
+            addr,
+            entry.origin,
+            entry.valid_until,
+            entry.preferred_until,
+            dad_counter,
+            now,
+        );
+        Some(addr)
+    }
+
+    /// 重複アドレス検出を進め，有効期限の切れたアドレスを取り除く
+    pub fn on_timer(&mut self, now: Instant) -> AddressTimerEvents {
+        let mut events: AddressTimerEvents = Default::default();
+        self.addrs
+            .retain(|entry| !is_expired(entry.valid_until, now));
+
+        for entry in self.addrs.iter_mut() {
+            if entry.state == AddressState::Tentative && entry.dad_next <= now {
+                if entry.dad_remaining > 0 {
+                    entry.dad_remaining -= 1;
+                    entry.dad_next = now + RETRANS_TIMER;
+                    events.dad_solicitations.push(entry.addr);
+                    continue;
+                }
+                entry.state = AddressState::Preferred;
+                events.assigned.push(entry.addr);
+            }
+            if entry.state == AddressState::Preferred && is_expired(entry.preferred_until, now) {
+                entry.state = AddressState::Deprecated;
+            }
+        }
+        events
+    }
+}
+
+impl From<&str> for AddressGenerationMode {
+    fn from(s: &str) -> Self {
+        match s {
+            "eui64" => AddressGenerationMode::Eui64,
+            "stable_privacy" => AddressGenerationMode::StablePrivacy,
+            _ => panic!("unsupported address generation mode => '{}'", s),
+        }
+    }
+}
+
+/// ルータ広告の期限(秒)を時刻に変換する
+fn lifetime_to_deadline(lifetime: u32, now: Instant) -> Option<Instant> {
+    if lifetime == INFINITE_LIFETIME {
+        return None;
+    }
+    Some(now + Duration::from_secs(lifetime as u64))
+}
+
+fn is_expired(deadline: Option<Instant>, now: Instant) -> bool {
+    deadline.is_some_and(|deadline| deadline <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(mode: AddressGenerationMode) -> PeachPSOption {
+        let mut opt = PeachPSOption {
+            dev_addr: MacAddress([0x08, 0x00, 0x27, 0x3c, 0xa9, 0x81]),
+            ipv6_addrs: vec![IPv6Addr::from("2001:db8::10")],
+            ..Default::default()
+        };
+        opt.ipv6.addr_gen_mode = mode;
+        opt.ipv6.stable_secret = [0x5a; 16];
+        opt
+    }
+
+    /// 重複アドレス検出を終えた状態のテーブル
+    fn assigned_table(opt: &PeachPSOption, now: Instant) -> AddressTable {
+        let mut table = AddressTable::new(opt, now);
+        table.on_timer(now);
+        table.on_timer(now + RETRANS_TIMER);
+        table
+    }
+
+    fn prefix_information(valid_lifetime: u32, preferred_lifetime: u32) -> PrefixInformation {
+        PrefixInformation {
+            prefix_length: 64,
+            on_link: true,
+            autonomous: true,
+            valid_lifetime,
+            preferred_lifetime,
+            prefix: IPv6Addr::from("2001:db8:1::"),
+        }
+    }
+
+    #[test]
+    fn generate_test() {
+        let opt = option(AddressGenerationMode::Eui64);
+        let table = AddressTable::new(&opt, Instant::now());
+        assert_eq!(
+            IPv6Addr::from("2001:db8:1:0:a00:27ff:fe3c:a981"),
+            table.generate(IPv6Addr::from("2001:db8:1::"), 0)
+        );
+
+        // 安定プライバシーアドレスはプレフィックスとDAD_Counter毎に異なり，同じ入力には同じアドレスを返す
+        let opt = option(AddressGenerationMode::StablePrivacy);
+        let table = AddressTable::new(&opt, Instant::now());
+        let prefix = IPv6Addr::from("2001:db8:1::");
+        let addr = table.generate(prefix, 0);
+        assert_eq!(prefix.0 >> 64, addr.0 >> 64);
+        assert_eq!(addr, table.generate(prefix, 0));
+        assert_ne!(addr, table.generate(prefix, 1));
+        assert_ne!(
+            addr.0 as u64,
+            table.generate(IPv6Addr::from("2001:db8:2::"), 0).0 as u64
+        );
+        assert_ne!(
+            IPv6Addr::link_local_from_mac(opt.dev_addr),
+            table.generate(IPv6Addr::LINK_LOCAL_PREFIX, 0)
+        );
+    }
+
+    #[test]
+    fn duplicate_address_detection_test() {
+        let opt = option(AddressGenerationMode::Eui64);
+        let now = Instant::now();
+        let link_local = IPv6Addr::from("fe80::a00:27ff:fe3c:a981");
+        let mut table = AddressTable::new(&opt, now);
+
+        // 検出中のアドレスは使えないが，要請ノードマルチキャストには参加する
+        assert!(table.is_tentative(link_local));
+        assert!(!table.is_for_me(link_local));
+        assert!(table.is_for_me(link_local.solicited_node()));
+        assert_eq!(
+            IPv6Addr::UNSPECIFIED,
+            table.source_address(IPv6Addr::ALL_ROUTERS)
+        );
+
+        let events = table.on_timer(now);
+        assert_eq!(
+            vec![link_local, IPv6Addr::from("2001:db8::10")],
+            events.dad_solicitations
+        );
+        assert!(table.on_timer(now).dad_solicitations.is_empty());
+
+        let events = table.on_timer(now + RETRANS_TIMER);
+        assert_eq!(2, events.assigned.len());
+        assert!(table.is_for_me(link_local));
+        assert_eq!(link_local, table.source_address(IPv6Addr::ALL_ROUTERS));
+
+        // 重複した安定プライバシーアドレスは生成し直す
+        let opt = option(AddressGenerationMode::StablePrivacy);
+        let mut table = AddressTable::new(&opt, now);
+        let first = table.generate(IPv6Addr::LINK_LOCAL_PREFIX, 0);
+        let second = table.on_duplicate_address(first, now).unwrap();
+        assert_eq!(table.generate(IPv6Addr::LINK_LOCAL_PREFIX, 1), second);
+        assert!(table.get(first).is_none());
+        assert!(table.is_tentative(second));
+        // 設定されたアドレスは生成し直さない
+        assert_eq!(
+            None,
+            table.on_duplicate_address(IPv6Addr::from("2001:db8::10"), now)
+        );
+        assert!(table.get(IPv6Addr::from("2001:db8::10")).is_none());
+    }
+
+    #[test]
+    fn address_selection_test() {
+        let opt = option(AddressGenerationMode::Eui64);
+        let table = assigned_table(&opt, Instant::now());
+        let link_local = IPv6Addr::from("fe80::a00:27ff:fe3c:a981");
+
+        assert!(table.is_for_me(link_local));
+        assert!(table.is_for_me(IPv6Addr::from("2001:db8::10")));
+        assert!(table.is_for_me(IPv6Addr::ALL_NODES));
+        assert!(table.is_for_me(IPv6Addr::from("ff02::1:ff3c:a981")));
+        assert!(table.is_for_me(IPv6Addr::from("ff02::1:ff00:10")));
+        assert!(!table.is_for_me(IPv6Addr::from("2001:db8::11")));
+        assert!(!table.is_for_me(IPv6Addr::from("ff02::1:ff00:11")));
+        assert!(!table.is_for_me(IPv6Addr::ALL_ROUTERS));
+
+        assert!(table.is_on_link(IPv6Addr::from("fe80::1")));
+        assert!(table.is_on_link(IPv6Addr::from("2001:db8::1")));
+        assert!(!table.is_on_link(IPv6Addr::from("2001:db8:1::1")));
+
+        assert_eq!(link_local, table.source_address(IPv6Addr::from("fe80::1")));
+        assert_eq!(
+            IPv6Addr::from("2001:db8::10"),
+            table.source_address(IPv6Addr::from("2001:db8:1::1"))
+        );
+
+        // グローバルアドレスがなければリンクローカルアドレスを使う
+        let mut opt = opt;
+        opt.ipv6_addrs.clear();
+        let table = assigned_table(&opt, Instant::now());
+        assert_eq!(
+            link_local,
+            table.source_address(IPv6Addr::from("2001:db8:1::1"))
+        );
+    }
+
+    #[test]
+    fn prefix_information_test() {
+        let mut opt = option(AddressGenerationMode::Eui64);
+        opt.ipv6_addrs.clear();
+        let now = Instant::now();
+        let mut table = assigned_table(&opt, now);
+        let addr = IPv6Addr::from("2001:db8:1:0:a00:27ff:fe3c:a981");
+
+        // 自律フラグのないもの，推奨期限が有効期限を超えるものは無視する
+        let mut info = prefix_information(7200, 3600);
+        info.autonomous = false;
+        assert_eq!(None, table.on_prefix_information(&info, now));
+        assert_eq!(
+            None,
+            table.on_prefix_information(&prefix_information(3600, 7200), now)
+        );
+
+        assert_eq!(
+            Some(addr),
+            table.on_prefix_information(&prefix_information(86400, 3600), now)
+        );
+        table.on_timer(now);
+        table.on_timer(now + RETRANS_TIMER);
+        assert_eq!(addr, table.source_address(IPv6Addr::from("2001:db8:2::1")));
+
+        // 推奨期限が切れると，リンクローカルアドレスより優先はするが非推奨となる
+        let later = now + Duration::from_secs(3600);
+        table.on_timer(later);
+        assert_eq!(AddressState::Deprecated, table.get(addr).unwrap().state);
+        assert_eq!(addr, table.source_address(IPv6Addr::from("2001:db8:2::1")));
+
+        // 有効期限を2時間未満に縮める広告は，2時間に切り詰める
+        assert_eq!(
+            None,
+            table.on_prefix_information(&prefix_information(60, 60), later)
+        );
+        let entry = table.get(addr).unwrap();
+        assert_eq!(AddressState::Preferred, entry.state);
+        assert_eq!(Some(later + MIN_VALID_LIFETIME), entry.valid_until);
+
+        // 残りが2時間以下になれば，それ以上は縮められない
+        let later = later + Duration::from_secs(3600);
+        table.on_prefix_information(&prefix_information(60, 0), later);
+        assert_eq!(
+            Some(later + Duration::from_secs(3600)),
+            table.get(addr).unwrap().valid_until
+        );
+
+        // 有効期限が切れたアドレスは取り除く
+        table.on_timer(later + Duration::from_secs(3600));
+        assert!(table.get(addr).is_none());
+    }
+
+    #[test]
+    fn autoconf_address_limit_test() {
+        let opt = option(AddressGenerationMode::Eui64);
+        let now = Instant::now();
+        let mut table = assigned_table(&opt, now);
+
+        let mut info = prefix_information(86400, 3600);
+        for i in 0..MAX_AUTOCONF_ADDRESSES as u128 {
+            info.prefix = IPv6Addr(IPv6Addr::from("2001:db8:100::").0 + (i << 64));
+            assert!(table.on_prefix_information(&info, now).is_some());
+        }
+
+        // 上限に達した後の新しいプレフィックスは無視する
+        info.prefix = IPv6Addr::from("2001:db8:200::");
+        assert_eq!(None, table.on_prefix_information(&info, now));
+        assert!(table.get(table.generate(info.prefix, 0)).is_none());
+
+        // 設定済みのプレフィックスの期限は引き続き更新する
+        info.prefix = IPv6Addr::from("2001:db8:100::");
+        let addr = table.generate(info.prefix, 0);
+        let later = now + Duration::from_secs(60);
+        assert_eq!(None, table.on_prefix_information(&info, later));
+        assert_eq!(
+            Some(later + Duration::from_secs(86400)),
+            table.get(addr).unwrap().valid_until
+        );
+
+        // 有効期限が切れて減れば，再び自動設定する
+        table.on_timer(now + Duration::from_secs(86400));
+        info.prefix = IPv6Addr::from("2001:db8:200::");
+        assert!(table
+            .on_prefix_information(&info, now + Duration::from_secs(86400))
+            .is_some());
+    }
+}
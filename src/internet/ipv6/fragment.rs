@@ -0,0 +1,391 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use super::{IPv6Addr, IPv6Header, NextHeader};
+use crate::{byteorder_wrapper, RxResult};
+
+/// 最初の断片を受信してから再構築を諦めるまでの時間
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-4.5)
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Packet Too Bigで知った経路MTUを保持する時間．
+/// 経路が変わってMTUが大きくなった場合に備え，期限が切れればリンクのMTUに戻す
+/// See also [RFC8201](https://tools.ietf.org/html/rfc8201#section-4)
+pub const PATH_MTU_AGING: Duration = Duration::from_secs(10 * 60);
+/// 同時に再構築するパケットの上限．断片を送りつけてメモリを消費させる攻撃を防ぐ
+const MAX_REASSEMBLIES: usize = 64;
+/// 再構築後のペイロード長の上限
+const MAX_PAYLOAD_LENGTH: usize = 0xffff;
+/// fragment offset領域のうち，オフセットが該当する部分のマスク
+const OFFSET_MASK: u16 = 0xfff8;
+/// fragment offset領域のうち，Mフラグが該当する部分のマスク
+const MORE_FRAGMENTS_FLAG: u16 = 0x0001;
+
+/// フラグメントヘッダ
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-4.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    /// 再構築したパケットの，分割可能部分の先頭のヘッダの種類
+    pub next_header: NextHeader,
+    /// 8オクテット単位のオフセット
+    pub offset: u16,
+    pub more_fragments: bool,
+    pub identification: u32,
+}
+
+/// 再構築中のパケットを識別する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ReassemblyKey {
+    src_addr: IPv6Addr,
+    dst_addr: IPv6Addr,
+    identification: u32,
+}
+
+/// 再構築中のパケット
+#[derive(Debug, Clone)]
+struct Reassembly {
+    /// 先頭の断片の固定ヘッダと分割不可能部分．次ヘッダ領域は書き換え済み
+    unfragmentable: Option<Vec<u8>>,
+    /// 先頭の断片の受信結果とデータ．タイムアウト時のICMPv6エラーで引用する
+    first_fragment: Option<(RxResult, Vec<u8>)>,
+    /// オフセット(バイト)と断片のデータ
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// 最後の断片から求めた，分割可能部分の長さ
+    total_length: Option<usize>,
+    expires_at: Instant,
+}
+
+/// 断片の再構築を行うテーブル
+#[derive(Debug, Clone, Default)]
+pub struct ReassemblyTable {
+    reassemblies: HashMap<ReassemblyKey, Reassembly>,
+}
+
+impl FragmentHeader {
+    pub const LENGTH: usize = 8;
+
+    pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut reader = Cursor::new(buf);
+        let next_header = NextHeader::from(byteorder_wrapper::read_u8(&mut reader, err)?);
+        let _reserved = byteorder_wrapper::read_u8(&mut reader, err)?;
+        let offset_flags = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+
+        Ok(Self {
+            next_header,
+            offset: (offset_flags & OFFSET_MASK) >> 3,
+            more_fragments: offset_flags & MORE_FRAGMENTS_FLAG != 0,
+            identification: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+        })
+    }
+
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut buf = Vec::new();
+        byteorder_wrapper::write_u8(&mut buf, self.next_header.into(), err)?;
+        byteorder_wrapper::write_u8(&mut buf, 0, err)?;
+        let more_fragments = if self.more_fragments {
+            MORE_FRAGMENTS_FLAG
+        } else {
+            0
+        };
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.offset << 3 | more_fragments, err)?;
+        byteorder_wrapper::write_u32_as_be(&mut buf, self.identification, err)?;
+
+        Ok(buf)
+    }
+
+    pub fn byte_offset(&self) -> usize {
+        self.offset as usize * 8
+    }
+
+    /// 分割されていないパケットに付けられたフラグメントヘッダ(atomic fragment)か．
+    /// 他の断片と混ぜずにそのまま処理する
+    /// See also [RFC6946](https://tools.ietf.org/html/rfc6946#section-4)
+    pub fn is_atomic(&self) -> bool {
+        self.offset == 0 && !self.more_fragments
+    }
+}
+
+impl ReassemblyTable {
+    /// 断片を追加する．全ての断片が揃えば，再構築したパケットを返す．
+    /// `unfragmentable` は固定ヘッダと分割不可能部分で，フラグメントヘッダを指す次ヘッダ領域は
+    /// `fragment.next_header` に書き換えておく．
+    /// `first_fragment` はオフセット0の断片でのみ渡す
+    pub fn insert(
+        &mut self,
+        fragment: &FragmentHeader,
+        unfragmentable: Vec<u8>,
+        data: &[u8],
+        first_fragment: Option<(RxResult, Vec<u8>)>,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(&unfragmentable[8..24]);
+        let src_addr = IPv6Addr::from(octets);
+        octets.copy_from_slice(&unfragmentable[24..40]);
+        let dst_addr = IPv6Addr::from(octets);
+        let key = ReassemblyKey {
+            src_addr,
+            dst_addr,
+            identification: fragment.identification,
+        };
+        if !self.reassemblies.contains_key(&key) && self.reassemblies.len() >= MAX_REASSEMBLIES {
+            return None;
+        }
+        let reassembly = self.reassemblies.entry(key).or_insert_with(|| Reassembly {
+            unfragmentable: None,
+            first_fragment: None,
+            fragments: BTreeMap::new(),
+            total_length: None,
+            expires_at: now + REASSEMBLY_TIMEOUT,
+        });
+
+        let start = fragment.byte_offset();
+        let end = start + data.len();
+        // 同じ断片の再送は無視し，重なり合う断片があれば再構築自体を諦める
+        // See also [RFC5722](https://tools.ietf.org/html/rfc5722#section-4)
+        if reassembly.fragments.get(&start).map(|d| d.as_slice()) == Some(data) {
+            return None;
+        }
+        let overlapped = reassembly
+            .fragments
+            .iter()
+            .any(|(offset, d)| *offset < end && start < *offset + d.len());
+        let inconsistent = match (fragment.more_fragments, reassembly.total_length) {
+            (false, Some(total_length)) => total_length != end,
+            (false, None) => reassembly
+                .fragments
+                .iter()
+                .any(|(offset, d)| *offset + d.len() > end),
+            (true, Some(total_length)) => end > total_length,
+            (true, None) => false,
+        };
+        if overlapped || inconsistent {
+            self.reassemblies.remove(&key);
+            return None;
+        }
+
+        if !fragment.more_fragments {
+            reassembly.total_length = Some(end);
+        }
+        if start == 0 {
+            reassembly.unfragmentable = Some(unfragmentable);
+            reassembly.first_fragment = first_fragment;
+        }
+        reassembly.fragments.insert(start, data.to_vec());
+
+        if !reassembly.is_complete() {
+            return None;
+        }
+        let reassembly = self.reassemblies.remove(&key)?;
+        let mut packet = reassembly.unfragmentable?;
+        for (_, d) in reassembly.fragments {
+            packet.extend(d);
+        }
+        Some(set_payload_length(packet))
+    }
+
+    /// 期限の切れた再構築を取り除く．
+    /// 先頭の断片を受信していたものは，Time Exceededで引用するためにその受信結果を返す
+    pub fn expire(&mut self, now: Instant) -> Vec<(RxResult, Vec<u8>)> {
+        let expired: Vec<ReassemblyKey> = self
+            .reassemblies
+            .iter()
+            .filter(|(_, reassembly)| reassembly.expires_at <= now)
+            .map(|(key, _)| *key)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|key| self.reassemblies.remove(&key)?.first_fragment)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.reassemblies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reassemblies.is_empty()
+    }
+}
+
+impl Reassembly {
+    fn is_complete(&self) -> bool {
+        let total_length = match self.total_length {
+            Some(total_length) => total_length,
+            None => return false,
+        };
+        if self.unfragmentable.is_none() {
+            return false;
+        }
+
+        let mut next = 0;
+        for (offset, d) in self.fragments.iter() {
+            if *offset != next {
+                return false;
+            }
+            next += d.len();
+        }
+        next == total_length
+    }
+}
+
+/// 固定ヘッダのペイロード長を，パケット全体の長さに合わせる
+pub fn set_payload_length(mut packet: Vec<u8>) -> Vec<u8> {
+    let payload_length = (packet.len() - IPv6Header::LENGTH) as u16;
+    packet[4..6].copy_from_slice(&payload_length.to_be_bytes());
+    packet
+}
+
+/// パケットをMTUに収まる断片に分割する．
+/// 拡張ヘッダは送信しないので，分割不可能部分は固定ヘッダのみとなる
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-4.5)
+pub fn fragment<E>(
+    packet_hdr: &IPv6Header,
+    payload: &[u8],
+    mtu: usize,
+    identification: u32,
+    err: E,
+) -> Result<Vec<Vec<u8>>, E>
+where
+    E: std::error::Error + Copy,
+{
+    if payload.len() > MAX_PAYLOAD_LENGTH - FragmentHeader::LENGTH {
+        return Err(err);
+    }
+    // 最後以外の断片のデータ長は8の倍数でなければならない
+    let chunk_size = mtu.saturating_sub(IPv6Header::LENGTH + FragmentHeader::LENGTH) & !7;
+    if chunk_size == 0 {
+        return Err(err);
+    }
+
+    let mut packets = Vec::new();
+    for (i, chunk) in payload.chunks(chunk_size).enumerate() {
+        let offset = i * chunk_size;
+        let fragment_hdr = FragmentHeader {
+            next_header: packet_hdr.next_header,
+            offset: (offset / 8) as u16,
+            more_fragments: offset + chunk.len() < payload.len(),
+            identification,
+        };
+
+        let mut hdr = *packet_hdr;
+        hdr.next_header = NextHeader::Fragment;
+        hdr.payload_length = (FragmentHeader::LENGTH + chunk.len()) as u16;
+
+        let mut packet = hdr.to_bytes(err)?;
+        packet.append(&mut fragment_hdr.to_bytes(err)?);
+        packet.extend_from_slice(chunk);
+        packets.push(packet);
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internet::InternetProtocolError;
+
+    fn header(payload_length: u16) -> IPv6Header {
+        IPv6Header {
+            version_class_flow: 0x6000_0000,
+            payload_length,
+            next_header: NextHeader::UDP,
+            hop_limit: 64,
+            src_addr: IPv6Addr::from("2001:db8::1"),
+            dst_addr: IPv6Addr::from("2001:db8::2"),
+        }
+    }
+
+    /// 分割した断片を，受信側と同じ手順でテーブルに入れる
+    fn insert(table: &mut ReassemblyTable, packet: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let err = InternetProtocolError::CannotParsePacketHeader;
+        let fragment_hdr = FragmentHeader::new_from_bytes(&packet[40..], err).unwrap();
+        let mut unfragmentable = packet[..40].to_vec();
+        unfragmentable[6] = fragment_hdr.next_header.into();
+
+        table.insert(&fragment_hdr, unfragmentable, &packet[48..], None, now)
+    }
+
+    #[test]
+    fn fragment_header_test() {
+        let err = InternetProtocolError::CannotParsePacketHeader;
+        let raw = [17, 0, 0x05, 0x39, 0xde, 0xad, 0xbe, 0xef];
+        let hdr = FragmentHeader::new_from_bytes(&raw, err).unwrap();
+        assert_eq!(NextHeader::UDP, hdr.next_header);
+        assert_eq!(167, hdr.offset);
+        assert_eq!(1336, hdr.byte_offset());
+        assert!(hdr.more_fragments);
+        assert_eq!(0xdeadbeef, hdr.identification);
+        assert!(!hdr.is_atomic());
+        assert_eq!(raw.to_vec(), hdr.to_bytes(err).unwrap());
+
+        let hdr = FragmentHeader::new_from_bytes(&[17, 0, 0, 0, 0, 0, 0, 1], err).unwrap();
+        assert!(hdr.is_atomic());
+    }
+
+    #[test]
+    fn fragment_and_reassemble_test() {
+        let err = InternetProtocolError::CannotConstructPacket;
+        let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let hdr = header(payload.len() as u16);
+        let fragments = fragment(&hdr, &payload, 1280, 7, err).unwrap();
+
+        assert_eq!(3, fragments.len());
+        assert!(fragments.iter().all(|f| f.len() <= 1280));
+        // 最後以外のデータ長は8の倍数
+        assert_eq!(1232, fragments[0].len() - 48);
+
+        // 順不同で受信しても再構築できる
+        let now = Instant::now();
+        let mut table: ReassemblyTable = Default::default();
+        assert_eq!(None, insert(&mut table, &fragments[2], now));
+        assert_eq!(None, insert(&mut table, &fragments[0], now));
+        // 同じ断片の再送は無視する
+        assert_eq!(None, insert(&mut table, &fragments[0], now));
+        let packet = insert(&mut table, &fragments[1], now).unwrap();
+
+        let mut expected = hdr.to_bytes(err).unwrap();
+        expected.extend_from_slice(&payload);
+        assert_eq!(expected, packet);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn reassembly_error_test() {
+        let err = InternetProtocolError::CannotConstructPacket;
+        let payload = vec![0xaa; 3000];
+        let hdr = header(payload.len() as u16);
+        let fragments = fragment(&hdr, &payload, 1280, 7, err).unwrap();
+        let now = Instant::now();
+
+        // 重なり合う断片を受信したら再構築を諦める
+        let mut table: ReassemblyTable = Default::default();
+        assert_eq!(None, insert(&mut table, &fragments[0], now));
+        let mut overlapped = fragments[1].clone();
+        overlapped[42..44].copy_from_slice(&(1224u16 | 1).to_be_bytes());
+        assert_eq!(None, insert(&mut table, &overlapped, now));
+        assert!(table.is_empty());
+
+        // 期限切れ
+        let mut table: ReassemblyTable = Default::default();
+        assert_eq!(None, insert(&mut table, &fragments[0], now));
+        assert!(table.expire(now).is_empty());
+        assert_eq!(1, table.len());
+        table.expire(now + REASSEMBLY_TIMEOUT);
+        assert!(table.is_empty());
+
+        // MTUが小さすぎる，ペイロードが大きすぎる場合は分割できない
+        assert!(fragment(&hdr, &payload, 48, 7, err).is_err());
+        assert!(fragment(&hdr, &[0; 65530], 1280, 7, err).is_err());
+    }
+}
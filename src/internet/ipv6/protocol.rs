@@ -0,0 +1,311 @@
+use std::time::Instant;
+
+use super::{
+    fragment, set_payload_length, ExtensionHeaderChain, FragmentHeader, IPv6Addr, IPv6Header,
+    NextHeader,
+};
+use crate::{
+    internet::{InternetProtocol, InternetProtocolError},
+    link::{self, MacAddress},
+    network_device,
+    transport::{self, TransportProtocol},
+    Items, RxResult,
+};
+
+/// 送信するパケットのデフォルトのホップリミット
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+pub async fn rx<'a, ND: network_device::NetworkDevice>(
+    table: &'a Items<ND>,
+    mut rx_result: RxResult,
+    buf: &'a [u8],
+) -> Result<(RxResult, Vec<u8>), InternetProtocolError> {
+    // 断片を再構築できた場合は，再構築したパケットを処理し直す
+    let mut packet = buf.to_vec();
+    loop {
+        let packet_hdr =
+            IPv6Header::new_from_bytes(&packet, InternetProtocolError::CannotParsePacketHeader)?;
+
+        if table.opt.debug {
+            eprintln!("++++++++ rx ipv6 packet ++++++++");
+            eprintln!("{}", packet_hdr);
+        }
+
+        validate_ipv6_packet(&packet_hdr, packet.len())?;
+
+        // 転送は未実装なので，他のホストに向けられたパケットは処理しない
+        if !table
+            .ipv6_addrs
+            .lock()
+            .unwrap()
+            .is_for_me(packet_hdr.dst_addr)
+        {
+            return Err(InternetProtocolError::Ignore);
+        }
+
+        // リンク層のパディングを取り除く
+        packet.truncate(IPv6Header::LENGTH + packet_hdr.payload_length as usize);
+        let payload = &packet[IPv6Header::LENGTH..];
+        let chain = ExtensionHeaderChain::walk(
+            packet_hdr.next_header,
+            payload,
+            InternetProtocolError::InvalidExtensionHeader,
+        )?;
+        let (raw_ext_headers, rest) = payload.split_at(chain.payload_offset);
+
+        let mut raw_header = packet[..IPv6Header::LENGTH].to_vec();
+        raw_header.extend_from_slice(raw_ext_headers);
+
+        rx_result.src_ip_addr = packet_hdr.src_addr.into();
+        rx_result.dst_ip_addr = packet_hdr.dst_addr.into();
+        rx_result.raw_ip_header = raw_header;
+        rx_result.tp_type = TransportProtocol::from(Into::<u8>::into(chain.upper_layer));
+        rx_result.message_len = rest.len();
+
+        match chain.upper_layer {
+            NextHeader::NoNextHeader => return Err(InternetProtocolError::Ignore),
+            NextHeader::Fragment => match rx_fragment(table, &rx_result, &packet, &chain).await? {
+                Some(reassembled) => {
+                    packet = reassembled;
+                    continue;
+                }
+                None => return Err(InternetProtocolError::Ignore),
+            },
+            _ => {}
+        }
+
+        // 対応していない次ヘッダの場合はParameter Problemを返す
+        if rx_result.tp_type == TransportProtocol::UnAssigned {
+            let error = transport::icmpv6::ErrorMessage::ParameterProblem {
+                code: transport::icmpv6::ParameterProblemCode::UnrecognizedNextHeader,
+                pointer: chain.next_header_pointer() as u32,
+            };
+            send_error(table, error, rx_result, rest).await;
+            return Err(InternetProtocolError::Ignore);
+        }
+
+        return Ok((rx_result, rest.to_vec()));
+    }
+}
+
+/// 断片を再構築テーブルに加え，全て揃えば再構築したパケットを返す．
+/// 分割されていないパケット(atomic fragment)は，フラグメントヘッダを取り除いてすぐに返す
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-4.5)
+async fn rx_fragment<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    rx_result: &RxResult,
+    packet: &[u8],
+    chain: &ExtensionHeaderChain,
+) -> Result<Option<Vec<u8>>, InternetProtocolError> {
+    let fragment_end = IPv6Header::LENGTH + chain.payload_offset;
+    let fragment_start = fragment_end - FragmentHeader::LENGTH;
+    let fragment_hdr = FragmentHeader::new_from_bytes(
+        &packet[fragment_start..],
+        InternetProtocolError::InvalidExtensionHeader,
+    )?;
+    let data = &packet[fragment_end..];
+
+    let mut unfragmentable = packet[..fragment_start].to_vec();
+    unfragmentable[chain.fragment_header_pointer()] = fragment_hdr.next_header.into();
+    if fragment_hdr.is_atomic() {
+        unfragmentable.extend_from_slice(data);
+        return Ok(Some(set_payload_length(unfragmentable)));
+    }
+
+    // 最後以外の断片のデータ長が8の倍数でないか，再構築したパケットのペイロード長が上限を超える
+    let payload_length =
+        fragment_start - IPv6Header::LENGTH + fragment_hdr.byte_offset() + data.len();
+    let pointer = if fragment_hdr.more_fragments && !data.len().is_multiple_of(8) {
+        // 固定ヘッダのペイロード長領域
+        Some(4)
+    } else if payload_length > u16::MAX as usize {
+        // フラグメントヘッダのオフセット領域
+        Some(fragment_start + 2)
+    } else {
+        None
+    };
+    if let Some(pointer) = pointer {
+        let error = transport::icmpv6::ErrorMessage::ParameterProblem {
+            code: transport::icmpv6::ParameterProblemCode::ErroneousHeaderField,
+            pointer: pointer as u32,
+        };
+        send_error(table, error, rx_result.clone(), data).await;
+        return Ok(None);
+    }
+
+    // 先頭の断片は，上位層のヘッダまでの拡張ヘッダを全て含まなければならない
+    // See also [RFC7112](https://tools.ietf.org/html/rfc7112#section-5)
+    let first_fragment = if fragment_hdr.offset == 0 {
+        if ExtensionHeaderChain::walk(
+            fragment_hdr.next_header,
+            data,
+            InternetProtocolError::InvalidExtensionHeader,
+        )
+        .is_err()
+        {
+            return Ok(None);
+        }
+        let mut first = rx_result.clone();
+        first.tp_type = TransportProtocol::from(Into::<u8>::into(fragment_hdr.next_header));
+        Some((first, data.to_vec()))
+    } else {
+        None
+    };
+
+    Ok(table.ipv6_reassembly.lock().unwrap().insert(
+        &fragment_hdr,
+        unfragmentable,
+        data,
+        first_fragment,
+        Instant::now(),
+    ))
+}
+
+/// 期限の切れた断片の再構築を諦め，先頭の断片を受信していればTime Exceededを返す
+pub async fn on_timer<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    now: Instant,
+) -> Result<(), InternetProtocolError> {
+    let expired = table.ipv6_reassembly.lock().unwrap().expire(now);
+
+    for (rx_result, payload) in expired {
+        let error = transport::icmpv6::ErrorMessage::TimeExceeded(
+            transport::icmpv6::TimeExceededCode::FragmentReassemblyTimeExceeded,
+        );
+        send_error(table, error, rx_result, &payload).await;
+    }
+
+    Ok(())
+}
+
+/// ICMPv6エラーを送信する．送信できなくても受信処理は続ける
+async fn send_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    error: transport::icmpv6::ErrorMessage,
+    rx_result: RxResult,
+    payload: &[u8],
+) {
+    if let Err(e) = transport::icmpv6::tx_error(table, error, rx_result, payload).await {
+        if table.opt.debug {
+            eprintln!("++++++++ cannot send icmpv6 error: {} ++++++++", e);
+        }
+    }
+}
+
+pub async fn tx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    tp: TransportProtocol,
+    rx_result: RxResult,
+    tp_payload: Vec<u8>,
+) -> Result<(), InternetProtocolError> {
+    tx_with_hop_limit(table, tp, rx_result, tp_payload, DEFAULT_HOP_LIMIT).await
+}
+
+/// ホップリミットを指定して送信する．
+/// `rx_result.src_ipv6_addr` を宛先，`rx_result.dst_ipv6_addr` を送信元とする．
+/// 宛先のリンク層アドレスは近隣キャッシュから引き，なければ近隣要請で解決する
+pub async fn tx_with_hop_limit<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    tp: TransportProtocol,
+    rx_result: RxResult,
+    tp_payload: Vec<u8>,
+    hop_limit: u8,
+) -> Result<(), InternetProtocolError> {
+    let dst_mac_addr = resolve_link_address(table, rx_result.src_ipv6_addr()).await?;
+
+    tx_core(table, tp, rx_result, tp_payload, hop_limit, dst_mac_addr).await
+}
+
+/// 宛先のリンク層アドレスを指定して送信する．
+/// 近隣探索のメッセージはアドレス解決を経ずにこれを使う．
+/// 経路MTUを超える場合は断片化して送信する
+pub async fn tx_core<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    tp: TransportProtocol,
+    rx_result: RxResult,
+    mut tp_payload: Vec<u8>,
+    hop_limit: u8,
+    dst_mac_addr: MacAddress,
+) -> Result<(), InternetProtocolError> {
+    let packet_hdr = IPv6Header {
+        version_class_flow: (IPv6Header::VERSION6 as u32) << 28,
+        payload_length: tp_payload.len() as u16,
+        next_header: NextHeader::from(Into::<u8>::into(tp)),
+        hop_limit,
+        src_addr: rx_result.dst_ipv6_addr(),
+        dst_addr: rx_result.src_ipv6_addr(),
+    };
+    if table.opt.debug {
+        eprintln!("++++++++ tx ipv6 packet ++++++++");
+        eprintln!("{}", packet_hdr);
+    }
+
+    // ルータは断片化しないので，経路MTUを超えるパケットは送信元で分割する
+    let mtu = table.path_mtu(packet_hdr.dst_addr);
+    if IPv6Header::LENGTH + tp_payload.len() > mtu {
+        let packets = fragment(
+            &packet_hdr,
+            &tp_payload,
+            mtu,
+            rand::random(),
+            InternetProtocolError::CannotConstructPacket,
+        )?;
+        for packet in packets {
+            link::ethernet::tx(table, InternetProtocol::IPv6, dst_mac_addr, packet).await?;
+        }
+        return Ok(());
+    }
+
+    let mut packet = packet_hdr.to_bytes(InternetProtocolError::CannotConstructPacket)?;
+    packet.append(&mut tp_payload);
+
+    link::ethernet::tx(table, InternetProtocol::IPv6, dst_mac_addr, packet).await?;
+
+    Ok(())
+}
+
+/// 宛先のリンク層アドレスを求める．
+/// リンク外の宛先であればデフォルトルータのリンク層アドレスを返す
+async fn resolve_link_address<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    dst: IPv6Addr,
+) -> Result<MacAddress, InternetProtocolError> {
+    if dst.is_multicast() {
+        return Ok(link::ethernet::ipv6_multicast_mac_address(dst));
+    }
+
+    let on_link = table.ipv6_addrs.lock().unwrap().is_on_link(dst);
+    let next_hop = if on_link || table.lookup_neighbor_cache(&dst).is_some() {
+        dst
+    } else {
+        table
+            .default_router()
+            .ok_or(InternetProtocolError::NoRouteToHost { dst })?
+    };
+
+    if let Some(mac_addr) = table.lookup_neighbor_cache(&next_hop) {
+        return Ok(mac_addr);
+    }
+    transport::icmpv6::resolve_mac_address(table, next_hop).await
+}
+
+fn validate_ipv6_packet(
+    packet_hdr: &IPv6Header,
+    raw_packet_len: usize,
+) -> Result<(), InternetProtocolError> {
+    if packet_hdr.version() != IPv6Header::VERSION6 {
+        return Err(InternetProtocolError::NotIPv6Packet);
+    }
+
+    // ヘッダに格納されているペイロード長が実際のバッファサイズより大きければエラーとする
+    if raw_packet_len < IPv6Header::LENGTH + packet_hdr.payload_length as usize {
+        return Err(InternetProtocolError::InvalidPacketLength);
+    }
+
+    // マルチキャストアドレスは送信元になり得ない
+    if packet_hdr.src_addr.is_multicast() {
+        return Err(InternetProtocolError::Ignore);
+    }
+
+    Ok(())
+}
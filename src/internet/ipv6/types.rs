@@ -0,0 +1,619 @@
+use std::io::Cursor;
+
+use crate::{byteorder_wrapper, link::MacAddress};
+
+/// version_class_flow領域のうちversionが該当する部分のマスク
+const VCF_VERSION_MASK: u32 = 0xf000_0000;
+/// version_class_flow領域のうちtraffic classが該当する部分のマスク
+const VCF_TRAFFIC_CLASS_MASK: u32 = 0x0ff0_0000;
+/// version_class_flow領域のうちflow labelが該当する部分のマスク
+const VCF_FLOW_LABEL_MASK: u32 = 0x000f_ffff;
+
+/// IPv6パケットの固定ヘッダ構造体
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IPv6Header {
+    /// 上位4ビット: version, 次の8ビット: traffic class, 下位20ビット: flow label
+    pub version_class_flow: u32,
+    /// 固定ヘッダを除いた，拡張ヘッダを含むペイロードの長さ
+    pub payload_length: u16,
+    /// 固定ヘッダの直後に続くヘッダの種類
+    pub next_header: NextHeader,
+    /// IPv4のTTLに相当する，転送可能な残りのホップ数
+    pub hop_limit: u8,
+    /// 送信元IPv6アドレス
+    pub src_addr: IPv6Addr,
+    /// 宛先IPv6アドレス
+    pub dst_addr: IPv6Addr,
+}
+
+/// IPv6ヘッダ及び拡張ヘッダの次ヘッダ領域の値
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NextHeader {
+    HopByHopOptions,
+    TCP,
+    UDP,
+    Routing,
+    Fragment,
+    EncapsulatingSecurityPayload,
+    Authentication,
+    ICMPv6,
+    NoNextHeader,
+    DestinationOptions,
+    Unknown(u8),
+}
+
+/// 拡張ヘッダ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionHeader {
+    /// この拡張ヘッダの種類
+    pub ty: NextHeader,
+    /// 次に続くヘッダの種類
+    pub next_header: NextHeader,
+    /// 次ヘッダ領域と長さ領域を除いた内容
+    pub data: Vec<u8>,
+}
+
+/// 固定ヘッダに続く拡張ヘッダを辿った結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionHeaderChain {
+    pub headers: Vec<ExtensionHeader>,
+    /// 拡張ヘッダの後に続く上位層のプロトコル
+    pub upper_layer: NextHeader,
+    /// ペイロードの先頭から，上位層のデータまでの長さ
+    pub payload_offset: usize,
+}
+
+/// IPv6 Address
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+pub struct IPv6Addr(pub u128);
+
+impl IPv6Header {
+    /// 固定ヘッダの長さ
+    pub const LENGTH: usize = 40;
+    pub const VERSION6: u8 = 6;
+
+    pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut reader = Cursor::new(buf);
+
+        Ok(Self {
+            version_class_flow: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+            payload_length: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            next_header: NextHeader::from(byteorder_wrapper::read_u8(&mut reader, err)?),
+            hop_limit: byteorder_wrapper::read_u8(&mut reader, err)?,
+            src_addr: IPv6Addr::from_cursor(&mut reader, err)?,
+            dst_addr: IPv6Addr::from_cursor(&mut reader, err)?,
+        })
+    }
+
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut buf = Vec::new();
+        byteorder_wrapper::write_u32_as_be(&mut buf, self.version_class_flow, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.payload_length, err)?;
+        byteorder_wrapper::write_u8(&mut buf, self.next_header.into(), err)?;
+        byteorder_wrapper::write_u8(&mut buf, self.hop_limit, err)?;
+        buf.extend_from_slice(&self.src_addr.octets());
+        buf.extend_from_slice(&self.dst_addr.octets());
+
+        Ok(buf)
+    }
+
+    pub fn version(&self) -> u8 {
+        ((self.version_class_flow & VCF_VERSION_MASK) >> 28) as u8
+    }
+
+    pub fn traffic_class(&self) -> u8 {
+        ((self.version_class_flow & VCF_TRAFFIC_CLASS_MASK) >> 20) as u8
+    }
+
+    pub fn flow_label(&self) -> u32 {
+        self.version_class_flow & VCF_FLOW_LABEL_MASK
+    }
+}
+
+impl NextHeader {
+    /// 拡張ヘッダかどうか．上位層のプロトコルであれば `false` を返す
+    pub fn is_extension_header(&self) -> bool {
+        matches!(
+            self,
+            NextHeader::HopByHopOptions
+                | NextHeader::Routing
+                | NextHeader::Fragment
+                | NextHeader::Authentication
+                | NextHeader::DestinationOptions
+        )
+    }
+}
+
+impl ExtensionHeaderChain {
+    /// 固定ヘッダの次ヘッダ領域 `first` から，上位層に到達するまで拡張ヘッダを辿る．
+    /// `payload` は固定ヘッダを除いたペイロード．
+    /// フラグメントヘッダより後ろは断片化されたデータなので，そこで辿るのをやめて
+    /// `upper_layer` を `NextHeader::Fragment` とする
+    pub fn walk<E>(first: NextHeader, payload: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut headers = Vec::new();
+        let mut ty = first;
+        let mut offset = 0;
+
+        while ty.is_extension_header() {
+            // Hop-by-Hopオプションヘッダは固定ヘッダの直後にしか置けない
+            if ty == NextHeader::HopByHopOptions && offset != 0 {
+                return Err(err);
+            }
+
+            let rest = payload.get(offset..).ok_or(err)?;
+            if rest.len() < 2 {
+                return Err(err);
+            }
+            let next_header = NextHeader::from(rest[0]);
+            let length = match ty {
+                // 長さ領域を持たない固定長のヘッダ
+                NextHeader::Fragment => 8,
+                // 長さ領域は4オクテット単位で，先頭の8オクテットを含まない
+                // See also [RFC4302](https://tools.ietf.org/html/rfc4302#section-2.2)
+                NextHeader::Authentication => (rest[1] as usize + 2) * 4,
+                // 長さ領域は8オクテット単位で，先頭の8オクテットを含まない
+                _ => (rest[1] as usize + 1) * 8,
+            };
+            if rest.len() < length {
+                return Err(err);
+            }
+
+            headers.push(ExtensionHeader {
+                ty,
+                next_header,
+                data: rest[2..length].to_vec(),
+            });
+            offset += length;
+            if ty == NextHeader::Fragment {
+                break;
+            }
+            ty = next_header;
+        }
+
+        Ok(Self {
+            headers,
+            upper_layer: ty,
+            payload_offset: offset,
+        })
+    }
+
+    /// フラグメントヘッダを指す次ヘッダ領域の，固定ヘッダ先頭からの位置．
+    /// 再構築したパケットでは，この領域を断片化されたデータの種類に書き換える
+    pub fn fragment_header_pointer(&self) -> usize {
+        let count = self.headers.len();
+        if count < 2 {
+            return 6;
+        }
+        let fragment_start =
+            IPv6Header::LENGTH + self.payload_offset - self.headers[count - 1].data.len() - 2;
+        fragment_start - (self.headers[count - 2].data.len() + 2)
+    }
+
+    /// 上位層のプロトコルを指す次ヘッダ領域の，固定ヘッダ先頭からの位置．
+    /// Parameter Problemで未知の次ヘッダを指し示すのに使う
+    pub fn next_header_pointer(&self) -> usize {
+        match self.headers.last() {
+            Some(last) => IPv6Header::LENGTH + self.payload_offset - (last.data.len() + 2),
+            // 固定ヘッダの次ヘッダ領域
+            None => 6,
+        }
+    }
+}
+
+impl IPv6Addr {
+    /// 未指定アドレス(::)
+    pub const UNSPECIFIED: Self = Self(0);
+    /// ループバックアドレス(::1)
+    pub const LOOPBACK: Self = Self(1);
+    /// リンクローカルの全ノードマルチキャストアドレス(ff02::1)
+    pub const ALL_NODES: Self = Self(0xff02_0000_0000_0000_0000_0000_0000_0001);
+    /// リンクローカルの全ルータマルチキャストアドレス(ff02::2)
+    pub const ALL_ROUTERS: Self = Self(0xff02_0000_0000_0000_0000_0000_0000_0002);
+    /// リンクローカルアドレスのプレフィックス(fe80::/64)
+    pub const LINK_LOCAL_PREFIX: Self = Self(0xfe80 << 112);
+
+    pub fn from_cursor<E>(reader: &mut Cursor<&[u8]>, err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut v = 0u128;
+        for _ in 0..4 {
+            v = v << 32 | byteorder_wrapper::read_u32_as_be(reader, err)? as u128;
+        }
+        Ok(Self(v))
+    }
+
+    pub fn octets(&self) -> [u8; 16] {
+        self.0.to_be_bytes()
+    }
+
+    /// 16ビット毎に区切った8つのグループ
+    pub fn segments(&self) -> [u16; 8] {
+        let mut segments = [0; 8];
+        for (i, segment) in segments.iter_mut().enumerate() {
+            *segment = (self.0 >> (112 - i * 16)) as u16;
+        }
+        segments
+    }
+
+    /// 文字列表記を解釈する．`::` による省略と，末尾のIPv4アドレス表記を受け付ける
+    /// See also [RFC4291](https://tools.ietf.org/html/rfc4291#section-2.2)
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, "::");
+        let head = parts.next()?;
+        let tail = parts.next();
+        if tail.is_some_and(|t| t.contains("::")) {
+            return None;
+        }
+
+        let head = Self::parse_groups(head, tail.is_none())?;
+        let groups = match tail {
+            Some(tail) => {
+                let tail = Self::parse_groups(tail, true)?;
+                if head.len() + tail.len() > 7 {
+                    return None;
+                }
+                let mut groups = head;
+                groups.resize(8 - tail.len(), 0);
+                groups.extend(tail);
+                groups
+            }
+            None => head,
+        };
+        if groups.len() != 8 {
+            return None;
+        }
+
+        Some(Self(
+            groups.iter().fold(0u128, |acc, g| acc << 16 | *g as u128),
+        ))
+    }
+
+    /// `:` 区切りのグループを解釈する．
+    /// `allow_ipv4` が真であれば，最後のグループをIPv4アドレス表記として受け付ける
+    fn parse_groups(s: &str, allow_ipv4: bool) -> Option<Vec<u16>> {
+        if s.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let parts: Vec<&str> = s.split(':').collect();
+        let mut groups = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            if part.contains('.') {
+                if !allow_ipv4 || i != parts.len() - 1 {
+                    return None;
+                }
+                let octets: Vec<u8> = part
+                    .split('.')
+                    .map(|o| o.parse::<u8>().ok())
+                    .collect::<Option<_>>()?;
+                if octets.len() != 4 {
+                    return None;
+                }
+                groups.push(u16::from_be_bytes([octets[0], octets[1]]));
+                groups.push(u16::from_be_bytes([octets[2], octets[3]]));
+                continue;
+            }
+            if part.is_empty() || part.len() > 4 {
+                return None;
+            }
+            groups.push(u16::from_str_radix(part, 16).ok()?);
+        }
+        Some(groups)
+    }
+
+    /// MACアドレスから修正EUI-64形式のインタフェースIDを作り，
+    /// リンクローカルアドレス(fe80::/64)を生成する
+    /// See also [RFC4291](https://tools.ietf.org/html/rfc4291#appendix-A)
+    pub fn link_local_from_mac(mac_addr: MacAddress) -> Self {
+        Self(Self::LINK_LOCAL_PREFIX.0 | Self::interface_identifier(mac_addr) as u128)
+    }
+
+    /// 修正EUI-64形式のインタフェースID
+    pub fn interface_identifier(mac_addr: MacAddress) -> u64 {
+        let m = mac_addr.0;
+        u64::from_be_bytes([m[0] ^ 0x02, m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]])
+    }
+
+    /// 要請ノードマルチキャストアドレス(ff02::1:ffXX:XXXX)
+    /// See also [RFC4291](https://tools.ietf.org/html/rfc4291#section-2.7.1)
+    pub fn solicited_node(&self) -> Self {
+        Self(0xff02_0000_0000_0000_0000_0001_ff00_0000 | (self.0 & 0xff_ffff))
+    }
+
+    /// マルチキャストアドレス(ff00::/8)かどうか
+    pub fn is_multicast(&self) -> bool {
+        self.0 >> 120 == 0xff
+    }
+
+    /// リンクローカルユニキャストアドレス(fe80::/10)かどうか
+    pub fn is_link_local(&self) -> bool {
+        self.0 >> 118 == 0xfe80 >> 6
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        *self == Self::LOOPBACK
+    }
+
+    /// IPv4射影アドレス(::ffff:0:0/96)であれば，そのIPv4アドレスを返す
+    pub fn to_ipv4_mapped(&self) -> Option<crate::internet::ip::IPv4Addr> {
+        if self.0 >> 32 != 0xffff {
+            return None;
+        }
+        Some(crate::internet::ip::IPv4Addr(self.0 as u32))
+    }
+}
+
+impl std::fmt::Display for IPv6Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "version: {}", self.version())?;
+        writeln!(f, "traffic_class: {}", self.traffic_class())?;
+        writeln!(f, "flow_label: {}", self.flow_label())?;
+        writeln!(f, "payload_length (bytes): {}", self.payload_length)?;
+        writeln!(f, "next_header: {}", self.next_header)?;
+        writeln!(f, "hop_limit: {}", self.hop_limit)?;
+        writeln!(f, "src_addr: {}", self.src_addr)?;
+        writeln!(f, "dst_addr: {}", self.dst_addr)?;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for NextHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let type_str = match self {
+            NextHeader::HopByHopOptions => "Hop-by-Hop Options",
+            NextHeader::TCP => "TCP",
+            NextHeader::UDP => "UDP",
+            NextHeader::Routing => "Routing",
+            NextHeader::Fragment => "Fragment",
+            NextHeader::EncapsulatingSecurityPayload => "Encapsulating Security Payload",
+            NextHeader::Authentication => "Authentication",
+            NextHeader::ICMPv6 => "ICMPv6",
+            NextHeader::NoNextHeader => "No Next Header",
+            NextHeader::DestinationOptions => "Destination Options",
+            NextHeader::Unknown(v) => return write!(f, "Unknown({})", v),
+        };
+        write!(f, "{}", type_str)
+    }
+}
+
+/// RFC5952の推奨表記で出力する．
+/// 最も長い(同じ長さであれば最初の)2つ以上連続した0のグループを `::` で省略する
+/// See also [RFC5952](https://tools.ietf.org/html/rfc5952#section-4)
+impl std::fmt::Display for IPv6Addr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(v4) = self.to_ipv4_mapped() {
+            return write!(f, "::ffff:{}", v4);
+        }
+
+        let segments = self.segments();
+        let mut longest: Option<(usize, usize)> = None;
+        let mut i = 0;
+        while i < segments.len() {
+            let len = segments[i..].iter().take_while(|s| **s == 0).count();
+            if len >= 2 && longest.is_none_or(|(_, l)| len > l) {
+                longest = Some((i, len));
+            }
+            i += len.max(1);
+        }
+
+        let join = |segments: &[u16]| {
+            segments
+                .iter()
+                .map(|s| format!("{:x}", s))
+                .collect::<Vec<String>>()
+                .join(":")
+        };
+        match longest {
+            Some((start, len)) => write!(
+                f,
+                "{}::{}",
+                join(&segments[..start]),
+                join(&segments[start + len..])
+            ),
+            None => write!(f, "{}", join(&segments)),
+        }
+    }
+}
+
+impl Default for IPv6Addr {
+    fn default() -> Self {
+        Self::UNSPECIFIED
+    }
+}
+
+impl From<&str> for IPv6Addr {
+    fn from(s: &str) -> Self {
+        Self::parse(s).unwrap_or_else(|| panic!("invalid ipv6 address => '{}'", s))
+    }
+}
+
+impl From<[u8; 16]> for IPv6Addr {
+    fn from(v: [u8; 16]) -> Self {
+        Self(u128::from_be_bytes(v))
+    }
+}
+
+impl From<u8> for NextHeader {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => NextHeader::HopByHopOptions,
+            6 => NextHeader::TCP,
+            17 => NextHeader::UDP,
+            43 => NextHeader::Routing,
+            44 => NextHeader::Fragment,
+            50 => NextHeader::EncapsulatingSecurityPayload,
+            51 => NextHeader::Authentication,
+            58 => NextHeader::ICMPv6,
+            59 => NextHeader::NoNextHeader,
+            60 => NextHeader::DestinationOptions,
+            _ => NextHeader::Unknown(v),
+        }
+    }
+}
+
+impl From<NextHeader> for u8 {
+    fn from(val: NextHeader) -> Self {
+        match val {
+            NextHeader::HopByHopOptions => 0,
+            NextHeader::TCP => 6,
+            NextHeader::UDP => 17,
+            NextHeader::Routing => 43,
+            NextHeader::Fragment => 44,
+            NextHeader::EncapsulatingSecurityPayload => 50,
+            NextHeader::Authentication => 51,
+            NextHeader::ICMPv6 => 58,
+            NextHeader::NoNextHeader => 59,
+            NextHeader::DestinationOptions => 60,
+            NextHeader::Unknown(v) => v,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internet::InternetProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn parse_address_test() {
+        assert_eq!(
+            Some(IPv6Addr(0x2001_0db8_0000_0000_0000_0000_0000_0001)),
+            IPv6Addr::parse("2001:db8::1")
+        );
+        assert_eq!(Some(IPv6Addr::UNSPECIFIED), IPv6Addr::parse("::"));
+        assert_eq!(Some(IPv6Addr::LOOPBACK), IPv6Addr::parse("::1"));
+        assert_eq!(Some(IPv6Addr(0xfe80 << 112)), IPv6Addr::parse("fe80::"));
+        assert_eq!(
+            Some(IPv6Addr(0xffff_c0a8_0b1e)),
+            IPv6Addr::parse("::ffff:192.168.11.30")
+        );
+        assert_eq!(
+            IPv6Addr::parse("2001:db8::1"),
+            IPv6Addr::parse("2001:0db8:0:0:0:0:0:1")
+        );
+
+        for invalid in [
+            "",
+            "1:2:3:4:5:6:7",
+            "1:2:3:4:5:6:7:8:9",
+            "1::2::3",
+            "1:2:3:4:5:6:7::8",
+            "12345::",
+            "g::",
+            "::1.2.3",
+            "1.2.3.4::",
+        ] {
+            assert_eq!(None, IPv6Addr::parse(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn display_address_test() {
+        for s in [
+            "2001:db8::1",
+            "::",
+            "::1",
+            "fe80::",
+            "2001:db8:0:1:1:1:1:1",
+            "2001:db8::1:0:0:1",
+            "ff02::1:ff00:1",
+            "::ffff:192.168.11.30",
+        ] {
+            assert_eq!(s, IPv6Addr::from(s).to_string());
+        }
+        // 0を省略し，小文字で表記する
+        assert_eq!(
+            "2001:db8::1",
+            IPv6Addr::from("2001:0DB8:0000:0000:0000:0000:0000:0001").to_string()
+        );
+    }
+
+    #[test]
+    fn link_local_address_test() {
+        let mac_addr = MacAddress([0x08, 0x00, 0x27, 0x3c, 0xa9, 0x81]);
+        let addr = IPv6Addr::link_local_from_mac(mac_addr);
+        assert_eq!("fe80::a00:27ff:fe3c:a981", addr.to_string());
+        assert!(addr.is_link_local());
+        assert_eq!("ff02::1:ff3c:a981", addr.solicited_node().to_string());
+        assert!(addr.solicited_node().is_multicast());
+    }
+
+    #[test]
+    fn parse_ipv6_packet_test() {
+        let mut raw_packet = vec![0x60, 0x00, 0x00, 0x00, 0x00, 0x08, 0x3a, 0x40];
+        raw_packet.extend_from_slice(&IPv6Addr::from("fe80::1").octets());
+        raw_packet.extend_from_slice(&IPv6Addr::ALL_NODES.octets());
+
+        let hdr =
+            IPv6Header::new_from_bytes(&raw_packet, InternetProtocolError::CannotParsePacketHeader)
+                .unwrap();
+        assert_eq!(6, hdr.version());
+        assert_eq!(8, hdr.payload_length);
+        assert_eq!(NextHeader::ICMPv6, hdr.next_header);
+        assert_eq!(64, hdr.hop_limit);
+        assert_eq!(IPv6Addr::ALL_NODES, hdr.dst_addr);
+        assert_eq!(
+            raw_packet,
+            hdr.to_bytes(InternetProtocolError::CannotConstructPacket)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn walk_extension_headers_test() {
+        let err = InternetProtocolError::InvalidExtensionHeader;
+        // Hop-by-Hop(8) -> Destination Options(16) -> Fragment(8)
+        let mut payload = vec![60, 0, 1, 4, 0, 0, 0, 0];
+        payload.extend_from_slice(&[44, 1, 1, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        payload.extend_from_slice(&[17, 0, 0, 0, 0, 0, 0, 1]);
+        payload.extend_from_slice(&[0; 8]);
+
+        let chain = ExtensionHeaderChain::walk(NextHeader::HopByHopOptions, &payload, err).unwrap();
+        assert_eq!(
+            vec![
+                NextHeader::HopByHopOptions,
+                NextHeader::DestinationOptions,
+                NextHeader::Fragment
+            ],
+            chain.headers.iter().map(|h| h.ty).collect::<Vec<_>>()
+        );
+        // フラグメントヘッダの後ろは辿らない
+        assert_eq!(NextHeader::Fragment, chain.upper_layer);
+        assert_eq!(32, chain.payload_offset);
+        assert_eq!(64, chain.next_header_pointer());
+        // Destination Optionsヘッダの次ヘッダ領域
+        assert_eq!(48, chain.fragment_header_pointer());
+
+        // 拡張ヘッダがなければそのまま上位層となる
+        let chain = ExtensionHeaderChain::walk(NextHeader::TCP, &payload, err).unwrap();
+        assert!(chain.headers.is_empty());
+        assert_eq!(0, chain.payload_offset);
+        assert_eq!(6, chain.next_header_pointer());
+
+        // 長さが足りない場合
+        assert!(
+            ExtensionHeaderChain::walk(NextHeader::HopByHopOptions, &payload[..20], err).is_err()
+        );
+        // Hop-by-Hopオプションヘッダが先頭以外にある場合
+        let payload = [0, 0, 0, 0, 0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0];
+        assert!(ExtensionHeaderChain::walk(NextHeader::DestinationOptions, &payload, err).is_err());
+    }
+}
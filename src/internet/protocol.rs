@@ -1,17 +1,26 @@
-use internet::ip::IPv4Addr;
+use internet::{ip::IPv4Addr, ipv6::IPv6Addr};
 
-use crate::{internet, link::LinkProtocolError, network_device, Items, RxResult};
+use crate::{
+    internet, link::LinkProtocolError, network_device, transport::TransportProtocol, Items,
+    RxResult,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum InternetProtocol {
     IP,
     ARP,
     IPv6,
+    /// 対応していないEtherType．IEEE 802.3形式のフレームでは長さを保持する
+    Unknown(u16),
 }
 #[derive(thiserror::Error, Debug, Clone, Copy)]
 pub enum InternetProtocolError {
     #[error("frame type is ipv4 but version isn't 4 in vhl")]
     NotIPv4Packet,
+    #[error("frame type is ipv6 but version isn't 6")]
+    NotIPv6Packet,
+    #[error("invalid extension header found")]
+    InvalidExtensionHeader,
     #[error("invalid packet length was found")]
     InvalidPacketLength,
     #[error("invalid checksum found")]
@@ -30,8 +39,13 @@ pub enum InternetProtocolError {
     UnsupportedHeaderOption,
     #[error("cannot resolve MAC address from {unknown_ip:?}")]
     CannotResolveMACAddressFrom { unknown_ip: IPv4Addr },
+    #[error("cannot resolve MAC address from {unknown_ip:}")]
+    CannotResolveMACAddressFromIPv6 { unknown_ip: IPv6Addr },
+    #[error("no route to {dst:}")]
+    NoRouteToHost { dst: IPv6Addr },
 }
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn rx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     rx_result: RxResult,
@@ -44,7 +58,28 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
     match rx_result.ip_type {
         InternetProtocol::IP => internet::ip::rx(table, rx_result, buf).await,
         InternetProtocol::ARP => internet::arp::rx(table, rx_result, buf).await,
-        _ => unimplemented!(),
+        InternetProtocol::IPv6 => internet::ipv6::rx(table, rx_result, buf).await,
+        // リンク層で処理されるので，ここには到達しない
+        InternetProtocol::Unknown(_) => Err(InternetProtocolError::Ignore),
+    }
+}
+
+/// `rx_result.src_ip_addr` のアドレスファミリに応じてIPv4/IPv6で送信する．
+/// `ttl` はIPv6ではホップリミットとして使う
+pub async fn tx_with_ttl<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    tp: TransportProtocol,
+    rx_result: RxResult,
+    tp_payload: Vec<u8>,
+    ttl: u8,
+) -> Result<(), InternetProtocolError> {
+    match rx_result.src_ip_addr {
+        internet::IpAddr::V4(_) => {
+            internet::ip::tx_with_ttl(table, tp, rx_result, tp_payload, ttl).await
+        }
+        internet::IpAddr::V6(_) => {
+            internet::ipv6::tx_with_hop_limit(table, tp, rx_result, tp_payload, ttl).await
+        }
     }
 }
 
@@ -54,6 +89,7 @@ impl std::fmt::Display for InternetProtocol {
             InternetProtocol::IP => "IP",
             InternetProtocol::ARP => "ARP",
             InternetProtocol::IPv6 => "IPv6",
+            InternetProtocol::Unknown(v) => return write!(f, "Unknown(0x{:04x})", v),
         };
         write!(f, "{}", type_str)
     }
@@ -75,20 +111,23 @@ impl From<u16> for InternetProtocol {
             0x0800 => InternetProtocol::IP,
             0x0806 => InternetProtocol::ARP,
             0x86dd => InternetProtocol::IPv6,
-            _ => unimplemented!(),
+            _ => InternetProtocol::Unknown(v),
         }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<u16> for InternetProtocol {
     fn into(self) -> u16 {
         match self {
             InternetProtocol::IP => 0x0800,
             InternetProtocol::ARP => 0x0806,
             InternetProtocol::IPv6 => 0x86dd,
+            InternetProtocol::Unknown(v) => v,
         }
     }
 }
+#[allow(clippy::derivable_impls)]
 impl Default for InternetProtocol {
     fn default() -> Self {
         Self::IP
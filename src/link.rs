@@ -3,5 +3,8 @@ pub use mac_address::*;
 
 pub mod ethernet;
 
+mod types;
+pub use types::*;
+
 mod protocol;
 pub use protocol::*;
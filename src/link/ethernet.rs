@@ -3,3 +3,9 @@ pub use types::*;
 
 mod protocol;
 pub use protocol::*;
+
+mod vlan;
+pub use vlan::*;
+
+mod llc;
+pub use llc::*;
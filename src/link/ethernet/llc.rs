@@ -0,0 +1,151 @@
+use std::io::Cursor;
+
+use crate::byteorder_wrapper;
+
+/// タイプフィールドをIEEE 802.3の長さとして扱う上限
+pub const MAX_802_3_LENGTH: u16 = 1500;
+/// SNAPを表すSAP
+const SNAP_SAP: u8 = 0xaa;
+/// 非番号制(U形式)のUIフレームの制御フィールド
+const UI_CONTROL: u8 = 0x03;
+/// SNAPのPIDをEtherTypeとして扱うOUI(RFC1042カプセル化)
+/// See also [RFC1042](https://tools.ietf.org/html/rfc1042)
+const OUI_RFC1042: u32 = 0x000000;
+/// SNAPのPIDをEtherTypeとして扱うOUI(IEEE 802.1Hのブリッジトンネル)
+const OUI_BRIDGE_TUNNEL: u32 = 0x0000f8;
+
+/// IEEE 802.2 LLCヘッダ．802.3の長さフィールドを持つフレームで，長さの直後に続く
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LlcHeader {
+    /// 宛先サービスアクセスポイント
+    pub dsap: u8,
+    /// 送信元サービスアクセスポイント．最下位ビットはコマンド/レスポンスを表す
+    pub ssap: u8,
+    /// 制御フィールド．U形式は1オクテット，I/S形式は2オクテット
+    pub control: u16,
+    pub snap: Option<SnapHeader>,
+    /// 制御フィールドの長さ
+    control_length: usize,
+}
+
+/// SNAPヘッダ．DSAP/SSAPが0xaaのUIフレームで，LLCヘッダの直後に続く
+/// See also [RFC1042](https://tools.ietf.org/html/rfc1042)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapHeader {
+    /// 組織を表すOUI(24ビット)
+    pub oui: u32,
+    /// OUIの中で定義されたプロトコルID
+    pub pid: u16,
+}
+
+impl LlcHeader {
+    pub fn from_cursor<E>(reader: &mut Cursor<&[u8]>, err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let dsap = byteorder_wrapper::read_u8(reader, err)?;
+        let ssap = byteorder_wrapper::read_u8(reader, err)?;
+        let first_control = byteorder_wrapper::read_u8(reader, err)?;
+        // 下位2ビットがともに1であればU形式
+        let (control, control_length) = if first_control & 0x03 == 0x03 {
+            (first_control as u16, 1)
+        } else {
+            let second_control = byteorder_wrapper::read_u8(reader, err)?;
+            ((first_control as u16) << 8 | second_control as u16, 2)
+        };
+
+        let snap = if dsap == SNAP_SAP && ssap & 0xfe == SNAP_SAP && control == UI_CONTROL as u16 {
+            let oui_high = byteorder_wrapper::read_u8(reader, err)? as u32;
+            let oui_low = byteorder_wrapper::read_u16_as_be(reader, err)? as u32;
+            Some(SnapHeader {
+                oui: oui_high << 16 | oui_low,
+                pid: byteorder_wrapper::read_u16_as_be(reader, err)?,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            dsap,
+            ssap,
+            control,
+            snap,
+            control_length,
+        })
+    }
+
+    /// SNAPヘッダを含めたヘッダの長さ
+    pub fn length(&self) -> usize {
+        let snap_length = if self.snap.is_some() { 5 } else { 0 };
+        2 + self.control_length + snap_length
+    }
+
+    /// SNAPで運ばれるEtherType．SNAPでないか，PIDがEtherTypeでなければ `None` を返す
+    pub fn ether_type(&self) -> Option<u16> {
+        self.snap
+            .filter(|snap| matches!(snap.oui, OUI_RFC1042 | OUI_BRIDGE_TUNNEL))
+            .map(|snap| snap.pid)
+    }
+}
+
+impl std::fmt::Display for LlcHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "dsap=0x{:02x} ssap=0x{:02x} control=0x{:x}",
+            self.dsap, self.ssap, self.control
+        )?;
+        if let Some(snap) = self.snap {
+            write!(f, " oui=0x{:06x} pid=0x{:04x}", snap.oui, snap.pid)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::LinkProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn parse_llc_header_test() {
+        // STPのBPDU
+        let raw = [0x42, 0x42, 0x03, 0x00, 0x00];
+        let mut reader = Cursor::new(&raw[..]);
+        let llc =
+            LlcHeader::from_cursor(&mut reader, LinkProtocolError::CannotParseFrameHeader).unwrap();
+        assert_eq!(0x42, llc.dsap);
+        assert_eq!(0x03, llc.control);
+        assert_eq!(None, llc.snap);
+        assert_eq!(3, llc.length());
+        assert_eq!(None, llc.ether_type());
+
+        // I形式の制御フィールドは2オクテット
+        let raw = [0xf0, 0xf0, 0x00, 0x01];
+        let mut reader = Cursor::new(&raw[..]);
+        let llc =
+            LlcHeader::from_cursor(&mut reader, LinkProtocolError::CannotParseFrameHeader).unwrap();
+        assert_eq!(0x0001, llc.control);
+        assert_eq!(4, llc.length());
+    }
+
+    #[test]
+    fn parse_snap_header_test() {
+        // RFC1042でカプセル化されたIPv4
+        let raw = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00];
+        let mut reader = Cursor::new(&raw[..]);
+        let llc =
+            LlcHeader::from_cursor(&mut reader, LinkProtocolError::CannotParseFrameHeader).unwrap();
+        assert_eq!(8, llc.length());
+        assert_eq!(Some(0x0800), llc.ether_type());
+
+        // CiscoのOUIを持つCDPはEtherTypeとして扱わない
+        let raw = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x0c, 0x20, 0x00];
+        let mut reader = Cursor::new(&raw[..]);
+        let llc =
+            LlcHeader::from_cursor(&mut reader, LinkProtocolError::CannotParseFrameHeader).unwrap();
+        assert_eq!(Some(0x2000), llc.snap.map(|snap| snap.pid));
+        assert_eq!(None, llc.ether_type());
+    }
+}
@@ -1,6 +1,15 @@
-use super::FrameHeader;
-use crate::{internet::InternetProtocol, link::MacAddress, Items};
+use std::collections::HashMap;
+
+use super::{FrameHeader, VlanInterface, VlanTag};
+use crate::{
+    internet::{ip::IPv4Addr, ipv6, InternetProtocol},
+    link::MacAddress,
+    option::{PeachPSOption, VlanOption},
+    transport::igmp,
+    Items,
+};
 use crate::{link::LinkProtocolError, network_device};
+#[allow(clippy::needless_lifetimes)]
 pub async fn rx<'a, ND: network_device::NetworkDevice>(
     items: &'a Items<ND>,
     buf: &[u8],
@@ -8,37 +17,328 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
     let (frame_hdr, rest) =
         FrameHeader::new_from_bytes(buf, LinkProtocolError::CannotParseFrameHeader)?;
 
-    if !ethernet_frame_for_me(items.opt.dev_addr, frame_hdr.dst_addr) {
+    let for_me = ethernet_frame_for_me(
+        &items.opt,
+        &items.ipv6_addrs.lock().unwrap(),
+        &items.igmp_groups.lock().unwrap(),
+        frame_hdr.dst_addr,
+    );
+    if !for_me {
         return Err(LinkProtocolError::Ignore);
     }
 
+    let interface = receiving_interface(&items.opt.vlan, &frame_hdr.tags)?;
+    if !items.opt.vlan.interfaces.is_empty() {
+        items
+            .vlan_neighbors
+            .lock()
+            .unwrap()
+            .insert(frame_hdr.src_addr, interface);
+    }
+
     Ok((frame_hdr, rest))
 }
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn tx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     ip_type: InternetProtocol,
     dst_addr: MacAddress,
-    mut payload: Vec<u8>,
+    payload: Vec<u8>,
 ) -> Result<(), LinkProtocolError> {
-    let mut ethernet_frame = Vec::<u8>::new();
-    let frame_hdr = FrameHeader {
+    let outgoing = outgoing_tags(
+        &table.opt.vlan,
+        &table.vlan_neighbors.lock().unwrap(),
         dst_addr,
-        src_addr: table.opt.dev_addr,
-        ty: ip_type,
-    };
+    );
 
-    ethernet_frame.append(&mut frame_hdr.to_bytes(LinkProtocolError::CannotConstructFrame)?);
-    ethernet_frame.append(&mut payload);
+    for tags in outgoing {
+        let mut ethernet_frame = Vec::<u8>::new();
+        let frame_hdr = FrameHeader {
+            dst_addr,
+            src_addr: table.opt.dev_addr,
+            tags,
+            ty: ip_type,
+            llc: None,
+        };
 
-    if let Ok(ref mut dev) = table.dev.lock() {
+        ethernet_frame.append(&mut frame_hdr.to_bytes(LinkProtocolError::CannotConstructFrame)?);
+        ethernet_frame.extend_from_slice(&payload);
+
+        let dev = *table.dev.lock().unwrap();
         dev.write(&ethernet_frame).await?;
     }
 
     Ok(())
 }
 
-/// プロトコルスタックが処理すべきデータかどうか検査
-fn ethernet_frame_for_me(device_addr: MacAddress, frame_dst_addr: MacAddress) -> bool {
-    device_addr == frame_dst_addr || frame_dst_addr == MacAddress::BLOADCAST
+/// 受信したフレームのタグから，どのサブインタフェースで受信したかを決める．
+/// `None` はタグなしで受信したことを表し，VLAN IDが0のタグ(priority tag)もこれに含める
+fn receiving_interface(
+    opt: &VlanOption,
+    tags: &[VlanTag],
+) -> Result<Option<VlanInterface>, LinkProtocolError> {
+    if tags.is_empty() || matches!(tags, [tag] if tag.vid == 0) {
+        return if opt.untagged {
+            Ok(None)
+        } else {
+            Err(LinkProtocolError::Ignore)
+        };
+    }
+
+    opt.interfaces
+        .iter()
+        .find(|interface| interface.matches(tags))
+        .map(|interface| Some(*interface))
+        .ok_or(LinkProtocolError::Ignore)
+}
+
+/// 送信するフレームに付与するタグの組．組の数だけフレームを送信する．
+/// 受信で学習した宛先はそのサブインタフェースへ，
+/// ブロードキャスト・マルチキャスト及び未学習の宛先はすべてのサブインタフェースへ送信する
+fn outgoing_tags(
+    opt: &VlanOption,
+    neighbors: &HashMap<MacAddress, Option<VlanInterface>>,
+    dst_addr: MacAddress,
+) -> Vec<Vec<VlanTag>> {
+    if opt.interfaces.is_empty() {
+        return vec![Vec::new()];
+    }
+
+    if !dst_addr.is_multicast() {
+        if let Some(interface) = neighbors.get(&dst_addr) {
+            return vec![interface.map(|i| i.tags()).unwrap_or_default()];
+        }
+    }
+
+    let mut outgoing = Vec::new();
+    if opt.untagged {
+        outgoing.push(Vec::new());
+    }
+    outgoing.extend(opt.interfaces.iter().map(|i| i.tags()));
+    outgoing
+}
+
+/// IPv6マルチキャストアドレスに対応するMACアドレス．
+/// 33:33に続けてアドレスの下位32ビットを並べる
+/// See also [RFC2464](https://tools.ietf.org/html/rfc2464#section-7)
+pub fn ipv6_multicast_mac_address(addr: ipv6::IPv6Addr) -> MacAddress {
+    let o = addr.octets();
+    MacAddress([0x33, 0x33, o[12], o[13], o[14], o[15]])
+}
+
+/// IPv4マルチキャストアドレスに対応するMACアドレス．
+/// 01:00:5eに続けてアドレスの下位23ビットを並べる
+/// See also [RFC1112](https://tools.ietf.org/html/rfc1112#section-6.4)
+pub fn ipv4_multicast_mac_address(addr: IPv4Addr) -> MacAddress {
+    let o = addr.0.to_be_bytes();
+    MacAddress([0x01, 0x00, 0x5e, o[1] & 0x7f, o[2], o[3]])
+}
+
+/// ブリッジが転送しない，IEEE 802.1で予約されたグループアドレス(01:80:c2:00:00:00-0f)か．
+/// STPやLLDPのフレームが使う
+fn is_link_local_group(addr: MacAddress) -> bool {
+    addr.0[..5] == [0x01, 0x80, 0xc2, 0x00, 0x00] && addr.0[5] <= 0x0f
+}
+
+/// プロトコルスタックが処理すべきデータかどうか検査．
+/// IPv4/IPv6が有効であれば，参加しているマルチキャストグループ宛てのフレームも受け付ける．
+/// STPやLLDPは対応していないタイプとして数えるので，リンクローカルなグループアドレス宛ても受け付ける
+fn ethernet_frame_for_me(
+    opt: &PeachPSOption,
+    ipv6_addrs: &ipv6::AddressTable,
+    igmp_groups: &igmp::GroupTable,
+    frame_dst_addr: MacAddress,
+) -> bool {
+    if opt.dev_addr == frame_dst_addr
+        || frame_dst_addr == MacAddress::BLOADCAST
+        || is_link_local_group(frame_dst_addr)
+    {
+        return true;
+    }
+
+    let ipv4_group = opt.internet_filter.contains(&InternetProtocol::IP)
+        && igmp_groups
+            .groups()
+            .into_iter()
+            .any(|group| ipv4_multicast_mac_address(group) == frame_dst_addr);
+    let ipv6_group = opt.internet_filter.contains(&InternetProtocol::IPv6)
+        && ipv6_addrs
+            .multicast_groups()
+            .into_iter()
+            .any(|group| ipv6_multicast_mac_address(group) == frame_dst_addr);
+    ipv4_group || ipv6_group
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv6_multicast_mac_address_test() {
+        let addr = ipv6::IPv6Addr::from("fe80::a00:27ff:fe3c:a981");
+        assert_eq!(
+            MacAddress::from("33:33:ff:3c:a9:81"),
+            ipv6_multicast_mac_address(addr.solicited_node())
+        );
+        assert_eq!(
+            MacAddress::from("33:33:00:00:00:01"),
+            ipv6_multicast_mac_address(ipv6::IPv6Addr::ALL_NODES)
+        );
+    }
+
+    #[test]
+    fn ipv4_multicast_mac_address_test() {
+        assert_eq!(
+            MacAddress::from("01:00:5e:00:00:01"),
+            ipv4_multicast_mac_address(IPv4Addr::ALL_SYSTEMS)
+        );
+        // 上位9ビットは対応付けられない
+        assert_eq!(
+            MacAddress::from("01:00:5e:01:02:03"),
+            ipv4_multicast_mac_address(IPv4Addr::from("239.129.2.3"))
+        );
+    }
+
+    #[test]
+    fn ethernet_frame_for_me_test() {
+        let mut opt = PeachPSOption {
+            dev_addr: MacAddress::from("08:00:27:3c:a9:81"),
+            ..Default::default()
+        };
+        let addrs = ipv6::AddressTable::new(&opt, std::time::Instant::now());
+        let groups = igmp::GroupTable::new(igmp::IgmpVersion::V3);
+        assert!(ethernet_frame_for_me(&opt, &addrs, &groups, opt.dev_addr));
+        assert!(ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::BLOADCAST
+        ));
+        assert!(!ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("33:33:ff:3c:a9:81")
+        ));
+        // STP及びLLDP
+        assert!(ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("01:80:c2:00:00:00")
+        ));
+        assert!(ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("01:80:c2:00:00:0e")
+        ));
+        assert!(!ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("01:80:c2:00:00:10")
+        ));
+
+        // IPv6が有効であれば要請ノードマルチキャスト宛てを受け付ける
+        opt.internet_filter.insert(InternetProtocol::IPv6);
+        assert!(ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("33:33:ff:3c:a9:81")
+        ));
+        assert!(!ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("33:33:ff:3c:a9:82")
+        ));
+    }
+
+    #[test]
+    fn ethernet_frame_for_me_ipv4_multicast_test() {
+        let mut opt = PeachPSOption {
+            dev_addr: MacAddress::from("08:00:27:3c:a9:81"),
+            ..Default::default()
+        };
+        opt.internet_filter.insert(InternetProtocol::IP);
+        let addrs = ipv6::AddressTable::new(&opt, std::time::Instant::now());
+        let mut groups = igmp::GroupTable::new(igmp::IgmpVersion::V3);
+        let group_mac = MacAddress::from("01:00:5e:01:02:03");
+
+        // 全ホストグループには常に参加している
+        assert!(ethernet_frame_for_me(
+            &opt,
+            &addrs,
+            &groups,
+            MacAddress::from("01:00:5e:00:00:01")
+        ));
+        assert!(!ethernet_frame_for_me(&opt, &addrs, &groups, group_mac));
+
+        groups.join(IPv4Addr::from("239.1.2.3"), std::time::Instant::now());
+        assert!(ethernet_frame_for_me(&opt, &addrs, &groups, group_mac));
+    }
+
+    #[test]
+    fn receiving_interface_test() {
+        let interface = VlanInterface {
+            outer_vid: None,
+            vid: 10,
+            pcp: 0,
+        };
+        let mut opt: VlanOption = Default::default();
+        opt.interfaces.push(interface);
+
+        assert_eq!(None, receiving_interface(&opt, &[]).unwrap());
+        assert_eq!(
+            Some(interface),
+            receiving_interface(&opt, &interface.tags()).unwrap()
+        );
+        // 設定していないVLANのフレームは破棄する
+        let mut other = interface.tags();
+        other[0].vid = 20;
+        assert!(receiving_interface(&opt, &other).is_err());
+
+        // priority tagはタグなしとして扱う
+        other[0].vid = 0;
+        opt.untagged = false;
+        assert!(receiving_interface(&opt, &other).is_err());
+        assert!(receiving_interface(&opt, &[]).is_err());
+    }
+
+    #[test]
+    fn outgoing_tags_test() {
+        let interface = VlanInterface {
+            outer_vid: Some(200),
+            vid: 10,
+            pcp: 3,
+        };
+        let mut opt: VlanOption = Default::default();
+        let mut neighbors = HashMap::new();
+        let known = MacAddress::from("08:00:27:3c:a9:82");
+        let unknown = MacAddress::from("08:00:27:3c:a9:83");
+
+        // サブインタフェースがなければタグを付けない
+        assert_eq!(
+            vec![Vec::<VlanTag>::new()],
+            outgoing_tags(&opt, &neighbors, known)
+        );
+
+        opt.interfaces.push(interface);
+        neighbors.insert(known, Some(interface));
+        assert_eq!(
+            vec![interface.tags()],
+            outgoing_tags(&opt, &neighbors, known)
+        );
+        assert_eq!(
+            vec![Vec::new(), interface.tags()],
+            outgoing_tags(&opt, &neighbors, unknown)
+        );
+        assert_eq!(
+            vec![Vec::new(), interface.tags()],
+            outgoing_tags(&opt, &neighbors, MacAddress::BLOADCAST)
+        );
+    }
 }
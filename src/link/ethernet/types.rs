@@ -2,6 +2,7 @@ use std::io::Cursor;
 
 use link::MacAddress;
 
+use super::{LlcHeader, VlanTag, MAX_802_3_LENGTH, MAX_VLAN_TAGS};
 use crate::{byteorder_wrapper, internet, link};
 
 #[allow(dead_code)]
@@ -9,14 +10,27 @@ use crate::{byteorder_wrapper, internet, link};
 pub struct FrameHeader {
     pub dst_addr: link::MacAddress,
     pub src_addr: link::MacAddress,
+    /// VLANタグ．QinQの場合は外側のタグから順に並ぶ
+    pub tags: Vec<VlanTag>,
+    /// 対応していないタイプは `Unknown` として保持する．
+    /// IEEE 802.3形式のフレームでは，SNAPで運ばれるEtherTypeか，なければ長さを持つ
     pub ty: internet::InternetProtocol,
+    /// IEEE 802.3形式のフレームのLLCヘッダ．
+    /// 送信は常にEthernet II形式で行うので，`to_bytes` では出力しない
+    pub llc: Option<LlcHeader>,
 }
 
 impl std::fmt::Display for FrameHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "dst_addr: {}", self.dst_addr)?;
         writeln!(f, "src_addr: {}", self.src_addr)?;
+        for tag in self.tags.iter() {
+            writeln!(f, "vlan_tag: {}", tag)?;
+        }
         writeln!(f, "frame_type: {}", self.ty)?;
+        if let Some(llc) = self.llc {
+            writeln!(f, "llc: {}", llc)?;
+        }
 
         Ok(())
     }
@@ -27,14 +41,22 @@ impl Default for FrameHeader {
         Self {
             dst_addr: link::MacAddress([0; 6]),
             src_addr: link::MacAddress([0; 6]),
+            tags: Vec::new(),
             ty: internet::InternetProtocol::IP,
+            llc: None,
         }
     }
 }
 
 impl FrameHeader {
+    /// タグを含まないヘッダの長さ
     pub const LENGTH: usize = 0xe;
 
+    /// タグ及びLLCヘッダを含めたヘッダの長さ
+    pub fn length(&self) -> usize {
+        Self::LENGTH + self.tags.len() * VlanTag::LENGTH + self.llc.map_or(0, |llc| llc.length())
+    }
+
     pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
     where
         E: std::error::Error + Copy,
@@ -42,26 +64,58 @@ impl FrameHeader {
         let mut buf = Vec::new();
         buf.append(&mut self.dst_addr.to_bytes(err)?);
         buf.append(&mut self.src_addr.to_bytes(err)?);
+        for tag in self.tags.iter() {
+            buf.append(&mut tag.to_bytes(err)?);
+        }
         byteorder_wrapper::write_u16_as_be(&mut buf, self.ty.into(), err)?;
 
         Ok(buf)
     }
 
+    #[allow(clippy::field_reassign_with_default)]
     pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<(Self, Vec<u8>), E>
     where
         E: std::error::Error + Copy,
     {
-        let (raw_header, rest) = buf.split_at(FrameHeader::LENGTH);
-        let mut reader = Cursor::new(raw_header);
+        if buf.len() < FrameHeader::LENGTH {
+            return Err(err);
+        }
+
+        let mut reader = Cursor::new(buf);
         let mut frame_hdr: FrameHeader = Default::default();
 
         frame_hdr.dst_addr = MacAddress::from_cursor(&mut reader, err)?;
 
         frame_hdr.src_addr = MacAddress::from_cursor(&mut reader, err)?;
 
-        let frame_type = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+        // VLANタグが続く限り読み進め，その後のフィールドをタイプとして扱う
+        let mut frame_type = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+        while VlanTag::is_tpid(frame_type) {
+            if frame_hdr.tags.len() == MAX_VLAN_TAGS {
+                return Err(err);
+            }
+            frame_hdr
+                .tags
+                .push(VlanTag::from_cursor(frame_type, &mut reader, err)?);
+            frame_type = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+        }
+
+        // 1500以下の値はIEEE 802.3の長さで，LLCヘッダが続く．
+        // 長さを超える部分はパディングなので取り除く
+        if frame_type <= MAX_802_3_LENGTH {
+            let data_end = frame_hdr.length() + frame_type as usize;
+            let llc = LlcHeader::from_cursor(&mut reader, err)?;
+            if buf.len() < data_end || (frame_type as usize) < llc.length() {
+                return Err(err);
+            }
+            frame_hdr.ty = internet::InternetProtocol::from(llc.ether_type().unwrap_or(frame_type));
+            frame_hdr.llc = Some(llc);
+            let rest = &buf[frame_hdr.length()..data_end];
+            return Ok((frame_hdr, rest.to_vec()));
+        }
 
         frame_hdr.ty = internet::InternetProtocol::from(frame_type);
+        let rest = &buf[frame_hdr.length()..];
         Ok((frame_hdr, rest.to_vec()))
     }
 }
@@ -80,7 +134,7 @@ mod tests {
         let result =
             FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader);
         assert!(result.is_ok());
-        let frame_hdr = result.unwrap();
+        let (frame_hdr, _) = result.unwrap();
 
         assert_eq!([0x00, 0x15, 0x5d, 0x22, 0x1e, 0xff], frame_hdr.dst_addr.0);
         assert_eq!([0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66], frame_hdr.src_addr.0);
@@ -95,10 +149,86 @@ mod tests {
         let result =
             FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader);
         assert!(result.is_ok());
-        let frame_hdr = result.unwrap();
+        let (frame_hdr, _) = result.unwrap();
 
         assert_eq!([0xa8, 0x5e, 0x45, 0x2f, 0x94, 0x2e], frame_hdr.dst_addr.0);
         assert_eq!([0x18, 0xec, 0xe7, 0x56, 0x5e, 0x60], frame_hdr.src_addr.0);
         assert_eq!(internet::InternetProtocol::ARP, frame_hdr.ty);
     }
+
+    #[test]
+    fn parse_vlan_tagged_frame_test() {
+        // 802.1adのS-TAG(VID=200)と802.1QのC-TAG(PCP=3, VID=10)を持つIPv4フレーム
+        let raw_frame = [
+            0x00, 0x15, 0x5d, 0x22, 0x1e, 0xff, 0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66, 0x88, 0xa8,
+            0x00, 0xc8, 0x81, 0x00, 0x60, 0x0a, 0x08, 0x00, 0x45,
+        ];
+        let (frame_hdr, rest) =
+            FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader)
+                .unwrap();
+
+        assert_eq!(2, frame_hdr.tags.len());
+        assert_eq!(link::ethernet::TPID_8021AD, frame_hdr.tags[0].tpid);
+        assert_eq!(200, frame_hdr.tags[0].vid);
+        assert_eq!(3, frame_hdr.tags[1].pcp);
+        assert_eq!(10, frame_hdr.tags[1].vid);
+        assert_eq!(internet::InternetProtocol::IP, frame_hdr.ty);
+        assert_eq!(vec![0x45], rest);
+
+        let raw_header = frame_hdr
+            .to_bytes(LinkProtocolError::CannotConstructFrame)
+            .unwrap();
+        assert_eq!(&raw_frame[..raw_frame.len() - 1], raw_header.as_slice());
+    }
+
+    #[test]
+    fn parse_unknown_ether_type_test() {
+        // LLDP
+        let raw_frame = [
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e, 0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66, 0x88, 0xcc,
+            0x02, 0x07,
+        ];
+        let (frame_hdr, rest) =
+            FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader)
+                .unwrap();
+        assert_eq!(internet::InternetProtocol::Unknown(0x88cc), frame_hdr.ty);
+        assert_eq!(None, frame_hdr.llc);
+        assert_eq!(vec![0x02, 0x07], rest);
+    }
+
+    #[test]
+    fn parse_802_3_frame_test() {
+        // STPのBPDU．長さを超える部分はパディング
+        let raw_frame = [
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x00, 0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66, 0x00, 0x05,
+            0x42, 0x42, 0x03, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let (frame_hdr, rest) =
+            FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader)
+                .unwrap();
+        assert_eq!(internet::InternetProtocol::Unknown(5), frame_hdr.ty);
+        assert_eq!(Some(0x42), frame_hdr.llc.map(|llc| llc.dsap));
+        assert_eq!(vec![0x00, 0x00], rest);
+
+        // RFC1042でカプセル化されたIPv4はIPとして扱う
+        let raw_frame = [
+            0x00, 0x15, 0x5d, 0x22, 0x1e, 0xff, 0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66, 0x00, 0x09,
+            0xaa, 0xaa, 0x03, 0x00, 0x00, 0x00, 0x08, 0x00, 0x45,
+        ];
+        let (frame_hdr, rest) =
+            FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader)
+                .unwrap();
+        assert_eq!(internet::InternetProtocol::IP, frame_hdr.ty);
+        assert_eq!(vec![0x45], rest);
+
+        // 長さがフレームを超えていれば破棄する
+        let raw_frame = [
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x00, 0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66, 0x00, 0x40,
+            0x42, 0x42, 0x03,
+        ];
+        assert!(
+            FrameHeader::new_from_bytes(&raw_frame, LinkProtocolError::CannotParseFrameHeader)
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,177 @@
+use std::io::Cursor;
+
+use crate::byteorder_wrapper;
+
+/// IEEE 802.1QのタグのTPID(C-TAG)
+pub const TPID_8021Q: u16 = 0x8100;
+/// IEEE 802.1adのタグのTPID(S-TAG)
+pub const TPID_8021AD: u16 = 0x88a8;
+/// 802.1ad以前にQinQの外側のタグとして使われていたTPID
+const TPID_QINQ_LEGACY: u16 = 0x9100;
+/// 解釈するタグの数の上限．QinQの2段までとする
+pub const MAX_VLAN_TAGS: usize = 2;
+
+/// VLANタグ(IEEE 802.1Q 9.6節)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VlanTag {
+    /// タグの種類を表すTPID
+    pub tpid: u16,
+    /// Priority Code Point．フレームの優先度
+    pub pcp: u8,
+    /// Drop Eligible Indicator．輻輳時に優先して破棄してよいか
+    pub dei: bool,
+    /// VLAN ID．0は優先度のみを示すタグ(priority tag)を表す
+    pub vid: u16,
+}
+
+/// 1つの生ソケット上に設けるVLANサブインタフェース
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VlanInterface {
+    /// QinQで使う外側(S-VLAN)のVLAN ID
+    pub outer_vid: Option<u16>,
+    /// VLAN ID
+    pub vid: u16,
+    /// 送信するフレームに付与する優先度
+    pub pcp: u8,
+}
+
+impl VlanTag {
+    pub const LENGTH: usize = 4;
+    const PCP_SHIFT: u16 = 13;
+    const DEI_MASK: u16 = 0x1000;
+    const VID_MASK: u16 = 0x0fff;
+
+    /// TPIDがVLANタグを表すか
+    pub fn is_tpid(tpid: u16) -> bool {
+        matches!(tpid, TPID_8021Q | TPID_8021AD | TPID_QINQ_LEGACY)
+    }
+
+    /// TPIDを読んだ後のカーソルから，TCIを読み取る
+    pub fn from_cursor<E>(tpid: u16, reader: &mut Cursor<&[u8]>, err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let tci = byteorder_wrapper::read_u16_as_be(reader, err)?;
+        Ok(Self {
+            tpid,
+            pcp: (tci >> Self::PCP_SHIFT) as u8,
+            dei: tci & Self::DEI_MASK != 0,
+            vid: tci & Self::VID_MASK,
+        })
+    }
+
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut buf = Vec::new();
+        let tci = ((self.pcp as u16 & 0x07) << Self::PCP_SHIFT)
+            | if self.dei { Self::DEI_MASK } else { 0 }
+            | (self.vid & Self::VID_MASK);
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.tpid, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, tci, err)?;
+        Ok(buf)
+    }
+}
+
+impl VlanInterface {
+    /// 送信するフレームに付与するタグ．外側から順に並べる
+    pub fn tags(&self) -> Vec<VlanTag> {
+        let mut tags = Vec::with_capacity(MAX_VLAN_TAGS);
+        if let Some(outer_vid) = self.outer_vid {
+            tags.push(VlanTag {
+                tpid: TPID_8021AD,
+                pcp: self.pcp,
+                dei: false,
+                vid: outer_vid,
+            });
+        }
+        tags.push(VlanTag {
+            tpid: TPID_8021Q,
+            pcp: self.pcp,
+            dei: false,
+            vid: self.vid,
+        });
+        tags
+    }
+
+    /// 受信したフレームのタグがこのサブインタフェースのものか
+    pub fn matches(&self, tags: &[VlanTag]) -> bool {
+        match (self.outer_vid, tags) {
+            (None, [inner]) => inner.vid == self.vid,
+            (Some(outer_vid), [outer, inner]) => outer.vid == outer_vid && inner.vid == self.vid,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for VlanTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tpid=0x{:04x} pcp={} dei={} vid={}",
+            self.tpid, self.pcp, self.dei as u8, self.vid
+        )
+    }
+}
+
+impl std::fmt::Display for VlanInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.outer_vid {
+            Some(outer_vid) => write!(f, "vlan{}.{}", outer_vid, self.vid),
+            None => write!(f, "vlan{}", self.vid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::LinkProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn vlan_tag_round_trip_test() {
+        let tag = VlanTag {
+            tpid: TPID_8021Q,
+            pcp: 5,
+            dei: true,
+            vid: 100,
+        };
+        let raw = tag
+            .to_bytes(LinkProtocolError::CannotConstructFrame)
+            .unwrap();
+        assert_eq!(vec![0x81, 0x00, 0xb0, 0x64], raw);
+
+        let mut reader = Cursor::new(&raw[2..]);
+        assert_eq!(
+            tag,
+            VlanTag::from_cursor(
+                TPID_8021Q,
+                &mut reader,
+                LinkProtocolError::CannotParseFrameHeader
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn vlan_interface_matches_test() {
+        let single = VlanInterface {
+            outer_vid: None,
+            vid: 10,
+            pcp: 0,
+        };
+        let qinq = VlanInterface {
+            outer_vid: Some(200),
+            vid: 10,
+            pcp: 0,
+        };
+
+        assert!(single.matches(&single.tags()));
+        assert!(!single.matches(&qinq.tags()));
+        assert!(qinq.matches(&qinq.tags()));
+        assert!(!qinq.matches(&single.tags()));
+        assert_eq!(TPID_8021AD, qinq.tags()[0].tpid);
+    }
+}
@@ -4,19 +4,24 @@ use crate::byteorder_wrapper;
 
 pub type RawMacAddress = [u8; 6];
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
 pub struct MacAddress(pub RawMacAddress);
 
 impl MacAddress {
     pub const BLOADCAST: MacAddress = MacAddress([0xff; 6]);
 
+    /// グループアドレス(ブロードキャストを含む)かどうか
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
     pub fn from_cursor<E>(reader: &mut Cursor<&[u8]>, err: E) -> Result<Self, E>
     where
         E: std::error::Error + Copy,
     {
         let mut addr = [0x00; 6];
-        for i in 0..6 {
-            addr[i] = byteorder_wrapper::read_u8(reader, err)?;
+        for b in addr.iter_mut() {
+            *b = byteorder_wrapper::read_u8(reader, err)?;
         }
 
         Ok(Self(addr))
@@ -65,6 +70,7 @@ impl From<&str> for MacAddress {
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<u64> for MacAddress {
     fn into(self) -> u64 {
         let addr = [
@@ -74,6 +80,7 @@ impl Into<u64> for MacAddress {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for MacAddress {
     fn default() -> Self {
         Self([0x00; 6])
@@ -99,6 +106,6 @@ mod tests {
     #[test]
     fn into_test() {
         let addr = MacAddress([0x12, 0x34, 0x56, 0x78, 0x90, 0x12]);
-        assert_eq!(0x123456789012 as u64, addr.into());
+        assert_eq!(0x123456789012_u64, addr.into());
     }
 }
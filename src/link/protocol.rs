@@ -1,8 +1,11 @@
+use std::sync::atomic::Ordering;
+
 use thiserror::Error;
+use tokio::sync::mpsc;
 
-use crate::{link, network_device, Items, RxResult};
+use crate::{internet::InternetProtocol, network_device, Items, RxResult};
 
-use super::ethernet;
+use super::{ethernet, UnknownFrame, UnknownFrameReceiver, UNKNOWN_FRAME_QUEUE_LENGTH};
 
 pub const MTU: usize = 1500;
 
@@ -25,6 +28,7 @@ pub enum LinkProtocolError {
     },
 }
 
+#[allow(clippy::field_reassign_with_default, clippy::needless_lifetimes)]
 pub async fn rx<'a, ND: network_device::NetworkDevice>(
     items: &'a Items<ND>,
     lp: LinkProtocol,
@@ -34,8 +38,36 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
         LinkProtocol::Ethernet => {
             let (frame_header, rest) = ethernet::rx(items, buf).await?;
 
+            // 対応していないタイプは数えるだけで，登録されていればアプリケーションに渡す
+            if let InternetProtocol::Unknown(ty) = frame_header.ty {
+                items
+                    .link_stats
+                    .in_unknown_protos
+                    .fetch_add(1, Ordering::Relaxed);
+                if items.opt.debug {
+                    eprintln!("++++++++ rx unknown frame ++++++++");
+                    eprintln!("{}", frame_header);
+                }
+
+                let sender = items.unknown_frame_handler.lock().unwrap().clone();
+                if let Some(sender) = sender {
+                    // 受信キューが溢れている場合は破棄する
+                    let _ = sender.try_send(UnknownFrame {
+                        src_addr: frame_header.src_addr,
+                        dst_addr: frame_header.dst_addr,
+                        vlan_tags: frame_header.tags,
+                        ty,
+                        llc: frame_header.llc,
+                        payload: rest,
+                    });
+                }
+                return Err(LinkProtocolError::Ignore);
+            }
+
             let mut result = RxResult::default();
             result.src_mac_addr = frame_header.src_addr;
+            result.dst_mac_addr = frame_header.dst_addr;
+            result.vlan_tags = frame_header.tags;
             result.ip_type = frame_header.ty;
 
             Ok((result, rest))
@@ -43,6 +75,21 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
     }
 }
 
+/// 対応していないタイプのフレームを受け取るキューを登録する．
+/// 既に登録されていた場合は置き換える
+pub fn subscribe_unknown_frames<ND: network_device::NetworkDevice>(
+    items: &Items<ND>,
+) -> UnknownFrameReceiver {
+    let (sender, receiver) = mpsc::channel(UNKNOWN_FRAME_QUEUE_LENGTH);
+    *items.unknown_frame_handler.lock().unwrap() = Some(sender);
+    receiver
+}
+
+/// 登録したキューを解除する
+pub fn unsubscribe_unknown_frames<ND: network_device::NetworkDevice>(items: &Items<ND>) {
+    *items.unknown_frame_handler.lock().unwrap() = None;
+}
+
 impl std::fmt::Display for LinkProtocol {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
@@ -52,6 +99,7 @@ impl std::fmt::Display for LinkProtocol {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for LinkProtocol {
     fn default() -> Self {
         LinkProtocol::Ethernet
@@ -63,3 +111,41 @@ impl From<network_device::NetworkDeviceError> for LinkProtocolError {
         Self::NetworkDeviceError { e }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_device::FakeDevice;
+
+    #[tokio::test]
+    async fn link_local_unknown_frames_are_counted_test() {
+        let items = Items::new(Default::default(), FakeDevice::default());
+        let mut receiver = subscribe_unknown_frames(&items);
+
+        // LLDP
+        let lldp = [
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e, 0x00, 0x15, 0x5d, 0x22, 0x1e, 0xff, 0x88, 0xcc,
+            0x02, 0x07,
+        ];
+        // STPのBPDU
+        let stp = [
+            0x01, 0x80, 0xc2, 0x00, 0x00, 0x00, 0x00, 0x15, 0x5d, 0x22, 0x1e, 0xff, 0x00, 0x05,
+            0x42, 0x42, 0x03, 0x00, 0x00,
+        ];
+        for frame in [&lldp[..], &stp[..]].iter() {
+            assert!(matches!(
+                rx(&items, LinkProtocol::Ethernet, frame).await,
+                Err(LinkProtocolError::Ignore)
+            ));
+        }
+
+        assert_eq!(
+            2,
+            items.link_stats.in_unknown_protos.load(Ordering::Relaxed)
+        );
+        assert_eq!(0x88cc, receiver.try_recv().unwrap().ty);
+        let bpdu = receiver.try_recv().unwrap();
+        assert_eq!(5, bpdu.ty);
+        assert_eq!(Some(0x42), bpdu.llc.map(|llc| llc.dsap));
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::atomic::AtomicU64;
+
+use tokio::sync::mpsc;
+
+use super::{ethernet, MacAddress};
+
+/// 未対応のフレームを配送するキューの長さ
+pub const UNKNOWN_FRAME_QUEUE_LENGTH: usize = 64;
+
+/// リンク層の受信に関する統計情報
+/// See also [RFC2863](https://tools.ietf.org/html/rfc2863#section-6)
+#[derive(Debug, Default)]
+pub struct Statistics {
+    /// 対応していないタイプのため処理しなかったフレームの数(ifInUnknownProtos)
+    pub in_unknown_protos: AtomicU64,
+}
+
+/// 対応していないタイプのフレーム．
+/// LLDPやSTPなどを扱うアプリケーションに配送される
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFrame {
+    pub src_addr: MacAddress,
+    pub dst_addr: MacAddress,
+    pub vlan_tags: Vec<ethernet::VlanTag>,
+    /// タイプフィールドの値．IEEE 802.3形式のフレームでは長さ
+    pub ty: u16,
+    pub llc: Option<ethernet::LlcHeader>,
+    /// ヘッダ(LLCヘッダを含む)を除いたデータ
+    pub payload: Vec<u8>,
+}
+
+pub type UnknownFrameSender = mpsc::Sender<UnknownFrame>;
+pub type UnknownFrameReceiver = mpsc::Receiver<UnknownFrame>;
@@ -4,6 +4,11 @@ pub use device::*;
 mod raw_socket;
 pub use raw_socket::*;
 
+#[cfg(test)]
+mod fake;
+#[cfg(test)]
+pub use fake::*;
+
 #[link(name = "setup_c")]
 extern "C" {
     fn _setup_raw_sock(
@@ -0,0 +1,40 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{NetworkDevice, NetworkDeviceError};
+use crate::link::MacAddress;
+
+/// テスト用のデバイス．受信は常にタイムアウトし，送信したフレームは記録するだけ
+#[derive(Clone, Copy)]
+pub struct FakeDevice {
+    pub frames: &'static Mutex<Vec<Vec<u8>>>,
+}
+
+impl FakeDevice {
+    pub const ADDR: MacAddress = MacAddress([0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66]);
+}
+
+impl Default for FakeDevice {
+    fn default() -> Self {
+        Self {
+            frames: Box::leak(Box::new(Mutex::new(Vec::new()))),
+        }
+    }
+}
+
+#[async_trait]
+impl NetworkDevice for FakeDevice {
+    async fn read(&self, _buf: &mut [u8]) -> Result<usize, NetworkDeviceError> {
+        Err(NetworkDeviceError::Timeout)
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, NetworkDeviceError> {
+        self.frames.lock().unwrap().push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn device_addr(&self) -> MacAddress {
+        Self::ADDR
+    }
+}
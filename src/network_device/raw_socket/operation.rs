@@ -5,7 +5,10 @@ use std::ffi::CString;
 #[allow(dead_code)]
 pub fn setup_raw_socket(interface_name: String) -> Result<Socket, NetworkDeviceError> {
     unsafe {
-        let mut raw_sock: RawSocket = std::mem::MaybeUninit::uninit().assume_init();
+        let mut raw_sock = RawSocket {
+            fd: -1,
+            mac_addr: [0; 6],
+        };
 
         let interface_name = match CString::new(interface_name) {
             Ok(s) => s,
@@ -1,9 +1,35 @@
-use crate::link;
+use crate::link::{
+    self,
+    ethernet::{VlanTag, TPID_8021Q},
+};
 use crate::network_device;
 use async_trait::async_trait;
 
 use network_device::NetworkDeviceError;
 
+/// 受信を待つ最大時間(ミリ秒)．
+/// `transport::tcp::CLOCK_GRANULARITY` と揃えている
+const POLL_TIMEOUT_MS: i32 = 100;
+
+/// `linux/if_packet.h` の定義．libcクレートには含まれていない
+const PACKET_AUXDATA: libc::c_int = 8;
+const TP_STATUS_VLAN_VALID: u32 = 1 << 4;
+const TP_STATUS_VLAN_TPID_VALID: u32 = 1 << 6;
+
+/// `PACKET_AUXDATA` で受け取る補助データ．
+/// カーネルがフレームから取り除いたVLANタグを含む
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TpacketAuxdata {
+    tp_status: u32,
+    tp_len: u32,
+    tp_snaplen: u32,
+    tp_mac: u16,
+    tp_net: u16,
+    tp_vlan_tci: u16,
+    tp_vlan_tpid: u16,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct RawSocket {
@@ -27,20 +53,17 @@ impl network_device::NetworkDevice for Socket {
                 events: libc::POLLIN,
                 revents: 0,
             };
-            let ret = libc::poll(&mut pollfd, 1, 3000);
+            // 受信がなくてもTCPのタイマを処理できるよう，短い間隔で戻る
+            let ret = libc::poll(&mut pollfd, 1, POLL_TIMEOUT_MS);
             if ret == -1 && *libc::__errno_location() != libc::EINTR {
                 return Err(NetworkDeviceError::FailedToReadFrom { fd: self.fd });
             } else if ret == 0 {
                 return Err(NetworkDeviceError::Timeout);
             }
-            libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            self.recv_frame(buf)
         };
 
-        if result == -1 {
-            return Err(NetworkDeviceError::FailedToReadFrom { fd: self.fd });
-        }
-
-        Ok(result as usize)
+        result.ok_or(NetworkDeviceError::FailedToReadFrom { fd: self.fd })
     }
     async fn write(&self, buf: &[u8]) -> Result<usize, NetworkDeviceError> {
         let result =
@@ -58,10 +81,96 @@ impl network_device::NetworkDevice for Socket {
 }
 
 impl Socket {
+    /// フレームを1つ受信する．
+    /// カーネルがVLANタグを取り除いている場合は，補助データを使ってフレームに戻す
+    /// See also [packet(7)](https://man7.org/linux/man-pages/man7/packet.7.html)
+    unsafe fn recv_frame(&self, buf: &mut [u8]) -> Option<usize> {
+        // 取り除かれたタグを戻せるよう，その分を空けて受信する
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len().saturating_sub(VlanTag::LENGTH),
+        };
+        let mut control = [0u64; 8];
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let result = libc::recvmsg(self.fd, &mut msg, 0);
+        if result == -1 {
+            return None;
+        }
+
+        let mut len = result as usize;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_PACKET && (*cmsg).cmsg_type == PACKET_AUXDATA {
+                let aux: TpacketAuxdata =
+                    std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const TpacketAuxdata);
+                if aux.tp_status & TP_STATUS_VLAN_VALID != 0 {
+                    let tpid = if aux.tp_status & TP_STATUS_VLAN_TPID_VALID != 0 {
+                        aux.tp_vlan_tpid
+                    } else {
+                        TPID_8021Q
+                    };
+                    len = insert_vlan_tag(buf, len, tpid, aux.tp_vlan_tci);
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+
+        Some(len)
+    }
+
+    /// # Safety
+    ///
+    /// `fd` はオープン済みのRaw Socketを指している必要がある
     pub unsafe fn from_raw(fd: network_device::FileDescriptor, addr: link::RawMacAddress) -> Self {
         Self {
-            fd: fd,
+            fd,
             mac_addr: link::MacAddress(addr),
         }
     }
 }
+
+/// 宛先・送信元MACアドレスの直後にVLANタグを挿入し，フレームの長さを返す
+fn insert_vlan_tag(buf: &mut [u8], len: usize, tpid: u16, tci: u16) -> usize {
+    const TAG_OFFSET: usize = 12;
+    if len < TAG_OFFSET || len + VlanTag::LENGTH > buf.len() {
+        return len;
+    }
+
+    buf.copy_within(TAG_OFFSET..len, TAG_OFFSET + VlanTag::LENGTH);
+    buf[TAG_OFFSET..TAG_OFFSET + 2].copy_from_slice(&tpid.to_be_bytes());
+    buf[TAG_OFFSET + 2..TAG_OFFSET + 4].copy_from_slice(&tci.to_be_bytes());
+    len + VlanTag::LENGTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::{ethernet::FrameHeader, LinkProtocolError};
+
+    #[test]
+    fn insert_vlan_tag_test() {
+        let mut buf = [0; 64];
+        let frame = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x15, 0x5d, 0x74, 0x4d, 0x66, 0x08, 0x00,
+            0x45,
+        ];
+        buf[..frame.len()].copy_from_slice(&frame);
+
+        // PCP=3，VID=100
+        let len = insert_vlan_tag(&mut buf, frame.len(), TPID_8021Q, 0x6064);
+        assert_eq!(frame.len() + VlanTag::LENGTH, len);
+        assert_eq!(&frame[..12], &buf[..12]);
+        assert_eq!(&[0x81, 0x00, 0x60, 0x64, 0x08, 0x00, 0x45], &buf[12..len]);
+
+        let (hdr, _) =
+            FrameHeader::new_from_bytes(&buf[..len], LinkProtocolError::CannotParseFrameHeader)
+                .unwrap();
+        assert_eq!(100, hdr.tags[0].vid);
+        assert_eq!(3, hdr.tags[0].pcp);
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
 
 use yaml_rust::YamlLoader;
 
@@ -9,20 +12,235 @@ pub struct PeachPSOption {
     pub dev_addr: link::MacAddress,
     pub ip_addr: internet::ip::IPv4Addr,
     pub network_mask: internet::ip::IPv4Addr,
+    /// 他のネットワーク宛てのパケットを送るルータ
+    pub default_gateway: Option<internet::ip::IPv4Addr>,
+    /// インタフェースに割り当てるIPv6アドレス．
+    /// リンクローカルアドレスは `dev_addr` から生成するので含めなくてよい
+    pub ipv6_addrs: Vec<internet::ipv6::IPv6Addr>,
     pub debug: bool,
     pub internet_filter: HashSet<internet::InternetProtocol>,
     pub transport_filter: HashSet<transport::TransportProtocol>,
+    pub tcp: TcpOption,
+    pub icmp: IcmpOption,
+    pub icmpv6: Icmpv6Option,
+    pub ipv6: Ipv6Option,
+    pub igmp: IgmpOption,
+    pub vlan: VlanOption,
 }
 
+/// TCPの動作に関する設定
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcpOption {
+    /// 確立済みの接続で，タイムアウトとみなすまでの再送回数
+    pub max_retransmissions: u32,
+    /// SYN及びSYN/ACKの再送回数
+    pub max_syn_retransmissions: u32,
+    /// 輻輳制御アルゴリズム．接続毎に変更することもできる
+    pub congestion_control: transport::tcp::CongestionControlAlgorithm,
+    /// ウィンドウスケールオプションを使用するか
+    pub window_scaling: bool,
+    /// タイムスタンプオプションを使用するか
+    pub timestamps: bool,
+    /// SACKを使用するか
+    pub sack: bool,
+    /// ポート毎に保持する確立中(SYN-RECEIVED)の接続数の上限
+    pub syn_backlog: usize,
+    /// 確立中の接続が上限に達した場合に，SYN cookieで応答するか．
+    /// 無効の場合はSYNを破棄する
+    pub syn_cookies: bool,
+    /// Nagleアルゴリズムを無効にするか(TCP_NODELAY)
+    pub nodelay: bool,
+    /// 確認応答を遅延させる時間．`None` の場合は遅延させない
+    pub delayed_ack: Option<Duration>,
+    /// キープアライブの設定．`None` の場合は使用しない
+    pub keepalive: Option<transport::tcp::Keepalive>,
+}
+
+/// ICMPの動作に関する設定
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IcmpOption {
+    /// 送信するメッセージの種類毎の流量制限．含まれない種類は制限しない
+    pub rate_limits: BTreeMap<transport::icmp::MessageType, transport::icmp::RateLimit>,
+    /// タイムスタンプ要求に応答するか
+    pub timestamp_reply: bool,
+    /// アドレスマスク要求に応答するか．
+    /// サブネットマスクを正しく知っているホストのみが応答すべきなので，デフォルトでは無効
+    /// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-3.2.2.9)
+    pub address_mask_reply: bool,
+}
+
+/// ICMPv6及び近隣探索の動作に関する設定
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Icmpv6Option {
+    /// 送信するメッセージの種類毎の流量制限．含まれない種類は制限しない
+    /// See also [RFC4443](https://tools.ietf.org/html/rfc4443#section-2.4)
+    pub rate_limits: BTreeMap<transport::icmpv6::MessageType, transport::icmp::RateLimit>,
+    /// 重複アドレス検出で送信する近隣要請の数(DupAddrDetectTransmits)
+    /// See also [RFC4862](https://tools.ietf.org/html/rfc4862#section-5.1)
+    pub dad_transmits: u32,
+}
+
+/// IPv6アドレスの生成と自動設定に関する設定
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ipv6Option {
+    /// インタフェースIDの生成方法
+    pub addr_gen_mode: internet::ipv6::AddressGenerationMode,
+    /// 安定プライバシーアドレスの生成に使う秘密鍵．
+    /// 再起動後も同じアドレスを得るには，設定ファイルで固定する必要がある
+    /// See also [RFC7217](https://tools.ietf.org/html/rfc7217#section-5)
+    pub stable_secret: [u8; 16],
+    /// ルータ広告のプレフィックス情報からグローバルアドレスを自動設定するか
+    pub autoconf: bool,
+}
+
+/// IGMPの動作に関する設定
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct IgmpOption {
+    /// ホストが動作するバージョン．古いクエリアを検出した場合はそれに合わせる
+    pub version: transport::igmp::IgmpVersion,
+}
+
+/// VLANサブインタフェースの設定
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VlanOption {
+    /// タグなしフレームを送受信するか
+    pub untagged: bool,
+    /// 1つの生ソケット上で扱うVLAN．
+    /// いずれにも該当しないタグ付きフレームは破棄する
+    pub interfaces: Vec<link::ethernet::VlanInterface>,
+}
+
+#[allow(clippy::derivable_impls)]
 impl Default for PeachPSOption {
     fn default() -> Self {
         Self {
             dev_addr: Default::default(),
             ip_addr: Default::default(),
             network_mask: Default::default(),
+            default_gateway: None,
+            ipv6_addrs: Vec::new(),
             debug: false,
             internet_filter: Default::default(),
             transport_filter: Default::default(),
+            tcp: Default::default(),
+            icmp: Default::default(),
+            icmpv6: Default::default(),
+            ipv6: Default::default(),
+            igmp: Default::default(),
+            vlan: Default::default(),
+        }
+    }
+}
+
+impl Default for TcpOption {
+    fn default() -> Self {
+        // Linuxの tcp_retries2, tcp_syn_retries, tcp_max_syn_backlog に倣う
+        Self {
+            max_retransmissions: 15,
+            max_syn_retransmissions: 6,
+            congestion_control: Default::default(),
+            window_scaling: true,
+            timestamps: true,
+            sack: true,
+            syn_backlog: 256,
+            syn_cookies: true,
+            nodelay: false,
+            delayed_ack: Some(transport::tcp::DEFAULT_DELAYED_ACK_TIMEOUT),
+            keepalive: None,
+        }
+    }
+}
+
+impl Default for IcmpOption {
+    fn default() -> Self {
+        use transport::icmp::{MessageType, RateLimit};
+
+        // 問い合わせへの応答はLinuxの icmp_msgs_per_sec, icmp_msgs_burst に倣い，
+        // エラーメッセージはそれより厳しく制限する
+        let mut rate_limits = BTreeMap::new();
+        for ty in [
+            MessageType::EchoReply,
+            MessageType::TimestampReply,
+            MessageType::AddressMaskReply,
+        ] {
+            rate_limits.insert(
+                ty,
+                RateLimit {
+                    rate: 1000,
+                    burst: 50,
+                },
+            );
+        }
+        for ty in [
+            MessageType::DestinationUnreachable,
+            MessageType::TimeExceeded,
+            MessageType::ParameterProblem,
+        ] {
+            rate_limits.insert(
+                ty,
+                RateLimit {
+                    rate: 100,
+                    burst: 10,
+                },
+            );
+        }
+        Self {
+            rate_limits,
+            timestamp_reply: false,
+            address_mask_reply: false,
+        }
+    }
+}
+
+impl Default for Icmpv6Option {
+    fn default() -> Self {
+        use transport::icmpv6::MessageType;
+
+        // ICMPと同じく，エラーメッセージは問い合わせへの応答より厳しく制限する
+        let mut rate_limits = BTreeMap::new();
+        rate_limits.insert(
+            MessageType::EchoReply,
+            transport::icmp::RateLimit {
+                rate: 1000,
+                burst: 50,
+            },
+        );
+        for ty in [
+            MessageType::DestinationUnreachable,
+            MessageType::PacketTooBig,
+            MessageType::TimeExceeded,
+            MessageType::ParameterProblem,
+        ] {
+            rate_limits.insert(
+                ty,
+                transport::icmp::RateLimit {
+                    rate: 100,
+                    burst: 10,
+                },
+            );
+        }
+        Self {
+            rate_limits,
+            dad_transmits: 1,
+        }
+    }
+}
+
+impl Default for VlanOption {
+    fn default() -> Self {
+        Self {
+            untagged: true,
+            interfaces: Vec::new(),
+        }
+    }
+}
+
+impl Default for Ipv6Option {
+    fn default() -> Self {
+        Self {
+            addr_gen_mode: internet::ipv6::AddressGenerationMode::Eui64,
+            stable_secret: rand::random(),
+            autoconf: true,
         }
     }
 }
@@ -37,6 +255,18 @@ impl PeachPSOption {
             dev_addr: link::MacAddress::from(yaml["device_addr"].as_str().unwrap()),
             ip_addr: internet::ip::IPv4Addr::from(yaml["ip_addr"].as_str().unwrap()),
             network_mask: internet::ip::IPv4Addr::from(yaml["network_mask"].as_str().unwrap()),
+            default_gateway: yaml["default_gateway"]
+                .as_str()
+                .map(internet::ip::IPv4Addr::from),
+            ipv6_addrs: yaml["ipv6_addrs"]
+                .as_vec()
+                .map(|addrs| {
+                    addrs
+                        .iter()
+                        .map(|addr| internet::ipv6::IPv6Addr::from(addr.as_str().unwrap()))
+                        .collect()
+                })
+                .unwrap_or_default(),
             debug: yaml["debug"].as_bool().unwrap(),
             internet_filter: {
                 let mut s: HashSet<internet::InternetProtocol> = Default::default();
@@ -54,6 +284,178 @@ impl PeachPSOption {
                 }
                 s
             },
+            tcp: TcpOption::from_yaml(&yaml["tcp"]),
+            icmp: IcmpOption::from_yaml(&yaml["icmp"]),
+            icmpv6: Icmpv6Option::from_yaml(&yaml["icmpv6"]),
+            ipv6: Ipv6Option::from_yaml(&yaml["ipv6"]),
+            igmp: IgmpOption::from_yaml(&yaml["igmp"]),
+            vlan: VlanOption::from_yaml(&yaml["vlan"]),
+        }
+    }
+}
+
+impl TcpOption {
+    /// 指定されていない項目はデフォルト値を用いる
+    fn from_yaml(yaml: &yaml_rust::Yaml) -> TcpOption {
+        let mut opt: TcpOption = Default::default();
+        if let Some(n) = yaml["max_retransmissions"].as_i64() {
+            opt.max_retransmissions = n as u32;
+        }
+        if let Some(n) = yaml["max_syn_retransmissions"].as_i64() {
+            opt.max_syn_retransmissions = n as u32;
+        }
+        if let Some(s) = yaml["congestion_control"].as_str() {
+            opt.congestion_control = transport::tcp::CongestionControlAlgorithm::from(s);
+        }
+        if let Some(b) = yaml["window_scaling"].as_bool() {
+            opt.window_scaling = b;
+        }
+        if let Some(b) = yaml["timestamps"].as_bool() {
+            opt.timestamps = b;
+        }
+        if let Some(b) = yaml["sack"].as_bool() {
+            opt.sack = b;
+        }
+        if let Some(n) = yaml["syn_backlog"].as_i64() {
+            opt.syn_backlog = n as usize;
+        }
+        if let Some(b) = yaml["syn_cookies"].as_bool() {
+            opt.syn_cookies = b;
+        }
+        if let Some(b) = yaml["nodelay"].as_bool() {
+            opt.nodelay = b;
+        }
+        // 0を指定した場合は遅延させない
+        if let Some(ms) = yaml["delayed_ack_ms"].as_i64() {
+            opt.delayed_ack = Some(Duration::from_millis(ms as u64)).filter(|d| !d.is_zero());
+        }
+        opt.keepalive = Self::keepalive_from_yaml(&yaml["keepalive"]);
+        opt
+    }
+
+    /// `keepalive: true` または `idle`, `interval`(秒), `count` を持つ連想配列を受け付ける
+    fn keepalive_from_yaml(yaml: &yaml_rust::Yaml) -> Option<transport::tcp::Keepalive> {
+        if let Some(b) = yaml.as_bool() {
+            return Some(Default::default()).filter(|_| b);
+        }
+        yaml.as_hash()?;
+
+        let mut keepalive: transport::tcp::Keepalive = Default::default();
+        if let Some(n) = yaml["idle"].as_i64() {
+            keepalive.idle = Duration::from_secs(n as u64);
+        }
+        if let Some(n) = yaml["interval"].as_i64() {
+            keepalive.interval = Duration::from_secs(n as u64);
+        }
+        if let Some(n) = yaml["count"].as_i64() {
+            keepalive.count = n as u32;
+        }
+        Some(keepalive)
+    }
+}
+
+impl IcmpOption {
+    /// `rate_limits` には種類名をキーとして，`rate` と `burst` を持つ連想配列か，
+    /// 制限しないことを表す `false` を指定する．
+    /// 指定されていない種類はデフォルト値を用いる
+    fn from_yaml(yaml: &yaml_rust::Yaml) -> IcmpOption {
+        let mut opt: IcmpOption = Default::default();
+        if let Some(b) = yaml["timestamp_reply"].as_bool() {
+            opt.timestamp_reply = b;
+        }
+        if let Some(b) = yaml["address_mask_reply"].as_bool() {
+            opt.address_mask_reply = b;
         }
+        rate_limits_from_yaml(&yaml["rate_limits"], &mut opt.rate_limits);
+        opt
+    }
+}
+
+impl Icmpv6Option {
+    /// `rate_limits` はICMPと同じ形式で，ICMPv6の種類名をキーとする
+    fn from_yaml(yaml: &yaml_rust::Yaml) -> Icmpv6Option {
+        let mut opt: Icmpv6Option = Default::default();
+        if let Some(n) = yaml["dad_transmits"].as_i64() {
+            opt.dad_transmits = n as u32;
+        }
+        rate_limits_from_yaml(&yaml["rate_limits"], &mut opt.rate_limits);
+        opt
+    }
+}
+
+impl Ipv6Option {
+    /// `stable_secret` は128ビットの16進数で指定する
+    fn from_yaml(yaml: &yaml_rust::Yaml) -> Ipv6Option {
+        let mut opt: Ipv6Option = Default::default();
+        if let Some(mode) = yaml["addr_gen_mode"].as_str() {
+            opt.addr_gen_mode = internet::ipv6::AddressGenerationMode::from(mode);
+        }
+        if let Some(secret) = yaml["stable_secret"].as_str() {
+            opt.stable_secret = u128::from_str_radix(secret, 16).unwrap().to_be_bytes();
+        }
+        if let Some(autoconf) = yaml["autoconf"].as_bool() {
+            opt.autoconf = autoconf;
+        }
+        opt
+    }
+}
+
+impl IgmpOption {
+    /// `version` には1から3を指定する
+    fn from_yaml(yaml: &yaml_rust::Yaml) -> IgmpOption {
+        let mut opt: IgmpOption = Default::default();
+        if let Some(n) = yaml["version"].as_i64() {
+            opt.version = transport::igmp::IgmpVersion::from(n.to_string().as_str());
+        }
+        opt
+    }
+}
+
+impl VlanOption {
+    /// `interfaces` には `vid` と，省略可能な `pcp`, `outer_vid`(QinQ) を持つ連想配列を並べる
+    fn from_yaml(yaml: &yaml_rust::Yaml) -> VlanOption {
+        let mut opt: VlanOption = Default::default();
+        if let Some(b) = yaml["untagged"].as_bool() {
+            opt.untagged = b;
+        }
+        if let Some(interfaces) = yaml["interfaces"].as_vec() {
+            opt.interfaces = interfaces
+                .iter()
+                .map(|interface| link::ethernet::VlanInterface {
+                    outer_vid: interface["outer_vid"].as_i64().map(|n| n as u16),
+                    vid: interface["vid"].as_i64().unwrap() as u16,
+                    pcp: interface["pcp"].as_i64().unwrap_or(0) as u8,
+                })
+                .collect();
+        }
+        opt
+    }
+}
+
+/// 種類名をキーとした流量制限の設定で，デフォルト値を上書きする
+fn rate_limits_from_yaml<T>(
+    yaml: &yaml_rust::Yaml,
+    rate_limits: &mut BTreeMap<T, transport::icmp::RateLimit>,
+) where
+    T: Ord + for<'a> From<&'a str>,
+{
+    let limits = match yaml.as_hash() {
+        Some(limits) => limits,
+        None => return,
+    };
+
+    for (ty, limit) in limits.iter() {
+        let ty = T::from(ty.as_str().unwrap());
+        if limit.as_bool() == Some(false) {
+            rate_limits.remove(&ty);
+            continue;
+        }
+        rate_limits.insert(
+            ty,
+            transport::icmp::RateLimit {
+                rate: limit["rate"].as_i64().unwrap() as u32,
+                burst: limit["burst"].as_i64().unwrap() as u32,
+            },
+        );
     }
 }
@@ -1,6 +1,7 @@
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use crate::{
@@ -20,6 +21,34 @@ pub struct Items<ND: network_device::NetworkDevice> {
     pub opt: option::PeachPSOption,
     pub dev: Arc<Mutex<ND>>,
     pub arp_table: Arc<Mutex<HashMap<internet::ip::IPv4Addr, link::MacAddress>>>,
+    /// バインドされているUDPポートと受信キューの対応
+    pub udp_table: Arc<Mutex<HashMap<u16, transport::udp::DatagramSender>>>,
+    pub tcp_table: Arc<Mutex<transport::tcp::ConnectionTable>>,
+    pub icmp_stats: Arc<transport::icmp::Statistics>,
+    pub icmp_rate_limiter: Arc<Mutex<transport::icmp::RateLimiter>>,
+    /// エコー要求の識別子毎に，応答を受け取るキュー
+    pub icmp_echo_table: Arc<Mutex<HashMap<u16, transport::icmp::EchoSender>>>,
+    /// IPv6アドレスとMACアドレスの対応．ARPテーブルに相当する
+    pub neighbor_cache: Arc<Mutex<transport::icmpv6::NeighborCache>>,
+    /// ルータ広告で知ったデフォルトルータと，その有効期限
+    pub default_routers: Arc<Mutex<HashMap<internet::ipv6::IPv6Addr, Instant>>>,
+    /// インタフェースに割り当てられたIPv6アドレスと，その状態・有効期限
+    pub ipv6_addrs: Arc<Mutex<internet::ipv6::AddressTable>>,
+    pub ipv6_reassembly: Arc<Mutex<internet::ipv6::ReassemblyTable>>,
+    /// Packet Too Bigで知った宛先毎の経路MTUと，それを受信した時刻
+    pub path_mtu_cache: Arc<Mutex<HashMap<internet::ipv6::IPv6Addr, (usize, Instant)>>>,
+    pub icmpv6_stats: Arc<transport::icmp::Statistics>,
+    pub icmpv6_rate_limiter:
+        Arc<Mutex<transport::icmp::RateLimiter<transport::icmpv6::MessageType>>>,
+    /// 参加しているIPv4マルチキャストグループとIGMPのタイマ
+    pub igmp_groups: Arc<Mutex<transport::igmp::GroupTable>>,
+    /// 受信したフレームから学習した，MACアドレス毎のVLANサブインタフェース．
+    /// `None` はタグなしで受信したことを表す
+    pub vlan_neighbors:
+        Arc<Mutex<HashMap<link::MacAddress, Option<link::ethernet::VlanInterface>>>>,
+    pub link_stats: Arc<link::Statistics>,
+    /// 対応していないタイプのフレームを配送するキュー
+    pub unknown_frame_handler: Arc<Mutex<Option<link::UnknownFrameSender>>>,
 }
 
 #[derive(Error, Debug)]
@@ -45,14 +74,55 @@ pub enum PeachPSError {
 }
 
 /// 下位層から上位層に向かって伝播させる情報の集約
+#[derive(Debug, Clone)]
 pub struct RxResult {
     pub src_mac_addr: link::MacAddress,
-    pub src_ip_addr: internet::ip::IPv4Addr,
+    /// ブロードキャスト・マルチキャストで受信したかの判定に使う
+    pub dst_mac_addr: link::MacAddress,
+    /// 受信したフレームのVLANタグ．外側のタグから順に並ぶ
+    pub vlan_tags: Vec<link::ethernet::VlanTag>,
+    pub src_ip_addr: internet::IpAddr,
+    pub dst_ip_addr: internet::IpAddr,
+    /// ICMPエラーで引用するために保持しておく受信IPヘッダ
+    pub raw_ip_header: Vec<u8>,
     pub ip_type: internet::InternetProtocol,
     pub tp_type: transport::TransportProtocol,
     pub message_len: usize,
 }
 
+impl RxResult {
+    /// 受信したVLANのID．QinQの場合は内側のタグのものを返す
+    pub fn vid(&self) -> Option<u16> {
+        self.vlan_tags.last().map(|tag| tag.vid)
+    }
+
+    /// 受信したフレームの優先度(PCP)．QinQの場合は内側のタグのものを返す
+    pub fn pcp(&self) -> Option<u8> {
+        self.vlan_tags.last().map(|tag| tag.pcp)
+    }
+
+    /// 送信元のIPv4アドレス．IPv6で受信した場合は `0.0.0.0` を返す
+    pub fn src_ipv4_addr(&self) -> internet::ip::IPv4Addr {
+        self.src_ip_addr.v4().unwrap_or_default()
+    }
+
+    /// 宛先のIPv4アドレス．IPv6で受信した場合は `0.0.0.0` を返す
+    pub fn dst_ipv4_addr(&self) -> internet::ip::IPv4Addr {
+        self.dst_ip_addr.v4().unwrap_or_default()
+    }
+
+    /// 送信元のIPv6アドレス．IPv4で受信した場合は `::` を返す
+    pub fn src_ipv6_addr(&self) -> internet::ipv6::IPv6Addr {
+        self.src_ip_addr.v6().unwrap_or_default()
+    }
+
+    /// 宛先のIPv6アドレス．IPv4で受信した場合は `::` を返す
+    pub fn dst_ipv6_addr(&self) -> internet::ipv6::IPv6Addr {
+        self.dst_ip_addr.v6().unwrap_or_default()
+    }
+}
+
+#[allow(clippy::needless_lifetimes)]
 async fn rx_datalink<'a, ND>(
     table: &'a Items<ND>,
     lp: link::LinkProtocol,
@@ -62,20 +132,19 @@ where
 {
     let mut buf: [u8; 2048] = [0; 2048];
 
-    if let Ok(dev) = table.dev.lock() {
-        let nbytes = dev.read(&mut buf).await?;
-        if nbytes == 0 {
-            return Err(PeachPSError::EOF);
-        }
-
-        let (result, rest) = link::rx(table, lp, &buf).await?;
-
-        return Ok((result, rest));
+    // デバイスはCopyなので，ロックを保持したまま待たないように取り出しておく
+    let dev = *table.dev.lock().unwrap();
+    let nbytes = dev.read(&mut buf).await?;
+    if nbytes == 0 {
+        return Err(PeachPSError::EOF);
     }
 
-    unreachable!()
+    let (result, rest) = link::rx(table, lp, &buf[..nbytes]).await?;
+
+    Ok((result, rest))
 }
 
+#[allow(clippy::needless_lifetimes)]
 async fn rx_internet<'a, ND>(
     table: &'a Items<ND>,
     lp: link::LinkProtocol,
@@ -89,6 +158,7 @@ where
     Ok((result, rest))
 }
 
+#[allow(clippy::needless_lifetimes)]
 async fn rx_transport<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     lp: link::LinkProtocol,
@@ -100,24 +170,81 @@ async fn rx_transport<'a, ND: network_device::NetworkDevice>(
     Ok(data)
 }
 
+/// 受信処理を繰り返す．
+/// 受信したデータはソケット(`transport::udp::UdpSocket` 等)に配送されるので，
+/// アプリケーションはこの関数を別タスクで動かしつつソケットを使用する．
+///
+/// プロトコル処理中のエラーはそのパケットを破棄するだけで処理を継続し，
+/// ネットワークデバイスのエラーの場合のみ返る
+#[allow(clippy::needless_lifetimes)]
 pub async fn run<'a, ND>(table: &'a Items<ND>, lp: link::LinkProtocol) -> Result<(), PeachPSError>
 where
     ND: network_device::NetworkDevice,
 {
     loop {
-        match rx_transport(&table, lp).await {
+        let result = rx_transport(table, lp).await;
+
+        // 受信の有無に関わらず，一定間隔でタイマを処理する
+        on_timer(table, std::time::Instant::now()).await;
+
+        match result {
             Ok(_data) => {}
             Err(e) => match e {
                 PeachPSError::Ignore => {}
+                // 一定時間受信がなかっただけなので，待ち直す
+                PeachPSError::NetworkDeviceError {
+                    e: network_device::NetworkDeviceError::Timeout,
+                } => {}
+                PeachPSError::NetworkDeviceError { .. } | PeachPSError::EOF => {
+                    return Err(e);
+                }
                 _ => {
-                    eprintln!("Error Found: {}", e);
-                    std::process::exit(1);
+                    if table.opt.debug {
+                        eprintln!("Error Found: {}", e);
+                    }
                 }
             },
         }
     }
 }
 
+/// 各プロトコルのタイマを処理する．
+/// エラーは `run` の受信処理と同様に，デバッグ出力するだけで処理を継続する
+async fn on_timer<ND: network_device::NetworkDevice>(table: &Items<ND>, now: Instant) {
+    if let Err(e) = transport::tcp::on_timer(table, now).await {
+        if table.opt.debug {
+            eprintln!("Error Found: {}", e);
+        }
+    }
+    if table
+        .opt
+        .internet_filter
+        .contains(&internet::InternetProtocol::IPv6)
+    {
+        if let Err(e) = internet::ipv6::on_timer(table, now).await {
+            if table.opt.debug {
+                eprintln!("Error Found: {}", e);
+            }
+        }
+        if let Err(e) = transport::icmpv6::on_timer(table, now).await {
+            if table.opt.debug {
+                eprintln!("Error Found: {}", e);
+            }
+        }
+    }
+    if table
+        .opt
+        .transport_filter
+        .contains(&transport::TransportProtocol::IGMP)
+    {
+        if let Err(e) = transport::igmp::on_timer(table, now).await {
+            if table.opt.debug {
+                eprintln!("Error Found: {}", e);
+            }
+        }
+    }
+}
+
 impl From<network_device::NetworkDeviceError> for PeachPSError {
     fn from(e: network_device::NetworkDeviceError) -> Self {
         Self::NetworkDeviceError { e }
@@ -151,11 +278,16 @@ impl From<link::LinkProtocolError> for PeachPSError {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for RxResult {
     fn default() -> Self {
         Self {
             src_mac_addr: Default::default(),
+            dst_mac_addr: Default::default(),
+            vlan_tags: Vec::new(),
             src_ip_addr: Default::default(),
+            dst_ip_addr: Default::default(),
+            raw_ip_header: Vec::new(),
             ip_type: Default::default(),
             tp_type: Default::default(),
             message_len: 0,
@@ -165,10 +297,31 @@ impl Default for RxResult {
 
 impl<ND: NetworkDevice> Items<ND> {
     pub fn new(opt: option::PeachPSOption, dev: ND) -> Self {
+        let tcp_table = transport::tcp::ConnectionTable::new(opt.tcp.clone());
+        let icmp_rate_limiter = transport::icmp::RateLimiter::new(&opt.icmp.rate_limits);
+        let icmpv6_rate_limiter = transport::icmp::RateLimiter::new(&opt.icmpv6.rate_limits);
+        let ipv6_addrs = internet::ipv6::AddressTable::new(&opt, Instant::now());
+        let igmp_groups = transport::igmp::GroupTable::new(opt.igmp.version);
         Self {
             opt,
             dev: Arc::new(Mutex::new(dev)),
             arp_table: Arc::new(Mutex::new(HashMap::with_capacity(16))),
+            udp_table: Arc::new(Mutex::new(HashMap::new())),
+            tcp_table: Arc::new(Mutex::new(tcp_table)),
+            icmp_stats: Default::default(),
+            icmp_rate_limiter: Arc::new(Mutex::new(icmp_rate_limiter)),
+            icmp_echo_table: Arc::new(Mutex::new(HashMap::new())),
+            neighbor_cache: Default::default(),
+            default_routers: Arc::new(Mutex::new(HashMap::new())),
+            ipv6_addrs: Arc::new(Mutex::new(ipv6_addrs)),
+            ipv6_reassembly: Default::default(),
+            path_mtu_cache: Arc::new(Mutex::new(HashMap::new())),
+            icmpv6_stats: Default::default(),
+            icmpv6_rate_limiter: Arc::new(Mutex::new(icmpv6_rate_limiter)),
+            igmp_groups: Arc::new(Mutex::new(igmp_groups)),
+            vlan_neighbors: Arc::new(Mutex::new(HashMap::new())),
+            link_stats: Default::default(),
+            unknown_frame_handler: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -181,4 +334,97 @@ impl<ND: NetworkDevice> Items<ND> {
 
         None
     }
+
+    pub fn lookup_neighbor_cache(&self, ip: &internet::ipv6::IPv6Addr) -> Option<MacAddress> {
+        self.neighbor_cache.lock().unwrap().get(ip)
+    }
+
+    /// 宛先までの経路MTU．Packet Too Bigを受信していなければリンクのMTUとする
+    pub fn path_mtu(&self, dst: internet::ipv6::IPv6Addr) -> usize {
+        let now = Instant::now();
+        self.path_mtu_cache
+            .lock()
+            .unwrap()
+            .get(&dst)
+            .filter(|(_, updated_at)| now < *updated_at + internet::ipv6::PATH_MTU_AGING)
+            .map(|(mtu, _)| *mtu)
+            .unwrap_or(link::MTU)
+    }
+
+    /// 宛先に対して使う自身のアドレス．
+    /// IPv4では設定されたアドレスを，IPv6ではアドレステーブルから選んだものを返す
+    pub fn source_address(&self, dst: internet::IpAddr) -> internet::IpAddr {
+        match dst {
+            internet::IpAddr::V4(_) => self.opt.ip_addr.into(),
+            internet::IpAddr::V6(dst) => self.ipv6_addrs.lock().unwrap().source_address(dst).into(),
+        }
+    }
+
+    /// 有効期限内のデフォルトルータを1つ選ぶ
+    pub fn default_router(&self) -> Option<internet::ipv6::IPv6Addr> {
+        let now = Instant::now();
+        self.default_routers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, expires_at)| now < **expires_at)
+            .map(|(router, _)| *router)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use network_device::FakeDevice;
+
+    use super::*;
+
+    fn fake_items(internet_filter: &[internet::InternetProtocol]) -> Items<FakeDevice> {
+        let opt = option::PeachPSOption {
+            internet_filter: internet_filter.iter().copied().collect(),
+            ..Default::default()
+        };
+        Items::new(opt, FakeDevice::default())
+    }
+
+    /// 先頭以外の断片を1つだけ再構築テーブルに入れる
+    fn insert_fragment(table: &Items<FakeDevice>, now: Instant) {
+        let fragment_hdr = internet::ipv6::FragmentHeader {
+            next_header: internet::ipv6::NextHeader::UDP,
+            offset: 1,
+            more_fragments: false,
+            identification: 1,
+        };
+        let mut unfragmentable = vec![0; internet::ipv6::IPv6Header::LENGTH];
+        unfragmentable[0] = 0x60;
+        unfragmentable[8] = 0xfe;
+        unfragmentable[9] = 0x80;
+        unfragmentable[23] = 0x01;
+        table.ipv6_reassembly.lock().unwrap().insert(
+            &fragment_hdr,
+            unfragmentable,
+            &[0; 8],
+            None,
+            now,
+        );
+    }
+
+    #[tokio::test]
+    async fn ipv6_reassembly_expires_on_timer_test() {
+        let now = Instant::now();
+        let expired_at = now + internet::ipv6::REASSEMBLY_TIMEOUT;
+
+        let table = fake_items(&[internet::InternetProtocol::IPv6]);
+        insert_fragment(&table, now);
+        on_timer(&table, now).await;
+        assert_eq!(1, table.ipv6_reassembly.lock().unwrap().len());
+        on_timer(&table, expired_at).await;
+        assert!(table.ipv6_reassembly.lock().unwrap().is_empty());
+
+        // IPv6を扱わない場合はタイマを処理しない
+        let table = fake_items(&[]);
+        insert_fragment(&table, now);
+        on_timer(&table, expired_at).await;
+        assert_eq!(1, table.ipv6_reassembly.lock().unwrap().len());
+    }
 }
@@ -2,3 +2,7 @@ mod protocol;
 pub use protocol::*;
 
 pub mod icmp;
+pub mod icmpv6;
+pub mod igmp;
+pub mod tcp;
+pub mod udp;
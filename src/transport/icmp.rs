@@ -3,3 +3,12 @@ pub use types::*;
 
 mod protocol;
 pub use protocol::*;
+
+mod rate_limit;
+pub use rate_limit::*;
+
+mod error_message;
+pub use error_message::*;
+
+mod echo;
+pub use echo::*;
@@ -0,0 +1,753 @@
+use std::{
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::mpsc;
+
+use super::{send_with_ttl, Message, MessageData, MessageType, ReceivedError};
+use crate::{
+    internet::{
+        self,
+        ip::{IPHeader, IPv4Addr},
+        IpAddr,
+    },
+    link, network_device,
+    transport::{icmpv6, TransportProtocolError},
+    Items, RxResult,
+};
+
+/// セッション毎に保持する受信キューの長さ
+const RECEIVE_QUEUE_LENGTH: usize = 16;
+const MILLIS_PER_DAY: u128 = 24 * 60 * 60 * 1000;
+/// IPヘッダ，ICMPヘッダ及び識別子・シーケンス番号を除いた，エコー要求で送信可能なデータ長
+pub const MAX_ECHO_PAYLOAD_LENGTH: usize =
+    link::MTU - IPHeader::LEAST_LENGTH as usize - Message::LENGTH - 4;
+
+/// セッションに配送される，要求に対する応答かICMPエラー
+#[derive(Debug, Clone)]
+pub struct EchoResponse {
+    /// 応答またはエラーを送信したホスト
+    pub src_addr: IpAddr,
+    pub sequence_number: u16,
+    /// 受信したパケットのTTL(IPv6ではホップリミット)
+    pub ttl: u8,
+    /// 応答の場合はその内容，ICMPエラーの場合は通知するエラー
+    pub result: Result<MessageData, TransportProtocolError>,
+    pub received_at: Instant,
+    /// 受信した時刻を，タイムスタンプメッセージと同じ形式で表したもの
+    pub received_timestamp: u32,
+}
+
+pub type EchoSender = mpsc::Sender<EchoResponse>;
+
+/// エコー要求に対する応答
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoReply {
+    /// 応答を返したホスト
+    pub src_addr: IpAddr,
+    pub sequence_number: u16,
+    /// 受信した応答のTTL
+    pub ttl: u8,
+    /// 応答に含まれていたデータの長さ
+    pub payload_len: usize,
+    /// 要求を送信してから応答を受信するまでの時間
+    pub rtt: Duration,
+}
+
+/// 経路上のホストから返ってきた応答
+#[derive(Debug, Clone, Copy)]
+pub struct ProbeResponse {
+    /// 応答したホスト
+    pub src_addr: IpAddr,
+    /// 要求を送信してから応答を受信するまでの時間
+    pub rtt: Duration,
+    /// 宛先に到達しなかった場合に，ICMPエラーが示すエラー
+    pub error: Option<TransportProtocolError>,
+}
+
+/// タイムスタンプ要求に対する応答
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampReply {
+    /// 応答を返したホスト
+    pub src_addr: IpAddr,
+    /// 要求を送信した時刻
+    pub originate_timestamp: u32,
+    /// 相手が要求を受信した時刻
+    pub receive_timestamp: u32,
+    /// 相手が応答を送信した時刻
+    pub transmit_timestamp: u32,
+    /// 応答を受信した時刻
+    pub arrival_timestamp: u32,
+    pub rtt: Duration,
+}
+
+impl TimestampReply {
+    /// 相手の時計が自身よりどれだけ進んでいるか(ミリ秒)．
+    /// 往路と復路の遅延が等しいとみなして推定する
+    pub fn clock_offset(&self) -> i64 {
+        let outbound = self.receive_timestamp as i64 - self.originate_timestamp as i64;
+        let inbound = self.transmit_timestamp as i64 - self.arrival_timestamp as i64;
+        (outbound + inbound) / 2
+    }
+}
+
+/// UTCの0時からの経過ミリ秒．タイムスタンプメッセージで使う形式
+/// See also [RFC792](https://tools.ietf.org/html/rfc792)
+pub fn current_timestamp() -> u32 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_millis() % MILLIS_PER_DAY) as u32
+}
+
+/// 1つの識別子を使ってエコー要求などの問い合わせを送るセッション．
+/// 要求毎にシーケンス番号を進め，対応する応答だけを受け取る
+///
+/// 応答は `peachps::run` が配送するので，`run` を別タスクで動かしておく必要がある．
+pub struct EchoSession<ND: network_device::NetworkDevice> {
+    items: Items<ND>,
+    identifier: u16,
+    next_sequence_number: AtomicU16,
+    receiver: tokio::sync::Mutex<mpsc::Receiver<EchoResponse>>,
+}
+
+impl<ND: network_device::NetworkDevice> EchoSession<ND> {
+    /// 使用されていない識別子を割り当てたセッションを作成する
+    pub fn new(items: &Items<ND>) -> Result<Self, TransportProtocolError> {
+        let mut echo_table = items.icmp_echo_table.lock().unwrap();
+
+        let offset = rand::random::<u16>();
+        let identifier = (0..=u16::MAX)
+            .map(|i| offset.wrapping_add(i))
+            .find(|id| !echo_table.contains_key(id))
+            .ok_or(TransportProtocolError::NoAvailablePort)?;
+
+        let (sender, receiver) = mpsc::channel(RECEIVE_QUEUE_LENGTH);
+        echo_table.insert(identifier, sender);
+
+        Ok(Self {
+            items: items.clone(),
+            identifier,
+            next_sequence_number: AtomicU16::new(1),
+            receiver: tokio::sync::Mutex::new(receiver),
+        })
+    }
+
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// `payload_size` バイトのデータを持つエコー要求を送り，応答を待つ．
+    /// 宛先がIPv6アドレスであればICMPv6のエコー要求を送る．
+    /// `timeout` までに応答がなければ `RequestTimedOut` を返す．
+    /// 途中のルータなどからICMPエラーが返ってきた場合は，そのエラーを返す
+    pub async fn ping(
+        &self,
+        dst: IpAddr,
+        payload_size: usize,
+        ttl: u8,
+        timeout: Duration,
+    ) -> Result<EchoReply, TransportProtocolError> {
+        let (response, rtt) = self.request_echo(dst, payload_size, ttl, timeout).await?;
+        let payload_len = match response.result? {
+            MessageData::Echo { raw_data, .. } => raw_data.len(),
+            _ => return Err(TransportProtocolError::CannotParseICMPMessage),
+        };
+
+        Ok(EchoReply {
+            src_addr: response.src_addr,
+            sequence_number: response.sequence_number,
+            ttl: response.ttl,
+            payload_len,
+            rtt,
+        })
+    }
+
+    /// `ping()` と同様にエコー要求を送るが，ICMPエラーもそれを送信したホストと共に返す．
+    /// TTLを1つずつ増やしながら呼び出すことで，経路上のルータを調べられる
+    pub async fn probe(
+        &self,
+        dst: IpAddr,
+        payload_size: usize,
+        ttl: u8,
+        timeout: Duration,
+    ) -> Result<ProbeResponse, TransportProtocolError> {
+        let (response, rtt) = self.request_echo(dst, payload_size, ttl, timeout).await?;
+
+        Ok(ProbeResponse {
+            src_addr: response.src_addr,
+            rtt,
+            error: response.result.err(),
+        })
+    }
+
+    /// タイムスタンプ要求を送り，相手の時刻を問い合わせる
+    pub async fn timestamp(
+        &self,
+        dst: IPv4Addr,
+        timeout: Duration,
+    ) -> Result<TimestampReply, TransportProtocolError> {
+        let identifier = self.identifier;
+        let (response, rtt) = self
+            .request(
+                dst,
+                false,
+                internet::ip::DEFAULT_TTL,
+                timeout,
+                |sequence_number| Message {
+                    ty: MessageType::Timestamp,
+                    data: MessageData::Timestamp {
+                        identifier,
+                        sequence_number,
+                        originate_timestamp: current_timestamp(),
+                        receive_timestamp: 0,
+                        transmit_timestamp: 0,
+                    },
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        match response.result? {
+            MessageData::Timestamp {
+                originate_timestamp,
+                receive_timestamp,
+                transmit_timestamp,
+                ..
+            } => Ok(TimestampReply {
+                src_addr: response.src_addr,
+                originate_timestamp,
+                receive_timestamp,
+                transmit_timestamp,
+                arrival_timestamp: response.received_timestamp,
+                rtt,
+            }),
+            _ => Err(TransportProtocolError::CannotParseICMPMessage),
+        }
+    }
+
+    /// アドレスマスク要求を送り，ネットワークのサブネットマスクを問い合わせる．
+    /// 宛先にはブロードキャストアドレスも指定できる
+    pub async fn address_mask(
+        &self,
+        dst: IPv4Addr,
+        timeout: Duration,
+    ) -> Result<IPv4Addr, TransportProtocolError> {
+        let identifier = self.identifier;
+        let (response, _) = self
+            .request(
+                dst,
+                true,
+                internet::ip::DEFAULT_TTL,
+                timeout,
+                |sequence_number| Message {
+                    ty: MessageType::AddressMaskRequest,
+                    data: MessageData::AddressMask {
+                        identifier,
+                        sequence_number,
+                        address_mask: IPv4Addr::ANY,
+                    },
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        match response.result? {
+            MessageData::AddressMask { address_mask, .. } => Ok(address_mask),
+            _ => Err(TransportProtocolError::CannotParseICMPMessage),
+        }
+    }
+
+    async fn request_echo(
+        &self,
+        dst: IpAddr,
+        payload_size: usize,
+        ttl: u8,
+        timeout: Duration,
+    ) -> Result<(EchoResponse, Duration), TransportProtocolError> {
+        if payload_size > MAX_ECHO_PAYLOAD_LENGTH {
+            return Err(TransportProtocolError::MessageTooLong);
+        }
+
+        let identifier = self.identifier;
+        let raw_data: Vec<u8> = (0..payload_size).map(|i| i as u8).collect();
+        let dst = match dst {
+            IpAddr::V4(dst) => dst,
+            IpAddr::V6(dst) => return self.request_icmpv6_echo(dst, raw_data, ttl, timeout).await,
+        };
+        self.request(dst, false, ttl, timeout, |sequence_number| Message {
+            ty: MessageType::EchoRequest,
+            data: MessageData::Echo {
+                identifier,
+                sequence_number,
+                raw_data,
+            },
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// ICMPv6のエコー要求を送り，対応する応答かエラーと往復時間を返す
+    async fn request_icmpv6_echo(
+        &self,
+        dst: internet::ipv6::IPv6Addr,
+        raw_data: Vec<u8>,
+        hop_limit: u8,
+        timeout: Duration,
+    ) -> Result<(EchoResponse, Duration), TransportProtocolError> {
+        let sequence_number = self.next_sequence_number.fetch_add(1, Ordering::Relaxed);
+        let request = icmpv6::Message {
+            ty: icmpv6::MessageType::EchoRequest,
+            data: icmpv6::MessageData::Echo {
+                identifier: self.identifier,
+                sequence_number,
+                raw_data,
+            },
+            ..Default::default()
+        };
+
+        let rx_result = RxResult {
+            src_ip_addr: dst.into(),
+            ..Default::default()
+        };
+
+        let mut receiver = self.receiver.lock().await;
+        while receiver.try_recv().is_ok() {}
+
+        let sent_at = Instant::now();
+        icmpv6::send_with_hop_limit(&self.items, request, rx_result, hop_limit).await?;
+
+        self.wait_response(&mut receiver, sequence_number, sent_at, timeout)
+            .await
+    }
+
+    /// シーケンス番号を割り当てて `build` で作成した要求を送り，
+    /// 対応する応答かICMPエラーと往復時間を返す
+    async fn request<F>(
+        &self,
+        dst: IPv4Addr,
+        broadcast: bool,
+        ttl: u8,
+        timeout: Duration,
+        build: F,
+    ) -> Result<(EchoResponse, Duration), TransportProtocolError>
+    where
+        F: FnOnce(u16) -> Message,
+    {
+        let directed_broadcast = self
+            .items
+            .opt
+            .ip_addr
+            .to_broadcast(self.items.opt.network_mask);
+        if (dst == IPv4Addr::BLOADCAST || dst == directed_broadcast) && !broadcast {
+            return Err(TransportProtocolError::BroadcastNotPermitted);
+        }
+
+        let sequence_number = self.next_sequence_number.fetch_add(1, Ordering::Relaxed);
+        let request = build(sequence_number);
+
+        let rx_result = RxResult {
+            src_ip_addr: dst.into(),
+            ..Default::default()
+        };
+
+        // 以前の要求に対する遅れた応答を取り除いてから送信する
+        let mut receiver = self.receiver.lock().await;
+        while receiver.try_recv().is_ok() {}
+
+        let sent_at = Instant::now();
+        send_with_ttl(&self.items, request, rx_result, ttl).await?;
+
+        self.wait_response(&mut receiver, sequence_number, sent_at, timeout)
+            .await
+    }
+
+    /// `sequence_number` に対応する応答かICMPエラーを待つ
+    async fn wait_response(
+        &self,
+        receiver: &mut mpsc::Receiver<EchoResponse>,
+        sequence_number: u16,
+        sent_at: Instant,
+        timeout: Duration,
+    ) -> Result<(EchoResponse, Duration), TransportProtocolError> {
+        let deadline = tokio::time::Instant::from_std(sent_at + timeout);
+        loop {
+            let response = tokio::time::timeout_at(deadline, receiver.recv())
+                .await
+                .map_err(|_| TransportProtocolError::RequestTimedOut)?
+                .ok_or(TransportProtocolError::SocketClosed)?;
+            if response.sequence_number != sequence_number {
+                continue;
+            }
+
+            let rtt = response.received_at.saturating_duration_since(sent_at);
+            return Ok((response, rtt));
+        }
+    }
+}
+
+impl<ND: network_device::NetworkDevice> Drop for EchoSession<ND> {
+    fn drop(&mut self) {
+        self.items
+            .icmp_echo_table
+            .lock()
+            .unwrap()
+            .remove(&self.identifier);
+    }
+}
+
+/// エコー要求を1度だけ送り，応答を待つ
+pub async fn ping<ND: network_device::NetworkDevice>(
+    items: &Items<ND>,
+    dst: IpAddr,
+    payload_size: usize,
+    ttl: u8,
+    timeout: Duration,
+) -> Result<EchoReply, TransportProtocolError> {
+    EchoSession::new(items)?
+        .ping(dst, payload_size, ttl, timeout)
+        .await
+}
+
+/// 受信したエコー応答などを，識別子に対応するセッションに配送する
+pub(super) fn on_reply<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg: &Message,
+    rx_result: &RxResult,
+) {
+    let (identifier, sequence_number) = match msg.data {
+        MessageData::Echo {
+            identifier,
+            sequence_number,
+            ..
+        }
+        | MessageData::Timestamp {
+            identifier,
+            sequence_number,
+            ..
+        }
+        | MessageData::AddressMask {
+            identifier,
+            sequence_number,
+            ..
+        } => (identifier, sequence_number),
+        _ => return,
+    };
+    let ttl = IPHeader::new_from_bytes(
+        &rx_result.raw_ip_header,
+        TransportProtocolError::CannotParseICMPMessage,
+    )
+    .map(|hdr| hdr.time_to_live)
+    .unwrap_or(0);
+
+    deliver(
+        table,
+        identifier,
+        EchoResponse {
+            src_addr: rx_result.src_ip_addr,
+            sequence_number,
+            ttl,
+            result: Ok(msg.data.clone()),
+            received_at: Instant::now(),
+            received_timestamp: current_timestamp(),
+        },
+    );
+}
+
+/// 受信したICMPv6のエコー応答を，識別子に対応するセッションに配送する．
+/// セッションからはICMPのエコー応答と同じ形で見える
+pub(crate) fn on_icmpv6_reply<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg: &icmpv6::Message,
+    rx_result: &RxResult,
+) {
+    let (identifier, sequence_number, raw_data) = match &msg.data {
+        icmpv6::MessageData::Echo {
+            identifier,
+            sequence_number,
+            raw_data,
+        } => (*identifier, *sequence_number, raw_data.clone()),
+        _ => return,
+    };
+    // IPv6ヘッダの8オクテット目がホップリミット
+    let hop_limit = rx_result.raw_ip_header.get(7).copied().unwrap_or(0);
+
+    deliver(
+        table,
+        identifier,
+        EchoResponse {
+            src_addr: rx_result.src_ip_addr,
+            sequence_number,
+            ttl: hop_limit,
+            result: Ok(MessageData::Echo {
+                identifier,
+                sequence_number,
+                raw_data,
+            }),
+            received_at: Instant::now(),
+            received_timestamp: current_timestamp(),
+        },
+    );
+}
+
+/// 送信したエコー要求に対するICMPエラーを，識別子に対応するセッションに配送する
+pub(super) fn on_icmp_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    received: &ReceivedError,
+) {
+    // 引用されたICMPヘッダ(種類，コード，チェックサム，識別子，シーケンス番号)
+    let d = &received.quoted_data;
+    let is_request = match received.src_addr {
+        IpAddr::V4(_) => matches!(
+            MessageType::from(d[0]),
+            MessageType::EchoRequest | MessageType::Timestamp | MessageType::AddressMaskRequest
+        ),
+        IpAddr::V6(_) => icmpv6::MessageType::from(d[0]) == icmpv6::MessageType::EchoRequest,
+    };
+    if !is_request {
+        return;
+    }
+
+    deliver(
+        table,
+        u16::from_be_bytes([d[4], d[5]]),
+        EchoResponse {
+            src_addr: received.reporter,
+            sequence_number: u16::from_be_bytes([d[6], d[7]]),
+            ttl: 0,
+            result: Err(received.error),
+            received_at: Instant::now(),
+            received_timestamp: current_timestamp(),
+        },
+    );
+}
+
+fn deliver<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    identifier: u16,
+    response: EchoResponse,
+) {
+    let sender = table
+        .icmp_echo_table
+        .lock()
+        .unwrap()
+        .get(&identifier)
+        .cloned();
+
+    // 受信キューが溢れている場合は破棄する
+    if let Some(sender) = sender {
+        let _ = sender.try_send(response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network_device::FakeDevice, option, transport::TransportProtocol};
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn fake_items() -> Items<FakeDevice> {
+        let opt = option::PeachPSOption {
+            ip_addr: IPv4Addr::from("192.168.11.30"),
+            network_mask: IPv4Addr::from("255.255.255.0"),
+            ..Default::default()
+        };
+        Items::new(opt, FakeDevice::default())
+    }
+
+    fn sent_frames(items: &Items<FakeDevice>) -> usize {
+        items.dev.lock().unwrap().frames.lock().unwrap().len()
+    }
+
+    /// `n` 個目の要求が送信されるまで待つ
+    async fn wait_sent(items: &Items<FakeDevice>, n: usize) {
+        while sent_frames(items) < n {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// `src` からのエコー応答を受信させる
+    fn receive_reply(
+        items: &Items<FakeDevice>,
+        session: &EchoSession<FakeDevice>,
+        src: IPv4Addr,
+        sequence_number: u16,
+    ) {
+        let msg = Message {
+            ty: MessageType::EchoReply,
+            data: MessageData::Echo {
+                identifier: session.identifier(),
+                sequence_number,
+                raw_data: vec![0; 8],
+            },
+            ..Default::default()
+        };
+        let mut raw_ip_header = vec![0x45, 0, 0, 36, 0, 0, 0, 0, 60, 1, 0, 0];
+        raw_ip_header.extend_from_slice(&src.0.to_be_bytes());
+        raw_ip_header.extend_from_slice(&items.opt.ip_addr.0.to_be_bytes());
+        let rx_result = RxResult {
+            src_ip_addr: src.into(),
+            dst_ip_addr: items.opt.ip_addr.into(),
+            raw_ip_header,
+            ..Default::default()
+        };
+        on_reply(items, &msg, &rx_result);
+    }
+
+    /// `reporter` から，`ty` の要求を引用したICMPエラーを受信させる
+    fn receive_error(
+        items: &Items<FakeDevice>,
+        session: &EchoSession<FakeDevice>,
+        reporter: IPv4Addr,
+        ty: MessageType,
+        sequence_number: u16,
+    ) {
+        let mut quoted_data = vec![ty.into(), 0, 0, 0];
+        quoted_data.extend_from_slice(&session.identifier().to_be_bytes());
+        quoted_data.extend_from_slice(&sequence_number.to_be_bytes());
+        on_icmp_error(
+            items,
+            &ReceivedError {
+                reporter: reporter.into(),
+                src_addr: items.opt.ip_addr.into(),
+                dst_addr: IpAddr::from("224.0.0.1"),
+                protocol: TransportProtocol::ICMP,
+                quoted_data,
+                error: TransportProtocolError::TimeToLiveExceeded,
+            },
+        );
+    }
+
+    #[test]
+    fn session_identifier_test() {
+        let items = fake_items();
+        let first = EchoSession::new(&items).unwrap();
+        let second = EchoSession::new(&items).unwrap();
+        assert_ne!(first.identifier(), second.identifier());
+
+        let identifier = first.identifier();
+        assert!(items
+            .icmp_echo_table
+            .lock()
+            .unwrap()
+            .contains_key(&identifier));
+        drop(first);
+        let echo_table = items.icmp_echo_table.lock().unwrap();
+        assert!(!echo_table.contains_key(&identifier));
+        assert!(echo_table.contains_key(&second.identifier()));
+    }
+
+    #[tokio::test]
+    async fn late_reply_is_dropped_test() {
+        let items = fake_items();
+        let session = EchoSession::new(&items).unwrap();
+        let dst = IPv4Addr::from("224.0.0.1");
+        let responder = IPv4Addr::from("192.168.11.1");
+
+        // 1つ目の要求は応答がないままタイムアウトする
+        assert!(matches!(
+            session
+                .ping(dst.into(), 8, 64, Duration::from_millis(10))
+                .await,
+            Err(TransportProtocolError::RequestTimedOut)
+        ));
+
+        // 2つ目の要求を待っている間に，1つ目に対する応答が遅れて届く
+        let (reply, _) = tokio::join!(session.ping(dst.into(), 8, 64, TIMEOUT), async {
+            wait_sent(&items, 2).await;
+            receive_reply(&items, &session, responder, 1);
+            receive_reply(&items, &session, responder, 2);
+        });
+        let reply = reply.unwrap();
+        assert_eq!(2, reply.sequence_number);
+        assert_eq!(IpAddr::from(responder), reply.src_addr);
+        assert_eq!(60, reply.ttl);
+        assert_eq!(8, reply.payload_len);
+    }
+
+    #[tokio::test]
+    async fn request_timed_out_test() {
+        let items = fake_items();
+        let session = EchoSession::new(&items).unwrap();
+
+        let started = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(
+            session
+                .ping(IpAddr::from("224.0.0.1"), 8, 64, timeout)
+                .await,
+            Err(TransportProtocolError::RequestTimedOut)
+        ));
+        assert!(started.elapsed() >= timeout);
+        assert_eq!(1, sent_frames(&items));
+    }
+
+    #[tokio::test]
+    async fn broadcast_is_rejected_test() {
+        let items = fake_items();
+        let session = EchoSession::new(&items).unwrap();
+
+        for dst in ["255.255.255.255", "192.168.11.255"].iter() {
+            assert!(matches!(
+                session.ping(IpAddr::from(*dst), 8, 64, TIMEOUT).await,
+                Err(TransportProtocolError::BroadcastNotPermitted)
+            ));
+        }
+        assert_eq!(0, sent_frames(&items));
+
+        // アドレスマスク要求はブロードキャストできる
+        assert!(matches!(
+            session
+                .address_mask(IPv4Addr::BLOADCAST, Duration::from_millis(10))
+                .await,
+            Err(TransportProtocolError::RequestTimedOut)
+        ));
+        assert_eq!(1, sent_frames(&items));
+    }
+
+    #[tokio::test]
+    async fn icmp_error_is_routed_to_session_test() {
+        let items = fake_items();
+        let session = EchoSession::new(&items).unwrap();
+        let router = IPv4Addr::from("192.168.11.1");
+
+        let (response, _) = tokio::join!(
+            session.probe(IpAddr::from("224.0.0.1"), 8, 1, TIMEOUT),
+            async {
+                wait_sent(&items, 1).await;
+                // 要求以外を引用したエラーは配送しない
+                let other = IPv4Addr::from("192.168.11.2");
+                receive_error(&items, &session, other, MessageType::EchoReply, 1);
+                receive_error(&items, &session, router, MessageType::EchoRequest, 1);
+            }
+        );
+        let response = response.unwrap();
+        assert_eq!(IpAddr::from(router), response.src_addr);
+        assert!(matches!(
+            response.error,
+            Some(TransportProtocolError::TimeToLiveExceeded)
+        ));
+    }
+
+    #[test]
+    fn clock_offset_test() {
+        let mut reply = TimestampReply {
+            src_addr: IpAddr::from("192.168.11.1"),
+            originate_timestamp: 1000,
+            receive_timestamp: 1510,
+            transmit_timestamp: 1512,
+            arrival_timestamp: 1022,
+            rtt: Duration::from_millis(22),
+        };
+        // 片道10msで，相手の時計が500ms進んでいる
+        assert_eq!(500, reply.clock_offset());
+
+        reply.receive_timestamp = 510;
+        reply.transmit_timestamp = 512;
+        assert_eq!(-500, reply.clock_offset());
+
+        assert!((current_timestamp() as u128) < MILLIS_PER_DAY);
+    }
+}
@@ -0,0 +1,428 @@
+use super::{send, Message, MessageData, MessageType, TimeExceededCode, UnreachableCode};
+use crate::{
+    internet::{
+        ip::{IPHeader, IPv4Addr},
+        ipv6::{self, ExtensionHeaderChain, IPv6Header},
+        IpAddr,
+    },
+    network_device,
+    option::PeachPSOption,
+    transport::{icmpv6, TransportProtocol, TransportProtocolError},
+    Items, RxResult,
+};
+
+/// エラーメッセージで引用する，元のデータグラムのデータ部の長さ
+/// See also [RFC792](https://tools.ietf.org/html/rfc792)
+pub const QUOTED_DATA_LENGTH: usize = 8;
+
+/// 送信するICMPエラーの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorMessage {
+    DestinationUnreachable(UnreachableCode),
+    /// DFが立っているパケットを分割する必要がある場合
+    FragmentationNeeded {
+        next_hop_mtu: u16,
+    },
+    TimeExceeded(TimeExceededCode),
+    /// `pointer` は誤りのあったオクテットの，IPヘッダ先頭からの位置
+    ParameterProblem {
+        pointer: u8,
+    },
+}
+
+/// 受信したICMPエラーの内容．
+/// 引用された元のデータグラムから，それを送信したソケットを特定するために使う
+#[derive(Debug, Clone)]
+pub struct ReceivedError {
+    /// エラーを送信したホスト
+    pub reporter: IpAddr,
+    /// 元のデータグラムの送信元．自身のアドレスのいずれか
+    pub src_addr: IpAddr,
+    /// 元のデータグラムの宛先
+    pub dst_addr: IpAddr,
+    /// 元のデータグラムのプロトコル
+    pub protocol: TransportProtocol,
+    /// 引用された，元のデータグラムのデータ部の先頭
+    pub quoted_data: Vec<u8>,
+    /// アプリケーションに通知するエラー
+    pub error: TransportProtocolError,
+}
+
+impl ReceivedError {
+    /// Destination UnreachableとTime Exceededから，元のデータグラムの情報を取り出す．
+    /// 自身が送信したものでなければ `None` を返す
+    pub fn new(msg: &Message, reporter: IPv4Addr, local_addr: IPv4Addr) -> Option<Self> {
+        let (error, original_datagram) = match &msg.data {
+            MessageData::DestinationUnreachable {
+                original_datagram, ..
+            } => (
+                Self::unreachable_error(UnreachableCode::from(msg.code)),
+                original_datagram,
+            ),
+            MessageData::TimeExceeded { original_datagram } => (
+                TransportProtocolError::TimeToLiveExceeded,
+                original_datagram,
+            ),
+            _ => return None,
+        };
+
+        let ip_header = IPHeader::new_from_bytes(
+            original_datagram,
+            TransportProtocolError::CannotParseICMPMessage,
+        )
+        .ok()?;
+        let header_length = ip_header.ihl_bytes_from_vhl() as usize;
+        if ip_header.src_addr != local_addr
+            || original_datagram.len() < header_length + QUOTED_DATA_LENGTH
+        {
+            return None;
+        }
+
+        Some(Self {
+            reporter: reporter.into(),
+            src_addr: ip_header.src_addr.into(),
+            dst_addr: ip_header.dst_addr.into(),
+            protocol: ip_header.protocol,
+            quoted_data: original_datagram[header_length..].to_vec(),
+            error,
+        })
+    }
+
+    /// ICMPv6のDestination Unreachable，Time Exceeded及びParameter Problemから，
+    /// 元のパケットの情報を取り出す．
+    /// 自身のアドレスから送信したものでなければ `None` を返す
+    pub fn from_icmpv6(
+        msg: &icmpv6::Message,
+        reporter: ipv6::IPv6Addr,
+        local_addrs: &ipv6::AddressTable,
+    ) -> Option<Self> {
+        let (error, original_packet) = match &msg.data {
+            icmpv6::MessageData::DestinationUnreachable { original_packet } => (
+                Self::icmpv6_unreachable_error(icmpv6::UnreachableCode::from(msg.code)),
+                original_packet,
+            ),
+            icmpv6::MessageData::TimeExceeded { original_packet } => {
+                (TransportProtocolError::TimeToLiveExceeded, original_packet)
+            }
+            icmpv6::MessageData::ParameterProblem {
+                original_packet, ..
+            } if icmpv6::ParameterProblemCode::from(msg.code)
+                == icmpv6::ParameterProblemCode::UnrecognizedNextHeader =>
+            {
+                (TransportProtocolError::ProtocolUnreachable, original_packet)
+            }
+            _ => return None,
+        };
+
+        let err = TransportProtocolError::CannotParseICMPMessage;
+        let packet_hdr = IPv6Header::new_from_bytes(original_packet, err).ok()?;
+        let payload = &original_packet[IPv6Header::LENGTH.min(original_packet.len())..];
+        let chain = ExtensionHeaderChain::walk(packet_hdr.next_header, payload, err).ok()?;
+        if local_addrs.get(packet_hdr.src_addr).is_none()
+            || payload.len() < chain.payload_offset + QUOTED_DATA_LENGTH
+        {
+            return None;
+        }
+
+        Some(Self {
+            reporter: reporter.into(),
+            src_addr: packet_hdr.src_addr.into(),
+            dst_addr: packet_hdr.dst_addr.into(),
+            protocol: TransportProtocol::from(Into::<u8>::into(chain.upper_layer)),
+            quoted_data: payload[chain.payload_offset..].to_vec(),
+            error,
+        })
+    }
+
+    /// 引用されたデータの先頭にある，送信元と宛先のポート番号
+    pub fn ports(&self) -> (u16, u16) {
+        let d = &self.quoted_data;
+        (
+            u16::from_be_bytes([d[0], d[1]]),
+            u16::from_be_bytes([d[2], d[3]]),
+        )
+    }
+
+    fn unreachable_error(code: UnreachableCode) -> TransportProtocolError {
+        match code {
+            UnreachableCode::NetUnreachable
+            | UnreachableCode::DestinationNetworkUnknown
+            | UnreachableCode::NetworkUnreachableForTos
+            | UnreachableCode::NetworkAdministrativelyProhibited => {
+                TransportProtocolError::NetworkUnreachable
+            }
+            UnreachableCode::ProtocolUnreachable => TransportProtocolError::ProtocolUnreachable,
+            UnreachableCode::PortUnreachable => TransportProtocolError::ConnectionRefused,
+            UnreachableCode::FragmentationNeeded => TransportProtocolError::MessageTooLong,
+            _ => TransportProtocolError::HostUnreachable,
+        }
+    }
+
+    fn icmpv6_unreachable_error(code: icmpv6::UnreachableCode) -> TransportProtocolError {
+        match code {
+            icmpv6::UnreachableCode::NoRouteToDestination => {
+                TransportProtocolError::NetworkUnreachable
+            }
+            icmpv6::UnreachableCode::PortUnreachable => TransportProtocolError::ConnectionRefused,
+            _ => TransportProtocolError::HostUnreachable,
+        }
+    }
+}
+
+/// 受信したパケットに対してICMPエラーを送信する．
+/// `rx_result` には原因となったパケットの受信結果を，`payload` にはそのデータ部を渡す．
+/// エラーを返してはならないパケットの場合は何もしない
+pub async fn tx_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    error: ErrorMessage,
+    rx_result: RxResult,
+    payload: &[u8],
+) -> Result<(), TransportProtocolError> {
+    if is_error_suppressed(&table.opt, &rx_result, payload) {
+        if table.opt.debug {
+            eprintln!("++++++++ suppress icmp error ({:?}) ++++++++", error);
+        }
+        return Ok(());
+    }
+
+    // 元のIPヘッダとデータの先頭64ビットを引用する
+    let mut original_datagram = rx_result.raw_ip_header.clone();
+    original_datagram.extend_from_slice(&payload[..payload.len().min(QUOTED_DATA_LENGTH)]);
+
+    let mut icmp_message: Message = Default::default();
+    match error {
+        ErrorMessage::DestinationUnreachable(code) => {
+            icmp_message.ty = MessageType::DestinationUnreachable;
+            icmp_message.code = code.into();
+            icmp_message.data = MessageData::DestinationUnreachable {
+                next_hop_mtu: 0,
+                original_datagram,
+            };
+        }
+        ErrorMessage::FragmentationNeeded { next_hop_mtu } => {
+            icmp_message.ty = MessageType::DestinationUnreachable;
+            icmp_message.code = UnreachableCode::FragmentationNeeded.into();
+            icmp_message.data = MessageData::DestinationUnreachable {
+                next_hop_mtu,
+                original_datagram,
+            };
+        }
+        ErrorMessage::TimeExceeded(code) => {
+            icmp_message.ty = MessageType::TimeExceeded;
+            icmp_message.code = code.into();
+            icmp_message.data = MessageData::TimeExceeded { original_datagram };
+        }
+        ErrorMessage::ParameterProblem { pointer } => {
+            icmp_message.ty = MessageType::ParameterProblem;
+            icmp_message.data = MessageData::ParameterProblem {
+                pointer,
+                original_datagram,
+            };
+        }
+    }
+
+    send(table, icmp_message, rx_result).await
+}
+
+/// ICMPエラーを返してはならないパケットか
+/// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-3.2.2)
+/// See also [RFC1812](https://tools.ietf.org/html/rfc1812#section-4.3.2.7)
+fn is_error_suppressed(opt: &PeachPSOption, rx_result: &RxResult, payload: &[u8]) -> bool {
+    let ip_header = match IPHeader::new_from_bytes(
+        &rx_result.raw_ip_header,
+        TransportProtocolError::CannotConstructICMPMessage,
+    ) {
+        Ok(hdr) => hdr,
+        Err(_) => return true,
+    };
+
+    // 先頭以外のフラグメント
+    if ip_header.offset_from_flg_offset() != 0 {
+        return true;
+    }
+    // ブロードキャスト・マルチキャスト宛てのパケット
+    if is_broadcast_or_multicast(opt, rx_result.dst_ipv4_addr())
+        || rx_result.dst_mac_addr.is_multicast()
+    {
+        return true;
+    }
+    // 送信元が単一のホストを指さないパケット
+    let src = rx_result.src_ipv4_addr();
+    if src == IPv4Addr::ANY
+        || is_broadcast_or_multicast(opt, src)
+        || src.is_loopback()
+        || src.is_reserved()
+    {
+        return true;
+    }
+    // ICMPエラーに対するエラー
+    if rx_result.tp_type == TransportProtocol::ICMP {
+        return payload.first().is_none_or(|ty| {
+            !matches!(
+                MessageType::from(*ty),
+                MessageType::EchoReply
+                    | MessageType::EchoRequest
+                    | MessageType::Timestamp
+                    | MessageType::TimestampReply
+            )
+        });
+    }
+
+    false
+}
+
+/// ブロードキャストアドレス(リミテッド・ディレクテッド)かマルチキャストアドレスか
+pub fn is_broadcast_or_multicast(opt: &PeachPSOption, addr: IPv4Addr) -> bool {
+    addr == IPv4Addr::BLOADCAST
+        || addr == opt.ip_addr.to_broadcast(opt.network_mask)
+        || addr.is_multicast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{internet::ipv6::IPv6Addr, link::MacAddress};
+
+    fn option() -> PeachPSOption {
+        PeachPSOption {
+            ip_addr: IPv4Addr::from("192.168.11.30"),
+            network_mask: IPv4Addr::from("255.255.255.0"),
+            ..Default::default()
+        }
+    }
+
+    fn rx_result(tp: TransportProtocol, flg_offset: u16) -> RxResult {
+        let hdr = IPHeader {
+            version_ihl: 0x45,
+            flg_offset,
+            time_to_live: 64,
+            protocol: tp,
+            src_addr: IPv4Addr::from("192.168.11.1"),
+            dst_addr: IPv4Addr::from("192.168.11.30"),
+            ..Default::default()
+        };
+
+        RxResult {
+            src_ip_addr: hdr.src_addr.into(),
+            dst_ip_addr: hdr.dst_addr.into(),
+            tp_type: tp,
+            raw_ip_header: hdr
+                .to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+                .unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn suppress_icmp_error_test() {
+        let opt = option();
+        let udp = [0; 8];
+        assert!(!is_error_suppressed(
+            &opt,
+            &rx_result(TransportProtocol::UDP, 0),
+            &udp
+        ));
+
+        // 先頭以外のフラグメント
+        assert!(is_error_suppressed(
+            &opt,
+            &rx_result(TransportProtocol::UDP, 185),
+            &udp
+        ));
+
+        // ブロードキャスト宛て
+        let mut result = rx_result(TransportProtocol::UDP, 0);
+        result.dst_ip_addr = IPv4Addr::from("192.168.11.255").into();
+        assert!(is_error_suppressed(&opt, &result, &udp));
+        let mut result = rx_result(TransportProtocol::UDP, 0);
+        result.dst_mac_addr = MacAddress::BLOADCAST;
+        assert!(is_error_suppressed(&opt, &result, &udp));
+
+        // 送信元がループバックアドレス
+        let mut result = rx_result(TransportProtocol::UDP, 0);
+        result.src_ip_addr = IPv4Addr::from("127.0.0.1").into();
+        assert!(is_error_suppressed(&opt, &result, &udp));
+
+        // ICMPエラーにはエラーを返さないが，問い合わせには返す
+        let icmp = rx_result(TransportProtocol::ICMP, 0);
+        assert!(is_error_suppressed(&opt, &icmp, &[3, 3, 0, 0]));
+        assert!(is_error_suppressed(&opt, &icmp, &[42, 0, 0, 0]));
+        assert!(!is_error_suppressed(&opt, &icmp, &[8, 0, 0, 0]));
+    }
+
+    #[test]
+    fn received_error_test() {
+        let local_addr = IPv4Addr::from("192.168.11.1");
+        let reporter = IPv4Addr::from("192.168.11.30");
+        let mut original_datagram = rx_result(TransportProtocol::UDP, 0).raw_ip_header;
+        original_datagram.extend_from_slice(&[0xc3, 0x50, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+
+        let mut msg = Message {
+            ty: MessageType::DestinationUnreachable,
+            code: UnreachableCode::PortUnreachable.into(),
+            data: MessageData::DestinationUnreachable {
+                next_hop_mtu: 0,
+                original_datagram: original_datagram.clone(),
+            },
+            ..Default::default()
+        };
+        let e = ReceivedError::new(&msg, reporter, local_addr).unwrap();
+        assert!(matches!(e.error, TransportProtocolError::ConnectionRefused));
+        assert_eq!(TransportProtocol::UDP, e.protocol);
+        assert_eq!(IpAddr::from("192.168.11.30"), e.dst_addr);
+        assert_eq!((50000, 53), e.ports());
+
+        // 自身が送信したものでなければ無視する
+        assert!(ReceivedError::new(&msg, reporter, IPv4Addr::from("192.168.11.2")).is_none());
+
+        msg.ty = MessageType::TimeExceeded;
+        msg.code = TimeExceededCode::TimeToLiveExceeded.into();
+        msg.data = MessageData::TimeExceeded { original_datagram };
+        let e = ReceivedError::new(&msg, reporter, local_addr).unwrap();
+        assert!(matches!(
+            e.error,
+            TransportProtocolError::TimeToLiveExceeded
+        ));
+    }
+
+    #[test]
+    fn received_icmpv6_error_test() {
+        let opt = option();
+        let local_addrs = ipv6::AddressTable::new(&opt, std::time::Instant::now());
+        let local_addr = IPv6Addr::link_local_from_mac(opt.dev_addr);
+        let reporter = IPv6Addr::from("fe80::1");
+
+        let packet_hdr = IPv6Header {
+            version_class_flow: (IPv6Header::VERSION6 as u32) << 28,
+            payload_length: 8,
+            next_header: ipv6::NextHeader::UDP,
+            hop_limit: 64,
+            src_addr: local_addr,
+            dst_addr: IPv6Addr::from("fe80::2"),
+        };
+        let mut original_packet = packet_hdr
+            .to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+            .unwrap();
+        original_packet.extend_from_slice(&[0xc3, 0x50, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+
+        let mut msg = icmpv6::Message {
+            ty: icmpv6::MessageType::DestinationUnreachable,
+            code: icmpv6::UnreachableCode::PortUnreachable.into(),
+            data: icmpv6::MessageData::DestinationUnreachable {
+                original_packet: original_packet.clone(),
+            },
+            ..Default::default()
+        };
+        let e = ReceivedError::from_icmpv6(&msg, reporter, &local_addrs).unwrap();
+        assert!(matches!(e.error, TransportProtocolError::ConnectionRefused));
+        assert_eq!(TransportProtocol::UDP, e.protocol);
+        assert_eq!(IpAddr::from(local_addr), e.src_addr);
+        assert_eq!(IpAddr::from("fe80::2"), e.dst_addr);
+        assert_eq!((50000, 53), e.ports());
+
+        // 自身のアドレスから送信したものでなければ無視する
+        original_packet[8..24].copy_from_slice(&IPv6Addr::from("fe80::3").octets());
+        msg.data = icmpv6::MessageData::DestinationUnreachable { original_packet };
+        assert!(ReceivedError::from_icmpv6(&msg, reporter, &local_addrs).is_none());
+    }
+}
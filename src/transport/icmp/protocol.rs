@@ -1,29 +1,44 @@
-use super::{Message, MessageType};
+use std::{sync::atomic::Ordering, time::Instant};
+
+use super::{echo, Message, MessageData, MessageType, ReceivedError};
 use crate::{
     checksum::calculate_checksum_u16,
-    internet::{self},
+    internet::{self, ip::IPv4Addr},
     network_device,
-    transport::{TransportProtocol, TransportProtocolError},
+    transport::{tcp, udp, TransportProtocol, TransportProtocolError},
     Items, RxResult,
 };
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn rx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     rx_result: RxResult,
     buf: &[u8],
 ) -> Result<(Message, Vec<u8>), TransportProtocolError> {
-    let mut msg = Message::new_from_bytes(
-        &buf[..rx_result.message_len],
-        TransportProtocolError::CannotParseICMPMessage,
-    )?;
-    msg.checksum = 0;
-    let msg_buf = msg.to_bytes(TransportProtocolError::CannotParseICMPMessage)?;
+    let raw_message = &buf[..rx_result.message_len.min(buf.len())];
+    table.icmp_stats.in_messages.fetch_add(1, Ordering::Relaxed);
 
-    let _cksum = calculate_checksum_u16(
-        &msg_buf,
-        msg_buf.len() as u16,
-        TransportProtocolError::InvalidChecksum,
-    )?;
+    if let Err(e) = verify_checksum(raw_message) {
+        table.icmp_stats.in_errors.fetch_add(1, Ordering::Relaxed);
+        table
+            .icmp_stats
+            .in_checksum_errors
+            .fetch_add(1, Ordering::Relaxed);
+        if table.opt.debug {
+            eprintln!("++++++++ drop icmp message with invalid checksum ++++++++");
+        }
+        return Err(e);
+    }
+    let msg = match Message::new_from_bytes(
+        raw_message,
+        TransportProtocolError::CannotParseICMPMessage,
+    ) {
+        Ok(msg) => msg,
+        Err(e) => {
+            table.icmp_stats.in_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+    };
 
     if table.opt.debug {
         eprintln!("++++++++ rx icmp message ++++++++");
@@ -32,13 +47,71 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
 
     let (_, rest) = buf.split_at(Message::LENGTH);
 
-    if msg.ty == MessageType::EchoRequest {
-        tx(table, MessageType::EchoReply, &msg, rx_result).await?;
+    match msg.ty {
+        MessageType::EchoRequest => {
+            tx(table, MessageType::EchoReply, &msg, rx_result).await?;
+        }
+        MessageType::Timestamp if table.opt.icmp.timestamp_reply => {
+            tx_timestamp_reply(table, &msg, rx_result).await?;
+        }
+        MessageType::AddressMaskRequest if table.opt.icmp.address_mask_reply => {
+            tx_address_mask_reply(table, &msg, rx_result).await?;
+        }
+        MessageType::EchoReply | MessageType::TimestampReply | MessageType::AddressMaskReply => {
+            echo::on_reply(table, &msg, &rx_result);
+        }
+        _ => {
+            if let Some(received) =
+                ReceivedError::new(&msg, rx_result.src_ipv4_addr(), table.opt.ip_addr)
+            {
+                deliver_error(table, &received);
+            }
+        }
     }
 
     Ok((msg, rest.to_vec()))
 }
 
+/// 受信したICMP/ICMPv6エラーを，原因となったデータグラムを送信したソケットに通知する
+pub(crate) fn deliver_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    received: &ReceivedError,
+) {
+    if table.opt.debug {
+        eprintln!(
+            "++++++++ deliver icmp error ({}) to {} socket ++++++++",
+            received.error, received.protocol
+        );
+    }
+
+    match received.protocol {
+        TransportProtocol::UDP => udp::on_icmp_error(table, received),
+        TransportProtocol::TCP => tcp::on_icmp_error(table, received),
+        TransportProtocol::ICMP | TransportProtocol::ICMPv6 => echo::on_icmp_error(table, received),
+        _ => {}
+    }
+}
+
+/// 受信したメッセージのチェックサムを検証する．
+/// チェックサム領域を含めて計算した結果が0になれば正しい
+/// See also [RFC792](https://tools.ietf.org/html/rfc792)
+fn verify_checksum(raw_message: &[u8]) -> Result<(), TransportProtocolError> {
+    if raw_message.len() < Message::LENGTH {
+        return Err(TransportProtocolError::CannotParseICMPMessage);
+    }
+    if calculate_checksum_u16(
+        raw_message,
+        raw_message.len() as u16,
+        TransportProtocolError::InvalidChecksum,
+    )? != 0
+    {
+        return Err(TransportProtocolError::InvalidChecksum);
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::field_reassign_with_default, clippy::needless_lifetimes)]
 pub async fn tx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     msg_type: MessageType,
@@ -50,6 +123,107 @@ pub async fn tx<'a, ND: network_device::NetworkDevice>(
     icmp_message.code = received_msg.code;
     icmp_message.data = received_msg.data.clone();
 
+    send(table, icmp_message, rx_result).await
+}
+
+/// タイムスタンプ要求に，受信時刻と送信時刻を埋めて応答する
+/// See also [RFC792](https://tools.ietf.org/html/rfc792)
+async fn tx_timestamp_reply<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    received_msg: &Message,
+    rx_result: RxResult,
+) -> Result<(), TransportProtocolError> {
+    let icmp_message = Message {
+        ty: MessageType::TimestampReply,
+        data: match received_msg.data {
+            MessageData::Timestamp {
+                identifier,
+                sequence_number,
+                originate_timestamp,
+                ..
+            } => {
+                let now = echo::current_timestamp();
+                MessageData::Timestamp {
+                    identifier,
+                    sequence_number,
+                    originate_timestamp,
+                    receive_timestamp: now,
+                    transmit_timestamp: now,
+                }
+            }
+            _ => return Err(TransportProtocolError::CannotParseICMPMessage),
+        },
+        ..Default::default()
+    };
+
+    send(table, icmp_message, rx_result).await
+}
+
+/// アドレスマスク要求に，自身のサブネットマスクで応答する．
+/// 送信元が未定のホストにはブロードキャストで返す
+/// See also [RFC950](https://tools.ietf.org/html/rfc950#appendix-I)
+async fn tx_address_mask_reply<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    received_msg: &Message,
+    mut rx_result: RxResult,
+) -> Result<(), TransportProtocolError> {
+    let icmp_message = Message {
+        ty: MessageType::AddressMaskReply,
+        data: match received_msg.data {
+            MessageData::AddressMask {
+                identifier,
+                sequence_number,
+                ..
+            } => MessageData::AddressMask {
+                identifier,
+                sequence_number,
+                address_mask: table.opt.network_mask,
+            },
+            _ => return Err(TransportProtocolError::CannotParseICMPMessage),
+        },
+        ..Default::default()
+    };
+    if rx_result.src_ipv4_addr() == IPv4Addr::ANY {
+        rx_result.src_ip_addr = IPv4Addr::BLOADCAST.into();
+    }
+
+    send(table, icmp_message, rx_result).await
+}
+
+pub(super) async fn send<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    icmp_message: Message,
+    rx_result: RxResult,
+) -> Result<(), TransportProtocolError> {
+    send_with_ttl(table, icmp_message, rx_result, internet::ip::DEFAULT_TTL).await
+}
+
+pub(super) async fn send_with_ttl<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    mut icmp_message: Message,
+    rx_result: RxResult,
+    ttl: u8,
+) -> Result<(), TransportProtocolError> {
+    // 流量制限を超えたメッセージは送信せずに破棄する
+    if !table
+        .icmp_rate_limiter
+        .lock()
+        .unwrap()
+        .allow(icmp_message.ty, Instant::now())
+    {
+        table
+            .icmp_stats
+            .out_rate_limited
+            .fetch_add(1, Ordering::Relaxed);
+        if table.opt.debug {
+            eprintln!(
+                "++++++++ drop icmp message by rate limit ({}) ++++++++",
+                icmp_message.ty
+            );
+        }
+        return Ok(());
+    }
+
     let before_buf = icmp_message.to_bytes(TransportProtocolError::CannotConstructICMPMessage)?;
     let cksum = calculate_checksum_u16(
         &before_buf,
@@ -63,11 +237,16 @@ pub async fn tx<'a, ND: network_device::NetworkDevice>(
         eprintln!("{}", icmp_message);
     }
 
-    match internet::ip::tx(
+    table
+        .icmp_stats
+        .out_messages
+        .fetch_add(1, Ordering::Relaxed);
+    match internet::ip::tx_with_ttl(
         table,
         TransportProtocol::ICMP,
         rx_result,
         icmp_message.to_bytes(TransportProtocolError::CannotConstructICMPMessage)?,
+        ttl,
     )
     .await
     {
@@ -75,3 +254,25 @@ pub async fn tx<'a, ND: network_device::NetworkDevice>(
         Err(e) => Err(TransportProtocolError::IPError { e }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_checksum_test() {
+        // チェックサムが正しいエコー要求
+        let mut raw_message = vec![0x08, 0x00, 0xf7, 0xfa, 0x00, 0x01, 0x00, 0x04];
+        assert!(verify_checksum(&raw_message).is_ok());
+
+        raw_message[7] = 0x05;
+        assert!(matches!(
+            verify_checksum(&raw_message),
+            Err(TransportProtocolError::InvalidChecksum)
+        ));
+        assert!(matches!(
+            verify_checksum(&raw_message[..3]),
+            Err(TransportProtocolError::CannotParseICMPMessage)
+        ));
+    }
+}
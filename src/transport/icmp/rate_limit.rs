@@ -0,0 +1,115 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use super::MessageType;
+
+/// 送信メッセージの流量制限の設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// 1秒あたりに補充するトークン数
+    pub rate: u32,
+    /// バケットに貯められるトークン数の上限．一度に送信できるメッセージ数となる
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// 次のトークンが補充されるまでの時間
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.rate.max(1)
+    }
+}
+
+/// トークンバケット
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_updated: now,
+        }
+    }
+
+    /// 経過時間分のトークンを補充してから，トークンを1つ消費する
+    fn consume(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_updated);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.rate as f64)
+            .min(self.limit.burst as f64);
+        self.last_updated = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// 送信するICMPメッセージの流量を種類毎に制限する．
+/// エコー応答とエラーメッセージでバケットを分けるため，
+/// エコー要求の洪水を受けてもエラーの送信は妨げられない．
+/// ICMPv6では `T` にICMPv6のメッセージタイプを用いる
+/// See also [RFC1812](https://tools.ietf.org/html/rfc1812#section-4.3.2.8)
+#[derive(Debug, Clone)]
+pub struct RateLimiter<T = MessageType> {
+    buckets: BTreeMap<T, TokenBucket>,
+}
+
+impl<T: Ord + Copy> RateLimiter<T> {
+    pub fn new(limits: &BTreeMap<T, RateLimit>) -> Self {
+        let now = Instant::now();
+        Self {
+            buckets: limits
+                .iter()
+                .map(|(ty, limit)| (*ty, TokenBucket::new(*limit, now)))
+                .collect(),
+        }
+    }
+
+    /// 指定した種類のメッセージを送信してよいか．
+    /// 制限が設定されていない種類は常に送信できる
+    pub fn allow(&mut self, ty: T, now: Instant) -> bool {
+        match self.buckets.get_mut(&ty) {
+            Some(bucket) => bucket.consume(now),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_test() {
+        let limit = RateLimit { rate: 10, burst: 2 };
+        let mut limits = BTreeMap::new();
+        limits.insert(MessageType::EchoReply, limit);
+        limits.insert(MessageType::DestinationUnreachable, limit);
+        let mut limiter = RateLimiter::new(&limits);
+        let now = Instant::now();
+
+        // バーストを使い切ると送信できない
+        assert!(limiter.allow(MessageType::EchoReply, now));
+        assert!(limiter.allow(MessageType::EchoReply, now));
+        assert!(!limiter.allow(MessageType::EchoReply, now));
+
+        // 別の種類のバケットは影響を受けない
+        assert!(limiter.allow(MessageType::DestinationUnreachable, now));
+        // 制限のない種類は常に送信できる
+        assert!(limiter.allow(MessageType::TimeExceeded, now));
+
+        // 時間が経てばトークンが補充される
+        let now = now + limit.interval();
+        assert!(limiter.allow(MessageType::EchoReply, now));
+        assert!(!limiter.allow(MessageType::EchoReply, now));
+    }
+}
@@ -1,6 +1,22 @@
-use std::io::Cursor;
+use std::{io::Cursor, sync::atomic::AtomicU64};
 
-use crate::{byteorder_wrapper, transport::TransportHeader};
+use crate::{byteorder_wrapper, internet::ip::IPv4Addr, transport::TransportHeader};
+
+/// ICMPの送受信に関する統計情報
+/// See also [RFC1213](https://tools.ietf.org/html/rfc1213#section-6.5)
+#[derive(Debug, Default)]
+pub struct Statistics {
+    /// 受信したメッセージの数(エラーを含む)
+    pub in_messages: AtomicU64,
+    /// チェックサムの誤りや長さの不足などで破棄したメッセージの数
+    pub in_errors: AtomicU64,
+    /// そのうち，チェックサムの誤りによるもの
+    pub in_checksum_errors: AtomicU64,
+    /// 送信したメッセージの数
+    pub out_messages: AtomicU64,
+    /// 流量制限によって送信しなかったメッセージの数
+    pub out_rate_limited: AtomicU64,
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Message {
@@ -17,9 +33,83 @@ pub enum MessageData {
         sequence_number: u16,
         raw_data: Vec<u8>,
     },
+    DestinationUnreachable {
+        /// Fragmentation Needed(code=4)の場合にのみ使用される
+        next_hop_mtu: u16,
+        /// エラーの原因となったIPヘッダとデータの先頭8バイト
+        original_datagram: Vec<u8>,
+    },
+    TimeExceeded {
+        original_datagram: Vec<u8>,
+    },
+    Redirect {
+        /// 以降の送信に使うべきゲートウェイ
+        gateway: IPv4Addr,
+        original_datagram: Vec<u8>,
+    },
+    ParameterProblem {
+        /// 誤りのあったオクテットの，元のIPヘッダ先頭からの位置
+        pointer: u8,
+        original_datagram: Vec<u8>,
+    },
+    /// Timestamp及びTimestamp Reply．時刻はUTCの0時からのミリ秒
+    /// See also [RFC792](https://tools.ietf.org/html/rfc792)
+    Timestamp {
+        identifier: u16,
+        sequence_number: u16,
+        originate_timestamp: u32,
+        receive_timestamp: u32,
+        transmit_timestamp: u32,
+    },
+    /// Address Mask Request及びAddress Mask Reply
+    /// See also [RFC950](https://tools.ietf.org/html/rfc950#appendix-I)
+    AddressMask {
+        identifier: u16,
+        sequence_number: u16,
+        address_mask: IPv4Addr,
+    },
+    /// 解釈できないメッセージ．ヘッダ以降をそのまま保持する
+    Unknown {
+        ty: u8,
+        code: u8,
+        raw: Vec<u8>,
+    },
     None,
 }
 
+/// Destination Unreachableのコード
+/// See also [RFC1812](https://tools.ietf.org/html/rfc1812#section-5.2.7.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnreachableCode {
+    NetUnreachable,
+    HostUnreachable,
+    ProtocolUnreachable,
+    PortUnreachable,
+    FragmentationNeeded,
+    SourceRouteFailed,
+    DestinationNetworkUnknown,
+    DestinationHostUnknown,
+    SourceHostIsolated,
+    NetworkAdministrativelyProhibited,
+    HostAdministrativelyProhibited,
+    NetworkUnreachableForTos,
+    HostUnreachableForTos,
+    CommunicationAdministrativelyProhibited,
+    HostPrecedenceViolation,
+    PrecedenceCutoffInEffect,
+    Unknown(u8),
+}
+
+/// Time Exceededのコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimeExceededCode {
+    /// 転送中にTTLが0になった
+    TimeToLiveExceeded,
+    /// フラグメントの再構築が時間内に終わらなかった
+    FragmentReassemblyTimeExceeded,
+    Unknown(u8),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MessageType {
     /// エコー応答
@@ -32,6 +122,18 @@ pub enum MessageType {
     EchoRequest,
     /// 時間超過
     TimeExceeded,
+    /// 引数異常
+    ParameterProblem,
+    /// タイムスタンプ要求
+    Timestamp,
+    /// タイムスタンプ応答
+    TimestampReply,
+    /// アドレスマスク要求
+    AddressMaskRequest,
+    /// アドレスマスク応答
+    AddressMaskReply,
+    /// 未対応のタイプ
+    Unknown(u8),
 }
 
 impl Message {
@@ -65,12 +167,70 @@ impl Message {
                     raw_data,
                 }
             }
-            _ => unimplemented!(),
+            MessageType::DestinationUnreachable => {
+                let _unused = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+                let next_hop_mtu = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+
+                MessageData::DestinationUnreachable {
+                    next_hop_mtu,
+                    original_datagram: Self::read_rest(&mut reader),
+                }
+            }
+            MessageType::TimeExceeded => {
+                let _unused = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+
+                MessageData::TimeExceeded {
+                    original_datagram: Self::read_rest(&mut reader),
+                }
+            }
+            MessageType::Redirect => {
+                let gateway = IPv4Addr(byteorder_wrapper::read_u32_as_be(&mut reader, err)?);
+
+                MessageData::Redirect {
+                    gateway,
+                    original_datagram: Self::read_rest(&mut reader),
+                }
+            }
+            MessageType::ParameterProblem => {
+                let pointer = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let _unused = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let _unused = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+
+                MessageData::ParameterProblem {
+                    pointer,
+                    original_datagram: Self::read_rest(&mut reader),
+                }
+            }
+            MessageType::Timestamp | MessageType::TimestampReply => MessageData::Timestamp {
+                identifier: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                sequence_number: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                originate_timestamp: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+                receive_timestamp: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+                transmit_timestamp: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+            },
+            MessageType::AddressMaskRequest | MessageType::AddressMaskReply => {
+                MessageData::AddressMask {
+                    identifier: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                    sequence_number: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                    address_mask: IPv4Addr(byteorder_wrapper::read_u32_as_be(&mut reader, err)?),
+                }
+            }
+            MessageType::Unknown(ty) => MessageData::Unknown {
+                ty,
+                code: message_header.code,
+                raw: Self::read_rest(&mut reader),
+            },
         };
 
         Ok(message_header)
     }
 
+    /// 残りのバイト列をすべて読み出す
+    fn read_rest(reader: &mut Cursor<&[u8]>) -> Vec<u8> {
+        let pos = (reader.position() as usize).min(reader.get_ref().len());
+        reader.get_ref()[pos..].to_vec()
+    }
+
     pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
     where
         E: std::error::Error + Copy,
@@ -91,6 +251,58 @@ impl Message {
                     byteorder_wrapper::write_u8(&mut buf, *byte, err)?;
                 }
             }
+            MessageData::DestinationUnreachable {
+                next_hop_mtu,
+                original_datagram,
+            } => {
+                byteorder_wrapper::write_u16_as_be(&mut buf, 0, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, *next_hop_mtu, err)?;
+                buf.extend_from_slice(original_datagram);
+            }
+            MessageData::TimeExceeded { original_datagram } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, 0, err)?;
+                buf.extend_from_slice(original_datagram);
+            }
+            MessageData::Redirect {
+                gateway,
+                original_datagram,
+            } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, gateway.0, err)?;
+                buf.extend_from_slice(original_datagram);
+            }
+            MessageData::ParameterProblem {
+                pointer,
+                original_datagram,
+            } => {
+                byteorder_wrapper::write_u8(&mut buf, *pointer, err)?;
+                buf.extend_from_slice(&[0; 3]);
+                buf.extend_from_slice(original_datagram);
+            }
+            MessageData::Timestamp {
+                identifier,
+                sequence_number,
+                originate_timestamp,
+                receive_timestamp,
+                transmit_timestamp,
+            } => {
+                byteorder_wrapper::write_u16_as_be(&mut buf, *identifier, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, *sequence_number, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut buf, *originate_timestamp, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut buf, *receive_timestamp, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut buf, *transmit_timestamp, err)?;
+            }
+            MessageData::AddressMask {
+                identifier,
+                sequence_number,
+                address_mask,
+            } => {
+                byteorder_wrapper::write_u16_as_be(&mut buf, *identifier, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, *sequence_number, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut buf, address_mask.0, err)?;
+            }
+            MessageData::Unknown { raw, .. } => {
+                buf.extend_from_slice(raw);
+            }
             MessageData::None => {}
         }
         Ok(buf)
@@ -125,7 +337,54 @@ impl std::fmt::Display for Message {
                 writeln!(f, "Sequence: {}", sequence_number)?;
                 writeln!(f, "Data: {:?}", raw_data)
             }
-            _ => Ok(()),
+            MessageData::DestinationUnreachable {
+                next_hop_mtu,
+                original_datagram,
+            } => {
+                writeln!(f, "Next-Hop MTU: {}", next_hop_mtu)?;
+                writeln!(f, "Original Datagram: {:?}", original_datagram)
+            }
+            MessageData::TimeExceeded { original_datagram } => {
+                writeln!(f, "Original Datagram: {:?}", original_datagram)
+            }
+            MessageData::Redirect {
+                gateway,
+                original_datagram,
+            } => {
+                writeln!(f, "Gateway: {}", gateway)?;
+                writeln!(f, "Original Datagram: {:?}", original_datagram)
+            }
+            MessageData::ParameterProblem {
+                pointer,
+                original_datagram,
+            } => {
+                writeln!(f, "Pointer: {}", pointer)?;
+                writeln!(f, "Original Datagram: {:?}", original_datagram)
+            }
+            MessageData::Timestamp {
+                identifier,
+                sequence_number,
+                originate_timestamp,
+                receive_timestamp,
+                transmit_timestamp,
+            } => {
+                writeln!(f, "Identifier: {}", identifier)?;
+                writeln!(f, "Sequence: {}", sequence_number)?;
+                writeln!(f, "Originate Timestamp: {}", originate_timestamp)?;
+                writeln!(f, "Receive Timestamp: {}", receive_timestamp)?;
+                writeln!(f, "Transmit Timestamp: {}", transmit_timestamp)
+            }
+            MessageData::AddressMask {
+                identifier,
+                sequence_number,
+                address_mask,
+            } => {
+                writeln!(f, "Identifier: {}", identifier)?;
+                writeln!(f, "Sequence: {}", sequence_number)?;
+                writeln!(f, "Address Mask: {}", address_mask)
+            }
+            MessageData::Unknown { raw, .. } => writeln!(f, "Data: {:?}", raw),
+            MessageData::None => Ok(()),
         }
     }
 }
@@ -138,11 +397,35 @@ impl std::fmt::Display for MessageType {
             MessageType::Redirect => "Redirect",
             MessageType::EchoRequest => "Echo Request",
             MessageType::TimeExceeded => "Time Exceeded",
+            MessageType::ParameterProblem => "Parameter Problem",
+            MessageType::Timestamp => "Timestamp",
+            MessageType::TimestampReply => "Timestamp Reply",
+            MessageType::AddressMaskRequest => "Address Mask Request",
+            MessageType::AddressMaskReply => "Address Mask Reply",
+            MessageType::Unknown(v) => return write!(f, "Unknown({})", v),
         };
         write!(f, "{}", type_str)
     }
 }
 
+impl From<&str> for MessageType {
+    fn from(s: &str) -> Self {
+        match s {
+            "EchoReply" => MessageType::EchoReply,
+            "DestinationUnreachable" => MessageType::DestinationUnreachable,
+            "Redirect" => MessageType::Redirect,
+            "EchoRequest" => MessageType::EchoRequest,
+            "TimeExceeded" => MessageType::TimeExceeded,
+            "ParameterProblem" => MessageType::ParameterProblem,
+            "Timestamp" => MessageType::Timestamp,
+            "TimestampReply" => MessageType::TimestampReply,
+            "AddressMaskRequest" => MessageType::AddressMaskRequest,
+            "AddressMaskReply" => MessageType::AddressMaskReply,
+            _ => panic!("unsupported icmp message type => '{}'", s),
+        }
+    }
+}
+
 impl From<u8> for MessageType {
     fn from(v: u8) -> Self {
         match v {
@@ -151,11 +434,17 @@ impl From<u8> for MessageType {
             5 => MessageType::Redirect,
             8 => MessageType::EchoRequest,
             11 => MessageType::TimeExceeded,
-            _ => unimplemented!(),
+            12 => MessageType::ParameterProblem,
+            13 => MessageType::Timestamp,
+            14 => MessageType::TimestampReply,
+            17 => MessageType::AddressMaskRequest,
+            18 => MessageType::AddressMaskReply,
+            _ => MessageType::Unknown(v),
         }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<u8> for MessageType {
     fn into(self) -> u8 {
         match self {
@@ -164,6 +453,80 @@ impl Into<u8> for MessageType {
             MessageType::Redirect => 5,
             MessageType::EchoRequest => 8,
             MessageType::TimeExceeded => 11,
+            MessageType::ParameterProblem => 12,
+            MessageType::Timestamp => 13,
+            MessageType::TimestampReply => 14,
+            MessageType::AddressMaskRequest => 17,
+            MessageType::AddressMaskReply => 18,
+            MessageType::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<u8> for UnreachableCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => UnreachableCode::NetUnreachable,
+            1 => UnreachableCode::HostUnreachable,
+            2 => UnreachableCode::ProtocolUnreachable,
+            3 => UnreachableCode::PortUnreachable,
+            4 => UnreachableCode::FragmentationNeeded,
+            5 => UnreachableCode::SourceRouteFailed,
+            6 => UnreachableCode::DestinationNetworkUnknown,
+            7 => UnreachableCode::DestinationHostUnknown,
+            8 => UnreachableCode::SourceHostIsolated,
+            9 => UnreachableCode::NetworkAdministrativelyProhibited,
+            10 => UnreachableCode::HostAdministrativelyProhibited,
+            11 => UnreachableCode::NetworkUnreachableForTos,
+            12 => UnreachableCode::HostUnreachableForTos,
+            13 => UnreachableCode::CommunicationAdministrativelyProhibited,
+            14 => UnreachableCode::HostPrecedenceViolation,
+            15 => UnreachableCode::PrecedenceCutoffInEffect,
+            _ => UnreachableCode::Unknown(v),
+        }
+    }
+}
+
+impl From<UnreachableCode> for u8 {
+    fn from(val: UnreachableCode) -> Self {
+        match val {
+            UnreachableCode::NetUnreachable => 0,
+            UnreachableCode::HostUnreachable => 1,
+            UnreachableCode::ProtocolUnreachable => 2,
+            UnreachableCode::PortUnreachable => 3,
+            UnreachableCode::FragmentationNeeded => 4,
+            UnreachableCode::SourceRouteFailed => 5,
+            UnreachableCode::DestinationNetworkUnknown => 6,
+            UnreachableCode::DestinationHostUnknown => 7,
+            UnreachableCode::SourceHostIsolated => 8,
+            UnreachableCode::NetworkAdministrativelyProhibited => 9,
+            UnreachableCode::HostAdministrativelyProhibited => 10,
+            UnreachableCode::NetworkUnreachableForTos => 11,
+            UnreachableCode::HostUnreachableForTos => 12,
+            UnreachableCode::CommunicationAdministrativelyProhibited => 13,
+            UnreachableCode::HostPrecedenceViolation => 14,
+            UnreachableCode::PrecedenceCutoffInEffect => 15,
+            UnreachableCode::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<u8> for TimeExceededCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => TimeExceededCode::TimeToLiveExceeded,
+            1 => TimeExceededCode::FragmentReassemblyTimeExceeded,
+            _ => TimeExceededCode::Unknown(v),
+        }
+    }
+}
+
+impl From<TimeExceededCode> for u8 {
+    fn from(val: TimeExceededCode) -> Self {
+        match val {
+            TimeExceededCode::TimeToLiveExceeded => 0,
+            TimeExceededCode::FragmentReassemblyTimeExceeded => 1,
+            TimeExceededCode::Unknown(v) => v,
         }
     }
 }
@@ -189,9 +552,160 @@ mod tests {
             MessageData::Echo {
                 identifier: 1,
                 sequence_number: 5,
-                raw_data: [0; 128],
+                raw_data: Vec::new(),
+            },
+            msg.data
+        );
+    }
+
+    #[test]
+    fn parse_destination_unreachable_test() {
+        let raw_message = [
+            0x03, 0x03, 0xfc, 0xfc, 0x00, 0x00, 0x00, 0x00, 0x45, 0x00, 0x00, 0x1c,
+        ];
+        let result =
+            Message::new_from_bytes(&raw_message, TransportProtocolError::CannotParseICMPMessage);
+        assert!(result.is_ok());
+        let msg = result.unwrap();
+        assert_eq!(MessageType::DestinationUnreachable, msg.ty);
+        assert_eq!(
+            UnreachableCode::PortUnreachable,
+            UnreachableCode::from(msg.code)
+        );
+        assert_eq!(
+            MessageData::DestinationUnreachable {
+                next_hop_mtu: 0,
+                original_datagram: vec![0x45, 0x00, 0x00, 0x1c],
             },
             msg.data
         );
+        assert_eq!(
+            raw_message.to_vec(),
+            msg.to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_error_messages_test() {
+        let original = [0x45, 0x00, 0x00, 0x1c];
+        let messages = [
+            (
+                vec![0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                MessageType::TimeExceeded,
+                MessageData::TimeExceeded {
+                    original_datagram: original.to_vec(),
+                },
+            ),
+            (
+                vec![0x05, 0x01, 0x00, 0x00, 0xc0, 0xa8, 0x0b, 0x01],
+                MessageType::Redirect,
+                MessageData::Redirect {
+                    gateway: IPv4Addr::from("192.168.11.1"),
+                    original_datagram: original.to_vec(),
+                },
+            ),
+            (
+                vec![0x0c, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00],
+                MessageType::ParameterProblem,
+                MessageData::ParameterProblem {
+                    pointer: 8,
+                    original_datagram: original.to_vec(),
+                },
+            ),
+        ];
+
+        for (mut raw_message, ty, data) in messages.iter().cloned() {
+            raw_message.extend_from_slice(&original);
+            let msg = Message::new_from_bytes(
+                &raw_message,
+                TransportProtocolError::CannotParseICMPMessage,
+            )
+            .unwrap();
+            assert_eq!(ty, msg.ty);
+            assert_eq!(data, msg.data);
+            assert_eq!(
+                raw_message,
+                msg.to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn parse_timestamp_test() {
+        let raw_message = [
+            0x0e, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00,
+            0x00, 0x0b, 0x00, 0x00, 0x00, 0x0c,
+        ];
+        let msg =
+            Message::new_from_bytes(&raw_message, TransportProtocolError::CannotParseICMPMessage)
+                .unwrap();
+        assert_eq!(MessageType::TimestampReply, msg.ty);
+        assert_eq!(
+            MessageData::Timestamp {
+                identifier: 1,
+                sequence_number: 2,
+                originate_timestamp: 10,
+                receive_timestamp: 11,
+                transmit_timestamp: 12,
+            },
+            msg.data
+        );
+
+        // 長さが足りない場合はエラーになる
+        assert!(Message::new_from_bytes(
+            &raw_message[..12],
+            TransportProtocolError::CannotParseICMPMessage
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn address_mask_test() {
+        let msg = Message {
+            ty: MessageType::AddressMaskReply,
+            data: MessageData::AddressMask {
+                identifier: 1,
+                sequence_number: 2,
+                address_mask: IPv4Addr::from("255.255.255.0"),
+            },
+            ..Default::default()
+        };
+        let raw_message = msg
+            .to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+            .unwrap();
+        assert_eq!(
+            vec![0x12, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0xff, 0xff, 0xff, 0x00],
+            raw_message
+        );
+        assert_eq!(
+            msg,
+            Message::new_from_bytes(&raw_message, TransportProtocolError::CannotParseICMPMessage)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_unknown_message_test() {
+        let raw_message = [0x2a, 0x07, 0x00, 0x00, 0xde, 0xad];
+        let msg =
+            Message::new_from_bytes(&raw_message, TransportProtocolError::CannotParseICMPMessage)
+                .unwrap();
+        assert_eq!(MessageType::Unknown(42), msg.ty);
+        assert_eq!(
+            MessageData::Unknown {
+                ty: 42,
+                code: 7,
+                raw: vec![0xde, 0xad],
+            },
+            msg.data
+        );
+        assert_eq!(
+            raw_message.to_vec(),
+            msg.to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+                .unwrap()
+        );
+        assert_eq!(UnreachableCode::Unknown(42), UnreachableCode::from(42));
     }
 }
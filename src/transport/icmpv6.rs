@@ -0,0 +1,14 @@
+mod types;
+pub use types::*;
+
+mod protocol;
+pub use protocol::*;
+
+mod error_message;
+pub use error_message::*;
+
+mod ndp;
+pub use ndp::*;
+
+mod neighbor_cache;
+pub use neighbor_cache::*;
@@ -0,0 +1,166 @@
+use super::{
+    send, Message, MessageData, MessageType, ParameterProblemCode, TimeExceededCode,
+    UnreachableCode,
+};
+use crate::{
+    internet::ipv6::IPv6Header,
+    network_device,
+    transport::{TransportProtocol, TransportProtocolError},
+    Items, RxResult,
+};
+
+/// IPv6の最小MTU．エラーメッセージはこれに収まるように元のパケットを引用する
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-5)
+pub const MINIMUM_MTU: usize = 1280;
+
+/// 送信するICMPv6エラーの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorMessage {
+    DestinationUnreachable(UnreachableCode),
+    PacketTooBig {
+        mtu: u32,
+    },
+    TimeExceeded(TimeExceededCode),
+    /// `pointer` は誤りのあったオクテットの，IPv6ヘッダ先頭からの位置
+    ParameterProblem {
+        code: ParameterProblemCode,
+        pointer: u32,
+    },
+}
+
+/// 受信したパケットに対してICMPv6エラーを送信する．
+/// `rx_result` には原因となったパケットの受信結果を，`payload` にはそのデータ部を渡す．
+/// エラーを返してはならないパケットの場合は何もしない
+pub async fn tx_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    error: ErrorMessage,
+    rx_result: RxResult,
+    payload: &[u8],
+) -> Result<(), TransportProtocolError> {
+    if is_error_suppressed(error, &rx_result, payload) {
+        if table.opt.debug {
+            eprintln!("++++++++ suppress icmpv6 error ({:?}) ++++++++", error);
+        }
+        return Ok(());
+    }
+
+    // 最小MTUを超えない範囲で，元のパケットをできるだけ引用する
+    let max_length = MINIMUM_MTU - IPv6Header::LENGTH - (Message::LENGTH + 4);
+    let mut original_packet = rx_result.raw_ip_header.clone();
+    original_packet.extend_from_slice(payload);
+    original_packet.truncate(max_length);
+
+    let mut icmp_message: Message = Default::default();
+    match error {
+        ErrorMessage::DestinationUnreachable(code) => {
+            icmp_message.ty = MessageType::DestinationUnreachable;
+            icmp_message.code = code.into();
+            icmp_message.data = MessageData::DestinationUnreachable { original_packet };
+        }
+        ErrorMessage::PacketTooBig { mtu } => {
+            icmp_message.ty = MessageType::PacketTooBig;
+            icmp_message.data = MessageData::PacketTooBig {
+                mtu,
+                original_packet,
+            };
+        }
+        ErrorMessage::TimeExceeded(code) => {
+            icmp_message.ty = MessageType::TimeExceeded;
+            icmp_message.code = code.into();
+            icmp_message.data = MessageData::TimeExceeded { original_packet };
+        }
+        ErrorMessage::ParameterProblem { code, pointer } => {
+            icmp_message.ty = MessageType::ParameterProblem;
+            icmp_message.code = code.into();
+            icmp_message.data = MessageData::ParameterProblem {
+                pointer,
+                original_packet,
+            };
+        }
+    }
+
+    send(table, icmp_message, rx_result).await
+}
+
+/// ICMPv6エラーを返してはならないパケットか．
+/// Packet Too Bigと未知のオプションによるParameter Problemは，
+/// 経路MTU探索などのためにマルチキャスト宛てでも返す
+/// See also [RFC4443](https://tools.ietf.org/html/rfc4443#section-2.4)
+fn is_error_suppressed(error: ErrorMessage, rx_result: &RxResult, payload: &[u8]) -> bool {
+    // ICMPv6エラーやリダイレクトに対するエラー
+    if rx_result.tp_type == TransportProtocol::ICMPv6 {
+        let is_error_or_redirect = payload.first().is_none_or(|ty| {
+            let ty = MessageType::from(*ty);
+            ty.is_error() || ty == MessageType::Redirect
+        });
+        if is_error_or_redirect {
+            return true;
+        }
+    }
+
+    // マルチキャスト宛てのパケット
+    let allow_multicast = matches!(
+        error,
+        ErrorMessage::PacketTooBig { .. }
+            | ErrorMessage::ParameterProblem {
+                code: ParameterProblemCode::UnrecognizedOption,
+                ..
+            }
+    );
+    if !allow_multicast
+        && (rx_result.dst_ipv6_addr().is_multicast() || rx_result.dst_mac_addr.is_multicast())
+    {
+        return true;
+    }
+
+    // 送信元が単一のノードを指さないパケット
+    let src = rx_result.src_ipv6_addr();
+    src.is_unspecified() || src.is_multicast()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{internet::ipv6::IPv6Addr, link::MacAddress};
+
+    fn rx_result(tp: TransportProtocol) -> RxResult {
+        RxResult {
+            src_ip_addr: IPv6Addr::from("fe80::1").into(),
+            dst_ip_addr: IPv6Addr::from("fe80::a00:27ff:fe3c:a981").into(),
+            tp_type: tp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn suppress_icmpv6_error_test() {
+        let unreachable = ErrorMessage::DestinationUnreachable(UnreachableCode::PortUnreachable);
+        let too_big = ErrorMessage::PacketTooBig { mtu: 1280 };
+        let udp = [0; 8];
+        assert!(!is_error_suppressed(
+            unreachable,
+            &rx_result(TransportProtocol::UDP),
+            &udp
+        ));
+
+        // マルチキャスト宛てにはPacket Too Bigのみ返す
+        let mut result = rx_result(TransportProtocol::UDP);
+        result.dst_ip_addr = IPv6Addr::ALL_NODES.into();
+        assert!(is_error_suppressed(unreachable, &result, &udp));
+        assert!(!is_error_suppressed(too_big, &result, &udp));
+        let mut result = rx_result(TransportProtocol::UDP);
+        result.dst_mac_addr = MacAddress::from("33:33:00:00:00:01");
+        assert!(is_error_suppressed(unreachable, &result, &udp));
+
+        // 送信元が未指定アドレス
+        let mut result = rx_result(TransportProtocol::UDP);
+        result.src_ip_addr = IPv6Addr::UNSPECIFIED.into();
+        assert!(is_error_suppressed(too_big, &result, &udp));
+
+        // ICMPv6エラーにはエラーを返さないが，問い合わせには返す
+        let icmp = rx_result(TransportProtocol::ICMPv6);
+        assert!(is_error_suppressed(unreachable, &icmp, &[1, 4, 0, 0]));
+        assert!(is_error_suppressed(unreachable, &icmp, &[137, 0, 0, 0]));
+        assert!(!is_error_suppressed(too_big, &icmp, &[128, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,550 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::{send_ndp, Message, MessageData, MessageType, NdpOption};
+use crate::{
+    internet::{
+        ipv6::{self, IPv6Addr},
+        InternetProtocolError,
+    },
+    link::{ethernet, MacAddress},
+    network_device,
+    transport::TransportProtocolError,
+    Items, RxResult,
+};
+
+/// 近隣探索のメッセージのホップリミット．
+/// 受信したメッセージがこれ以外であれば，リンク外から送られたものとして破棄する
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-6.1.1)
+pub const NDP_HOP_LIMIT: u8 = 255;
+/// 近隣要請を再送する間隔(RetransTimer)
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-10)
+pub const RETRANS_TIMER: Duration = Duration::from_secs(1);
+/// アドレス解決で送信する近隣要請の数(MAX_MULTICAST_SOLICIT)
+const MAX_MULTICAST_SOLICIT: u32 = 3;
+/// デフォルトルータ一覧に保持するルータ数の上限
+const MAX_DEFAULT_ROUTERS: usize = 16;
+
+/// 近隣要請を送信して，IPv6アドレスに対応するMACアドレスを解決する
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-7.2)
+pub async fn resolve_mac_address<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    target: IPv6Addr,
+) -> Result<MacAddress, InternetProtocolError> {
+    let src = table.ipv6_addrs.lock().unwrap().source_address(target);
+    // 使用可能なアドレスがなければ，重複アドレス検出と区別できない
+    if src.is_unspecified() {
+        return Err(InternetProtocolError::CannotResolveMACAddressFromIPv6 { unknown_ip: target });
+    }
+
+    for _ in 0..MAX_MULTICAST_SOLICIT {
+        if let Err(e) = tx_neighbor_solicitation(table, src, target).await {
+            return Err(match e {
+                TransportProtocolError::IPError { e } => e,
+                _ => InternetProtocolError::CannotConstructPacket,
+            });
+        }
+        tokio::time::sleep(RETRANS_TIMER).await;
+
+        if let Some(dst_mac_addr) = table.lookup_neighbor_cache(&target) {
+            return Ok(dst_mac_addr);
+        }
+    }
+
+    Err(InternetProtocolError::CannotResolveMACAddressFromIPv6 { unknown_ip: target })
+}
+
+/// アドレスを登録し，重複アドレス検出が終わるまで待つ．
+/// 検出は `run` のタイマ処理で進むので，`run` を別タスクで動かしておく必要がある
+/// See also [RFC4862](https://tools.ietf.org/html/rfc4862#section-5.4)
+pub async fn detect_duplicate_address<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    addr: IPv6Addr,
+) -> Result<(), TransportProtocolError> {
+    {
+        let mut addrs = table.ipv6_addrs.lock().unwrap();
+        let (origin, valid_until, preferred_until, dad_counter) = match addrs.get(addr) {
+            Some(entry) => (
+                entry.origin,
+                entry.valid_until,
+                entry.preferred_until,
+                entry.dad_counter,
+            ),
+            None => (ipv6::AddressOrigin::Manual, None, None, 0),
+        };
+        addrs.add(
+            addr,
+            origin,
+            valid_until,
+            preferred_until,
+            dad_counter,
+            Instant::now(),
+        );
+    }
+
+    loop {
+        tokio::time::sleep(RETRANS_TIMER / 10).await;
+
+        let state = table
+            .ipv6_addrs
+            .lock()
+            .unwrap()
+            .get(addr)
+            .map(|entry| entry.state);
+        match state {
+            Some(ipv6::AddressState::Tentative) => {}
+            Some(_) => return Ok(()),
+            None => return Err(TransportProtocolError::DuplicateAddressDetected { addr }),
+        }
+    }
+}
+
+/// 重複アドレス検出の近隣要請を送り，アドレス・近隣キャッシュ・デフォルトルータの有効期限を管理する．
+/// リンクローカルアドレスが使用可能になれば，ルータ要請を送ってルータ広告を促す
+pub async fn on_timer<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    now: Instant,
+) -> Result<(), TransportProtocolError> {
+    table.neighbor_cache.lock().unwrap().on_timer(now);
+    table
+        .default_routers
+        .lock()
+        .unwrap()
+        .retain(|_, expires_at| now < *expires_at);
+    let events = table.ipv6_addrs.lock().unwrap().on_timer(now);
+
+    for addr in events.dad_solicitations {
+        tx_neighbor_solicitation(table, IPv6Addr::UNSPECIFIED, addr).await?;
+    }
+    if table.opt.debug {
+        for addr in events.assigned.iter() {
+            eprintln!("++++++++ ipv6 address {} assigned ++++++++", addr);
+        }
+    }
+    if table.opt.ipv6.autoconf && events.assigned.iter().any(|addr| addr.is_link_local()) {
+        solicit_router(table).await?;
+    }
+
+    Ok(())
+}
+
+/// 全ルータマルチキャストアドレスにルータ要請を送信し，ルータ広告を促す
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-6.3.7)
+pub async fn solicit_router<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+) -> Result<(), TransportProtocolError> {
+    let src = table
+        .ipv6_addrs
+        .lock()
+        .unwrap()
+        .source_address(IPv6Addr::ALL_ROUTERS);
+    // 未指定アドレスから送る場合は送信元リンク層アドレスを含めない
+    let mut options = Vec::new();
+    if !src.is_unspecified() {
+        options.push(NdpOption::SourceLinkLayerAddress(table.opt.dev_addr));
+    }
+
+    let icmp_message = Message {
+        ty: MessageType::RouterSolicitation,
+        data: MessageData::RouterSolicitation { options },
+        ..Default::default()
+    };
+
+    let rx_result = RxResult {
+        src_ip_addr: IPv6Addr::ALL_ROUTERS.into(),
+        dst_ip_addr: src.into(),
+        ..Default::default()
+    };
+
+    send_ndp(
+        table,
+        icmp_message,
+        rx_result,
+        ethernet::ipv6_multicast_mac_address(IPv6Addr::ALL_ROUTERS),
+    )
+    .await
+}
+
+pub(super) async fn rx_ndp<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg: &Message,
+    rx_result: &RxResult,
+) -> Result<(), TransportProtocolError> {
+    // 自身が送信したフレームが折り返されてきた場合
+    if rx_result.src_mac_addr == table.opt.dev_addr {
+        return Ok(());
+    }
+    if !is_valid_ndp_message(msg, rx_result) {
+        if table.opt.debug {
+            eprintln!("++++++++ drop invalid ndp message ({}) ++++++++", msg.ty);
+        }
+        return Ok(());
+    }
+
+    match &msg.data {
+        MessageData::NeighborSolicitation { target, .. } => {
+            on_neighbor_solicitation(table, msg, *target, rx_result).await
+        }
+        MessageData::NeighborAdvertisement {
+            solicited_flag,
+            override_flag,
+            target,
+            ..
+        } => {
+            on_neighbor_advertisement(table, msg, *target, *solicited_flag, *override_flag);
+            Ok(())
+        }
+        MessageData::RouterAdvertisement {
+            router_lifetime, ..
+        } => {
+            on_router_advertisement(table, msg, *router_lifetime, rx_result);
+            Ok(())
+        }
+        // ホストはルータ要請を無視し，リダイレクトは未対応
+        _ => Ok(()),
+    }
+}
+
+/// 受信した近隣探索メッセージを検証する
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-7.1)
+fn is_valid_ndp_message(msg: &Message, rx_result: &RxResult) -> bool {
+    let hop_limit = rx_result.raw_ip_header.get(7).copied().unwrap_or_default();
+    if hop_limit != NDP_HOP_LIMIT || msg.code != 0 {
+        return false;
+    }
+
+    match &msg.data {
+        MessageData::NeighborSolicitation { target, .. } => {
+            // 重複アドレス検出の近隣要請は要請ノードマルチキャスト宛てで，送信元リンク層アドレスを含まない
+            let is_dad = rx_result.src_ipv6_addr().is_unspecified();
+            !target.is_multicast()
+                && (!is_dad
+                    || (rx_result.dst_ipv6_addr() == target.solicited_node()
+                        && msg.source_link_layer_address().is_none()))
+        }
+        MessageData::NeighborAdvertisement {
+            solicited_flag,
+            target,
+            ..
+        } => {
+            // マルチキャスト宛ての近隣広告は要請への応答ではない
+            let is_multicast = rx_result.dst_ipv6_addr().is_multicast();
+            !(target.is_multicast() || is_multicast && *solicited_flag)
+        }
+        MessageData::RouterAdvertisement { .. } => rx_result.src_ipv6_addr().is_link_local(),
+        _ => true,
+    }
+}
+
+/// 自身のアドレスに対する近隣要請であれば，近隣広告で応答する
+async fn on_neighbor_solicitation<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg: &Message,
+    target: IPv6Addr,
+    rx_result: &RxResult,
+) -> Result<(), TransportProtocolError> {
+    let src = rx_result.src_ipv6_addr();
+
+    // 検出中のアドレスに対して他のノードも重複アドレス検出を行っている
+    if table.ipv6_addrs.lock().unwrap().is_tentative(target) {
+        if src.is_unspecified() {
+            on_duplicate_address(table, target);
+        }
+        return Ok(());
+    }
+    if !table.ipv6_addrs.lock().unwrap().is_for_me(target) {
+        return Ok(());
+    }
+
+    if let Some(mac_addr) = msg.source_link_layer_address() {
+        if !src.is_unspecified() {
+            table
+                .neighbor_cache
+                .lock()
+                .unwrap()
+                .update_stale(src, mac_addr, Instant::now());
+        }
+    }
+
+    let icmp_message = Message {
+        ty: MessageType::NeighborAdvertisement,
+        data: MessageData::NeighborAdvertisement {
+            router_flag: false,
+            solicited_flag: !src.is_unspecified(),
+            override_flag: true,
+            target,
+            options: vec![NdpOption::TargetLinkLayerAddress(table.opt.dev_addr)],
+        },
+        ..Default::default()
+    };
+
+    // 重複アドレス検出への応答は全ノードマルチキャストアドレスに送る
+    let mut reply = RxResult {
+        dst_ip_addr: target.into(),
+        ..Default::default()
+    };
+    let dst_mac_addr = if src.is_unspecified() {
+        reply.src_ip_addr = IPv6Addr::ALL_NODES.into();
+        ethernet::ipv6_multicast_mac_address(IPv6Addr::ALL_NODES)
+    } else {
+        reply.src_ip_addr = src.into();
+        rx_result.src_mac_addr
+    };
+
+    send_ndp(table, icmp_message, reply, dst_mac_addr).await
+}
+
+/// 近隣キャッシュを更新する
+fn on_neighbor_advertisement<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg: &Message,
+    target: IPv6Addr,
+    solicited_flag: bool,
+    override_flag: bool,
+) {
+    if table.ipv6_addrs.lock().unwrap().is_tentative(target) {
+        on_duplicate_address(table, target);
+        return;
+    }
+
+    let mac_addr = match msg.target_link_layer_address() {
+        Some(mac_addr) => mac_addr,
+        None => return,
+    };
+    table.neighbor_cache.lock().unwrap().on_advertisement(
+        target,
+        mac_addr,
+        solicited_flag,
+        override_flag,
+        Instant::now(),
+    );
+}
+
+/// デフォルトルータの一覧を更新し，プレフィックス情報からアドレスを自動設定する
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-6.3.4)
+fn on_router_advertisement<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg: &Message,
+    router_lifetime: u16,
+    rx_result: &RxResult,
+) {
+    let router = rx_result.src_ipv6_addr();
+    let now = Instant::now();
+    if let Some(mac_addr) = msg.source_link_layer_address() {
+        table
+            .neighbor_cache
+            .lock()
+            .unwrap()
+            .update_stale(router, mac_addr, now);
+    }
+
+    update_default_routers(
+        &mut table.default_routers.lock().unwrap(),
+        router,
+        router_lifetime,
+        now,
+    );
+
+    if !table.opt.ipv6.autoconf {
+        return;
+    }
+    for option in msg.ndp_options() {
+        if let NdpOption::PrefixInformation(info) = option {
+            let generated = table
+                .ipv6_addrs
+                .lock()
+                .unwrap()
+                .on_prefix_information(info, now);
+            if let (Some(addr), true) = (generated, table.opt.debug) {
+                eprintln!("++++++++ ipv6 address {} generated ++++++++", addr);
+            }
+        }
+    }
+}
+
+/// デフォルトルータ一覧を更新する．
+/// 上限に達している場合は，有効期限の切れたものか最も早く切れるものを取り除いてから加える
+fn update_default_routers(
+    default_routers: &mut HashMap<IPv6Addr, Instant>,
+    router: IPv6Addr,
+    router_lifetime: u16,
+    now: Instant,
+) {
+    if router_lifetime == 0 {
+        default_routers.remove(&router);
+        return;
+    }
+
+    if !default_routers.contains_key(&router) && default_routers.len() >= MAX_DEFAULT_ROUTERS {
+        default_routers.retain(|_, expires_at| now < *expires_at);
+        if default_routers.len() >= MAX_DEFAULT_ROUTERS {
+            let victim = default_routers
+                .iter()
+                .min_by_key(|(_, expires_at)| **expires_at)
+                .map(|(router, _)| *router);
+            if let Some(victim) = victim {
+                default_routers.remove(&victim);
+            }
+        }
+    }
+    default_routers.insert(router, now + Duration::from_secs(router_lifetime as u64));
+}
+
+/// 重複アドレス検出中のアドレスが重複していた．
+/// 安定プライバシーアドレスであれば生成し直したアドレスで検出をやり直す
+fn on_duplicate_address<ND: network_device::NetworkDevice>(table: &Items<ND>, addr: IPv6Addr) {
+    let regenerated = table
+        .ipv6_addrs
+        .lock()
+        .unwrap()
+        .on_duplicate_address(addr, Instant::now());
+
+    if table.opt.debug {
+        eprintln!("++++++++ duplicate address {} detected ++++++++", addr);
+        if let Some(addr) = regenerated {
+            eprintln!("++++++++ ipv6 address {} generated ++++++++", addr);
+        }
+    }
+}
+
+/// 要請ノードマルチキャストアドレスに近隣要請を送信する．
+/// 送信元が未指定アドレスの場合(重複アドレス検出)は送信元リンク層アドレスを含めない
+async fn tx_neighbor_solicitation<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    src: IPv6Addr,
+    target: IPv6Addr,
+) -> Result<(), TransportProtocolError> {
+    let mut options = Vec::new();
+    if !src.is_unspecified() {
+        options.push(NdpOption::SourceLinkLayerAddress(table.opt.dev_addr));
+    }
+
+    let icmp_message = Message {
+        ty: MessageType::NeighborSolicitation,
+        data: MessageData::NeighborSolicitation { target, options },
+        ..Default::default()
+    };
+
+    let rx_result = RxResult {
+        src_ip_addr: target.solicited_node().into(),
+        dst_ip_addr: src.into(),
+        ..Default::default()
+    };
+
+    send_ndp(
+        table,
+        icmp_message,
+        rx_result,
+        ethernet::ipv6_multicast_mac_address(target.solicited_node()),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internet::ipv6::IPv6Header;
+
+    fn ns_rx_result(src: IPv6Addr, dst: IPv6Addr, hop_limit: u8) -> RxResult {
+        let hdr = IPv6Header {
+            version_class_flow: 0x6000_0000,
+            payload_length: 24,
+            next_header: ipv6::NextHeader::ICMPv6,
+            hop_limit,
+            src_addr: src,
+            dst_addr: dst,
+        };
+
+        RxResult {
+            src_ip_addr: src.into(),
+            dst_ip_addr: dst.into(),
+            raw_ip_header: hdr
+                .to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+                .unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn neighbor_solicitation(target: IPv6Addr, options: Vec<NdpOption>) -> Message {
+        Message {
+            ty: MessageType::NeighborSolicitation,
+            data: MessageData::NeighborSolicitation { target, options },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_ndp_message_test() {
+        let target = IPv6Addr::from("fe80::a00:27ff:fe3c:a981");
+        let src = IPv6Addr::from("fe80::1");
+        let slla = vec![NdpOption::SourceLinkLayerAddress(MacAddress::from(
+            "00:15:5d:22:1e:ff",
+        ))];
+
+        let msg = neighbor_solicitation(target, slla.clone());
+        assert!(is_valid_ndp_message(
+            &msg,
+            &ns_rx_result(src, target.solicited_node(), 255)
+        ));
+        // ルータを越えてきたメッセージ
+        assert!(!is_valid_ndp_message(
+            &msg,
+            &ns_rx_result(src, target.solicited_node(), 254)
+        ));
+
+        // 重複アドレス検出は送信元リンク層アドレスを含まない
+        let dad = ns_rx_result(IPv6Addr::UNSPECIFIED, target.solicited_node(), 255);
+        assert!(!is_valid_ndp_message(&msg, &dad));
+        assert!(is_valid_ndp_message(
+            &neighbor_solicitation(target, Vec::new()),
+            &dad
+        ));
+
+        // マルチキャストアドレスは解決の対象にならない
+        assert!(!is_valid_ndp_message(
+            &neighbor_solicitation(IPv6Addr::ALL_NODES, slla),
+            &ns_rx_result(src, target.solicited_node(), 255)
+        ));
+
+        // マルチキャスト宛ての近隣広告は要請への応答ではない
+        let na = Message {
+            ty: MessageType::NeighborAdvertisement,
+            data: MessageData::NeighborAdvertisement {
+                router_flag: false,
+                solicited_flag: true,
+                override_flag: true,
+                target: src,
+                options: Vec::new(),
+            },
+            ..Default::default()
+        };
+        assert!(!is_valid_ndp_message(
+            &na,
+            &ns_rx_result(src, IPv6Addr::ALL_NODES, 255)
+        ));
+        assert!(is_valid_ndp_message(&na, &ns_rx_result(src, target, 255)));
+    }
+
+    #[test]
+    fn default_router_limit_test() {
+        let now = Instant::now();
+        let mut default_routers = HashMap::new();
+        for i in 0..MAX_DEFAULT_ROUTERS as u128 {
+            let router = IPv6Addr(IPv6Addr::from("fe80::100").0 + i);
+            update_default_routers(&mut default_routers, router, 1800 + i as u16, now);
+        }
+
+        // 上限に達していれば，最も早く期限が切れるルータと入れ替える
+        let router = IPv6Addr::from("fe80::1");
+        update_default_routers(&mut default_routers, router, 1800, now);
+        assert_eq!(MAX_DEFAULT_ROUTERS, default_routers.len());
+        assert!(default_routers.contains_key(&router));
+        assert!(!default_routers.contains_key(&IPv6Addr::from("fe80::100")));
+
+        // ルータの有効期限が0であれば取り除く
+        update_default_routers(&mut default_routers, router, 0, now);
+        assert!(!default_routers.contains_key(&router));
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{internet::ipv6::IPv6Addr, link::MacAddress};
+
+/// 到達確認を得てから，近隣が到達可能とみなす時間(REACHABLE_TIME)
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-10)
+pub const REACHABLE_TIME: Duration = Duration::from_secs(30);
+/// 近隣キャッシュに保持するエントリ数の上限
+const MAX_NEIGHBORS: usize = 256;
+
+/// 近隣キャッシュエントリの状態．
+/// 到達不能検知のプローブは行わないため，INCOMPLETE/DELAY/PROBEは持たない
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-7.3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborState {
+    Reachable,
+    Stale,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NeighborEntry {
+    pub mac_addr: MacAddress,
+    pub state: NeighborState,
+    pub updated_at: Instant,
+}
+
+/// IPv6アドレスとMACアドレスの対応を保持する近隣キャッシュ．
+/// 上限に達した場合は，STALEなエントリから古い順に削除する
+#[derive(Debug, Clone, Default)]
+pub struct NeighborCache {
+    entries: HashMap<IPv6Addr, NeighborEntry>,
+}
+
+impl NeighborCache {
+    pub fn get(&self, addr: &IPv6Addr) -> Option<MacAddress> {
+        self.entries.get(addr).map(|entry| entry.mac_addr)
+    }
+
+    pub fn entry(&self, addr: &IPv6Addr) -> Option<&NeighborEntry> {
+        self.entries.get(addr)
+    }
+
+    pub fn contains(&self, addr: &IPv6Addr) -> bool {
+        self.entries.contains_key(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 近隣要請やルータ広告のリンク層アドレスオプションでエントリを更新する．
+    /// MACアドレスが変わらない場合は状態を維持する
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-7.2.3)
+    pub fn update_stale(&mut self, addr: IPv6Addr, mac_addr: MacAddress, now: Instant) {
+        if self.get(&addr) == Some(mac_addr) {
+            return;
+        }
+        self.insert(addr, mac_addr, NeighborState::Stale, now);
+    }
+
+    /// 近隣広告を受信した際にエントリを更新する
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-7.2.5)
+    pub fn on_advertisement(
+        &mut self,
+        addr: IPv6Addr,
+        mac_addr: MacAddress,
+        solicited_flag: bool,
+        override_flag: bool,
+        now: Instant,
+    ) {
+        let update = match self.entries.get_mut(&addr) {
+            Some(entry) if !override_flag && entry.mac_addr != mac_addr => {
+                if entry.state == NeighborState::Reachable {
+                    entry.state = NeighborState::Stale;
+                }
+                false
+            }
+            Some(entry) => solicited_flag || entry.mac_addr != mac_addr,
+            None => solicited_flag,
+        };
+        if !update {
+            return;
+        }
+
+        let state = if solicited_flag {
+            NeighborState::Reachable
+        } else {
+            NeighborState::Stale
+        };
+        self.insert(addr, mac_addr, state, now);
+    }
+
+    /// REACHABLE_TIMEを過ぎたREACHABLEなエントリをSTALEにする
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-7.3.3)
+    pub fn on_timer(&mut self, now: Instant) {
+        for entry in self.entries.values_mut() {
+            if entry.state == NeighborState::Reachable
+                && now.saturating_duration_since(entry.updated_at) >= REACHABLE_TIME
+            {
+                entry.state = NeighborState::Stale;
+                entry.updated_at = now;
+            }
+        }
+    }
+
+    fn insert(&mut self, addr: IPv6Addr, mac_addr: MacAddress, state: NeighborState, now: Instant) {
+        if self.entries.len() >= MAX_NEIGHBORS && !self.entries.contains_key(&addr) {
+            self.evict();
+        }
+        self.entries.insert(
+            addr,
+            NeighborEntry {
+                mac_addr,
+                state,
+                updated_at: now,
+            },
+        );
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| (entry.state == NeighborState::Reachable, entry.updated_at))
+            .map(|(addr, _)| *addr);
+        if let Some(addr) = oldest {
+            self.entries.remove(&addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(last: u8) -> MacAddress {
+        MacAddress::from([0x00, 0x15, 0x5d, 0x22, 0x1e, last])
+    }
+
+    #[test]
+    fn neighbor_state_test() {
+        let mut cache = NeighborCache::default();
+        let addr = IPv6Addr::from("fe80::1");
+        let now = Instant::now();
+
+        // 要請されていない広告では新たなエントリを作らない
+        cache.on_advertisement(addr, mac(1), false, true, now);
+        assert!(cache.is_empty());
+
+        cache.on_advertisement(addr, mac(1), true, true, now);
+        assert_eq!(NeighborState::Reachable, cache.entry(&addr).unwrap().state);
+
+        // REACHABLE_TIMEを過ぎるとSTALEになる
+        cache.on_timer(now + REACHABLE_TIME / 2);
+        assert_eq!(NeighborState::Reachable, cache.entry(&addr).unwrap().state);
+        cache.on_timer(now + REACHABLE_TIME);
+        assert_eq!(NeighborState::Stale, cache.entry(&addr).unwrap().state);
+
+        // Overrideフラグのない広告ではMACアドレスを書き換えない
+        let now = now + REACHABLE_TIME;
+        cache.on_advertisement(addr, mac(1), true, false, now);
+        assert_eq!(NeighborState::Reachable, cache.entry(&addr).unwrap().state);
+        cache.on_advertisement(addr, mac(2), true, false, now);
+        assert_eq!(Some(mac(1)), cache.get(&addr));
+        assert_eq!(NeighborState::Stale, cache.entry(&addr).unwrap().state);
+
+        // リンク層アドレスオプションで異なるMACアドレスを得た場合はSTALEで更新する
+        cache.update_stale(addr, mac(2), now);
+        assert_eq!(Some(mac(2)), cache.get(&addr));
+        assert_eq!(NeighborState::Stale, cache.entry(&addr).unwrap().state);
+    }
+
+    #[test]
+    fn neighbor_cache_limit_test() {
+        let mut cache = NeighborCache::default();
+        let now = Instant::now();
+        let addr = |i: usize| IPv6Addr::from(format!("fe80::{:x}", i + 1).as_str());
+
+        for i in 0..MAX_NEIGHBORS {
+            cache.on_advertisement(addr(i), mac(i as u8), true, true, now);
+        }
+        // 最も古いSTALEなエントリから追い出される
+        cache.update_stale(addr(0), mac(0xff), now + Duration::from_secs(2));
+        cache.update_stale(addr(1), mac(0xff), now + Duration::from_secs(1));
+        cache.update_stale(addr(MAX_NEIGHBORS), mac(0), now + Duration::from_secs(3));
+
+        assert_eq!(MAX_NEIGHBORS, cache.len());
+        assert!(cache.contains(&addr(0)));
+        assert!(!cache.contains(&addr(1)));
+        assert!(cache.contains(&addr(MAX_NEIGHBORS)));
+    }
+}
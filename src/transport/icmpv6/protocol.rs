@@ -0,0 +1,315 @@
+use std::{sync::atomic::Ordering, time::Instant};
+
+use super::{ndp, Message, MessageData, MessageType, MINIMUM_MTU};
+use crate::{
+    checksum::calculate_checksum_with_ipv6_pseudo_header,
+    internet::{
+        ipv6,
+        ipv6::{IPv6Addr, IPv6Header},
+    },
+    link::{self, MacAddress},
+    network_device,
+    transport::{
+        icmp::{self, ReceivedError},
+        TransportProtocol, TransportProtocolError,
+    },
+    Items, RxResult,
+};
+
+pub async fn rx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    rx_result: RxResult,
+    buf: &[u8],
+) -> Result<(Message, Vec<u8>), TransportProtocolError> {
+    let raw_message = &buf[..rx_result.message_len.min(buf.len())];
+    table
+        .icmpv6_stats
+        .in_messages
+        .fetch_add(1, Ordering::Relaxed);
+
+    if let Err(e) = verify_checksum(
+        rx_result.src_ipv6_addr(),
+        rx_result.dst_ipv6_addr(),
+        raw_message,
+    ) {
+        table.icmpv6_stats.in_errors.fetch_add(1, Ordering::Relaxed);
+        table
+            .icmpv6_stats
+            .in_checksum_errors
+            .fetch_add(1, Ordering::Relaxed);
+        if table.opt.debug {
+            eprintln!("++++++++ drop icmpv6 message with invalid checksum ++++++++");
+        }
+        return Err(e);
+    }
+    let msg = match Message::new_from_bytes(
+        raw_message,
+        TransportProtocolError::CannotParseICMPMessage,
+    ) {
+        Ok(msg) => msg,
+        Err(e) => {
+            table.icmpv6_stats.in_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+    };
+
+    if table.opt.debug {
+        eprintln!("++++++++ rx icmpv6 message ++++++++");
+        eprintln!("{}", msg);
+    }
+
+    let (_, rest) = buf.split_at(Message::LENGTH);
+
+    match msg.ty {
+        MessageType::EchoRequest => {
+            tx(table, MessageType::EchoReply, &msg, rx_result).await?;
+        }
+        ty if ty.is_ndp() => {
+            ndp::rx_ndp(table, &msg, &rx_result).await?;
+        }
+        MessageType::EchoReply => icmp::on_icmpv6_reply(table, &msg, &rx_result),
+        MessageType::PacketTooBig => on_packet_too_big(table, &msg),
+        ty if ty.is_error() => {
+            let received = ReceivedError::from_icmpv6(
+                &msg,
+                rx_result.src_ipv6_addr(),
+                &table.ipv6_addrs.lock().unwrap(),
+            );
+            if let Some(received) = received {
+                icmp::deliver_error(table, &received);
+            }
+        }
+        _ => {}
+    }
+
+    Ok((msg, rest.to_vec()))
+}
+
+/// 引用されたパケットの宛先について，経路MTUを記録する．
+/// IPv6の最小MTUを下回る値は最小MTUとして扱う
+/// See also [RFC8201](https://tools.ietf.org/html/rfc8201#section-4)
+fn on_packet_too_big<ND: network_device::NetworkDevice>(table: &Items<ND>, msg: &Message) {
+    let (mtu, original_packet) = match &msg.data {
+        MessageData::PacketTooBig {
+            mtu,
+            original_packet,
+        } => (*mtu as usize, original_packet),
+        _ => return,
+    };
+    let original_hdr = match IPv6Header::new_from_bytes(
+        original_packet,
+        TransportProtocolError::CannotParseICMPMessage,
+    ) {
+        Ok(hdr) => hdr,
+        Err(_) => return,
+    };
+    // 自身が送信したパケットでなければ無視する
+    if !table
+        .ipv6_addrs
+        .lock()
+        .unwrap()
+        .unicast_addrs()
+        .contains(&original_hdr.src_addr)
+    {
+        return;
+    }
+
+    let mtu = mtu.clamp(MINIMUM_MTU, link::MTU);
+    if table.opt.debug {
+        eprintln!(
+            "++++++++ path mtu to {} is {} ++++++++",
+            original_hdr.dst_addr, mtu
+        );
+    }
+    table
+        .path_mtu_cache
+        .lock()
+        .unwrap()
+        .insert(original_hdr.dst_addr, (mtu, Instant::now()));
+}
+
+/// 受信したメッセージのチェックサムを検証する．
+/// 疑似ヘッダとチェックサム領域を含めて計算した結果が0になれば正しい
+/// See also [RFC4443](https://tools.ietf.org/html/rfc4443#section-2.3)
+fn verify_checksum(
+    src_addr: IPv6Addr,
+    dst_addr: IPv6Addr,
+    raw_message: &[u8],
+) -> Result<(), TransportProtocolError> {
+    if raw_message.len() < Message::LENGTH {
+        return Err(TransportProtocolError::CannotParseICMPMessage);
+    }
+    if calculate_checksum_with_ipv6_pseudo_header(
+        src_addr,
+        dst_addr,
+        TransportProtocol::ICMPv6,
+        raw_message,
+        TransportProtocolError::InvalidChecksum,
+    )? != 0
+    {
+        return Err(TransportProtocolError::InvalidChecksum);
+    }
+
+    Ok(())
+}
+
+pub async fn tx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    msg_type: MessageType,
+    received_msg: &Message,
+    rx_result: RxResult,
+) -> Result<(), TransportProtocolError> {
+    let icmp_message = Message {
+        ty: msg_type,
+        code: received_msg.code,
+        data: received_msg.data.clone(),
+        ..Default::default()
+    };
+
+    send(table, icmp_message, rx_result).await
+}
+
+/// `rx_result.src_ipv6_addr` に送信する．
+/// マルチキャスト宛てに受信したパケットへの応答では，送信元アドレスを選び直す
+pub(super) async fn send<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    icmp_message: Message,
+    rx_result: RxResult,
+) -> Result<(), TransportProtocolError> {
+    send_with_hop_limit(table, icmp_message, rx_result, ipv6::DEFAULT_HOP_LIMIT).await
+}
+
+/// ホップリミットを指定して送信する
+pub(crate) async fn send_with_hop_limit<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    icmp_message: Message,
+    mut rx_result: RxResult,
+    hop_limit: u8,
+) -> Result<(), TransportProtocolError> {
+    // 流量制限を超えたメッセージは送信せずに破棄する
+    if !table
+        .icmpv6_rate_limiter
+        .lock()
+        .unwrap()
+        .allow(icmp_message.ty, Instant::now())
+    {
+        table
+            .icmpv6_stats
+            .out_rate_limited
+            .fetch_add(1, Ordering::Relaxed);
+        if table.opt.debug {
+            eprintln!(
+                "++++++++ drop icmpv6 message by rate limit ({}) ++++++++",
+                icmp_message.ty
+            );
+        }
+        return Ok(());
+    }
+
+    if rx_result.dst_ipv6_addr().is_multicast() || rx_result.dst_ipv6_addr().is_unspecified() {
+        rx_result.dst_ip_addr = table
+            .ipv6_addrs
+            .lock()
+            .unwrap()
+            .source_address(rx_result.src_ipv6_addr())
+            .into();
+    }
+    let raw_message = finalize(table, icmp_message, &rx_result)?;
+
+    match ipv6::tx_with_hop_limit(
+        table,
+        TransportProtocol::ICMPv6,
+        rx_result,
+        raw_message,
+        hop_limit,
+    )
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(TransportProtocolError::IPError { e }),
+    }
+}
+
+/// 近隣探索のメッセージを，宛先のリンク層アドレスを指定して送信する．
+/// ホップリミットは常に255とし，アドレス解決は行わない
+pub(super) async fn send_ndp<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    icmp_message: Message,
+    rx_result: RxResult,
+    dst_mac_addr: MacAddress,
+) -> Result<(), TransportProtocolError> {
+    let raw_message = finalize(table, icmp_message, &rx_result)?;
+
+    match ipv6::tx_core(
+        table,
+        TransportProtocol::ICMPv6,
+        rx_result,
+        raw_message,
+        ndp::NDP_HOP_LIMIT,
+        dst_mac_addr,
+    )
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(TransportProtocolError::IPError { e }),
+    }
+}
+
+/// チェックサムを埋めてバイト列にする
+fn finalize<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    mut icmp_message: Message,
+    rx_result: &RxResult,
+) -> Result<Vec<u8>, TransportProtocolError> {
+    let before_buf = icmp_message.to_bytes(TransportProtocolError::CannotConstructICMPMessage)?;
+    icmp_message.checksum = calculate_checksum_with_ipv6_pseudo_header(
+        rx_result.dst_ipv6_addr(),
+        rx_result.src_ipv6_addr(),
+        TransportProtocol::ICMPv6,
+        &before_buf,
+        TransportProtocolError::InvalidChecksum,
+    )?;
+
+    if table.opt.debug {
+        eprintln!("++++++++ tx icmpv6 message ++++++++");
+        eprintln!("{}", icmp_message);
+    }
+
+    table
+        .icmpv6_stats
+        .out_messages
+        .fetch_add(1, Ordering::Relaxed);
+    icmp_message.to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_checksum_test() {
+        let src = IPv6Addr::from("fe80::1");
+        let dst = IPv6Addr::from("fe80::a00:27ff:fe3c:a981");
+        let mut raw_message = vec![128, 0, 0, 0, 0x00, 0x01, 0x00, 0x04];
+        let cksum = calculate_checksum_with_ipv6_pseudo_header(
+            src,
+            dst,
+            TransportProtocol::ICMPv6,
+            &raw_message,
+            TransportProtocolError::InvalidChecksum,
+        )
+        .unwrap();
+        raw_message[2..4].copy_from_slice(&cksum.to_be_bytes());
+        assert!(verify_checksum(src, dst, &raw_message).is_ok());
+
+        // 疑似ヘッダのアドレスが異なれば誤りとなる
+        assert!(matches!(
+            verify_checksum(src, IPv6Addr::ALL_NODES, &raw_message),
+            Err(TransportProtocolError::InvalidChecksum)
+        ));
+        assert!(matches!(
+            verify_checksum(src, dst, &raw_message[..3]),
+            Err(TransportProtocolError::CannotParseICMPMessage)
+        ));
+    }
+}
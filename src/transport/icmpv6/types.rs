@@ -0,0 +1,838 @@
+use std::io::Cursor;
+
+use crate::{
+    byteorder_wrapper, internet::ipv6::IPv6Addr, link::MacAddress, transport::TransportHeader,
+};
+
+/// ICMPv6メッセージ
+/// See also [RFC4443](https://tools.ietf.org/html/rfc4443#section-2.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub ty: MessageType,
+    pub code: u8,
+    pub checksum: u16,
+    pub data: MessageData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageData {
+    Echo {
+        identifier: u16,
+        sequence_number: u16,
+        raw_data: Vec<u8>,
+    },
+    /// エラーの原因となったパケットは，最小MTUに収まる範囲で引用される
+    DestinationUnreachable {
+        original_packet: Vec<u8>,
+    },
+    PacketTooBig {
+        /// 次のリンクのMTU
+        mtu: u32,
+        original_packet: Vec<u8>,
+    },
+    TimeExceeded {
+        original_packet: Vec<u8>,
+    },
+    ParameterProblem {
+        /// 誤りのあったオクテットの，元のIPv6ヘッダ先頭からの位置
+        pointer: u32,
+        original_packet: Vec<u8>,
+    },
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-4.1)
+    RouterSolicitation {
+        options: Vec<NdpOption>,
+    },
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-4.2)
+    RouterAdvertisement {
+        /// ホストが送信するパケットに使うべきホップリミット．0は未指定
+        cur_hop_limit: u8,
+        /// DHCPv6でアドレスを取得すべきか
+        managed_flag: bool,
+        /// DHCPv6でアドレス以外の設定を取得すべきか
+        other_flag: bool,
+        /// デフォルトルータとして扱ってよい秒数．0であればデフォルトルータではない
+        router_lifetime: u16,
+        /// 到達性を確認してから到達可能とみなすミリ秒．0は未指定
+        reachable_time: u32,
+        /// 近隣要請を再送する間隔のミリ秒．0は未指定
+        retrans_timer: u32,
+        options: Vec<NdpOption>,
+    },
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-4.3)
+    NeighborSolicitation {
+        /// アドレス解決の対象
+        target: IPv6Addr,
+        options: Vec<NdpOption>,
+    },
+    /// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-4.4)
+    NeighborAdvertisement {
+        /// 送信者がルータであるか
+        router_flag: bool,
+        /// 近隣要請に対する応答か
+        solicited_flag: bool,
+        /// 既存の近隣キャッシュのエントリを上書きすべきか
+        override_flag: bool,
+        target: IPv6Addr,
+        options: Vec<NdpOption>,
+    },
+    /// 解釈できないメッセージ．ヘッダ以降をそのまま保持する
+    Unknown {
+        raw: Vec<u8>,
+    },
+    None,
+}
+
+/// 近隣探索メッセージのオプション
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-4.6)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NdpOption {
+    SourceLinkLayerAddress(MacAddress),
+    TargetLinkLayerAddress(MacAddress),
+    PrefixInformation(PrefixInformation),
+    /// リンクのMTU
+    Mtu(u32),
+    /// 未対応のオプション．種類と長さを除いた内容を保持する
+    Unknown {
+        ty: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// ルータ広告で通知されるプレフィックス
+/// See also [RFC4861](https://tools.ietf.org/html/rfc4861#section-4.6.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefixInformation {
+    pub prefix_length: u8,
+    /// このプレフィックスを持つアドレスがリンク上にあるか
+    pub on_link: bool,
+    /// アドレスの自動設定に使ってよいか
+    pub autonomous: bool,
+    /// 有効期間の秒数．0xffffffffは無期限
+    pub valid_lifetime: u32,
+    /// 推奨期間の秒数．0xffffffffは無期限
+    pub preferred_lifetime: u32,
+    pub prefix: IPv6Addr,
+}
+
+/// Destination Unreachableのコード
+/// See also [RFC4443](https://tools.ietf.org/html/rfc4443#section-3.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnreachableCode {
+    NoRouteToDestination,
+    AdministrativelyProhibited,
+    BeyondScopeOfSourceAddress,
+    AddressUnreachable,
+    PortUnreachable,
+    SourceAddressFailedPolicy,
+    RejectRouteToDestination,
+    Unknown(u8),
+}
+
+/// Time Exceededのコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TimeExceededCode {
+    /// 転送中にホップリミットが0になった
+    HopLimitExceeded,
+    /// フラグメントの再構築が時間内に終わらなかった
+    FragmentReassemblyTimeExceeded,
+    Unknown(u8),
+}
+
+/// Parameter Problemのコード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ParameterProblemCode {
+    ErroneousHeaderField,
+    UnrecognizedNextHeader,
+    UnrecognizedOption,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageType {
+    /// 目的地到達不能
+    DestinationUnreachable,
+    /// パケット過大
+    PacketTooBig,
+    /// 時間超過
+    TimeExceeded,
+    /// 引数異常
+    ParameterProblem,
+    /// エコー要求
+    EchoRequest,
+    /// エコー応答
+    EchoReply,
+    /// ルータ要請
+    RouterSolicitation,
+    /// ルータ広告
+    RouterAdvertisement,
+    /// 近隣要請
+    NeighborSolicitation,
+    /// 近隣広告
+    NeighborAdvertisement,
+    /// 経路変更
+    Redirect,
+    /// 未対応のタイプ
+    Unknown(u8),
+}
+
+impl Message {
+    pub const LENGTH: usize = 4;
+
+    pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut reader = Cursor::new(buf);
+        let mut message_header: Message = Default::default();
+
+        message_header.ty = MessageType::from(byteorder_wrapper::read_u8(&mut reader, err)?);
+        message_header.code = byteorder_wrapper::read_u8(&mut reader, err)?;
+        message_header.checksum = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+
+        message_header.data = match message_header.ty {
+            MessageType::EchoRequest | MessageType::EchoReply => MessageData::Echo {
+                identifier: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                sequence_number: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                raw_data: Self::read_rest(&mut reader),
+            },
+            MessageType::DestinationUnreachable => {
+                let _unused = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                MessageData::DestinationUnreachable {
+                    original_packet: Self::read_rest(&mut reader),
+                }
+            }
+            MessageType::PacketTooBig => MessageData::PacketTooBig {
+                mtu: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+                original_packet: Self::read_rest(&mut reader),
+            },
+            MessageType::TimeExceeded => {
+                let _unused = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                MessageData::TimeExceeded {
+                    original_packet: Self::read_rest(&mut reader),
+                }
+            }
+            MessageType::ParameterProblem => MessageData::ParameterProblem {
+                pointer: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+                original_packet: Self::read_rest(&mut reader),
+            },
+            MessageType::RouterSolicitation => {
+                let _reserved = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                MessageData::RouterSolicitation {
+                    options: NdpOption::parse_all(&Self::read_rest(&mut reader), err)?,
+                }
+            }
+            MessageType::RouterAdvertisement => {
+                let cur_hop_limit = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let flags = byteorder_wrapper::read_u8(&mut reader, err)?;
+                MessageData::RouterAdvertisement {
+                    cur_hop_limit,
+                    managed_flag: flags & 0x80 != 0,
+                    other_flag: flags & 0x40 != 0,
+                    router_lifetime: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+                    reachable_time: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+                    retrans_timer: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+                    options: NdpOption::parse_all(&Self::read_rest(&mut reader), err)?,
+                }
+            }
+            MessageType::NeighborSolicitation => {
+                let _reserved = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                MessageData::NeighborSolicitation {
+                    target: IPv6Addr::from_cursor(&mut reader, err)?,
+                    options: NdpOption::parse_all(&Self::read_rest(&mut reader), err)?,
+                }
+            }
+            MessageType::NeighborAdvertisement => {
+                let flags = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                MessageData::NeighborAdvertisement {
+                    router_flag: flags & 0x8000_0000 != 0,
+                    solicited_flag: flags & 0x4000_0000 != 0,
+                    override_flag: flags & 0x2000_0000 != 0,
+                    target: IPv6Addr::from_cursor(&mut reader, err)?,
+                    options: NdpOption::parse_all(&Self::read_rest(&mut reader), err)?,
+                }
+            }
+            MessageType::Redirect | MessageType::Unknown(_) => MessageData::Unknown {
+                raw: Self::read_rest(&mut reader),
+            },
+        };
+
+        Ok(message_header)
+    }
+
+    /// 残りのバイト列をすべて読み出す
+    fn read_rest(reader: &mut Cursor<&[u8]>) -> Vec<u8> {
+        let pos = (reader.position() as usize).min(reader.get_ref().len());
+        reader.get_ref()[pos..].to_vec()
+    }
+
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut buf = Vec::<u8>::new();
+        byteorder_wrapper::write_u8(&mut buf, self.ty.into(), err)?;
+        byteorder_wrapper::write_u8(&mut buf, self.code, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.checksum, err)?;
+        match &self.data {
+            MessageData::Echo {
+                identifier,
+                sequence_number,
+                raw_data,
+            } => {
+                byteorder_wrapper::write_u16_as_be(&mut buf, *identifier, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, *sequence_number, err)?;
+                buf.extend_from_slice(raw_data);
+            }
+            MessageData::DestinationUnreachable { original_packet }
+            | MessageData::TimeExceeded { original_packet } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, 0, err)?;
+                buf.extend_from_slice(original_packet);
+            }
+            MessageData::PacketTooBig {
+                mtu,
+                original_packet,
+            } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, *mtu, err)?;
+                buf.extend_from_slice(original_packet);
+            }
+            MessageData::ParameterProblem {
+                pointer,
+                original_packet,
+            } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, *pointer, err)?;
+                buf.extend_from_slice(original_packet);
+            }
+            MessageData::RouterSolicitation { options } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, 0, err)?;
+                NdpOption::write_all(&mut buf, options, err)?;
+            }
+            MessageData::RouterAdvertisement {
+                cur_hop_limit,
+                managed_flag,
+                other_flag,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                options,
+            } => {
+                let flags = (*managed_flag as u8) << 7 | (*other_flag as u8) << 6;
+                byteorder_wrapper::write_u8(&mut buf, *cur_hop_limit, err)?;
+                byteorder_wrapper::write_u8(&mut buf, flags, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, *router_lifetime, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut buf, *reachable_time, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut buf, *retrans_timer, err)?;
+                NdpOption::write_all(&mut buf, options, err)?;
+            }
+            MessageData::NeighborSolicitation { target, options } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, 0, err)?;
+                buf.extend_from_slice(&target.octets());
+                NdpOption::write_all(&mut buf, options, err)?;
+            }
+            MessageData::NeighborAdvertisement {
+                router_flag,
+                solicited_flag,
+                override_flag,
+                target,
+                options,
+            } => {
+                let flags = (*router_flag as u32) << 31
+                    | (*solicited_flag as u32) << 30
+                    | (*override_flag as u32) << 29;
+                byteorder_wrapper::write_u32_as_be(&mut buf, flags, err)?;
+                buf.extend_from_slice(&target.octets());
+                NdpOption::write_all(&mut buf, options, err)?;
+            }
+            MessageData::Unknown { raw } => {
+                buf.extend_from_slice(raw);
+            }
+            MessageData::None => {}
+        }
+        Ok(buf)
+    }
+
+    /// 近隣探索メッセージに含まれるオプション
+    pub fn ndp_options(&self) -> &[NdpOption] {
+        match &self.data {
+            MessageData::RouterSolicitation { options }
+            | MessageData::RouterAdvertisement { options, .. }
+            | MessageData::NeighborSolicitation { options, .. }
+            | MessageData::NeighborAdvertisement { options, .. } => options,
+            _ => &[],
+        }
+    }
+
+    /// 送信元リンク層アドレスオプションの値
+    pub fn source_link_layer_address(&self) -> Option<MacAddress> {
+        self.ndp_options().iter().find_map(|opt| match opt {
+            NdpOption::SourceLinkLayerAddress(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// 対象リンク層アドレスオプションの値
+    pub fn target_link_layer_address(&self) -> Option<MacAddress> {
+        self.ndp_options().iter().find_map(|opt| match opt {
+            NdpOption::TargetLinkLayerAddress(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+}
+
+impl NdpOption {
+    /// 種類と長さの領域の大きさ
+    const HEADER_LENGTH: usize = 2;
+    /// 長さ領域の単位
+    const UNIT: usize = 8;
+
+    /// 連続したオプションを解釈する．
+    /// 長さが0のオプションを含むメッセージは破棄しなければならないのでエラーとする
+    pub fn parse_all<E>(buf: &[u8], err: E) -> Result<Vec<Self>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut options = Vec::new();
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if rest.len() < Self::HEADER_LENGTH {
+                return Err(err);
+            }
+            let length = rest[1] as usize * Self::UNIT;
+            if length == 0 || rest.len() < length {
+                return Err(err);
+            }
+            let (raw_option, next) = rest.split_at(length);
+            options.push(Self::new_from_bytes(raw_option, err)?);
+            rest = next;
+        }
+        Ok(options)
+    }
+
+    fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let ty = buf[0];
+        let mut reader = Cursor::new(&buf[Self::HEADER_LENGTH..]);
+        Ok(match ty {
+            1 => NdpOption::SourceLinkLayerAddress(MacAddress::from_cursor(&mut reader, err)?),
+            2 => NdpOption::TargetLinkLayerAddress(MacAddress::from_cursor(&mut reader, err)?),
+            3 => {
+                let prefix_length = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let flags = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let valid_lifetime = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                let preferred_lifetime = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                let _reserved = byteorder_wrapper::read_u32_as_be(&mut reader, err)?;
+                NdpOption::PrefixInformation(PrefixInformation {
+                    prefix_length,
+                    on_link: flags & 0x80 != 0,
+                    autonomous: flags & 0x40 != 0,
+                    valid_lifetime,
+                    preferred_lifetime,
+                    prefix: IPv6Addr::from_cursor(&mut reader, err)?,
+                })
+            }
+            5 => {
+                let _reserved = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+                NdpOption::Mtu(byteorder_wrapper::read_u32_as_be(&mut reader, err)?)
+            }
+            _ => NdpOption::Unknown {
+                ty,
+                data: buf[Self::HEADER_LENGTH..].to_vec(),
+            },
+        })
+    }
+
+    fn write_all<E>(buf: &mut Vec<u8>, options: &[Self], err: E) -> Result<(), E>
+    where
+        E: std::error::Error + Copy,
+    {
+        for option in options.iter() {
+            option.write(buf, err)?;
+        }
+        Ok(())
+    }
+
+    fn write<E>(&self, buf: &mut Vec<u8>, err: E) -> Result<(), E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut data = Vec::new();
+        let ty = match self {
+            NdpOption::SourceLinkLayerAddress(addr) => {
+                data.append(&mut addr.to_bytes(err)?);
+                1
+            }
+            NdpOption::TargetLinkLayerAddress(addr) => {
+                data.append(&mut addr.to_bytes(err)?);
+                2
+            }
+            NdpOption::PrefixInformation(info) => {
+                let flags = (info.on_link as u8) << 7 | (info.autonomous as u8) << 6;
+                byteorder_wrapper::write_u8(&mut data, info.prefix_length, err)?;
+                byteorder_wrapper::write_u8(&mut data, flags, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut data, info.valid_lifetime, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut data, info.preferred_lifetime, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut data, 0, err)?;
+                data.extend_from_slice(&info.prefix.octets());
+                3
+            }
+            NdpOption::Mtu(mtu) => {
+                byteorder_wrapper::write_u16_as_be(&mut data, 0, err)?;
+                byteorder_wrapper::write_u32_as_be(&mut data, *mtu, err)?;
+                5
+            }
+            NdpOption::Unknown { ty, data: raw } => {
+                data.extend_from_slice(raw);
+                *ty
+            }
+        };
+
+        // 8オクテット単位になるよう0で埋める
+        let length = (Self::HEADER_LENGTH + data.len()).div_ceil(Self::UNIT);
+        data.resize(length * Self::UNIT - Self::HEADER_LENGTH, 0);
+
+        byteorder_wrapper::write_u8(buf, ty, err)?;
+        byteorder_wrapper::write_u8(buf, length as u8, err)?;
+        buf.append(&mut data);
+        Ok(())
+    }
+}
+
+impl MessageType {
+    /// エラーメッセージかどうか．タイプ値の最上位ビットが0であればエラー
+    /// See also [RFC4443](https://tools.ietf.org/html/rfc4443#section-2.1)
+    pub fn is_error(&self) -> bool {
+        Into::<u8>::into(*self) & 0x80 == 0
+    }
+
+    /// 近隣探索プロトコルのメッセージかどうか
+    pub fn is_ndp(&self) -> bool {
+        matches!(
+            self,
+            MessageType::RouterSolicitation
+                | MessageType::RouterAdvertisement
+                | MessageType::NeighborSolicitation
+                | MessageType::NeighborAdvertisement
+                | MessageType::Redirect
+        )
+    }
+}
+
+impl TransportHeader for Message {}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self {
+            ty: MessageType::EchoReply,
+            code: 0,
+            checksum: 0,
+            data: MessageData::None,
+        }
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Type: {}", self.ty)?;
+        writeln!(f, "Code: {}", self.code)?;
+        writeln!(f, "Checksum: {}", self.checksum)?;
+        match &self.data {
+            MessageData::Echo {
+                identifier,
+                sequence_number,
+                raw_data,
+            } => {
+                writeln!(f, "Identifier: {}", identifier)?;
+                writeln!(f, "Sequence: {}", sequence_number)?;
+                writeln!(f, "Data: {:?}", raw_data)
+            }
+            MessageData::DestinationUnreachable { original_packet }
+            | MessageData::TimeExceeded { original_packet } => {
+                writeln!(f, "Original Packet: {:?}", original_packet)
+            }
+            MessageData::PacketTooBig {
+                mtu,
+                original_packet,
+            } => {
+                writeln!(f, "MTU: {}", mtu)?;
+                writeln!(f, "Original Packet: {:?}", original_packet)
+            }
+            MessageData::ParameterProblem {
+                pointer,
+                original_packet,
+            } => {
+                writeln!(f, "Pointer: {}", pointer)?;
+                writeln!(f, "Original Packet: {:?}", original_packet)
+            }
+            MessageData::RouterSolicitation { options } => {
+                writeln!(f, "Options: {:?}", options)
+            }
+            MessageData::RouterAdvertisement {
+                cur_hop_limit,
+                managed_flag,
+                other_flag,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                options,
+            } => {
+                writeln!(f, "Cur Hop Limit: {}", cur_hop_limit)?;
+                writeln!(f, "Managed: {}", managed_flag)?;
+                writeln!(f, "Other: {}", other_flag)?;
+                writeln!(f, "Router Lifetime: {}", router_lifetime)?;
+                writeln!(f, "Reachable Time: {}", reachable_time)?;
+                writeln!(f, "Retrans Timer: {}", retrans_timer)?;
+                writeln!(f, "Options: {:?}", options)
+            }
+            MessageData::NeighborSolicitation { target, options } => {
+                writeln!(f, "Target: {}", target)?;
+                writeln!(f, "Options: {:?}", options)
+            }
+            MessageData::NeighborAdvertisement {
+                router_flag,
+                solicited_flag,
+                override_flag,
+                target,
+                options,
+            } => {
+                writeln!(f, "Router: {}", router_flag)?;
+                writeln!(f, "Solicited: {}", solicited_flag)?;
+                writeln!(f, "Override: {}", override_flag)?;
+                writeln!(f, "Target: {}", target)?;
+                writeln!(f, "Options: {:?}", options)
+            }
+            MessageData::Unknown { raw } => writeln!(f, "Data: {:?}", raw),
+            MessageData::None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for MessageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let type_str = match self {
+            MessageType::DestinationUnreachable => "Destination Unreachable",
+            MessageType::PacketTooBig => "Packet Too Big",
+            MessageType::TimeExceeded => "Time Exceeded",
+            MessageType::ParameterProblem => "Parameter Problem",
+            MessageType::EchoRequest => "Echo Request",
+            MessageType::EchoReply => "Echo Reply",
+            MessageType::RouterSolicitation => "Router Solicitation",
+            MessageType::RouterAdvertisement => "Router Advertisement",
+            MessageType::NeighborSolicitation => "Neighbor Solicitation",
+            MessageType::NeighborAdvertisement => "Neighbor Advertisement",
+            MessageType::Redirect => "Redirect",
+            MessageType::Unknown(v) => return write!(f, "Unknown({})", v),
+        };
+        write!(f, "{}", type_str)
+    }
+}
+
+impl From<&str> for MessageType {
+    fn from(s: &str) -> Self {
+        match s {
+            "DestinationUnreachable" => MessageType::DestinationUnreachable,
+            "PacketTooBig" => MessageType::PacketTooBig,
+            "TimeExceeded" => MessageType::TimeExceeded,
+            "ParameterProblem" => MessageType::ParameterProblem,
+            "EchoRequest" => MessageType::EchoRequest,
+            "EchoReply" => MessageType::EchoReply,
+            "RouterSolicitation" => MessageType::RouterSolicitation,
+            "RouterAdvertisement" => MessageType::RouterAdvertisement,
+            "NeighborSolicitation" => MessageType::NeighborSolicitation,
+            "NeighborAdvertisement" => MessageType::NeighborAdvertisement,
+            "Redirect" => MessageType::Redirect,
+            _ => panic!("unsupported icmpv6 message type => '{}'", s),
+        }
+    }
+}
+
+impl From<u8> for MessageType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => MessageType::DestinationUnreachable,
+            2 => MessageType::PacketTooBig,
+            3 => MessageType::TimeExceeded,
+            4 => MessageType::ParameterProblem,
+            128 => MessageType::EchoRequest,
+            129 => MessageType::EchoReply,
+            133 => MessageType::RouterSolicitation,
+            134 => MessageType::RouterAdvertisement,
+            135 => MessageType::NeighborSolicitation,
+            136 => MessageType::NeighborAdvertisement,
+            137 => MessageType::Redirect,
+            _ => MessageType::Unknown(v),
+        }
+    }
+}
+
+impl From<MessageType> for u8 {
+    fn from(val: MessageType) -> Self {
+        match val {
+            MessageType::DestinationUnreachable => 1,
+            MessageType::PacketTooBig => 2,
+            MessageType::TimeExceeded => 3,
+            MessageType::ParameterProblem => 4,
+            MessageType::EchoRequest => 128,
+            MessageType::EchoReply => 129,
+            MessageType::RouterSolicitation => 133,
+            MessageType::RouterAdvertisement => 134,
+            MessageType::NeighborSolicitation => 135,
+            MessageType::NeighborAdvertisement => 136,
+            MessageType::Redirect => 137,
+            MessageType::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<u8> for UnreachableCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => UnreachableCode::NoRouteToDestination,
+            1 => UnreachableCode::AdministrativelyProhibited,
+            2 => UnreachableCode::BeyondScopeOfSourceAddress,
+            3 => UnreachableCode::AddressUnreachable,
+            4 => UnreachableCode::PortUnreachable,
+            5 => UnreachableCode::SourceAddressFailedPolicy,
+            6 => UnreachableCode::RejectRouteToDestination,
+            _ => UnreachableCode::Unknown(v),
+        }
+    }
+}
+
+impl From<UnreachableCode> for u8 {
+    fn from(val: UnreachableCode) -> Self {
+        match val {
+            UnreachableCode::NoRouteToDestination => 0,
+            UnreachableCode::AdministrativelyProhibited => 1,
+            UnreachableCode::BeyondScopeOfSourceAddress => 2,
+            UnreachableCode::AddressUnreachable => 3,
+            UnreachableCode::PortUnreachable => 4,
+            UnreachableCode::SourceAddressFailedPolicy => 5,
+            UnreachableCode::RejectRouteToDestination => 6,
+            UnreachableCode::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<u8> for TimeExceededCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => TimeExceededCode::HopLimitExceeded,
+            1 => TimeExceededCode::FragmentReassemblyTimeExceeded,
+            _ => TimeExceededCode::Unknown(v),
+        }
+    }
+}
+
+impl From<TimeExceededCode> for u8 {
+    fn from(val: TimeExceededCode) -> Self {
+        match val {
+            TimeExceededCode::HopLimitExceeded => 0,
+            TimeExceededCode::FragmentReassemblyTimeExceeded => 1,
+            TimeExceededCode::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<u8> for ParameterProblemCode {
+    fn from(v: u8) -> Self {
+        match v {
+            0 => ParameterProblemCode::ErroneousHeaderField,
+            1 => ParameterProblemCode::UnrecognizedNextHeader,
+            2 => ParameterProblemCode::UnrecognizedOption,
+            _ => ParameterProblemCode::Unknown(v),
+        }
+    }
+}
+
+impl From<ParameterProblemCode> for u8 {
+    fn from(val: ParameterProblemCode) -> Self {
+        match val {
+            ParameterProblemCode::ErroneousHeaderField => 0,
+            ParameterProblemCode::UnrecognizedNextHeader => 1,
+            ParameterProblemCode::UnrecognizedOption => 2,
+            ParameterProblemCode::Unknown(v) => v,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::TransportProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn neighbor_solicitation_test() {
+        let err = TransportProtocolError::CannotParseICMPMessage;
+        let mut raw_message = vec![135, 0, 0, 0, 0, 0, 0, 0];
+        raw_message.extend_from_slice(&IPv6Addr::from("fe80::1").octets());
+        raw_message.extend_from_slice(&[1, 1, 0x08, 0x00, 0x27, 0x3c, 0xa9, 0x81]);
+
+        let msg = Message::new_from_bytes(&raw_message, err).unwrap();
+        assert_eq!(MessageType::NeighborSolicitation, msg.ty);
+        assert_eq!(
+            Some(MacAddress::from("08:00:27:3c:a9:81")),
+            msg.source_link_layer_address()
+        );
+        assert_eq!(None, msg.target_link_layer_address());
+        assert_eq!(
+            raw_message,
+            msg.to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+                .unwrap()
+        );
+
+        // 長さが0のオプションを含むメッセージは不正
+        raw_message[25] = 0;
+        assert!(Message::new_from_bytes(&raw_message, err).is_err());
+    }
+
+    #[test]
+    fn router_advertisement_test() {
+        let prefix = PrefixInformation {
+            prefix_length: 64,
+            on_link: true,
+            autonomous: true,
+            valid_lifetime: 2592000,
+            preferred_lifetime: 604800,
+            prefix: IPv6Addr::from("2001:db8::"),
+        };
+        let msg = Message {
+            ty: MessageType::RouterAdvertisement,
+            data: MessageData::RouterAdvertisement {
+                cur_hop_limit: 64,
+                managed_flag: false,
+                other_flag: true,
+                router_lifetime: 1800,
+                reachable_time: 0,
+                retrans_timer: 0,
+                options: vec![
+                    NdpOption::SourceLinkLayerAddress(MacAddress::from("00:15:5d:22:1e:ff")),
+                    NdpOption::Mtu(1500),
+                    NdpOption::PrefixInformation(prefix),
+                ],
+            },
+            ..Default::default()
+        };
+
+        let raw_message = msg
+            .to_bytes(TransportProtocolError::CannotConstructICMPMessage)
+            .unwrap();
+        // ヘッダ16 + 送信元リンク層アドレス8 + MTU8 + プレフィックス情報32
+        assert_eq!(64, raw_message.len());
+        assert_eq!(0x40, raw_message[5]);
+        assert_eq!(
+            msg,
+            Message::new_from_bytes(&raw_message, TransportProtocolError::CannotParseICMPMessage)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn message_type_test() {
+        assert!(MessageType::PacketTooBig.is_error());
+        assert!(!MessageType::EchoRequest.is_error());
+        assert!(!MessageType::NeighborAdvertisement.is_error());
+        assert!(MessageType::NeighborAdvertisement.is_ndp());
+    }
+}
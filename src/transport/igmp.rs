@@ -0,0 +1,8 @@
+mod types;
+pub use types::*;
+
+mod group;
+pub use group::*;
+
+mod protocol;
+pub use protocol::*;
@@ -0,0 +1,425 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use super::{GroupRecord, IgmpVersion, Message, MessageData, MessageType, RecordType};
+use crate::internet::ip::IPv4Addr;
+
+/// 状態変化を通知するレポートを送信する回数(Robustness Variable)
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-8.1)
+pub const ROBUSTNESS: u32 = 2;
+/// IGMPv1/v2で参加時のレポートを再送する間隔
+/// See also [RFC2236](https://tools.ietf.org/html/rfc2236#section-8.10)
+const V2_UNSOLICITED_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// IGMPv3で状態変化レポートを再送する間隔
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-8.11)
+const V3_UNSOLICITED_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// 古いバージョンのクエリアが存在するとみなす時間．
+/// Robustness Variable * Query Interval + Query Response Interval で求める
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-8.12)
+const OLDER_VERSION_QUERIER_PRESENT_TIMEOUT: Duration = Duration::from_secs(2 * 125 + 10);
+
+/// 参加しているグループの状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Membership {
+    /// グループに参加しているソケットの数
+    refcount: usize,
+    /// 次にレポートを送信する時刻
+    report_at: Option<Instant>,
+    /// 状態変化レポートを残り何回送信するか
+    state_change_remaining: u32,
+    /// IGMPv2で最後にレポートを送信したのが自身かどうか．
+    /// 離脱時にLeave Groupを送るかの判断に使う
+    last_reporter: bool,
+}
+
+/// ホストが参加しているマルチキャストグループと，IGMPのタイマを管理する．
+/// 送信元フィルタは扱わず，すべてのグループにEXCLUDE{}モードで参加する
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-5)
+#[derive(Debug, Clone)]
+pub struct GroupTable {
+    /// 設定されたバージョン．古いクエリアがいればそれに合わせて下げる
+    version: IgmpVersion,
+    memberships: BTreeMap<IPv4Addr, Membership>,
+    v1_querier_until: Option<Instant>,
+    v2_querier_until: Option<Instant>,
+    /// IGMPv3の一般クエリに応答する時刻
+    general_report_at: Option<Instant>,
+    /// 離脱を通知するグループと，残りの送信回数・次に送信する時刻
+    pending_leaves: BTreeMap<IPv4Addr, (u32, Instant)>,
+}
+
+impl GroupTable {
+    pub fn new(version: IgmpVersion) -> Self {
+        let mut memberships = BTreeMap::new();
+        // 全ホストグループには常に参加しており，レポートも送らない
+        // See also [RFC2236](https://tools.ietf.org/html/rfc2236#section-6)
+        memberships.insert(
+            IPv4Addr::ALL_SYSTEMS,
+            Membership {
+                refcount: 1,
+                report_at: None,
+                state_change_remaining: 0,
+                last_reporter: false,
+            },
+        );
+        Self {
+            version,
+            memberships,
+            v1_querier_until: None,
+            v2_querier_until: None,
+            general_report_at: None,
+            pending_leaves: BTreeMap::new(),
+        }
+    }
+
+    /// グループに参加する．
+    /// 新たに参加した場合は，状態変化レポートを直ちに送信するようにスケジュールする
+    pub fn join(&mut self, group: IPv4Addr, now: Instant) {
+        if let Some(membership) = self.memberships.get_mut(&group) {
+            membership.refcount += 1;
+            return;
+        }
+
+        self.pending_leaves.remove(&group);
+        self.memberships.insert(
+            group,
+            Membership {
+                refcount: 1,
+                report_at: Some(now),
+                state_change_remaining: ROBUSTNESS,
+                last_reporter: true,
+            },
+        );
+    }
+
+    /// グループから離脱する．
+    /// 参加しているソケットがなくなった場合は，離脱の通知をスケジュールする
+    pub fn leave(&mut self, group: IPv4Addr, now: Instant) {
+        if group == IPv4Addr::ALL_SYSTEMS {
+            return;
+        }
+        let membership = match self.memberships.get_mut(&group) {
+            Some(membership) => membership,
+            None => return,
+        };
+        membership.refcount -= 1;
+        if membership.refcount != 0 {
+            return;
+        }
+
+        let last_reporter = membership.last_reporter;
+        self.memberships.remove(&group);
+        match self.compatibility_mode(now) {
+            // IGMPv1には離脱を通知する手段がない
+            IgmpVersion::V1 => {}
+            // 他のホストがレポートを送っていれば，そのホストがまだ参加しているので通知しない
+            // See also [RFC2236](https://tools.ietf.org/html/rfc2236#section-3)
+            IgmpVersion::V2 if !last_reporter => {}
+            IgmpVersion::V2 => {
+                self.pending_leaves.insert(group, (1, now));
+            }
+            IgmpVersion::V3 => {
+                self.pending_leaves.insert(group, (ROBUSTNESS, now));
+            }
+        }
+    }
+
+    pub fn is_member(&self, group: IPv4Addr) -> bool {
+        self.memberships.contains_key(&group)
+    }
+
+    /// 参加しているグループ．全ホストグループを含む
+    pub fn groups(&self) -> Vec<IPv4Addr> {
+        self.memberships.keys().copied().collect()
+    }
+
+    /// 古いバージョンのクエリアの有無を考慮した，現在の動作バージョン
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-7.2.1)
+    pub fn compatibility_mode(&self, now: Instant) -> IgmpVersion {
+        let present = |until: Option<Instant>| until.is_some_and(|until| now < until);
+        let detected = if present(self.v1_querier_until) {
+            IgmpVersion::V1
+        } else if present(self.v2_querier_until) {
+            IgmpVersion::V2
+        } else {
+            IgmpVersion::V3
+        };
+        self.version.min(detected)
+    }
+
+    /// クエリを受信した際に，応答するレポートを最大応答時間内のランダムな時刻にスケジュールする
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-5.2)
+    pub fn on_query(&mut self, msg: &Message, now: Instant) {
+        match msg.query_version() {
+            Some(IgmpVersion::V1) => {
+                self.v1_querier_until = Some(now + OLDER_VERSION_QUERIER_PRESENT_TIMEOUT)
+            }
+            Some(IgmpVersion::V2) => {
+                self.v2_querier_until = Some(now + OLDER_VERSION_QUERIER_PRESENT_TIMEOUT)
+            }
+            Some(IgmpVersion::V3) => {}
+            None => return,
+        }
+
+        let respond_at = now + msg.max_response_time().mul_f64(rand::random::<f64>());
+        let earlier =
+            |current: Option<Instant>| Some(current.map_or(respond_at, |c| c.min(respond_at)));
+
+        // 送信元を指定したクエリも，グループ全体の状態を報告すれば十分なので区別しない
+        match (self.compatibility_mode(now), msg.query_group()) {
+            (IgmpVersion::V3, None) => self.general_report_at = earlier(self.general_report_at),
+            (_, None) => {
+                for (group, membership) in self.memberships.iter_mut() {
+                    if *group != IPv4Addr::ALL_SYSTEMS {
+                        membership.report_at = earlier(membership.report_at);
+                    }
+                }
+            }
+            (_, Some(group)) => {
+                if let Some(membership) = self.memberships.get_mut(&group) {
+                    if group != IPv4Addr::ALL_SYSTEMS {
+                        membership.report_at = earlier(membership.report_at);
+                    }
+                }
+            }
+        }
+    }
+
+    /// IGMPv1/v2では，他のホストが同じグループのレポートを送信していれば自身のレポートを取り消す
+    /// See also [RFC2236](https://tools.ietf.org/html/rfc2236#section-3)
+    pub fn on_report(&mut self, group: IPv4Addr, now: Instant) {
+        if self.compatibility_mode(now) == IgmpVersion::V3 {
+            return;
+        }
+        if let Some(membership) = self.memberships.get_mut(&group) {
+            membership.report_at = None;
+            membership.state_change_remaining = 0;
+            membership.last_reporter = false;
+        }
+    }
+
+    /// 送信時刻を迎えたメッセージを，宛先と共に返す
+    pub fn on_timer(&mut self, now: Instant) -> Vec<(IPv4Addr, Message)> {
+        let mode = self.compatibility_mode(now);
+        let unsolicited_interval = match mode {
+            IgmpVersion::V3 => V3_UNSOLICITED_REPORT_INTERVAL,
+            _ => V2_UNSOLICITED_REPORT_INTERVAL,
+        };
+        let mut messages = Vec::new();
+        let mut records = Vec::new();
+
+        for (group, membership) in self.memberships.iter_mut() {
+            if membership.report_at.is_none_or(|at| at > now) {
+                continue;
+            }
+
+            match mode {
+                IgmpVersion::V1 => {
+                    messages.push((*group, report(MessageType::V1MembershipReport, *group)))
+                }
+                IgmpVersion::V2 => {
+                    messages.push((*group, report(MessageType::V2MembershipReport, *group)))
+                }
+                IgmpVersion::V3 => records.push(GroupRecord {
+                    ty: if membership.state_change_remaining != 0 {
+                        RecordType::ChangeToExclude
+                    } else {
+                        RecordType::ModeIsExclude
+                    },
+                    group: *group,
+                    sources: Vec::new(),
+                }),
+            }
+            membership.last_reporter = true;
+
+            // 状態変化レポートは，間隔を空けて規定回数送信する
+            membership.state_change_remaining = membership.state_change_remaining.saturating_sub(1);
+            membership.report_at = if membership.state_change_remaining != 0 {
+                Some(now + unsolicited_interval.mul_f64(rand::random::<f64>()))
+            } else {
+                None
+            };
+        }
+
+        // IGMPv3の一般クエリには，参加しているすべてのグループを1つのレポートで応答する
+        if mode == IgmpVersion::V3 && self.general_report_at.is_some_and(|at| at <= now) {
+            self.general_report_at = None;
+            for group in self.memberships.keys() {
+                if *group != IPv4Addr::ALL_SYSTEMS && !records.iter().any(|r| r.group == *group) {
+                    records.push(GroupRecord {
+                        ty: RecordType::ModeIsExclude,
+                        group: *group,
+                        sources: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let due_leaves: Vec<IPv4Addr> = self
+            .pending_leaves
+            .iter()
+            .filter(|(_, (_, at))| *at <= now)
+            .map(|(group, _)| *group)
+            .collect();
+        for group in due_leaves {
+            match mode {
+                IgmpVersion::V1 => {}
+                IgmpVersion::V2 => messages.push((
+                    IPv4Addr::ALL_ROUTERS,
+                    report(MessageType::LeaveGroup, group),
+                )),
+                IgmpVersion::V3 => records.push(GroupRecord {
+                    ty: RecordType::ChangeToInclude,
+                    group,
+                    sources: Vec::new(),
+                }),
+            }
+
+            let (remaining, at) = self.pending_leaves.get_mut(&group).unwrap();
+            *remaining -= 1;
+            *at = now + unsolicited_interval.mul_f64(rand::random::<f64>());
+            if *remaining == 0 || mode != IgmpVersion::V3 {
+                self.pending_leaves.remove(&group);
+            }
+        }
+
+        if !records.is_empty() {
+            let msg = Message {
+                ty: MessageType::V3MembershipReport,
+                data: MessageData::V3Report { records },
+                ..Default::default()
+            };
+            messages.push((IPv4Addr::ALL_IGMPV3_ROUTERS, msg));
+        }
+
+        messages
+    }
+}
+
+/// IGMPv1/v2のレポート及びLeave Group
+fn report(ty: MessageType, group: IPv4Addr) -> Message {
+    Message {
+        ty,
+        data: MessageData::Group { group },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v3_records(msg: &Message) -> Vec<(RecordType, IPv4Addr)> {
+        match &msg.data {
+            MessageData::V3Report { records } => records.iter().map(|r| (r.ty, r.group)).collect(),
+            _ => panic!("not a v3 report => {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn v3_join_and_leave_test() {
+        let now = Instant::now();
+        let group = IPv4Addr::from("239.1.2.3");
+        let mut table = GroupTable::new(IgmpVersion::V3);
+        assert!(table.is_member(IPv4Addr::ALL_SYSTEMS));
+        assert!(!table.is_member(group));
+
+        table.join(group, now);
+        assert!(table.is_member(group));
+        let messages = table.on_timer(now);
+        assert_eq!(1, messages.len());
+        assert_eq!(IPv4Addr::ALL_IGMPV3_ROUTERS, messages[0].0);
+        assert_eq!(
+            vec![(RecordType::ChangeToExclude, group)],
+            v3_records(&messages[0].1)
+        );
+
+        // 状態変化レポートはROBUSTNESS回送信する
+        let later = now + V3_UNSOLICITED_REPORT_INTERVAL;
+        assert_eq!(1, table.on_timer(later).len());
+        assert!(table.on_timer(later).is_empty());
+
+        table.leave(group, later);
+        assert!(!table.is_member(group));
+        let messages = table.on_timer(later);
+        assert_eq!(
+            vec![(RecordType::ChangeToInclude, group)],
+            v3_records(&messages[0].1)
+        );
+    }
+
+    #[test]
+    fn v3_general_query_test() {
+        let now = Instant::now();
+        let group = IPv4Addr::from("239.1.2.3");
+        let mut table = GroupTable::new(IgmpVersion::V3);
+        table.join(group, now);
+        table.on_timer(now);
+        table.on_timer(now + V3_UNSOLICITED_REPORT_INTERVAL);
+
+        let query = Message {
+            max_resp_code: 10,
+            data: MessageData::V3Query {
+                group: IPv4Addr::ANY,
+                suppress_router_processing: false,
+                robustness: 2,
+                query_interval_code: 125,
+                sources: Vec::new(),
+            },
+            ..Default::default()
+        };
+        table.on_query(&query, now);
+        assert_eq!(IgmpVersion::V3, table.compatibility_mode(now));
+
+        let messages = table.on_timer(now + Duration::from_secs(1));
+        assert_eq!(
+            vec![(RecordType::ModeIsExclude, group)],
+            v3_records(&messages[0].1)
+        );
+    }
+
+    #[test]
+    fn v2_compatibility_test() {
+        let now = Instant::now();
+        let group = IPv4Addr::from("239.1.2.3");
+        let mut table = GroupTable::new(IgmpVersion::V3);
+
+        // IGMPv2のクエリを受信したら，IGMPv2で動作する
+        let query = Message {
+            max_resp_code: 100,
+            ..Default::default()
+        };
+        table.on_query(&query, now);
+        assert_eq!(IgmpVersion::V2, table.compatibility_mode(now));
+        assert_eq!(
+            IgmpVersion::V3,
+            table.compatibility_mode(now + OLDER_VERSION_QUERIER_PRESENT_TIMEOUT)
+        );
+
+        table.join(group, now);
+        let messages = table.on_timer(now);
+        assert_eq!(
+            vec![(group, report(MessageType::V2MembershipReport, group))],
+            messages
+        );
+
+        // 自身が最後にレポートを送っていれば，離脱時にLeave Groupを送る
+        table.leave(group, now);
+        assert_eq!(
+            vec![(
+                IPv4Addr::ALL_ROUTERS,
+                report(MessageType::LeaveGroup, group)
+            )],
+            table.on_timer(now)
+        );
+
+        // 他のホストのレポートを受信していれば送らない
+        table.join(group, now);
+        table.on_report(group, now);
+        assert!(table.on_timer(now).is_empty());
+        table.leave(group, now);
+        assert!(table.on_timer(now).is_empty());
+    }
+}
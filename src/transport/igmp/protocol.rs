@@ -0,0 +1,146 @@
+use std::time::Instant;
+
+use super::{Message, MessageData, MessageType};
+use crate::{
+    checksum::calculate_checksum_u16,
+    internet::{self, ip::IPv4Addr},
+    network_device,
+    transport::{TransportProtocol, TransportProtocolError},
+    Items, RxResult,
+};
+
+/// IGMPメッセージを送信するIPパケットのTTL．リンク外に転送させない
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4)
+const IGMP_TTL: u8 = 1;
+/// ルータにIGMPメッセージを検査させるRouter Alertオプション
+/// See also [RFC2113](https://tools.ietf.org/html/rfc2113#section-2.1)
+const ROUTER_ALERT_OPTION: [u8; 4] = [0x94, 0x04, 0x00, 0x00];
+
+pub async fn rx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    rx_result: RxResult,
+    buf: &[u8],
+) -> Result<Message, TransportProtocolError> {
+    let raw_message = &buf[..rx_result.message_len.min(buf.len())];
+    if raw_message.len() < Message::LENGTH {
+        return Err(TransportProtocolError::CannotParseIGMPMessage);
+    }
+    if calculate_checksum_u16(
+        raw_message,
+        raw_message.len() as u16,
+        TransportProtocolError::InvalidChecksum,
+    )? != 0
+    {
+        if table.opt.debug {
+            eprintln!("++++++++ drop igmp message with invalid checksum ++++++++");
+        }
+        return Err(TransportProtocolError::InvalidChecksum);
+    }
+
+    let msg = Message::new_from_bytes(raw_message, TransportProtocolError::CannotParseIGMPMessage)?;
+
+    if table.opt.debug {
+        eprintln!("++++++++ rx igmp message ++++++++");
+        eprintln!("{}", msg);
+    }
+
+    // 自身が送信したメッセージでタイマを取り消さないようにする
+    if rx_result.src_ipv4_addr() == table.opt.ip_addr {
+        return Ok(msg);
+    }
+
+    let now = Instant::now();
+    match (&msg.ty, &msg.data) {
+        (MessageType::MembershipQuery, _) => table.igmp_groups.lock().unwrap().on_query(&msg, now),
+        (
+            MessageType::V1MembershipReport | MessageType::V2MembershipReport,
+            MessageData::Group { group },
+        ) => table.igmp_groups.lock().unwrap().on_report(*group, now),
+        _ => {}
+    }
+
+    Ok(msg)
+}
+
+/// 送信時刻を迎えたレポートを送信する
+pub async fn on_timer<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    now: Instant,
+) -> Result<(), TransportProtocolError> {
+    let messages = table.igmp_groups.lock().unwrap().on_timer(now);
+    for (dst, msg) in messages {
+        send(table, dst, msg).await?;
+    }
+
+    Ok(())
+}
+
+/// マルチキャストグループに参加する．
+/// レポートは `peachps::run` のタイマ処理で送信される
+pub fn join<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    group: IPv4Addr,
+) -> Result<(), TransportProtocolError> {
+    if !group.is_multicast() {
+        return Err(TransportProtocolError::NotMulticastAddress { addr: group });
+    }
+    table
+        .igmp_groups
+        .lock()
+        .unwrap()
+        .join(group, Instant::now());
+
+    Ok(())
+}
+
+/// マルチキャストグループから離脱する
+pub fn leave<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    group: IPv4Addr,
+) -> Result<(), TransportProtocolError> {
+    if !group.is_multicast() {
+        return Err(TransportProtocolError::NotMulticastAddress { addr: group });
+    }
+    table
+        .igmp_groups
+        .lock()
+        .unwrap()
+        .leave(group, Instant::now());
+
+    Ok(())
+}
+
+async fn send<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    dst: IPv4Addr,
+    mut msg: Message,
+) -> Result<(), TransportProtocolError> {
+    let before_buf = msg.to_bytes(TransportProtocolError::CannotConstructIGMPMessage)?;
+    msg.checksum = calculate_checksum_u16(
+        &before_buf,
+        before_buf.len() as u16,
+        TransportProtocolError::InvalidChecksum,
+    )?;
+
+    if table.opt.debug {
+        eprintln!("++++++++ tx igmp message ++++++++");
+        eprintln!("{}", msg);
+    }
+
+    let rx_result = RxResult {
+        src_ip_addr: dst.into(),
+        dst_ip_addr: table.opt.ip_addr.into(),
+        ..Default::default()
+    };
+    internet::ip::tx_with_options(
+        table,
+        TransportProtocol::IGMP,
+        rx_result,
+        msg.to_bytes(TransportProtocolError::CannotConstructIGMPMessage)?,
+        IGMP_TTL,
+        &ROUTER_ALERT_OPTION,
+    )
+    .await?;
+
+    Ok(())
+}
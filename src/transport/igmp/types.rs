@@ -0,0 +1,448 @@
+use std::{io::Cursor, time::Duration};
+
+use crate::{byteorder_wrapper, internet::ip::IPv4Addr, transport::TransportHeader};
+
+/// IGMPメッセージ
+/// See also [RFC2236](https://tools.ietf.org/html/rfc2236#section-2)
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub ty: MessageType,
+    /// クエリの場合は最大応答時間を表すコード．それ以外では0
+    pub max_resp_code: u8,
+    pub checksum: u16,
+    pub data: MessageData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageData {
+    /// IGMPv1/v2のクエリ，レポート及び離脱．
+    /// 一般クエリの場合，グループアドレスは0になる
+    Group { group: IPv4Addr },
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4.1)
+    V3Query {
+        group: IPv4Addr,
+        /// ルータ側のタイマ更新を抑制するか
+        suppress_router_processing: bool,
+        /// 送信したクエリアのRobustness Variable
+        robustness: u8,
+        /// Query Interval Code
+        query_interval_code: u8,
+        sources: Vec<IPv4Addr>,
+    },
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4.2)
+    V3Report { records: Vec<GroupRecord> },
+    /// 解釈できないメッセージ．ヘッダ以降をそのまま保持する
+    Unknown { raw: Vec<u8> },
+}
+
+/// IGMPv3レポートに含めるグループ毎の状態
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4.2.4)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupRecord {
+    pub ty: RecordType,
+    pub group: IPv4Addr,
+    pub sources: Vec<IPv4Addr>,
+}
+
+/// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4.2.12)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RecordType {
+    /// 現在の状態: 指定した送信元からのみ受信する
+    ModeIsInclude,
+    /// 現在の状態: 指定した送信元以外から受信する
+    ModeIsExclude,
+    /// 状態変化: INCLUDEモードへの変更
+    ChangeToInclude,
+    /// 状態変化: EXCLUDEモードへの変更
+    ChangeToExclude,
+    AllowNewSources,
+    BlockOldSources,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageType {
+    /// メンバーシップクエリ．長さとコードによってバージョンを区別する
+    MembershipQuery,
+    /// IGMPv1のメンバーシップレポート
+    V1MembershipReport,
+    /// IGMPv2のメンバーシップレポート
+    V2MembershipReport,
+    /// IGMPv2のグループ離脱
+    LeaveGroup,
+    /// IGMPv3のメンバーシップレポート
+    V3MembershipReport,
+    /// 未対応のタイプ
+    Unknown(u8),
+}
+
+/// ホストが動作するIGMPのバージョン
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum IgmpVersion {
+    V1,
+    V2,
+    #[default]
+    V3,
+}
+
+impl Message {
+    /// IGMPv1/v2のメッセージ長
+    pub const LENGTH: usize = 8;
+    /// IGMPv3のクエリの，送信元アドレスを除いた長さ
+    pub const V3_QUERY_LENGTH: usize = 12;
+
+    pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut reader = Cursor::new(buf);
+        let mut message_header: Message = Default::default();
+
+        message_header.ty = MessageType::from(byteorder_wrapper::read_u8(&mut reader, err)?);
+        message_header.max_resp_code = byteorder_wrapper::read_u8(&mut reader, err)?;
+        message_header.checksum = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+
+        message_header.data = match message_header.ty {
+            // 12オクテット以上のクエリはIGMPv3のもの
+            // See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-7.1)
+            MessageType::MembershipQuery if buf.len() >= Self::V3_QUERY_LENGTH => {
+                let group = IPv4Addr(byteorder_wrapper::read_u32_as_be(&mut reader, err)?);
+                let s_qrv = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let query_interval_code = byteorder_wrapper::read_u8(&mut reader, err)?;
+                let number_of_sources = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+                let sources = (0..number_of_sources)
+                    .map(|_| byteorder_wrapper::read_u32_as_be(&mut reader, err).map(IPv4Addr))
+                    .collect::<Result<Vec<IPv4Addr>, E>>()?;
+                MessageData::V3Query {
+                    group,
+                    suppress_router_processing: s_qrv & 0x08 != 0,
+                    robustness: s_qrv & 0x07,
+                    query_interval_code,
+                    sources,
+                }
+            }
+            MessageType::MembershipQuery
+            | MessageType::V1MembershipReport
+            | MessageType::V2MembershipReport
+            | MessageType::LeaveGroup => MessageData::Group {
+                group: IPv4Addr(byteorder_wrapper::read_u32_as_be(&mut reader, err)?),
+            },
+            MessageType::V3MembershipReport => {
+                let _reserved = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+                let number_of_records = byteorder_wrapper::read_u16_as_be(&mut reader, err)?;
+                let records = (0..number_of_records)
+                    .map(|_| GroupRecord::from_cursor(&mut reader, err))
+                    .collect::<Result<Vec<GroupRecord>, E>>()?;
+                MessageData::V3Report { records }
+            }
+            MessageType::Unknown(_) => MessageData::Unknown {
+                raw: buf[Message::LENGTH.min(buf.len())..].to_vec(),
+            },
+        };
+
+        Ok(message_header)
+    }
+
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut buf = Vec::<u8>::new();
+        byteorder_wrapper::write_u8(&mut buf, self.ty.into(), err)?;
+        byteorder_wrapper::write_u8(&mut buf, self.max_resp_code, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.checksum, err)?;
+        match &self.data {
+            MessageData::Group { group } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, group.0, err)?;
+            }
+            MessageData::V3Query {
+                group,
+                suppress_router_processing,
+                robustness,
+                query_interval_code,
+                sources,
+            } => {
+                byteorder_wrapper::write_u32_as_be(&mut buf, group.0, err)?;
+                let s_flag = if *suppress_router_processing { 0x08 } else { 0 };
+                byteorder_wrapper::write_u8(&mut buf, s_flag | (robustness & 0x07), err)?;
+                byteorder_wrapper::write_u8(&mut buf, *query_interval_code, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, sources.len() as u16, err)?;
+                for source in sources.iter() {
+                    byteorder_wrapper::write_u32_as_be(&mut buf, source.0, err)?;
+                }
+            }
+            MessageData::V3Report { records } => {
+                byteorder_wrapper::write_u16_as_be(&mut buf, 0, err)?;
+                byteorder_wrapper::write_u16_as_be(&mut buf, records.len() as u16, err)?;
+                for record in records.iter() {
+                    record.write(&mut buf, err)?;
+                }
+            }
+            MessageData::Unknown { raw } => buf.extend_from_slice(raw),
+        }
+
+        Ok(buf)
+    }
+
+    /// クエリを送信したルータのIGMPバージョン．クエリでなければ `None` を返す
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-7.1)
+    pub fn query_version(&self) -> Option<IgmpVersion> {
+        match (&self.ty, &self.data) {
+            (MessageType::MembershipQuery, MessageData::V3Query { .. }) => Some(IgmpVersion::V3),
+            (MessageType::MembershipQuery, _) if self.max_resp_code == 0 => Some(IgmpVersion::V1),
+            (MessageType::MembershipQuery, _) => Some(IgmpVersion::V2),
+            _ => None,
+        }
+    }
+
+    /// クエリの最大応答時間．
+    /// IGMPv1のクエリは常に10秒とし，IGMPv3では128以上のコードを浮動小数点表現として扱う
+    /// See also [RFC3376](https://tools.ietf.org/html/rfc3376#section-4.1.1)
+    pub fn max_response_time(&self) -> Duration {
+        let code = self.max_resp_code as u64;
+        let deciseconds = match self.query_version() {
+            Some(IgmpVersion::V1) => 100,
+            Some(IgmpVersion::V3) if code >= 128 => {
+                let exp = (code >> 4) & 0x07;
+                let mant = code & 0x0f;
+                (mant | 0x10) << (exp + 3)
+            }
+            _ => code,
+        };
+        Duration::from_millis(deciseconds * 100)
+    }
+
+    /// クエリの対象グループ．一般クエリであれば `None` を返す
+    pub fn query_group(&self) -> Option<IPv4Addr> {
+        match &self.data {
+            MessageData::Group { group } | MessageData::V3Query { group, .. } => {
+                Some(*group).filter(|g| *g != IPv4Addr::ANY)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl GroupRecord {
+    fn from_cursor<E>(reader: &mut Cursor<&[u8]>, err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let ty = RecordType::from(byteorder_wrapper::read_u8(reader, err)?);
+        let aux_data_len = byteorder_wrapper::read_u8(reader, err)?;
+        let number_of_sources = byteorder_wrapper::read_u16_as_be(reader, err)?;
+        let group = IPv4Addr(byteorder_wrapper::read_u32_as_be(reader, err)?);
+        let sources = (0..number_of_sources)
+            .map(|_| byteorder_wrapper::read_u32_as_be(reader, err).map(IPv4Addr))
+            .collect::<Result<Vec<IPv4Addr>, E>>()?;
+        // 補助データは32ビット単位の長さで，解釈せずに読み飛ばす
+        for _ in 0..aux_data_len {
+            byteorder_wrapper::read_u32_as_be(reader, err)?;
+        }
+
+        Ok(Self { ty, group, sources })
+    }
+
+    fn write<E>(&self, buf: &mut Vec<u8>, err: E) -> Result<(), E>
+    where
+        E: std::error::Error + Copy,
+    {
+        byteorder_wrapper::write_u8(buf, self.ty.into(), err)?;
+        byteorder_wrapper::write_u8(buf, 0, err)?;
+        byteorder_wrapper::write_u16_as_be(buf, self.sources.len() as u16, err)?;
+        byteorder_wrapper::write_u32_as_be(buf, self.group.0, err)?;
+        for source in self.sources.iter() {
+            byteorder_wrapper::write_u32_as_be(buf, source.0, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl TransportHeader for Message {}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self {
+            ty: MessageType::MembershipQuery,
+            max_resp_code: 0,
+            checksum: 0,
+            data: MessageData::Group {
+                group: IPv4Addr::ANY,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Type: {}", self.ty)?;
+        writeln!(f, "Max Resp Code: {}", self.max_resp_code)?;
+        writeln!(f, "Checksum: 0x{:x}", self.checksum)?;
+        match &self.data {
+            MessageData::Group { group } => writeln!(f, "Group: {}", group),
+            MessageData::V3Query {
+                group,
+                robustness,
+                query_interval_code,
+                sources,
+                ..
+            } => {
+                writeln!(f, "Group: {}", group)?;
+                writeln!(f, "QRV: {}", robustness)?;
+                writeln!(f, "QQIC: {}", query_interval_code)?;
+                writeln!(f, "Sources: {:?}", sources)
+            }
+            MessageData::V3Report { records } => writeln!(f, "Records: {:?}", records),
+            MessageData::Unknown { raw } => writeln!(f, "Data: {:?}", raw),
+        }
+    }
+}
+
+impl std::fmt::Display for MessageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let type_str = match self {
+            MessageType::MembershipQuery => "Membership Query",
+            MessageType::V1MembershipReport => "Version 1 Membership Report",
+            MessageType::V2MembershipReport => "Version 2 Membership Report",
+            MessageType::LeaveGroup => "Leave Group",
+            MessageType::V3MembershipReport => "Version 3 Membership Report",
+            MessageType::Unknown(v) => return write!(f, "Unknown({})", v),
+        };
+        write!(f, "{}", type_str)
+    }
+}
+
+impl From<u8> for MessageType {
+    fn from(v: u8) -> Self {
+        match v {
+            0x11 => MessageType::MembershipQuery,
+            0x12 => MessageType::V1MembershipReport,
+            0x16 => MessageType::V2MembershipReport,
+            0x17 => MessageType::LeaveGroup,
+            0x22 => MessageType::V3MembershipReport,
+            _ => MessageType::Unknown(v),
+        }
+    }
+}
+
+impl From<MessageType> for u8 {
+    fn from(val: MessageType) -> Self {
+        match val {
+            MessageType::MembershipQuery => 0x11,
+            MessageType::V1MembershipReport => 0x12,
+            MessageType::V2MembershipReport => 0x16,
+            MessageType::LeaveGroup => 0x17,
+            MessageType::V3MembershipReport => 0x22,
+            MessageType::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<u8> for RecordType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => RecordType::ModeIsInclude,
+            2 => RecordType::ModeIsExclude,
+            3 => RecordType::ChangeToInclude,
+            4 => RecordType::ChangeToExclude,
+            5 => RecordType::AllowNewSources,
+            6 => RecordType::BlockOldSources,
+            _ => RecordType::Unknown(v),
+        }
+    }
+}
+
+impl From<RecordType> for u8 {
+    fn from(val: RecordType) -> Self {
+        match val {
+            RecordType::ModeIsInclude => 1,
+            RecordType::ModeIsExclude => 2,
+            RecordType::ChangeToInclude => 3,
+            RecordType::ChangeToExclude => 4,
+            RecordType::AllowNewSources => 5,
+            RecordType::BlockOldSources => 6,
+            RecordType::Unknown(v) => v,
+        }
+    }
+}
+
+impl From<&str> for IgmpVersion {
+    fn from(s: &str) -> Self {
+        match s {
+            "1" | "v1" => IgmpVersion::V1,
+            "2" | "v2" => IgmpVersion::V2,
+            "3" | "v3" => IgmpVersion::V3,
+            _ => panic!("unsupported igmp version => '{}'", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::TransportProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn parse_query_test() {
+        let err = TransportProtocolError::CannotParseIGMPMessage;
+
+        // IGMPv2のグループ指定クエリ
+        let raw = [0x11, 0x64, 0xee, 0x9b, 0xef, 0x01, 0x02, 0x03];
+        let msg = Message::new_from_bytes(&raw, err).unwrap();
+        assert_eq!(Some(IgmpVersion::V2), msg.query_version());
+        assert_eq!(Some(IPv4Addr::from("239.1.2.3")), msg.query_group());
+        assert_eq!(Duration::from_secs(10), msg.max_response_time());
+
+        // IGMPv1の一般クエリ
+        let raw = [0x11, 0x00, 0xee, 0xff, 0x00, 0x00, 0x00, 0x00];
+        let msg = Message::new_from_bytes(&raw, err).unwrap();
+        assert_eq!(Some(IgmpVersion::V1), msg.query_version());
+        assert_eq!(None, msg.query_group());
+        assert_eq!(Duration::from_secs(10), msg.max_response_time());
+
+        // IGMPv3の一般クエリ．コード0x8aは (0xa | 0x10) << 3 = 208 (20.8秒)
+        let raw = [
+            0x11, 0x8a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x7d, 0x00, 0x00,
+        ];
+        let msg = Message::new_from_bytes(&raw, err).unwrap();
+        assert_eq!(Some(IgmpVersion::V3), msg.query_version());
+        assert_eq!(Duration::from_millis(20800), msg.max_response_time());
+        assert!(matches!(
+            msg.data,
+            MessageData::V3Query {
+                robustness: 2,
+                query_interval_code: 125,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn v3_report_round_trip_test() {
+        let err = TransportProtocolError::CannotParseIGMPMessage;
+        let msg = Message {
+            ty: MessageType::V3MembershipReport,
+            data: MessageData::V3Report {
+                records: vec![
+                    GroupRecord {
+                        ty: RecordType::ChangeToExclude,
+                        group: IPv4Addr::from("239.1.2.3"),
+                        sources: Vec::new(),
+                    },
+                    GroupRecord {
+                        ty: RecordType::ModeIsInclude,
+                        group: IPv4Addr::from("232.1.1.1"),
+                        sources: vec![IPv4Addr::from("192.168.11.1")],
+                    },
+                ],
+            },
+            ..Default::default()
+        };
+
+        let raw = msg.to_bytes(err).unwrap();
+        assert_eq!(8 + 8 + 12, raw.len());
+        assert_eq!(msg, Message::new_from_bytes(&raw, err).unwrap());
+    }
+}
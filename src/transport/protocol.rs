@@ -1,12 +1,17 @@
-use crate::{internet::InternetProtocolError, network_device, Items, RxResult};
+use crate::{
+    internet::{ip::IPv4Addr, ipv6::IPv6Addr, InternetProtocol, InternetProtocolError},
+    network_device, Items, RxResult,
+};
 
-use super::icmp;
+use super::{icmp, icmpv6, igmp, tcp, udp};
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum TransportProtocol {
     ICMP,
     TCP,
     UDP,
+    ICMPv6,
+    IGMP,
     UnAssigned,
 }
 
@@ -18,16 +23,65 @@ pub enum TransportProtocolError {
     CannotParseICMPMessage,
     #[error("cannot parse TCP segment")]
     CannotParseTCPSegment,
+    #[error("cannot parse IGMP message")]
+    CannotParseIGMPMessage,
+    #[error("cannot parse UDP datagram")]
+    CannotParseUDPDatagram,
+    #[error("cannot construct UDP datagram")]
+    CannotConstructUDPDatagram,
+    #[error("port {port:} is already in use")]
+    PortAlreadyInUse { port: u16 },
+    #[error("no available port")]
+    NoAvailablePort,
+    #[error("message too long")]
+    MessageTooLong,
+    #[error("socket is not connected")]
+    NotConnected,
+    #[error("sending to broadcast address is not permitted")]
+    BroadcastNotPermitted,
+    #[error("socket was closed")]
+    SocketClosed,
+    #[error("cannot construct TCP segment")]
+    CannotConstructTCPSegment,
+    #[error("connection refused")]
+    ConnectionRefused,
+    #[error("connection reset by peer")]
+    ConnectionReset,
+    #[error("connection is closing")]
+    ConnectionClosing,
+    #[error("connection does not exist")]
+    ConnectionNotFound,
+    #[error("connection timed out")]
+    ConnectionTimedOut,
+    #[error("network is unreachable")]
+    NetworkUnreachable,
+    #[error("host is unreachable")]
+    HostUnreachable,
+    #[error("protocol is unreachable")]
+    ProtocolUnreachable,
+    #[error("time to live exceeded in transit")]
+    TimeToLiveExceeded,
+    #[error("request timed out")]
+    RequestTimedOut,
+    #[error("duplicate address {addr:} detected")]
+    DuplicateAddressDetected { addr: IPv6Addr },
+    #[error("{addr:} is not a multicast address")]
+    NotMulticastAddress { addr: IPv4Addr },
+    #[error("multicast group {addr:} has not been joined")]
+    NotJoinedMulticastGroup { addr: IPv4Addr },
     #[error("ignore this data")]
     Ignore,
     #[error("cannot construct ICMP message")]
     CannotConstructICMPMessage,
+    #[error("cannot construct IGMP message")]
+    CannotConstructIGMPMessage,
     #[error("invalid checksum")]
     InvalidChecksum,
     #[error("{e:}")]
     IPError { e: InternetProtocolError },
 }
 
+#[allow(clippy::needless_lifetimes)]
 pub async fn rx<'a, ND: network_device::NetworkDevice>(
     table: &'a Items<ND>,
     ip_result: RxResult,
@@ -36,13 +90,38 @@ pub async fn rx<'a, ND: network_device::NetworkDevice>(
     if !table.opt.transport_filter.contains(&ip_result.tp_type) {
         return Err(TransportProtocolError::Ignore);
     }
+    // ICMP/IGMPはIPv4上でのみ，ICMPv6はIPv6上でのみ扱う．UDP/TCPは両方で扱う
+    let is_ipv6 = ip_result.ip_type == InternetProtocol::IPv6;
+    match ip_result.tp_type {
+        TransportProtocol::ICMP | TransportProtocol::IGMP if is_ipv6 => {
+            return Err(TransportProtocolError::Ignore)
+        }
+        TransportProtocol::ICMPv6 if !is_ipv6 => return Err(TransportProtocolError::Ignore),
+        _ => {}
+    }
 
     match ip_result.tp_type {
         TransportProtocol::ICMP => {
             let (_message_header, rest) = icmp::rx(table, ip_result, buf).await?;
             Ok(rest)
         }
-        _ => unimplemented!(),
+        TransportProtocol::TCP => {
+            let (_segment_header, payload) = tcp::rx(table, ip_result, buf).await?;
+            Ok(payload)
+        }
+        TransportProtocol::UDP => {
+            let (_datagram_header, payload) = udp::rx(table, ip_result, buf).await?;
+            Ok(payload)
+        }
+        TransportProtocol::ICMPv6 => {
+            let (_message_header, rest) = icmpv6::rx(table, ip_result, buf).await?;
+            Ok(rest)
+        }
+        TransportProtocol::IGMP => {
+            igmp::rx(table, ip_result, buf).await?;
+            Ok(Vec::new())
+        }
+        _ => Err(TransportProtocolError::Ignore),
     }
 }
 
@@ -52,6 +131,8 @@ impl std::fmt::Display for TransportProtocol {
             TransportProtocol::ICMP => "ICMP",
             TransportProtocol::TCP => "TCP",
             TransportProtocol::UDP => "UDP",
+            TransportProtocol::ICMPv6 => "ICMPv6",
+            TransportProtocol::IGMP => "IGMP",
             TransportProtocol::UnAssigned => "UnAssigned",
         };
         write!(f, "{}", type_str)
@@ -63,6 +144,8 @@ impl From<&str> for TransportProtocol {
             "ICMP" => TransportProtocol::ICMP,
             "TCP" => TransportProtocol::TCP,
             "UDP" => TransportProtocol::UDP,
+            "ICMPv6" => TransportProtocol::ICMPv6,
+            "IGMP" => TransportProtocol::IGMP,
             _ => panic!("unsupported protocol => '{}'", s),
         }
     }
@@ -72,21 +155,25 @@ impl From<u8> for TransportProtocol {
     fn from(v: u8) -> Self {
         match v {
             1 => TransportProtocol::ICMP,
+            2 => TransportProtocol::IGMP,
             6 => TransportProtocol::TCP,
             17 => TransportProtocol::UDP,
-            2 => TransportProtocol::UnAssigned,
-            21..=63 => TransportProtocol::UnAssigned,
-            _ => panic!("unsupported transport protocol => {}", v),
+            58 => TransportProtocol::ICMPv6,
+            // 対応していないプロトコルはまとめて扱い，Protocol Unreachableを返す
+            _ => TransportProtocol::UnAssigned,
         }
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl Into<u8> for TransportProtocol {
     fn into(self) -> u8 {
         match self {
             TransportProtocol::ICMP => 1,
+            TransportProtocol::IGMP => 2,
             TransportProtocol::TCP => 6,
             TransportProtocol::UDP => 17,
+            TransportProtocol::ICMPv6 => 58,
             TransportProtocol::UnAssigned => panic!("now allowed into() with unassigned protocol"),
         }
     }
@@ -101,6 +188,35 @@ impl From<InternetProtocolError> for TransportProtocolError {
     }
 }
 
+impl From<TransportProtocolError> for std::io::Error {
+    fn from(e: TransportProtocolError) -> Self {
+        use std::io::ErrorKind;
+        let kind = match e {
+            TransportProtocolError::ConnectionRefused => ErrorKind::ConnectionRefused,
+            TransportProtocolError::ConnectionReset => ErrorKind::ConnectionReset,
+            TransportProtocolError::ConnectionTimedOut
+            | TransportProtocolError::RequestTimedOut => ErrorKind::TimedOut,
+            TransportProtocolError::NetworkUnreachable => ErrorKind::NetworkUnreachable,
+            TransportProtocolError::HostUnreachable
+            | TransportProtocolError::TimeToLiveExceeded => ErrorKind::HostUnreachable,
+            TransportProtocolError::ConnectionClosing => ErrorKind::BrokenPipe,
+            TransportProtocolError::NotConnected | TransportProtocolError::ConnectionNotFound => {
+                ErrorKind::NotConnected
+            }
+            TransportProtocolError::PortAlreadyInUse { .. } => ErrorKind::AddrInUse,
+            TransportProtocolError::NoAvailablePort
+            | TransportProtocolError::DuplicateAddressDetected { .. }
+            | TransportProtocolError::NotJoinedMulticastGroup { .. } => ErrorKind::AddrNotAvailable,
+            TransportProtocolError::BroadcastNotPermitted => ErrorKind::PermissionDenied,
+            TransportProtocolError::MessageTooLong
+            | TransportProtocolError::NotMulticastAddress { .. } => ErrorKind::InvalidInput,
+            _ => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, e)
+    }
+}
+
+#[allow(clippy::derivable_impls)]
 impl Default for TransportProtocol {
     fn default() -> Self {
         Self::UnAssigned
@@ -0,0 +1,20 @@
+mod types;
+pub use types::*;
+
+mod congestion;
+pub use congestion::*;
+mod connection;
+pub use connection::*;
+
+mod isn;
+pub use isn::*;
+mod syn_cookie;
+pub use syn_cookie::*;
+mod rto;
+pub use rto::*;
+
+mod protocol;
+pub use protocol::*;
+
+mod socket;
+pub use socket::*;
@@ -0,0 +1,340 @@
+use std::time::{Duration, Instant};
+
+/// 輻輳制御アルゴリズムの選択肢
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CongestionControlAlgorithm {
+    #[default]
+    NewReno,
+    Cubic,
+}
+
+/// 送信側の輻輳制御．
+/// 重複ACKの計数や高速再送の判断は `Connection` が行い，
+/// アルゴリズムは各イベントに応じて輻輳ウィンドウを調整する．
+/// ウィンドウや送信中のデータ量はバイト単位で扱う．
+/// 渡されるMSSは `Connection` が `MIN_MSS` 以上に切り上げたものなので，0にはならない
+pub trait CongestionControl: std::fmt::Debug + Send {
+    fn algorithm(&self) -> CongestionControlAlgorithm;
+    /// 輻輳ウィンドウ(cwnd)
+    fn window(&self) -> u32;
+    /// スロースタート閾値(ssthresh)
+    fn slow_start_threshold(&self) -> u32;
+    /// 新しいデータが確認応答された
+    fn on_ack(&mut self, acked: u32, mss: u32, srtt: Option<Duration>, now: Instant);
+    /// 3つ目の重複ACKを受け取り，高速再送を行った
+    fn on_enter_recovery(&mut self, flight_size: u32, mss: u32, now: Instant);
+    /// 高速回復中に重複ACKを受け取った
+    fn on_duplicate_ack_in_recovery(&mut self, mss: u32);
+    /// 高速回復中に，再送したデータの一部のみが確認応答された
+    fn on_partial_ack(&mut self, acked: u32, mss: u32);
+    /// 高速回復の開始時点で送信済みだったデータがすべて確認応答された
+    fn on_exit_recovery(&mut self, flight_size: u32, mss: u32);
+    /// 再送タイマが満了した
+    fn on_retransmission_timeout(&mut self, flight_size: u32, mss: u32);
+}
+
+impl CongestionControlAlgorithm {
+    pub fn build(self, mss: u32) -> Box<dyn CongestionControl> {
+        debug_assert!(mss >= super::MIN_MSS as u32);
+        match self {
+            CongestionControlAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+            CongestionControlAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+        }
+    }
+}
+
+/// 初期ウィンドウ
+/// See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-3.1)
+pub fn initial_window(mss: u32) -> u32 {
+    if mss > 2190 {
+        2 * mss
+    } else if mss > 1095 {
+        3 * mss
+    } else {
+        4 * mss
+    }
+}
+
+/// See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-3.1)
+fn slow_start_threshold_on_loss(flight_size: u32, mss: u32) -> u32 {
+    (flight_size / 2).max(2 * mss)
+}
+
+/// NewReno
+/// See also [RFC5681](https://tools.ietf.org/html/rfc5681),
+/// [RFC6582](https://tools.ietf.org/html/rfc6582)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewReno {
+    cwnd: u32,
+    ssthresh: u32,
+}
+
+impl NewReno {
+    pub fn new(mss: u32) -> Self {
+        Self {
+            cwnd: initial_window(mss),
+            ssthresh: u32::MAX,
+        }
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn algorithm(&self) -> CongestionControlAlgorithm {
+        CongestionControlAlgorithm::NewReno
+    }
+
+    fn window(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn slow_start_threshold(&self) -> u32 {
+        self.ssthresh
+    }
+
+    fn on_ack(&mut self, acked: u32, mss: u32, _srtt: Option<Duration>, _now: Instant) {
+        let increment = if self.cwnd < self.ssthresh {
+            // スロースタート
+            acked.min(mss)
+        } else {
+            // 輻輳回避．1RTTあたりおよそ1MSS増やす
+            (mss * mss / self.cwnd).max(1)
+        };
+        self.cwnd = self.cwnd.saturating_add(increment);
+    }
+
+    fn on_enter_recovery(&mut self, flight_size: u32, mss: u32, _now: Instant) {
+        self.ssthresh = slow_start_threshold_on_loss(flight_size, mss);
+        self.cwnd = self.ssthresh + 3 * mss;
+    }
+
+    fn on_duplicate_ack_in_recovery(&mut self, mss: u32) {
+        self.cwnd = self.cwnd.saturating_add(mss);
+    }
+
+    /// See also [RFC6582](https://tools.ietf.org/html/rfc6582#section-3.2)
+    fn on_partial_ack(&mut self, acked: u32, mss: u32) {
+        self.cwnd = self.cwnd.saturating_sub(acked);
+        if acked >= mss {
+            self.cwnd += mss;
+        }
+        self.cwnd = self.cwnd.max(mss);
+    }
+
+    fn on_exit_recovery(&mut self, flight_size: u32, mss: u32) {
+        self.cwnd = self.ssthresh.min(flight_size.max(mss) + mss);
+    }
+
+    fn on_retransmission_timeout(&mut self, flight_size: u32, mss: u32) {
+        self.ssthresh = slow_start_threshold_on_loss(flight_size, mss);
+        self.cwnd = mss;
+    }
+}
+
+/// CUBIC
+/// See also [RFC9438](https://tools.ietf.org/html/rfc9438)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cubic {
+    cwnd: u32,
+    ssthresh: u32,
+    /// 直前の輻輳発生時のウィンドウ(W_max)
+    w_max: f64,
+    /// 輻輳回避を開始した時刻
+    epoch_start: Option<Instant>,
+    /// ウィンドウがW_maxに戻るまでの時間(秒)
+    k: f64,
+    /// Renoと同等に振る舞った場合のウィンドウ(W_est)
+    w_est: f64,
+}
+
+impl Cubic {
+    const C: f64 = 0.4;
+    const BETA: f64 = 0.7;
+
+    pub fn new(mss: u32) -> Self {
+        Self {
+            cwnd: initial_window(mss),
+            ssthresh: u32::MAX,
+            w_max: 0.0,
+            epoch_start: None,
+            k: 0.0,
+            w_est: 0.0,
+        }
+    }
+
+    /// W_cubic(t) = C * (t - K)^3 + W_max (MSS単位)
+    fn w_cubic(&self, t: f64) -> f64 {
+        Self::C * (t - self.k).powi(3) + self.w_max
+    }
+
+    /// 輻輳が発生した際にW_maxとssthreshを更新する
+    /// See also [RFC9438](https://tools.ietf.org/html/rfc9438#section-4.6)
+    fn on_congestion(&mut self, mss: u32) {
+        let cwnd = self.cwnd as f64 / mss as f64;
+        // 高速収束
+        // See also [RFC9438](https://tools.ietf.org/html/rfc9438#section-4.7)
+        self.w_max = if cwnd < self.w_max {
+            cwnd * (1.0 + Self::BETA) / 2.0
+        } else {
+            cwnd
+        };
+        self.ssthresh = ((self.cwnd as f64 * Self::BETA) as u32).max(2 * mss);
+        self.epoch_start = None;
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn algorithm(&self) -> CongestionControlAlgorithm {
+        CongestionControlAlgorithm::Cubic
+    }
+
+    fn window(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn slow_start_threshold(&self) -> u32 {
+        self.ssthresh
+    }
+
+    fn on_ack(&mut self, acked: u32, mss: u32, srtt: Option<Duration>, now: Instant) {
+        if self.cwnd < self.ssthresh {
+            self.cwnd = self.cwnd.saturating_add(acked.min(mss));
+            return;
+        }
+
+        let mss_f = mss as f64;
+        let cwnd = self.cwnd as f64 / mss_f;
+        let epoch_start = match self.epoch_start {
+            Some(epoch_start) => epoch_start,
+            None => {
+                self.epoch_start = Some(now);
+                self.w_est = cwnd;
+                self.k = if cwnd < self.w_max {
+                    ((self.w_max - cwnd) / Self::C).cbrt()
+                } else {
+                    self.w_max = cwnd;
+                    0.0
+                };
+                now
+            }
+        };
+
+        // See also [RFC9438](https://tools.ietf.org/html/rfc9438#section-4.2)
+        let rtt = srtt.unwrap_or_default().as_secs_f64();
+        let t = now.saturating_duration_since(epoch_start).as_secs_f64();
+        let target = self.w_cubic(t + rtt).clamp(cwnd, cwnd * 1.5);
+
+        // Renoと同等以上の速度で増やす
+        // See also [RFC9438](https://tools.ietf.org/html/rfc9438#section-4.3)
+        let alpha = 3.0 * (1.0 - Self::BETA) / (1.0 + Self::BETA);
+        self.w_est += alpha * (acked as f64 / mss_f) / cwnd;
+        let target = target.max(self.w_est);
+
+        let increment = (target - cwnd) / cwnd * acked as f64;
+        self.cwnd = self.cwnd.saturating_add(increment as u32);
+    }
+
+    fn on_enter_recovery(&mut self, _flight_size: u32, mss: u32, _now: Instant) {
+        self.on_congestion(mss);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_duplicate_ack_in_recovery(&mut self, _mss: u32) {}
+
+    fn on_partial_ack(&mut self, _acked: u32, _mss: u32) {}
+
+    fn on_exit_recovery(&mut self, _flight_size: u32, _mss: u32) {
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_retransmission_timeout(&mut self, _flight_size: u32, mss: u32) {
+        self.on_congestion(mss);
+        self.cwnd = mss;
+    }
+}
+
+impl From<&str> for CongestionControlAlgorithm {
+    fn from(s: &str) -> Self {
+        match s {
+            "NewReno" => CongestionControlAlgorithm::NewReno,
+            "CUBIC" => CongestionControlAlgorithm::Cubic,
+            _ => panic!("unsupported congestion control => '{}'", s),
+        }
+    }
+}
+
+impl std::fmt::Display for CongestionControlAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let algorithm_str = match self {
+            CongestionControlAlgorithm::NewReno => "NewReno",
+            CongestionControlAlgorithm::Cubic => "CUBIC",
+        };
+        write!(f, "{}", algorithm_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u32 = 1000;
+
+    #[test]
+    fn new_reno_test() {
+        let now = Instant::now();
+        let mut cc = NewReno::new(MSS);
+        assert_eq!(4 * MSS, cc.window());
+
+        // スロースタートでは確認応答毎に1MSSずつ増える
+        cc.on_ack(MSS, MSS, None, now);
+        assert_eq!(5 * MSS, cc.window());
+
+        cc.on_enter_recovery(8 * MSS, MSS, now);
+        assert_eq!(4 * MSS, cc.slow_start_threshold());
+        assert_eq!(7 * MSS, cc.window());
+        cc.on_duplicate_ack_in_recovery(MSS);
+        assert_eq!(8 * MSS, cc.window());
+        cc.on_partial_ack(2 * MSS, MSS);
+        assert_eq!(7 * MSS, cc.window());
+        cc.on_exit_recovery(6 * MSS, MSS);
+        assert_eq!(4 * MSS, cc.window());
+
+        // 輻輳回避では1RTTあたりおよそ1MSS増える
+        for _ in 0..4 {
+            cc.on_ack(MSS, MSS, None, now);
+        }
+        assert!(4 * MSS < cc.window() && cc.window() <= 5 * MSS);
+
+        cc.on_retransmission_timeout(6 * MSS, MSS);
+        assert_eq!(3 * MSS, cc.slow_start_threshold());
+        assert_eq!(MSS, cc.window());
+    }
+
+    #[test]
+    fn cubic_test() {
+        let now = Instant::now();
+        let mut cc = Cubic::new(MSS);
+        for _ in 0..96 {
+            cc.on_ack(MSS, MSS, None, now);
+        }
+        assert_eq!(100 * MSS, cc.window());
+
+        // 乗法的減少はRenoより緩やか
+        cc.on_enter_recovery(100 * MSS, MSS, now);
+        assert_eq!(70 * MSS, cc.slow_start_threshold());
+        cc.on_exit_recovery(70 * MSS, MSS);
+        assert_eq!(70 * MSS, cc.window());
+
+        // 時間の経過に応じてW_maxに向かって回復する
+        let rtt = Duration::from_millis(100);
+        let mut t = now;
+        while cc.window() < 100 * MSS {
+            for _ in 0..(cc.window() / MSS) {
+                cc.on_ack(MSS, MSS, Some(rtt), t);
+            }
+            t += rtt;
+        }
+        // K = cbrt(30 / 0.4) ≒ 4.2秒
+        let elapsed = t.duration_since(now).as_secs_f64();
+        assert!((3.5..5.0).contains(&elapsed), "elapsed: {}", elapsed);
+    }
+}
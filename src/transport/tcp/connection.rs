@@ -0,0 +1,2351 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use super::{
+    CongestionControl, CongestionControlAlgorithm, ControlFlags, RttEstimator, Segment,
+    SegmentHeader, SegmentOption,
+};
+use crate::{
+    internet::{ip::IPHeader, ipv6::IPv6Header, IpAddr},
+    link,
+    option::TcpOption,
+    transport::TransportProtocolError,
+};
+
+/// MSSオプションを受け取っていない場合の送信MSS
+/// See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.7.1)
+pub const DEFAULT_MSS: usize = 536;
+/// 自身が受信できるMSS．SYNで相手に通知する
+/// See also [RFC879](https://tools.ietf.org/html/rfc879)
+pub const LOCAL_MSS: usize =
+    link::MTU - IPHeader::LEAST_LENGTH as usize - SegmentHeader::LEAST_LENGTH;
+/// IPv6上で自身が受信できるMSS．固定ヘッダの分だけIPv4より小さい
+/// See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-8.3)
+pub const LOCAL_IPV6_MSS: usize = link::MTU - IPv6Header::LENGTH - SegmentHeader::LEAST_LENGTH;
+/// 相手から受け取ったMSSの下限．オプションの最大長(40オクテット)を除いてもデータを運べるようにする
+/// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.2.2.6)
+pub const MIN_MSS: usize = 64;
+/// 受信バッファの大きさ
+pub const RECEIVE_BUFFER_SIZE: usize = 1 << 20;
+/// 順序外のデータとして保留するバイト数の上限
+pub const MAX_OUT_OF_ORDER_LEN: usize = RECEIVE_BUFFER_SIZE / 4;
+/// 順序外のデータとして保留するブロック数の上限
+pub const MAX_OUT_OF_ORDER_BLOCKS: usize = 64;
+/// 送信バッファの大きさ
+pub const SEND_BUFFER_SIZE: usize = 1 << 20;
+/// 通知するウィンドウスケール．受信バッファ全体を通知できる最小の値
+/// See also [RFC7323](https://tools.ietf.org/html/rfc7323#section-2)
+pub const WINDOW_SCALE_SHIFT: u8 = 5;
+/// ウィンドウスケールの上限
+pub const MAX_WINDOW_SCALE_SHIFT: u8 = 14;
+/// NOP2つとタイムスタンプオプションの長さ
+const TIMESTAMPS_OPTION_LENGTH: usize = 12;
+/// セグメントの最大生存時間．
+/// RFCでは2分とされているが，BSD系の実装に倣い短めにしている
+pub const MAXIMUM_SEGMENT_LIFETIME: Duration = Duration::from_secs(30);
+/// 高速再送を行う重複ACKの数
+/// See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-3.2)
+pub const DUPLICATE_ACK_THRESHOLD: u32 = 3;
+
+/// 遅延確認応答の既定の待ち時間
+/// See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.8.6.3)
+pub const DEFAULT_DELAYED_ACK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// キープアライブの設定
+/// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.2.3.6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// 最後にセグメントを受信してから，最初のプローブを送るまでの時間
+    pub idle: Duration,
+    /// プローブの送信間隔
+    pub interval: Duration,
+    /// 応答がないまま送るプローブの数．これを超えたら相手が停止したとみなす
+    pub count: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        // Linuxの tcp_keepalive_time, tcp_keepalive_intvl, tcp_keepalive_probes に倣う
+        Self {
+            idle: Duration::from_secs(7200),
+            interval: Duration::from_secs(75),
+            count: 9,
+        }
+    }
+}
+
+/// TCPの接続状態
+/// See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum State {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// 送信して確認応答を待っているセグメント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SentSegment {
+    seq: u32,
+    /// シーケンス番号空間での長さ(SYN/FINを含む)
+    len: u32,
+    sent_at: Instant,
+    /// 一度でも再送したか．再送したセグメントはRTTの計測に使わない
+    retransmitted: bool,
+    /// 相手がSACKで受信を通知したか
+    sacked: bool,
+}
+
+/// 損失からの回復中であることを表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    /// 重複ACKによる高速回復
+    Fast,
+    /// 再送タイムアウト後の回復
+    Timeout,
+}
+
+/// 接続を識別するソケットペア
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId {
+    pub local_addr: IpAddr,
+    pub local_port: u16,
+    pub remote_addr: IpAddr,
+    pub remote_port: u16,
+}
+
+impl ConnectionId {
+    /// 接続のアドレスファミリにおける，自身が受信できるMSS
+    pub fn local_mss(&self) -> usize {
+        match self.local_addr {
+            IpAddr::V4(_) => LOCAL_MSS,
+            IpAddr::V6(_) => LOCAL_IPV6_MSS,
+        }
+    }
+}
+
+/// TCB(Transmission Control Block)
+///
+/// セグメントの送受信そのものは行わず，受信したセグメントやユーザの操作に応じて
+/// 状態を遷移させ，送信すべきセグメントを `outgoing` に積む．
+/// 実際の送信は `tcp::protocol` が `take_outgoing()` で取り出して行う．
+#[derive(Debug)]
+pub struct Connection {
+    pub id: ConnectionId,
+    opt: TcpOption,
+    state: State,
+    /// LISTENからの受動オープンで作成された接続か
+    passive: bool,
+
+    /// 送信側の初期シーケンス番号
+    iss: u32,
+    /// 未確認の最も古いシーケンス番号
+    snd_una: u32,
+    /// 次に送信するシーケンス番号
+    snd_nxt: u32,
+    /// 相手の受信ウィンドウ
+    snd_wnd: u32,
+    /// 最後にウィンドウを更新したセグメントのシーケンス番号
+    snd_wl1: u32,
+    /// 最後にウィンドウを更新したセグメントの確認応答番号
+    snd_wl2: u32,
+    /// 送信MSS
+    snd_mss: usize,
+    /// 受信したウィンドウを左シフトする量
+    snd_wscale: u8,
+
+    /// 受信側の初期シーケンス番号
+    irs: u32,
+    /// 次に受信を期待するシーケンス番号
+    rcv_nxt: u32,
+    /// 最後に通知した受信ウィンドウ
+    rcv_wnd_advertised: u32,
+    /// 通知するウィンドウを右シフトする量
+    rcv_wscale: u8,
+    /// 最後に送った確認応答番号(Last.ACK.sent)
+    last_ack_sent: u32,
+
+    /// ウィンドウスケールオプションを交換したか
+    window_scaling: bool,
+    /// タイムスタンプオプションを交換したか
+    timestamps: bool,
+    /// 相手に返すタイムスタンプ(TS.Recent)
+    ts_recent: u32,
+    /// TSvalの基準時刻と，接続毎に加えるランダムなオフセット
+    ts_origin: Instant,
+    ts_offset: u32,
+    /// SACK-Permittedオプションを交換したか
+    sack: bool,
+    /// 最後に受信した順序外のデータ．SACKの最初のブロックにする
+    last_out_of_order_seq: Option<u32>,
+
+    /// 未確認及び未送信のデータ．先頭は `snd_buf_seq` に対応する
+    send_buffer: VecDeque<u8>,
+    snd_buf_seq: u32,
+    /// 受信済みでユーザが読み出していないデータ
+    recv_buffer: VecDeque<u8>,
+    /// 順序が入れ替わって届いたデータ．キーは先頭のシーケンス番号．
+    /// 各ブロックは重ならず，隣接するものは1つにまとめる
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+
+    /// ユーザがcloseを要求したか
+    fin_requested: bool,
+    /// 送信したFINのシーケンス番号
+    fin_seq: Option<u32>,
+    /// 相手からFINを受信したか
+    fin_received: bool,
+    /// 順序が入れ替わって届いたFINのシーケンス番号
+    pending_fin_seq: Option<u32>,
+
+    /// 再送キュー．送信順に並んでいる
+    retransmission_queue: VecDeque<SentSegment>,
+    /// 再送タイマの満了時刻
+    retransmission_deadline: Option<Instant>,
+    /// 連続してタイムアウトした回数
+    retransmission_count: u32,
+    rtt: RttEstimator,
+
+    cc: Box<dyn CongestionControl>,
+    /// 連続して受け取った重複ACKの数
+    dup_acks: u32,
+    recovery: Option<Recovery>,
+    /// 回復を開始した時点のSND.NXT
+    /// See also [RFC6582](https://tools.ietf.org/html/rfc6582#section-3.2)
+    recover: u32,
+
+    /// 確認応答を送る必要があるか
+    ack_pending: bool,
+    /// 遅延させている確認応答を送る時刻
+    delayed_ack_deadline: Option<Instant>,
+    /// 最後に受け入れ可能なセグメントを受信した時刻
+    last_received: Instant,
+    /// 応答のないキープアライブプローブの数
+    keepalive_probes: u32,
+    time_wait_deadline: Option<Instant>,
+    error: Option<TransportProtocolError>,
+    /// 接続を中断しないICMPエラー．タイムアウトした際に原因として報告する
+    soft_error: Option<TransportProtocolError>,
+    outgoing: VecDeque<Segment>,
+}
+
+impl Connection {
+    fn new(
+        id: ConnectionId,
+        opt: &TcpOption,
+        iss: u32,
+        state: State,
+        passive: bool,
+        now: Instant,
+    ) -> Self {
+        Self {
+            id,
+            opt: opt.clone(),
+            state,
+            passive,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            snd_mss: DEFAULT_MSS,
+            snd_wscale: 0,
+            irs: 0,
+            rcv_nxt: 0,
+            rcv_wnd_advertised: 0,
+            rcv_wscale: 0,
+            last_ack_sent: 0,
+            window_scaling: false,
+            timestamps: false,
+            ts_recent: 0,
+            ts_origin: now,
+            ts_offset: rand::random(),
+            sack: false,
+            last_out_of_order_seq: None,
+            send_buffer: VecDeque::new(),
+            snd_buf_seq: iss.wrapping_add(1),
+            recv_buffer: VecDeque::new(),
+            out_of_order: BTreeMap::new(),
+            fin_requested: false,
+            fin_seq: None,
+            fin_received: false,
+            pending_fin_seq: None,
+            retransmission_queue: VecDeque::new(),
+            retransmission_deadline: None,
+            retransmission_count: 0,
+            rtt: Default::default(),
+            cc: opt.congestion_control.build(DEFAULT_MSS as u32),
+            dup_acks: 0,
+            recovery: None,
+            recover: iss,
+            ack_pending: false,
+            delayed_ack_deadline: None,
+            last_received: now,
+            keepalive_probes: 0,
+            time_wait_deadline: None,
+            error: None,
+            soft_error: None,
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    /// 能動オープン．SYNを送信してSYN-SENTに遷移する
+    pub fn connect(id: ConnectionId, opt: &TcpOption, iss: u32, now: Instant) -> Self {
+        let mut conn = Self::new(id, opt, iss, State::SynSent, false, now);
+        conn.send_syn(now);
+        conn
+    }
+
+    /// LISTEN状態のポートにSYNが届いた場合の受動オープン．
+    /// SYN/ACKを送信してSYN-RECEIVEDに遷移する
+    pub fn accept(
+        id: ConnectionId,
+        opt: &TcpOption,
+        syn: &Segment,
+        iss: u32,
+        now: Instant,
+    ) -> Self {
+        let mut conn = Self::new(id, opt, iss, State::SynReceived, true, now);
+        conn.irs = syn.header.sequence_number;
+        conn.rcv_nxt = syn.header.sequence_number.wrapping_add(1);
+        conn.snd_wnd = syn.header.window as u32;
+        conn.snd_wl1 = syn.header.sequence_number;
+        conn.apply_syn_options(&syn.header);
+        conn.send_syn(now);
+        conn
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_passive(&self) -> bool {
+        self.passive
+    }
+
+    pub fn congestion_control(&self) -> CongestionControlAlgorithm {
+        self.cc.algorithm()
+    }
+
+    /// 輻輳制御アルゴリズムを切り替える．輻輳ウィンドウは初期値に戻る
+    pub fn set_congestion_control(&mut self, algorithm: CongestionControlAlgorithm) {
+        self.cc = algorithm.build(self.snd_mss as u32);
+    }
+
+    pub fn nodelay(&self) -> bool {
+        self.opt.nodelay
+    }
+
+    /// Nagleアルゴリズムを無効にするか(TCP_NODELAY)．
+    /// 無効にした場合は，保留していたデータを直ちに送る
+    pub fn set_nodelay(&mut self, nodelay: bool, now: Instant) {
+        self.opt.nodelay = nodelay;
+        if nodelay {
+            self.output(now);
+        }
+    }
+
+    pub fn keepalive(&self) -> Option<Keepalive> {
+        self.opt.keepalive
+    }
+
+    /// キープアライブを設定する．`None` の場合は無効になる
+    pub fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        self.opt.keepalive = keepalive;
+        self.keepalive_probes = 0;
+    }
+
+    pub fn delayed_ack(&self) -> Option<Duration> {
+        self.opt.delayed_ack
+    }
+
+    /// 確認応答を遅延させる時間を設定する．`None` の場合は直ちに確認応答を返す
+    pub fn set_delayed_ack(&mut self, timeout: Option<Duration>, now: Instant) {
+        self.opt.delayed_ack = timeout;
+        if timeout.is_none() && self.delayed_ack_deadline.is_some() {
+            self.ack_pending = true;
+            self.output(now);
+        }
+    }
+
+    /// 接続が異常終了した場合，その原因
+    pub fn error(&self) -> Option<TransportProtocolError> {
+        self.error
+    }
+
+    /// 送信すべきセグメントを取り出す
+    pub fn take_outgoing(&mut self) -> Vec<Segment> {
+        self.outgoing.drain(..).collect()
+    }
+
+    /// 読み出し可能なデータ量
+    pub fn readable_len(&self) -> usize {
+        self.recv_buffer.len()
+    }
+
+    /// 相手がこれ以上データを送ってこないか
+    pub fn is_read_closed(&self) -> bool {
+        self.fin_received || self.state == State::Closed
+    }
+
+    /// 送信バッファの空き容量
+    pub fn send_buffer_space(&self) -> usize {
+        SEND_BUFFER_SIZE - self.send_buffer.len()
+    }
+
+    /// 送信済みのデータがすべて確認応答されているか
+    pub fn is_all_acknowledged(&self) -> bool {
+        self.snd_una == self.snd_nxt && self.send_buffer.is_empty()
+    }
+
+    /// データを送信バッファに積む．積めたバイト数を返す
+    pub fn send(&mut self, data: &[u8], now: Instant) -> Result<usize, TransportProtocolError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        match self.state {
+            State::SynSent | State::SynReceived | State::Established | State::CloseWait => {}
+            State::Closed => return Err(TransportProtocolError::ConnectionNotFound),
+            _ => return Err(TransportProtocolError::ConnectionClosing),
+        }
+        if self.fin_requested {
+            return Err(TransportProtocolError::ConnectionClosing);
+        }
+
+        let len = data.len().min(self.send_buffer_space());
+        self.send_buffer.extend(&data[..len]);
+        self.output(now);
+
+        Ok(len)
+    }
+
+    /// 受信バッファからデータを読み出す
+    pub fn recv(&mut self, buf: &mut [u8], now: Instant) -> usize {
+        let len = buf.len().min(self.recv_buffer.len());
+        for (dst, src) in buf.iter_mut().zip(self.recv_buffer.drain(..len)) {
+            *dst = src;
+        }
+
+        // 受信側のSWS回避．ウィンドウが十分に開いた場合のみ更新を通知する
+        // See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.8.6.2.2)
+        let threshold = (RECEIVE_BUFFER_SIZE as u32 / 2).min(self.snd_mss as u32);
+        if self.is_synchronized()
+            && !self.fin_received
+            && self
+                .advertisable_window()
+                .saturating_sub(self.rcv_wnd_advertised)
+                >= threshold
+        {
+            self.ack_pending = true;
+            self.output(now);
+        }
+
+        len
+    }
+
+    /// 送信の終了を要求する．送信バッファのデータを送り切った後にFINを送る
+    pub fn close(&mut self, now: Instant) {
+        match self.state {
+            State::SynSent => {
+                self.state = State::Closed;
+                return;
+            }
+            State::SynReceived | State::Established => {
+                self.state = State::FinWait1;
+            }
+            State::CloseWait => {
+                self.state = State::LastAck;
+            }
+            _ => return,
+        }
+        self.fin_requested = true;
+        self.output(now);
+    }
+
+    /// 接続を強制終了する．同期済みであればRSTを送る
+    pub fn abort(&mut self) {
+        if self.is_synchronized() {
+            let rst = self.make_reset(self.snd_nxt);
+            self.outgoing.push_back(rst);
+        }
+        self.clear_send_state();
+        self.state = State::Closed;
+    }
+
+    /// 送信したセグメントに対するICMPエラーを受信した際の処理．
+    /// 接続の確立中に届いた到達不能は致命的なエラーとして接続を中断し，
+    /// それ以外はタイムアウトした際に原因として報告する
+    /// See also [RFC5461](https://tools.ietf.org/html/rfc5461#section-4)
+    pub fn on_icmp_error(&mut self, seq: u32, error: TransportProtocolError) {
+        // 確認応答されていないシーケンス番号でなければ，偽装されたものとみなす
+        // See also [RFC5927](https://tools.ietf.org/html/rfc5927#section-4.1)
+        if !(seq_le(self.snd_una, seq) && seq_lt(seq, self.snd_nxt)) {
+            return;
+        }
+
+        match error {
+            // 経路MTU探索は未実装なので，Fragmentation Neededは扱わない
+            TransportProtocolError::MessageTooLong => {}
+            TransportProtocolError::ConnectionRefused
+            | TransportProtocolError::ProtocolUnreachable
+                if self.state == State::SynSent =>
+            {
+                self.error = Some(error);
+                self.clear_send_state();
+                self.state = State::Closed;
+            }
+            _ => self.soft_error = Some(error),
+        }
+    }
+
+    /// タイマの処理
+    pub fn on_timer(&mut self, now: Instant) {
+        if let Some(deadline) = self.time_wait_deadline {
+            if self.state == State::TimeWait && deadline <= now {
+                self.state = State::Closed;
+            }
+        }
+        if let Some(deadline) = self.retransmission_deadline {
+            if deadline <= now {
+                self.on_retransmission_timeout(now);
+            }
+        }
+        if let Some(deadline) = self.delayed_ack_deadline {
+            if deadline <= now {
+                self.ack_pending = true;
+                self.output(now);
+            }
+        }
+        if let Some(deadline) = self.keepalive_deadline() {
+            if deadline <= now {
+                self.on_keepalive_timeout(now);
+            }
+        }
+    }
+
+    /// 次にタイマを処理すべき時刻
+    pub fn next_deadline(&self) -> Option<Instant> {
+        [
+            self.retransmission_deadline,
+            self.time_wait_deadline,
+            self.delayed_ack_deadline,
+            self.keepalive_deadline(),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+    }
+
+    /// 次にキープアライブプローブを送る時刻．
+    /// 送信中のデータがある場合は，再送タイマで相手の停止を検知する
+    fn keepalive_deadline(&self) -> Option<Instant> {
+        let keepalive = self.opt.keepalive?;
+        if !matches!(self.state, State::Established | State::CloseWait)
+            || !self.retransmission_queue.is_empty()
+        {
+            return None;
+        }
+        Some(self.last_received + keepalive.idle + keepalive.interval * self.keepalive_probes)
+    }
+
+    /// キープアライブプローブを送る．応答がないまま規定数に達したら接続を切る
+    /// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.2.3.6)
+    fn on_keepalive_timeout(&mut self, now: Instant) {
+        let keepalive = match self.opt.keepalive {
+            Some(keepalive) => keepalive,
+            None => return,
+        };
+        if self.keepalive_probes >= keepalive.count {
+            self.abort();
+            self.error = Some(self.timeout_error());
+            return;
+        }
+
+        // 確認応答済みのシーケンス番号を使い，相手に確認応答を返させる
+        let seg = self.make_segment(
+            self.snd_una.wrapping_sub(1),
+            ControlFlags::ACK,
+            Vec::new(),
+            now,
+        );
+        self.outgoing.push_back(seg);
+        self.keepalive_probes += 1;
+    }
+
+    /// 再送タイマが満了した際の処理
+    /// See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-5)
+    fn on_retransmission_timeout(&mut self, now: Instant) {
+        let limit = match self.state {
+            State::SynSent | State::SynReceived => self.opt.max_syn_retransmissions,
+            _ => self.opt.max_retransmissions,
+        };
+        if self.retransmission_count >= limit {
+            // 受け入れ前の受動オープンはアプリケーションが知らないので，エラーを残さない
+            if !(self.state == State::SynReceived && self.passive) {
+                self.error = Some(self.timeout_error());
+            }
+            self.clear_send_state();
+            self.state = State::Closed;
+            return;
+        }
+
+        // 2回目以降のタイムアウトではssthreshを更新しない
+        // See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-3.1)
+        if self.is_synchronized() {
+            if self.retransmission_count == 0 {
+                self.cc
+                    .on_retransmission_timeout(self.flight_size(), self.snd_mss as u32);
+            }
+            self.recovery = Some(Recovery::Timeout);
+            self.recover = self.snd_nxt;
+            self.dup_acks = 0;
+        }
+        // 相手が受信済みのデータを破棄している可能性があるので，SACKの情報は使わない
+        // See also [RFC2018](https://tools.ietf.org/html/rfc2018#section-8)
+        for sent in self.retransmission_queue.iter_mut() {
+            sent.sacked = false;
+        }
+
+        self.retransmission_count += 1;
+        self.rtt.backoff();
+        self.retransmit_oldest(now);
+        self.retransmission_deadline = Some(now + self.rtt.rto());
+    }
+
+    /// 再送キューの先頭のセグメントを再送する
+    fn retransmit_oldest(&mut self, now: Instant) {
+        self.retransmit(0, now);
+    }
+
+    /// SACKのスコアボードから，失われたと推定される最も古いセグメントを再送する．
+    /// より後ろのデータが受信されていて，まだ再送していないものを対象にする
+    /// See also [RFC6675](https://tools.ietf.org/html/rfc6675#section-4)
+    fn retransmit_next_hole(&mut self, now: Instant) {
+        if !self.sack {
+            self.retransmit_oldest(now);
+            return;
+        }
+        let highest_sacked = self
+            .retransmission_queue
+            .iter()
+            .rev()
+            .find(|sent| sent.sacked)
+            .map(|sent| sent.seq.wrapping_add(sent.len));
+        let hole = self.retransmission_queue.iter().position(|sent| {
+            !sent.sacked
+                && !sent.retransmitted
+                && highest_sacked.is_none_or(|highest| seq_lt(sent.seq, highest))
+        });
+        if let Some(index) = hole {
+            self.retransmit(index, now);
+        }
+    }
+
+    /// 再送キューの `index` 番目のセグメントを再送する
+    fn retransmit(&mut self, index: usize, now: Instant) {
+        let (seq, len) = match self.retransmission_queue.get_mut(index) {
+            Some(sent) => {
+                sent.retransmitted = true;
+                (sent.seq, sent.len)
+            }
+            None => return,
+        };
+
+        if seq == self.iss && matches!(self.state, State::SynSent | State::SynReceived) {
+            self.send_syn(now);
+            return;
+        }
+
+        // 一部が確認応答されている場合は，未確認の部分のみを送る
+        let start = if seq_lt(seq, self.snd_una) {
+            self.snd_una
+        } else {
+            seq
+        };
+        let end = seq.wrapping_add(len);
+        let fin = self
+            .fin_seq
+            .filter(|&fin_seq| seq_le(start, fin_seq) && seq_lt(fin_seq, end));
+
+        let offset = start.wrapping_sub(self.snd_buf_seq) as usize;
+        let data_end = fin.unwrap_or(end);
+        let data_len = (data_end.wrapping_sub(start) as usize)
+            .min(self.send_buffer.len().saturating_sub(offset))
+            .min(self.max_payload_len());
+        let payload: Vec<u8> = self
+            .send_buffer
+            .range(offset..offset + data_len)
+            .copied()
+            .collect();
+
+        let mut flags = ControlFlags::ACK;
+        if fin.is_some() && start.wrapping_add(data_len as u32) == fin.unwrap() {
+            flags |= ControlFlags::FIN;
+        }
+        let seg = self.make_segment(start, flags, payload, now);
+        self.outgoing.push_back(seg);
+    }
+
+    /// タイムアウトで接続を切る際に報告するエラー
+    fn timeout_error(&self) -> TransportProtocolError {
+        self.soft_error
+            .unwrap_or(TransportProtocolError::ConnectionTimedOut)
+    }
+
+    /// 送信バッファと再送キューを破棄する
+    fn clear_send_state(&mut self) {
+        self.send_buffer.clear();
+        self.retransmission_queue.clear();
+        self.retransmission_deadline = None;
+    }
+
+    /// セグメント受信時の処理
+    /// See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.10.7)
+    pub fn on_segment(&mut self, seg: &Segment, now: Instant) {
+        match self.state {
+            State::Closed | State::Listen => {
+                if let Some(rst) = reset_for(seg) {
+                    self.outgoing.push_back(rst);
+                }
+                return;
+            }
+            State::SynSent => {
+                self.on_segment_in_syn_sent(seg, now);
+                return;
+            }
+            _ => {}
+        }
+
+        let hdr = &seg.header;
+        let flags = hdr.flags;
+
+        // SYN/ACKが失われた場合，相手はSYNを再送してくるので応答し直す
+        if self.state == State::SynReceived
+            && flags.contains(ControlFlags::SYN)
+            && !flags.contains(ControlFlags::ACK)
+            && hdr.sequence_number == self.irs
+        {
+            self.send_syn(now);
+            return;
+        }
+
+        // PAWS: 古いタイムスタンプを持つセグメントは，以前の接続のものとみなして捨てる
+        // See also [RFC7323](https://tools.ietf.org/html/rfc7323#section-5.3)
+        let ts_val = hdr.timestamps().map(|(ts_val, _)| ts_val);
+        if self.timestamps
+            && !flags.contains(ControlFlags::RST)
+            && ts_val.is_some_and(|ts_val| seq_lt(ts_val, self.ts_recent))
+        {
+            self.ack_pending = true;
+            self.output(now);
+            return;
+        }
+
+        // 1. シーケンス番号の検査
+        let (acceptable, text_acceptable) = self.is_acceptable(seg);
+        if !acceptable {
+            if !flags.contains(ControlFlags::RST) {
+                self.ack_pending = true;
+                self.output(now);
+            }
+            return;
+        }
+
+        // See also [RFC7323](https://tools.ietf.org/html/rfc7323#section-4.3)
+        if let Some(ts_val) = ts_val {
+            if self.timestamps
+                && seq_ge(ts_val, self.ts_recent)
+                && seq_le(hdr.sequence_number, self.last_ack_sent)
+            {
+                self.ts_recent = ts_val;
+            }
+        }
+        self.last_received = now;
+        self.keepalive_probes = 0;
+
+        // 2. RSTの検査
+        if flags.contains(ControlFlags::RST) {
+            // 盲目的なリセット攻撃への対策として，完全に一致する場合のみ受け入れる
+            // See also [RFC5961](https://tools.ietf.org/html/rfc5961#section-3.2)
+            if hdr.sequence_number != self.rcv_nxt {
+                self.send_challenge_ack(now);
+                return;
+            }
+
+            match self.state {
+                State::SynReceived if !self.passive => {
+                    self.error = Some(TransportProtocolError::ConnectionRefused);
+                }
+                State::SynReceived => {}
+                State::Established | State::FinWait1 | State::FinWait2 | State::CloseWait => {
+                    self.error = Some(TransportProtocolError::ConnectionReset);
+                }
+                _ => {}
+            }
+            self.clear_send_state();
+            self.state = State::Closed;
+            return;
+        }
+
+        // 4. SYNの検査
+        if flags.contains(ControlFlags::SYN) {
+            if self.state == State::SynReceived && self.passive {
+                self.state = State::Closed;
+                return;
+            }
+            // See also [RFC5961](https://tools.ietf.org/html/rfc5961#section-4.2)
+            self.send_challenge_ack(now);
+            return;
+        }
+
+        // 5. ACKの検査
+        if !flags.contains(ControlFlags::ACK) {
+            return;
+        }
+        let ack = hdr.acknowledgment_number;
+
+        if self.state == State::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+                self.state = State::Established;
+                self.snd_wnd = self.segment_window(hdr);
+                self.snd_wl1 = hdr.sequence_number;
+                self.snd_wl2 = ack;
+                if self.retransmission_count > 0 {
+                    self.rtt.on_syn_retransmitted();
+                }
+            } else {
+                let rst = self.make_reset(ack);
+                self.outgoing.push_back(rst);
+                return;
+            }
+        }
+
+        // まだ送信していないデータへの確認応答
+        if seq_gt(ack, self.snd_nxt) {
+            self.ack_pending = true;
+            self.output(now);
+            return;
+        }
+
+        let ts_ecr = hdr.timestamps().map(|(_, ts_ecr)| ts_ecr);
+        if seq_lt(self.snd_una, ack) {
+            // SYNの分は除く
+            let acked = ack.wrapping_sub(self.snd_una) - (self.snd_una == self.iss) as u32;
+            self.acknowledge(ack, ts_ecr, now);
+            self.update_scoreboard(hdr.sack_blocks());
+            self.on_new_ack(ack, acked, now);
+        } else {
+            self.update_scoreboard(hdr.sack_blocks());
+            if self.is_duplicate_ack(seg) {
+                self.on_duplicate_ack(now);
+            }
+        }
+
+        // 送信ウィンドウの更新
+        if seq_le(self.snd_una, ack)
+            && (seq_lt(self.snd_wl1, hdr.sequence_number)
+                || (self.snd_wl1 == hdr.sequence_number && seq_le(self.snd_wl2, ack)))
+        {
+            self.snd_wnd = self.segment_window(hdr);
+            self.snd_wl1 = hdr.sequence_number;
+            self.snd_wl2 = ack;
+        }
+
+        let fin_acked = self.fin_seq.is_some_and(|fin_seq| seq_gt(ack, fin_seq));
+        match self.state {
+            State::FinWait1 if fin_acked => {
+                self.state = State::FinWait2;
+            }
+            State::Closing if fin_acked => {
+                self.enter_time_wait(now);
+            }
+            State::LastAck if fin_acked => {
+                self.clear_send_state();
+                self.state = State::Closed;
+                return;
+            }
+            _ => {}
+        }
+
+        // 7. データの処理
+        if text_acceptable
+            && !seg.payload.is_empty()
+            && matches!(
+                self.state,
+                State::Established | State::FinWait1 | State::FinWait2
+            )
+        {
+            let in_order = hdr.sequence_number == self.rcv_nxt && self.out_of_order.is_empty();
+            self.receive_text(hdr.sequence_number, &seg.payload, now);
+            self.schedule_ack(in_order && self.out_of_order.is_empty(), now);
+        }
+
+        // 8. FINの処理
+        if flags.contains(ControlFlags::FIN) && text_acceptable {
+            let fin_seq = hdr.sequence_number.wrapping_add(seg.payload.len() as u32);
+            if fin_seq == self.rcv_nxt {
+                self.receive_fin(now);
+            } else if seq_gt(fin_seq, self.rcv_nxt) {
+                self.pending_fin_seq = Some(fin_seq);
+            }
+            self.ack_pending = true;
+        }
+
+        self.output(now);
+    }
+
+    fn on_segment_in_syn_sent(&mut self, seg: &Segment, now: Instant) {
+        let hdr = &seg.header;
+        let flags = hdr.flags;
+
+        if flags.contains(ControlFlags::ACK) {
+            let ack = hdr.acknowledgment_number;
+            if seq_le(ack, self.iss) || seq_gt(ack, self.snd_nxt) {
+                if !flags.contains(ControlFlags::RST) {
+                    let rst = self.make_reset(ack);
+                    self.outgoing.push_back(rst);
+                }
+                return;
+            }
+        }
+
+        if flags.contains(ControlFlags::RST) {
+            // ACKが受け入れ可能な場合のみ接続を拒否されたとみなす
+            if flags.contains(ControlFlags::ACK) {
+                self.error = Some(TransportProtocolError::ConnectionRefused);
+                self.clear_send_state();
+                self.state = State::Closed;
+            }
+            return;
+        }
+
+        if !flags.contains(ControlFlags::SYN) {
+            return;
+        }
+
+        self.irs = hdr.sequence_number;
+        self.rcv_nxt = hdr.sequence_number.wrapping_add(1);
+        self.apply_syn_options(hdr);
+
+        let syn_retransmitted = self.retransmission_count > 0;
+        if flags.contains(ControlFlags::ACK) {
+            let ts_ecr = hdr.timestamps().map(|(_, ts_ecr)| ts_ecr);
+            self.acknowledge(hdr.acknowledgment_number, ts_ecr, now);
+        }
+
+        if seq_gt(self.snd_una, self.iss) {
+            self.state = State::Established;
+            self.snd_wnd = self.segment_window(hdr);
+            self.snd_wl1 = hdr.sequence_number;
+            self.snd_wl2 = hdr.acknowledgment_number;
+            if syn_retransmitted {
+                self.rtt.on_syn_retransmitted();
+            }
+            self.ack_pending = true;
+            self.output(now);
+        } else {
+            // 同時オープン
+            self.state = State::SynReceived;
+            self.snd_wnd = self.segment_window(hdr);
+            self.snd_wl1 = hdr.sequence_number;
+            self.send_syn(now);
+        }
+    }
+
+    /// SYNに含まれるオプションから，接続で使用するオプションを決める
+    fn apply_syn_options(&mut self, hdr: &SegmentHeader) {
+        let mut mss = DEFAULT_MSS;
+        let mut window_scale = None;
+        let mut sack_permitted = false;
+        for option in hdr.options.iter() {
+            match option {
+                SegmentOption::MaximumSegmentSize(peer_mss) => mss = *peer_mss as usize,
+                SegmentOption::WindowScale(shift) => window_scale = Some(*shift),
+                SegmentOption::SackPermitted => sack_permitted = true,
+                _ => {}
+            }
+        }
+
+        self.snd_mss = mss.clamp(MIN_MSS, self.id.local_mss());
+        // 双方がオプションを送った場合のみ有効になる
+        if let Some(shift) = window_scale.filter(|_| self.opt.window_scaling) {
+            self.window_scaling = true;
+            self.snd_wscale = shift.min(MAX_WINDOW_SCALE_SHIFT);
+            self.rcv_wscale = WINDOW_SCALE_SHIFT;
+        }
+        if let Some((ts_val, _)) = hdr.timestamps().filter(|_| self.opt.timestamps) {
+            self.timestamps = true;
+            self.ts_recent = ts_val;
+        }
+        self.sack = sack_permitted && self.opt.sack;
+        self.cc = self.cc.algorithm().build(self.snd_mss as u32);
+    }
+
+    /// シーケンス番号の検査．
+    /// (セグメントを受け入れるか, データを受け入れるか) を返す
+    fn is_acceptable(&self, seg: &Segment) -> (bool, bool) {
+        let seq = seg.header.sequence_number;
+        let seg_len = seg.len();
+        let rcv_wnd = self.rcv_window_end().wrapping_sub(self.rcv_nxt);
+
+        match (seg_len, rcv_wnd) {
+            (0, 0) => (seq == self.rcv_nxt, true),
+            (0, _) => (seq_in_window(seq, self.rcv_nxt, rcv_wnd), true),
+            // ウィンドウが0でも，ACKやRSTは処理する必要がある
+            (_, 0) => (seq == self.rcv_nxt, false),
+            (_, _) => {
+                let last = seq.wrapping_add(seg_len - 1);
+                let acceptable = seq_in_window(seq, self.rcv_nxt, rcv_wnd)
+                    || seq_in_window(last, self.rcv_nxt, rcv_wnd);
+                (acceptable, acceptable)
+            }
+        }
+    }
+
+    /// 確認応答されたデータを送信バッファ及び再送キューから取り除く
+    fn acknowledge(&mut self, ack: u32, ts_ecr: Option<u32>, now: Instant) {
+        if seq_gt(ack, self.snd_buf_seq) {
+            let acked = (ack.wrapping_sub(self.snd_buf_seq) as usize).min(self.send_buffer.len());
+            self.send_buffer.drain(..acked);
+            self.snd_buf_seq = self.snd_buf_seq.wrapping_add(acked as u32);
+        }
+        self.snd_una = ack;
+
+        // Karnのアルゴリズム: 再送したセグメントへの確認応答からはRTTを計測しない
+        // See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-3)
+        let mut rtt_sample = None;
+        let mut ambiguous = false;
+        while let Some(sent) = self.retransmission_queue.front() {
+            if seq_gt(sent.seq.wrapping_add(sent.len), ack) {
+                break;
+            }
+            ambiguous |= sent.retransmitted;
+            rtt_sample = Some(now.saturating_duration_since(sent.sent_at));
+            self.retransmission_queue.pop_front();
+        }
+        // タイムスタンプを使う場合は，再送したセグメントでも正確に計測できる
+        // See also [RFC7323](https://tools.ietf.org/html/rfc7323#section-4.1)
+        if let Some(ts_ecr) = ts_ecr.filter(|&ts_ecr| self.timestamps && ts_ecr != 0) {
+            let rtt = self.ts_value(now).wrapping_sub(ts_ecr);
+            self.rtt.sample(Duration::from_millis(rtt as u64));
+        } else if let Some(rtt) = rtt_sample.filter(|_| !ambiguous) {
+            self.rtt.sample(rtt);
+        }
+
+        // 新しいデータが確認応答されたらタイマを再始動する
+        self.retransmission_count = 0;
+        self.retransmission_deadline = if self.retransmission_queue.is_empty() {
+            None
+        } else {
+            Some(now + self.rtt.rto())
+        };
+    }
+
+    /// 新しいデータへの確認応答を輻輳制御に反映する
+    fn on_new_ack(&mut self, ack: u32, acked: u32, now: Instant) {
+        let mss = self.snd_mss as u32;
+        let srtt = self.rtt.srtt();
+        match self.recovery {
+            // 部分的な確認応答．次の未確認セグメントも失われたとみなして再送する
+            // See also [RFC6582](https://tools.ietf.org/html/rfc6582#section-3.2)
+            Some(recovery) if seq_lt(ack, self.recover) => {
+                self.retransmit_next_hole(now);
+                match recovery {
+                    Recovery::Fast => self.cc.on_partial_ack(acked, mss),
+                    Recovery::Timeout => self.cc.on_ack(acked, mss, srtt, now),
+                }
+            }
+            Some(Recovery::Fast) => {
+                self.recovery = None;
+                self.cc.on_exit_recovery(self.flight_size(), mss);
+            }
+            Some(Recovery::Timeout) => {
+                self.recovery = None;
+                self.cc.on_ack(acked, mss, srtt, now);
+            }
+            None => self.cc.on_ack(acked, mss, srtt, now),
+        }
+        self.dup_acks = 0;
+    }
+
+    /// 重複ACKか．ウィンドウの更新やデータを運ぶセグメントは含めない
+    /// See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-2)
+    fn is_duplicate_ack(&self, seg: &Segment) -> bool {
+        let hdr = &seg.header;
+        self.snd_una != self.snd_nxt
+            && hdr.acknowledgment_number == self.snd_una
+            && seg.payload.is_empty()
+            && !hdr.flags.contains(ControlFlags::SYN)
+            && !hdr.flags.contains(ControlFlags::FIN)
+            && self.segment_window(hdr) == self.snd_wnd
+    }
+
+    /// 高速再送・高速回復
+    /// See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-3.2)
+    fn on_duplicate_ack(&mut self, now: Instant) {
+        self.dup_acks += 1;
+        let mss = self.snd_mss as u32;
+        match self.recovery {
+            Some(Recovery::Fast) => {
+                self.cc.on_duplicate_ack_in_recovery(mss);
+                self.retransmit_next_hole(now);
+            }
+            // 前回の回復中に送ったデータに対する重複ACKでは回復を始めない
+            None if self.dup_acks == DUPLICATE_ACK_THRESHOLD
+                && seq_ge(self.snd_una, self.recover) =>
+            {
+                self.recovery = Some(Recovery::Fast);
+                self.recover = self.snd_nxt;
+                self.cc.on_enter_recovery(self.flight_size(), mss, now);
+                self.retransmit_oldest(now);
+            }
+            _ => {}
+        }
+    }
+
+    /// SACKブロックで通知された範囲を再送キューに記録する
+    /// See also [RFC2018](https://tools.ietf.org/html/rfc2018#section-3)
+    fn update_scoreboard(&mut self, blocks: &[(u32, u32)]) {
+        if !self.sack {
+            return;
+        }
+        for &(left, right) in blocks.iter() {
+            // 未確認の範囲外を指すブロックは無視する
+            if !(seq_lt(left, right) && seq_ge(left, self.snd_una) && seq_le(right, self.snd_nxt)) {
+                continue;
+            }
+            for sent in self.retransmission_queue.iter_mut() {
+                if seq_le(left, sent.seq) && seq_le(sent.seq.wrapping_add(sent.len), right) {
+                    sent.sacked = true;
+                }
+            }
+        }
+    }
+
+    /// 送信して確認応答されていないデータ量
+    fn flight_size(&self) -> u32 {
+        self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    /// 受信データをバッファに格納する．
+    /// 順序が入れ替わっている場合は保留しておく
+    fn receive_text(&mut self, seq: u32, payload: &[u8], now: Instant) {
+        let window_end = self.rcv_window_end();
+
+        // ウィンドウの前後にはみ出している部分を切り捨てる
+        let (mut seq, mut payload) = (seq, payload);
+        if seq_lt(seq, self.rcv_nxt) {
+            let skip = (self.rcv_nxt.wrapping_sub(seq) as usize).min(payload.len());
+            payload = &payload[skip..];
+            seq = self.rcv_nxt;
+        }
+        if seq_ge(seq, window_end) {
+            return;
+        }
+        let room = window_end.wrapping_sub(seq) as usize;
+        if payload.len() > room {
+            payload = &payload[..room];
+        }
+        if payload.is_empty() {
+            return;
+        }
+
+        if seq != self.rcv_nxt {
+            self.queue_out_of_order(seq, payload);
+            return;
+        }
+
+        self.recv_buffer.extend(payload);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(payload.len() as u32);
+
+        // 保留していたデータのうち，連続したものを取り込む
+        while let Some((&start, _)) = self.out_of_order.iter().next() {
+            if seq_gt(start, self.rcv_nxt) {
+                break;
+            }
+            let data = self.out_of_order.remove(&start).unwrap();
+            let skip = self.rcv_nxt.wrapping_sub(start) as usize;
+            if skip < data.len() {
+                self.recv_buffer.extend(&data[skip..]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add((data.len() - skip) as u32);
+            }
+        }
+
+        // 順序が入れ替わって届いていたFINに追いついた
+        if self.pending_fin_seq == Some(self.rcv_nxt) {
+            self.pending_fin_seq = None;
+            self.receive_fin(now);
+        }
+    }
+
+    /// 順序外のデータを保留する．
+    /// 既に保留しているブロックと重なるか隣接する場合は1つのブロックにまとめる．
+    /// 保留するデータ量かブロック数が上限を超える場合は破棄する
+    fn queue_out_of_order(&mut self, seq: u32, payload: &[u8]) {
+        let mut start = seq;
+        let mut end = seq.wrapping_add(payload.len() as u32);
+        let overlapping: Vec<u32> = self
+            .out_of_order
+            .iter()
+            .filter(|(&left, data)| {
+                seq_le(left, end) && seq_le(seq, left.wrapping_add(data.len() as u32))
+            })
+            .map(|(&left, _)| left)
+            .collect();
+        for left in overlapping.iter() {
+            let right = left.wrapping_add(self.out_of_order[left].len() as u32);
+            if seq_lt(*left, start) {
+                start = *left;
+            }
+            if seq_gt(right, end) {
+                end = right;
+            }
+        }
+
+        let merged_len = end.wrapping_sub(start) as usize;
+        let removed_len: usize = overlapping
+            .iter()
+            .map(|left| self.out_of_order[left].len())
+            .sum();
+        let queued_len = self.out_of_order_len() - removed_len + merged_len;
+        let blocks = self.out_of_order.len() - overlapping.len() + 1;
+        if queued_len > MAX_OUT_OF_ORDER_LEN || blocks > MAX_OUT_OF_ORDER_BLOCKS {
+            return;
+        }
+
+        let mut merged = vec![0; merged_len];
+        for left in overlapping.iter() {
+            let data = self.out_of_order.remove(left).unwrap();
+            let offset = left.wrapping_sub(start) as usize;
+            merged[offset..offset + data.len()].copy_from_slice(&data);
+        }
+        let offset = seq.wrapping_sub(start) as usize;
+        merged[offset..offset + payload.len()].copy_from_slice(payload);
+        self.out_of_order.insert(start, merged);
+        self.last_out_of_order_seq = Some(seq);
+    }
+
+    /// 順序外のデータとして保留しているバイト数
+    fn out_of_order_len(&self) -> usize {
+        self.out_of_order.values().map(Vec::len).sum()
+    }
+
+    /// 受信したデータへの確認応答を遅延させるか決める．
+    /// 順序外のデータや穴を埋めたデータには，相手の高速再送を助けるため直ちに応答する
+    /// See also [RFC5681](https://tools.ietf.org/html/rfc5681#section-4.2)
+    fn schedule_ack(&mut self, in_order: bool, now: Instant) {
+        // 最大長のセグメント2つ分を受信したら，遅延させずに応答する
+        let unacked = self.rcv_nxt.wrapping_sub(self.last_ack_sent) as usize;
+        match self.opt.delayed_ack {
+            Some(timeout) if in_order && unacked <= self.id.local_mss() => {
+                if self.delayed_ack_deadline.is_none() {
+                    self.delayed_ack_deadline = Some(now + timeout);
+                }
+            }
+            _ => self.ack_pending = true,
+        }
+    }
+
+    fn receive_fin(&mut self, now: Instant) {
+        let next_state = match self.state {
+            State::SynReceived | State::Established => State::CloseWait,
+            State::FinWait1 => State::Closing,
+            State::FinWait2 => State::TimeWait,
+            _ => return,
+        };
+
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+        self.fin_received = true;
+        self.out_of_order.clear();
+
+        if next_state == State::TimeWait {
+            self.enter_time_wait(now);
+        } else {
+            self.state = next_state;
+        }
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) {
+        self.state = State::TimeWait;
+        self.time_wait_deadline = Some(now + MAXIMUM_SEGMENT_LIFETIME * 2);
+    }
+
+    /// 送信可能なデータやFIN，保留中の確認応答を `outgoing` に積む
+    fn output(&mut self, now: Instant) {
+        let mut sent = false;
+
+        if self.can_send_data() {
+            loop {
+                let offset = self.snd_nxt.wrapping_sub(self.snd_buf_seq) as usize;
+                let unsent = self.send_buffer.len().saturating_sub(offset);
+                let window = self.snd_wnd.min(self.cc.window());
+                let window_end = self.snd_una.wrapping_add(window);
+                let usable = if seq_lt(self.snd_nxt, window_end) {
+                    window_end.wrapping_sub(self.snd_nxt) as usize
+                } else {
+                    0
+                };
+                let len = unsent.min(usable).min(self.max_payload_len());
+                if len == 0 {
+                    break;
+                }
+                // Nagleアルゴリズム: 未確認のデータがある間は，最大長に満たないセグメントを送らない
+                // See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.7.4)
+                if len < self.max_payload_len()
+                    && !self.opt.nodelay
+                    && !self.fin_requested
+                    && self.snd_nxt != self.snd_una
+                {
+                    break;
+                }
+
+                let payload: Vec<u8> = self
+                    .send_buffer
+                    .range(offset..offset + len)
+                    .copied()
+                    .collect();
+                let mut flags = ControlFlags::ACK;
+                if offset + len == self.send_buffer.len() {
+                    flags |= ControlFlags::PSH;
+                }
+                let seg = self.make_segment(self.snd_nxt, flags, payload, now);
+                self.transmit(seg, now);
+                self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                sent = true;
+            }
+
+            let all_sent =
+                self.snd_nxt.wrapping_sub(self.snd_buf_seq) as usize == self.send_buffer.len();
+            if self.fin_requested && self.fin_seq.is_none() && all_sent {
+                let seg = self.make_segment(
+                    self.snd_nxt,
+                    ControlFlags::FIN | ControlFlags::ACK,
+                    Vec::new(),
+                    now,
+                );
+                self.transmit(seg, now);
+                self.fin_seq = Some(self.snd_nxt);
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                sent = true;
+            }
+        }
+
+        if self.ack_pending && !sent && self.is_synchronized() {
+            let seg = self.make_segment(self.snd_nxt, ControlFlags::ACK, Vec::new(), now);
+            self.outgoing.push_back(seg);
+        }
+        self.ack_pending = false;
+    }
+
+    fn can_send_data(&self) -> bool {
+        match self.state {
+            State::Established | State::CloseWait => true,
+            // FINを送るまではデータを送り続ける
+            State::FinWait1 | State::LastAck => self.fin_seq.is_none(),
+            _ => false,
+        }
+    }
+
+    fn is_synchronized(&self) -> bool {
+        !matches!(self.state, State::Closed | State::Listen | State::SynSent)
+    }
+
+    fn send_syn(&mut self, now: Instant) {
+        let flags = if self.state == State::SynSent {
+            ControlFlags::SYN
+        } else {
+            ControlFlags::SYN | ControlFlags::ACK
+        };
+        let mut seg = self.make_segment(self.iss, flags, Vec::new(), now);
+
+        // SYN-SENTでは使用したいオプションを提示し，
+        // SYN-RECEIVEDでは相手が提示したもののみを返す
+        let offer = self.state == State::SynSent;
+        let sack = if offer { self.opt.sack } else { self.sack };
+        let timestamps = if offer {
+            self.opt.timestamps
+        } else {
+            self.timestamps
+        };
+        let window_scaling = if offer {
+            self.opt.window_scaling
+        } else {
+            self.window_scaling
+        };
+
+        let options = &mut seg.header.options;
+        options.push(SegmentOption::MaximumSegmentSize(self.id.local_mss() as u16));
+        let ts_option = SegmentOption::Timestamps {
+            value: self.ts_value(now),
+            echo_reply: if offer { 0 } else { self.ts_recent },
+        };
+        match (sack, timestamps) {
+            (true, true) => options.extend(vec![SegmentOption::SackPermitted, ts_option]),
+            (false, true) => options.extend(vec![
+                SegmentOption::NoOperation,
+                SegmentOption::NoOperation,
+                ts_option,
+            ]),
+            (true, false) => options.extend(vec![
+                SegmentOption::NoOperation,
+                SegmentOption::NoOperation,
+                SegmentOption::SackPermitted,
+            ]),
+            (false, false) => {}
+        }
+        if window_scaling {
+            options.push(SegmentOption::NoOperation);
+            options.push(SegmentOption::WindowScale(WINDOW_SCALE_SHIFT));
+        }
+
+        self.transmit(seg, now);
+        self.snd_nxt = self.iss.wrapping_add(1);
+    }
+
+    /// シーケンス番号を消費するセグメントを再送キューに記録して送信する．
+    /// 記録済みのセグメントであれば再送として扱う
+    fn transmit(&mut self, seg: Segment, now: Instant) {
+        let seq = seg.header.sequence_number;
+        let len = seg.len();
+        if let Some(sent) = self
+            .retransmission_queue
+            .iter_mut()
+            .find(|sent| sent.seq == seq)
+        {
+            sent.retransmitted = true;
+        } else {
+            self.retransmission_queue.push_back(SentSegment {
+                seq,
+                len,
+                sent_at: now,
+                retransmitted: false,
+                sacked: false,
+            });
+        }
+
+        // See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-5)
+        if self.retransmission_deadline.is_none() {
+            self.retransmission_deadline = Some(now + self.rtt.rto());
+        }
+        self.outgoing.push_back(seg);
+    }
+
+    /// See also [RFC5961](https://tools.ietf.org/html/rfc5961#section-3.2)
+    fn send_challenge_ack(&mut self, now: Instant) {
+        let seg = self.make_segment(self.snd_nxt, ControlFlags::ACK, Vec::new(), now);
+        self.outgoing.push_back(seg);
+    }
+
+    /// 受信ウィンドウ．保留している順序外のデータの分も受信バッファを消費する
+    fn rcv_wnd(&self) -> u32 {
+        (RECEIVE_BUFFER_SIZE - self.recv_buffer.len() - self.out_of_order_len()) as u32
+    }
+
+    /// 受信ウィンドウの右端．
+    /// 順序外のデータを保留している間も，通知済みの右端は後退させない
+    /// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.2.2.16)
+    fn rcv_window_end(&self) -> u32 {
+        let end = self.rcv_nxt.wrapping_add(self.rcv_wnd());
+        let advertised = self.last_ack_sent.wrapping_add(self.rcv_wnd_advertised);
+        if !self.out_of_order.is_empty() && seq_gt(advertised, end) {
+            advertised
+        } else {
+            end
+        }
+    }
+
+    /// ウィンドウ領域で表現できる受信ウィンドウ
+    fn advertisable_window(&self) -> u32 {
+        self.rcv_window_end()
+            .wrapping_sub(self.rcv_nxt)
+            .min((u16::MAX as u32) << self.rcv_wscale)
+    }
+
+    /// セグメントが通知しているウィンドウ．SYNを含むセグメントではスケールしない
+    /// See also [RFC7323](https://tools.ietf.org/html/rfc7323#section-2.2)
+    fn segment_window(&self, hdr: &SegmentHeader) -> u32 {
+        if hdr.flags.contains(ControlFlags::SYN) {
+            hdr.window as u32
+        } else {
+            (hdr.window as u32) << self.snd_wscale
+        }
+    }
+
+    /// 現在のTSval．1ミリ秒毎に1進む
+    fn ts_value(&self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.ts_origin).as_millis() as u32;
+        self.ts_offset.wrapping_add(elapsed)
+    }
+
+    /// 送信する確認応答に含めるSACKブロック．
+    /// 最後に受信したデータを含むブロックを先頭にする
+    /// See also [RFC2018](https://tools.ietf.org/html/rfc2018#section-4)
+    fn sack_blocks(&self) -> Vec<(u32, u32)> {
+        let mut blocks: Vec<(u32, u32)> = Vec::new();
+        for (&start, data) in self.out_of_order.iter() {
+            let end = start.wrapping_add(data.len() as u32);
+            if let Some(last) = blocks.last_mut() {
+                if seq_le(start, last.1) {
+                    if seq_gt(end, last.1) {
+                        last.1 = end;
+                    }
+                    continue;
+                }
+            }
+            blocks.push((start, end));
+        }
+
+        if let Some(seq) = self.last_out_of_order_seq {
+            if let Some(index) = blocks
+                .iter()
+                .position(|&(left, right)| seq_le(left, seq) && seq_lt(seq, right))
+            {
+                let block = blocks.remove(index);
+                blocks.insert(0, block);
+            }
+        }
+        // オプション領域に収まる数に制限する
+        blocks.truncate(if self.timestamps { 3 } else { 4 });
+        blocks
+    }
+
+    /// 1セグメントで送れるデータ量．MSSからオプションの長さを除く
+    /// See also [RFC6691](https://tools.ietf.org/html/rfc6691)
+    fn max_payload_len(&self) -> usize {
+        let mut options_len = 0;
+        if self.timestamps {
+            options_len += TIMESTAMPS_OPTION_LENGTH;
+        }
+        if self.sack && !self.out_of_order.is_empty() {
+            options_len += 4 + 8 * self.sack_blocks().len();
+        }
+        self.snd_mss.saturating_sub(options_len)
+    }
+
+    fn make_segment(
+        &mut self,
+        seq: u32,
+        flags: ControlFlags,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> Segment {
+        let mut header = SegmentHeader {
+            src_port: self.id.local_port,
+            dst_port: self.id.remote_port,
+            sequence_number: seq,
+            flags,
+            ..Default::default()
+        };
+
+        if flags.contains(ControlFlags::ACK) {
+            header.acknowledgment_number = self.rcv_nxt;
+            self.last_ack_sent = self.rcv_nxt;
+            self.delayed_ack_deadline = None;
+        }
+        if flags.contains(ControlFlags::SYN) {
+            let rcv_wnd = self.rcv_wnd().min(u16::MAX as u32);
+            header.window = rcv_wnd as u16;
+            self.rcv_wnd_advertised = rcv_wnd;
+            // SYNのオプションは `send_syn` で付与する
+            return Segment { header, payload };
+        }
+
+        let window = self.advertisable_window() >> self.rcv_wscale;
+        header.window = window as u16;
+        self.rcv_wnd_advertised = window << self.rcv_wscale;
+
+        if self.timestamps {
+            header.options.extend(vec![
+                SegmentOption::NoOperation,
+                SegmentOption::NoOperation,
+                SegmentOption::Timestamps {
+                    value: self.ts_value(now),
+                    echo_reply: self.ts_recent,
+                },
+            ]);
+        }
+        if self.sack && flags.contains(ControlFlags::ACK) && !self.out_of_order.is_empty() {
+            header.options.extend(vec![
+                SegmentOption::NoOperation,
+                SegmentOption::NoOperation,
+                SegmentOption::Sack(self.sack_blocks()),
+            ]);
+        }
+
+        Segment { header, payload }
+    }
+
+    /// RSTセグメントを作る
+    fn make_reset(&self, seq: u32) -> Segment {
+        let header = SegmentHeader {
+            src_port: self.id.local_port,
+            dst_port: self.id.remote_port,
+            sequence_number: seq,
+            flags: ControlFlags::RST,
+            ..Default::default()
+        };
+
+        Segment {
+            header,
+            payload: Vec::new(),
+        }
+    }
+}
+
+/// 対応する接続が存在しないセグメントに対して返すRSTを作る
+/// See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.10.7.1)
+pub fn reset_for(seg: &Segment) -> Option<Segment> {
+    let hdr = &seg.header;
+    if hdr.flags.contains(ControlFlags::RST) {
+        return None;
+    }
+
+    let mut rst = SegmentHeader {
+        src_port: hdr.dst_port,
+        dst_port: hdr.src_port,
+        ..Default::default()
+    };
+    if hdr.flags.contains(ControlFlags::ACK) {
+        rst.sequence_number = hdr.acknowledgment_number;
+        rst.flags = ControlFlags::RST;
+    } else {
+        rst.sequence_number = 0;
+        rst.acknowledgment_number = hdr.sequence_number.wrapping_add(seg.len());
+        rst.flags = ControlFlags::RST | ControlFlags::ACK;
+    }
+
+    Some(Segment {
+        header: rst,
+        payload: Vec::new(),
+    })
+}
+
+/// シーケンス番号の比較(a < b)
+/// 番号は一周するので，差分を符号付き整数として評価する
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+pub fn seq_gt(a: u32, b: u32) -> bool {
+    seq_lt(b, a)
+}
+
+pub fn seq_ge(a: u32, b: u32) -> bool {
+    seq_le(b, a)
+}
+
+/// start <= seq < start + len
+fn seq_in_window(seq: u32, start: u32, len: u32) -> bool {
+    seq.wrapping_sub(start) < len
+}
+
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state_str = match self {
+            State::Closed => "CLOSED",
+            State::Listen => "LISTEN",
+            State::SynSent => "SYN-SENT",
+            State::SynReceived => "SYN-RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN-WAIT-1",
+            State::FinWait2 => "FIN-WAIT-2",
+            State::CloseWait => "CLOSE-WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST-ACK",
+            State::TimeWait => "TIME-WAIT",
+        };
+        write!(f, "{}", state_str)
+    }
+}
+
+impl std::fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} <-> {}:{}",
+            self.local_addr, self.local_port, self.remote_addr, self.remote_port
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_id() -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.1"),
+            local_port: 50000,
+            remote_addr: IpAddr::from("192.168.11.30"),
+            remote_port: 80,
+        }
+    }
+
+    fn server_id() -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.30"),
+            local_port: 80,
+            remote_addr: IpAddr::from("192.168.11.1"),
+            remote_port: 50000,
+        }
+    }
+
+    /// 2つの接続の間でセグメントを受け渡す
+    fn exchange(a: &mut Connection, b: &mut Connection, now: Instant) {
+        loop {
+            let from_a = a.take_outgoing();
+            let from_b = b.take_outgoing();
+            if from_a.is_empty() && from_b.is_empty() {
+                break;
+            }
+            for seg in from_a.iter() {
+                b.on_segment(seg, now);
+            }
+            for seg in from_b.iter() {
+                a.on_segment(seg, now);
+            }
+        }
+    }
+
+    /// 確認応答の遅延とNagleアルゴリズムを無効にし，セグメントを受け渡した時点で結果が揃うようにする
+    fn test_option() -> TcpOption {
+        TcpOption {
+            delayed_ack: None,
+            nodelay: true,
+            ..Default::default()
+        }
+    }
+
+    fn establish(now: Instant) -> (Connection, Connection) {
+        establish_with(&test_option(), &test_option(), now)
+    }
+
+    fn establish_with(
+        client_opt: &TcpOption,
+        server_opt: &TcpOption,
+        now: Instant,
+    ) -> (Connection, Connection) {
+        let mut client = Connection::connect(client_id(), client_opt, 1000, now);
+        let syn = client.take_outgoing().remove(0);
+        assert_eq!(ControlFlags::SYN, syn.header.flags);
+
+        let mut server = Connection::accept(server_id(), server_opt, &syn, 5000, now);
+        assert_eq!(State::SynReceived, server.state());
+        exchange(&mut client, &mut server, now);
+
+        assert_eq!(State::Established, client.state());
+        assert_eq!(State::Established, server.state());
+        (client, server)
+    }
+
+    #[test]
+    fn sequence_number_comparison_test() {
+        assert!(seq_lt(1, 2));
+        assert!(seq_lt(0xffff_fff0, 0x10));
+        assert!(seq_gt(0x10, 0xffff_fff0));
+        assert!(seq_le(5, 5));
+        assert!(seq_ge(5, 5));
+        assert!(seq_in_window(0x5, 0xffff_fffe, 10));
+        assert!(!seq_in_window(0xffff_fffd, 0xffff_fffe, 10));
+    }
+
+    #[test]
+    fn three_way_handshake_and_data_transfer_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        assert_eq!(5, client.send(b"hello", now).unwrap());
+        exchange(&mut client, &mut server, now);
+
+        let mut buf = [0; 16];
+        assert_eq!(5, server.recv(&mut buf, now));
+        assert_eq!(b"hello", &buf[..5]);
+        assert!(client.is_all_acknowledged());
+    }
+
+    #[test]
+    fn graceful_close_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        client.close(now);
+        assert_eq!(State::FinWait1, client.state());
+        exchange(&mut client, &mut server, now);
+        assert_eq!(State::FinWait2, client.state());
+        assert_eq!(State::CloseWait, server.state());
+        assert!(server.is_read_closed());
+
+        server.close(now);
+        assert_eq!(State::LastAck, server.state());
+        exchange(&mut client, &mut server, now);
+        assert_eq!(State::TimeWait, client.state());
+        assert_eq!(State::Closed, server.state());
+
+        client.on_timer(now + MAXIMUM_SEGMENT_LIFETIME);
+        assert_eq!(State::TimeWait, client.state());
+        client.on_timer(now + MAXIMUM_SEGMENT_LIFETIME * 2);
+        assert_eq!(State::Closed, client.state());
+    }
+
+    #[test]
+    fn simultaneous_close_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        client.close(now);
+        server.close(now);
+        exchange(&mut client, &mut server, now);
+        assert_eq!(State::TimeWait, client.state());
+        assert_eq!(State::TimeWait, server.state());
+    }
+
+    #[test]
+    fn out_of_order_data_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        client.send(b"abc", now).unwrap();
+        let first = client.take_outgoing().remove(0);
+        client.send(b"def", now).unwrap();
+        let second = client.take_outgoing().remove(0);
+
+        server.on_segment(&second, now);
+        assert_eq!(0, server.readable_len());
+        // 重複した確認応答が返る
+        let dup_ack = server.take_outgoing().remove(0);
+        assert_eq!(
+            first.header.sequence_number,
+            dup_ack.header.acknowledgment_number
+        );
+
+        server.on_segment(&first, now);
+        let mut buf = [0; 16];
+        assert_eq!(6, server.recv(&mut buf, now));
+        assert_eq!(b"abcdef", &buf[..6]);
+    }
+
+    #[test]
+    fn overlapping_out_of_order_data_test() {
+        let now = Instant::now();
+        let (_, mut server) = establish(now);
+        let rcv_nxt = server.rcv_nxt;
+        // 確認応答でウィンドウを通知しておく
+        server.make_segment(server.snd_nxt, ControlFlags::ACK, Vec::new(), now);
+        let window = server.advertisable_window();
+
+        // [100, 200)，[150, 250)，[50, 110) の順に届く
+        server.receive_text(rcv_nxt.wrapping_add(100), &[b'b'; 100], now);
+        server.receive_text(rcv_nxt.wrapping_add(150), &[b'b'; 100], now);
+        server.receive_text(rcv_nxt.wrapping_add(50), &[b'b'; 60], now);
+        assert_eq!(1, server.out_of_order.len());
+        assert_eq!(200, server.out_of_order_len());
+        assert_eq!((RECEIVE_BUFFER_SIZE - 200) as u32, server.rcv_wnd());
+        // 通知済みのウィンドウの右端は後退しない
+        assert_eq!(window, server.advertisable_window());
+        assert_eq!(
+            vec![(rcv_nxt.wrapping_add(50), rcv_nxt.wrapping_add(250))],
+            server.sack_blocks()
+        );
+
+        // 穴が埋まると保留していたデータをまとめて取り込む
+        server.receive_text(rcv_nxt, &[b'a'; 50], now);
+        assert_eq!(0, server.out_of_order_len());
+        assert_eq!(250, server.readable_len());
+        assert_eq!(rcv_nxt.wrapping_add(250), server.rcv_nxt);
+    }
+
+    #[test]
+    fn out_of_order_data_limit_test() {
+        let now = Instant::now();
+        let (_, mut server) = establish(now);
+        let rcv_nxt = server.rcv_nxt;
+
+        // 1バイトずつ間を空けて送り，ブロック数の上限を超えさせる
+        for i in 0..MAX_OUT_OF_ORDER_BLOCKS as u32 + 10 {
+            server.receive_text(rcv_nxt.wrapping_add(1 + 2 * i), b"x", now);
+        }
+        assert_eq!(MAX_OUT_OF_ORDER_BLOCKS, server.out_of_order.len());
+        assert_eq!(MAX_OUT_OF_ORDER_BLOCKS, server.out_of_order_len());
+    }
+
+    #[test]
+    fn refused_connection_test() {
+        let now = Instant::now();
+        let mut client = Connection::connect(client_id(), &Default::default(), 1000, now);
+        let syn = client.take_outgoing().remove(0);
+
+        // ポートが閉じている場合の応答
+        let rst = reset_for(&syn).unwrap();
+        assert_eq!(ControlFlags::RST | ControlFlags::ACK, rst.header.flags);
+        assert_eq!(1001, rst.header.acknowledgment_number);
+
+        client.on_segment(&rst, now);
+        assert_eq!(State::Closed, client.state());
+        assert!(matches!(
+            client.error(),
+            Some(TransportProtocolError::ConnectionRefused)
+        ));
+    }
+
+    #[test]
+    fn reset_with_inexact_sequence_triggers_challenge_ack_test() {
+        let now = Instant::now();
+        let (client, mut server) = establish(now);
+
+        let mut rst = SegmentHeader {
+            src_port: 50000,
+            dst_port: 80,
+            sequence_number: client.snd_nxt.wrapping_add(10),
+            flags: ControlFlags::RST,
+            ..Default::default()
+        };
+        server.on_segment(
+            &Segment {
+                header: rst.clone(),
+                payload: Vec::new(),
+            },
+            now,
+        );
+        assert_eq!(State::Established, server.state());
+        let challenge = server.take_outgoing().remove(0);
+        assert_eq!(ControlFlags::ACK, challenge.header.flags);
+
+        rst.sequence_number = client.snd_nxt;
+        server.on_segment(
+            &Segment {
+                header: rst,
+                payload: Vec::new(),
+            },
+            now,
+        );
+        assert_eq!(State::Closed, server.state());
+        assert!(matches!(
+            server.error(),
+            Some(TransportProtocolError::ConnectionReset)
+        ));
+    }
+
+    #[test]
+    fn unacceptable_segment_is_acked_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        client.send(b"abc", now).unwrap();
+        let seg = client.take_outgoing().remove(0);
+        server.on_segment(&seg, now);
+        server.take_outgoing();
+
+        // 古いセグメントの再送は確認応答のみを返す
+        server.on_segment(&seg, now);
+        let ack = server.take_outgoing().remove(0);
+        assert_eq!(ControlFlags::ACK, ack.header.flags);
+        assert_eq!(
+            seg.header.sequence_number.wrapping_add(3),
+            ack.header.acknowledgment_number
+        );
+        assert_eq!(3, server.readable_len());
+    }
+
+    #[test]
+    fn retransmission_timeout_test() {
+        let now = Instant::now();
+        // タイムスタンプを使うとKarnのアルゴリズムは不要になるので，無効にしておく
+        let mut opt = test_option();
+        opt.timestamps = false;
+        let (mut client, mut server) = establish_with(&opt, &opt, now);
+
+        client.send(b"hello", now).unwrap();
+        let lost = client.take_outgoing().remove(0);
+
+        // RTOが満了するまでは再送しない
+        let rto = client.rtt.rto();
+        client.on_timer(now + rto / 2);
+        assert!(client.take_outgoing().is_empty());
+
+        client.on_timer(now + rto);
+        let retransmitted = client.take_outgoing().remove(0);
+        assert_eq!(
+            lost.header.sequence_number,
+            retransmitted.header.sequence_number
+        );
+        assert_eq!(lost.payload, retransmitted.payload);
+        // 指数バックオフ
+        assert_eq!(rto * 2, client.rtt.rto());
+
+        // 再送したセグメントへの確認応答ではRTTを計測しない
+        let srtt = client.rtt.srtt();
+        server.on_segment(&retransmitted, now + rto * 2);
+        exchange(&mut client, &mut server, now + rto * 2);
+        assert!(client.is_all_acknowledged());
+        assert_eq!(srtt, client.rtt.srtt());
+        assert_eq!(None, client.next_deadline());
+    }
+
+    #[test]
+    fn connection_times_out_after_retry_limit_test() {
+        let mut opt = test_option();
+        opt.max_syn_retransmissions = 2;
+        let mut now = Instant::now();
+        let mut client = Connection::connect(client_id(), &opt, 1000, now);
+        client.take_outgoing();
+
+        for _ in 0..2 {
+            now = client.next_deadline().unwrap();
+            client.on_timer(now);
+            let syn = client.take_outgoing().remove(0);
+            assert_eq!(ControlFlags::SYN, syn.header.flags);
+        }
+
+        now = client.next_deadline().unwrap();
+        client.on_timer(now);
+        assert!(client.take_outgoing().is_empty());
+        assert_eq!(State::Closed, client.state());
+        assert!(matches!(
+            client.error(),
+            Some(TransportProtocolError::ConnectionTimedOut)
+        ));
+    }
+
+    /// 輻輳ウィンドウを広げてから，`count` 個分のセグメントを送信させる
+    fn send_full_segments(
+        client: &mut Connection,
+        server: &mut Connection,
+        count: usize,
+        now: Instant,
+    ) -> Vec<Segment> {
+        let len = client.max_payload_len();
+        client.send(&vec![0; len * 3], now).unwrap();
+        exchange(client, server, now);
+        let mut buf = vec![0; len * 3];
+        server.recv(&mut buf, now);
+        exchange(client, server, now);
+
+        client.send(&vec![0; len * count], now).unwrap();
+        let segments = client.take_outgoing();
+        assert_eq!(count, segments.len());
+        segments
+    }
+
+    #[test]
+    fn fast_retransmit_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        let mut segments = send_full_segments(&mut client, &mut server, 4, now);
+        let len = client.max_payload_len();
+        let lost = segments.remove(0);
+
+        let mut dup_acks = Vec::new();
+        for seg in segments.iter() {
+            server.on_segment(seg, now);
+            dup_acks.extend(server.take_outgoing());
+        }
+        assert_eq!(3, dup_acks.len());
+
+        // 3つ目の重複ACKで，タイムアウトを待たずに再送する
+        for ack in dup_acks.iter() {
+            client.on_segment(ack, now);
+        }
+        let retransmitted = client.take_outgoing().remove(0);
+        assert_eq!(
+            lost.header.sequence_number,
+            retransmitted.header.sequence_number
+        );
+        assert_eq!(Some(Recovery::Fast), client.recovery);
+
+        server.on_segment(&retransmitted, now);
+        exchange(&mut client, &mut server, now);
+        assert!(client.is_all_acknowledged());
+        assert_eq!(None, client.recovery);
+        assert_eq!(len * 4, server.readable_len());
+    }
+
+    #[test]
+    fn option_negotiation_test() {
+        let now = Instant::now();
+        let (client, server) = establish(now);
+        for conn in [&client, &server].iter() {
+            assert_eq!(LOCAL_MSS, conn.snd_mss);
+            assert!(conn.window_scaling && conn.timestamps && conn.sack);
+            assert_eq!(WINDOW_SCALE_SHIFT, conn.snd_wscale);
+            assert_eq!(LOCAL_MSS - TIMESTAMPS_OPTION_LENGTH, conn.max_payload_len());
+        }
+        // SYN以外では64KiBを超えるウィンドウを通知できる
+        let (mut client, mut server) = (client, server);
+        server.send(b"abc", now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(server.snd_wnd > u16::MAX as u32);
+
+        // 片方が使わない場合は，どちらも使わない
+        let mut opt = test_option();
+        opt.window_scaling = false;
+        opt.timestamps = false;
+        opt.sack = false;
+        let (mut client, mut server) = establish_with(&test_option(), &opt, now);
+        for conn in [&client, &server].iter() {
+            assert!(!conn.window_scaling && !conn.timestamps && !conn.sack);
+            assert_eq!(0, conn.snd_wscale);
+        }
+        server.send(b"abc", now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert_eq!(u16::MAX as u32, server.snd_wnd);
+    }
+
+    #[test]
+    fn tiny_peer_mss_test() {
+        let now = Instant::now();
+        for peer_mss in [0, 4].iter() {
+            let mut client = Connection::connect(client_id(), &test_option(), 1000, now);
+            let mut syn = client.take_outgoing().remove(0);
+            for option in syn.header.options.iter_mut() {
+                if let SegmentOption::MaximumSegmentSize(mss) = option {
+                    *mss = *peer_mss;
+                }
+            }
+
+            // 相手のMSSが極端に小さくても下限に切り上げ，データを送れるようにする
+            let mut server = Connection::accept(server_id(), &test_option(), &syn, 5000, now);
+            exchange(&mut client, &mut server, now);
+            assert_eq!(State::Established, server.state());
+            assert!(server.timestamps);
+            assert_eq!(MIN_MSS, server.snd_mss);
+            assert_eq!(MIN_MSS - TIMESTAMPS_OPTION_LENGTH, server.max_payload_len());
+            assert!(server.cc.window() >= MIN_MSS as u32);
+
+            server.send(&[0; 256], now).unwrap();
+            exchange(&mut client, &mut server, now);
+            assert_eq!(256, client.readable_len());
+        }
+    }
+
+    #[test]
+    fn sack_retransmits_holes_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        let segments = send_full_segments(&mut client, &mut server, 6, now);
+        let mut dup_acks = Vec::new();
+        for seg in [&segments[1], &segments[3], &segments[4], &segments[5]].iter() {
+            server.on_segment(seg, now);
+            dup_acks.extend(server.take_outgoing());
+        }
+        assert_eq!(4, dup_acks.len());
+        let last_blocks = dup_acks[3].header.sack_blocks();
+        assert_eq!(
+            (
+                segments[3].header.sequence_number,
+                segments[5].header.sequence_number + segments[5].len()
+            ),
+            last_blocks[0]
+        );
+
+        // 3つ目の重複ACKで先頭を，4つ目でスコアボード上の次の穴を再送する
+        let mut retransmitted = Vec::new();
+        for ack in dup_acks.iter() {
+            client.on_segment(ack, now);
+            retransmitted.extend(client.take_outgoing());
+        }
+        let retransmitted_seqs: Vec<u32> = retransmitted
+            .iter()
+            .map(|seg| seg.header.sequence_number)
+            .collect();
+        assert_eq!(
+            vec![
+                segments[0].header.sequence_number,
+                segments[2].header.sequence_number
+            ],
+            retransmitted_seqs
+        );
+
+        for seg in retransmitted.iter() {
+            server.on_segment(seg, now);
+        }
+        exchange(&mut client, &mut server, now);
+        assert!(client.is_all_acknowledged());
+    }
+
+    #[test]
+    fn paws_test() {
+        let now = Instant::now();
+        let (mut client, mut server) = establish(now);
+
+        client.send(b"abc", now).unwrap();
+        let mut old = client.take_outgoing().remove(0);
+        server.on_segment(&old, now);
+        exchange(&mut client, &mut server, now);
+
+        // 古いタイムスタンプを持つ重複セグメント
+        let (ts_val, ts_ecr) = old.header.timestamps().unwrap();
+        old.header.options = vec![SegmentOption::Timestamps {
+            value: ts_val.wrapping_sub(1),
+            echo_reply: ts_ecr,
+        }];
+        old.header.sequence_number = client.snd_nxt;
+        server.on_segment(&old, now);
+        assert_eq!(3, server.readable_len());
+        let ack = server.take_outgoing().remove(0);
+        assert_eq!(client.snd_nxt, ack.header.acknowledgment_number);
+    }
+
+    #[test]
+    fn nagle_test() {
+        let now = Instant::now();
+        let mut opt = test_option();
+        opt.nodelay = false;
+        let (mut client, mut server) = establish_with(&opt, &test_option(), now);
+
+        // 未確認のデータがなければ小さなセグメントでも送る
+        client.send(b"a", now).unwrap();
+        let first = client.take_outgoing();
+        assert_eq!(1, first.len());
+        // 確認応答を受け取るまで，続くデータは溜めておく
+        client.send(b"b", now).unwrap();
+        client.send(b"c", now).unwrap();
+        assert!(client.take_outgoing().is_empty());
+
+        server.on_segment(&first[0], now);
+        for ack in server.take_outgoing() {
+            client.on_segment(&ack, now);
+        }
+        let coalesced = client.take_outgoing();
+        assert_eq!(1, coalesced.len());
+        assert_eq!(b"bc", &coalesced[0].payload[..]);
+
+        // TCP_NODELAYを設定すると，溜めていたデータを直ちに送る
+        client.send(b"d", now).unwrap();
+        assert!(client.take_outgoing().is_empty());
+        client.set_nodelay(true, now);
+        assert_eq!(b"d", &client.take_outgoing()[0].payload[..]);
+    }
+
+    #[test]
+    fn delayed_ack_test() {
+        let now = Instant::now();
+        let mut opt = test_option();
+        opt.delayed_ack = Some(DEFAULT_DELAYED_ACK_TIMEOUT);
+        let (mut client, mut server) = establish_with(&test_option(), &opt, now);
+
+        client.send(b"hello", now).unwrap();
+        for seg in client.take_outgoing() {
+            server.on_segment(&seg, now);
+        }
+        assert!(server.take_outgoing().is_empty());
+        assert_eq!(
+            Some(now + DEFAULT_DELAYED_ACK_TIMEOUT),
+            server.delayed_ack_deadline
+        );
+
+        // 応答するデータがあれば確認応答を載せる
+        server.send(b"world", now).unwrap();
+        let reply = server.take_outgoing();
+        assert_eq!(1, reply.len());
+        assert_eq!(client.snd_nxt, reply[0].header.acknowledgment_number);
+        assert_eq!(None, server.delayed_ack_deadline);
+        exchange(&mut client, &mut server, now);
+
+        // タイマが満了したら確認応答を送る
+        client.send(b"again", now).unwrap();
+        for seg in client.take_outgoing() {
+            server.on_segment(&seg, now);
+        }
+        server.on_timer(now + DEFAULT_DELAYED_ACK_TIMEOUT);
+        assert_eq!(1, server.take_outgoing().len());
+
+        // 最大長のセグメント2つ分を受信したら直ちに応答する
+        let len = client.max_payload_len();
+        client.send(&vec![0; len * 2], now).unwrap();
+        let segments = client.take_outgoing();
+        server.on_segment(&segments[0], now);
+        assert!(server.take_outgoing().is_empty());
+        server.on_segment(&segments[1], now);
+        assert_eq!(1, server.take_outgoing().len());
+    }
+
+    #[test]
+    fn keepalive_test() {
+        let now = Instant::now();
+        let keepalive = Keepalive {
+            idle: Duration::from_secs(60),
+            interval: Duration::from_secs(10),
+            count: 2,
+        };
+        let (mut client, mut server) = establish(now);
+        client.set_keepalive(Some(keepalive));
+        assert_eq!(Some(now + keepalive.idle), client.next_deadline());
+
+        // 相手が応答すれば接続を維持する
+        client.on_timer(now + keepalive.idle);
+        let probe = client.take_outgoing().remove(0);
+        assert_eq!(client.snd_una.wrapping_sub(1), probe.header.sequence_number);
+        let later = now + keepalive.idle;
+        server.on_segment(&probe, later);
+        for ack in server.take_outgoing() {
+            client.on_segment(&ack, later);
+        }
+        assert_eq!(0, client.keepalive_probes);
+        assert_eq!(Some(later + keepalive.idle), client.next_deadline());
+
+        // 応答がなければ規定数のプローブの後に切断する
+        for _ in 0..keepalive.count {
+            client.on_timer(client.next_deadline().unwrap());
+            assert_eq!(1, client.take_outgoing().len());
+        }
+        client.on_timer(client.next_deadline().unwrap());
+        assert!(client.take_outgoing()[0]
+            .header
+            .flags
+            .contains(ControlFlags::RST));
+        assert_eq!(State::Closed, client.state());
+        assert!(matches!(
+            client.error(),
+            Some(TransportProtocolError::ConnectionTimedOut)
+        ));
+    }
+
+    #[test]
+    fn icmp_error_test() {
+        let now = Instant::now();
+        let mut opt = test_option();
+        opt.max_syn_retransmissions = 0;
+
+        // 到達不能は接続を中断せず，タイムアウトした際に原因として報告する
+        let mut client = Connection::connect(client_id(), &opt, 1000, now);
+        client.on_icmp_error(1000, TransportProtocolError::HostUnreachable);
+        assert_eq!(State::SynSent, client.state());
+        let now = client.next_deadline().unwrap();
+        client.on_timer(now);
+        assert_eq!(State::Closed, client.state());
+        assert!(matches!(
+            client.error(),
+            Some(TransportProtocolError::HostUnreachable)
+        ));
+
+        // 送信していないシーケンス番号に対するエラーは無視する
+        let mut client = Connection::connect(client_id(), &opt, 1000, now);
+        client.on_icmp_error(2000, TransportProtocolError::ConnectionRefused);
+        assert_eq!(State::SynSent, client.state());
+
+        // 確立中のポート到達不能は接続を中断する
+        client.on_icmp_error(1000, TransportProtocolError::ConnectionRefused);
+        assert_eq!(State::Closed, client.state());
+        assert!(matches!(
+            client.error(),
+            Some(TransportProtocolError::ConnectionRefused)
+        ));
+    }
+}
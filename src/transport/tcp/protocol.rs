@@ -0,0 +1,749 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    task::Waker,
+    time::Instant,
+};
+
+use super::{
+    reset_for, Connection, ConnectionId, IsnGenerator, Segment, SegmentHeader, SegmentOption,
+    State, SynCookieGenerator, DEFAULT_MSS,
+};
+use crate::{
+    checksum::calculate_checksum_with_ip_pseudo_header,
+    internet::{self, IpAddr},
+    network_device,
+    option::TcpOption,
+    transport::{icmp, tcp::ControlFlags, TransportProtocol, TransportProtocolError},
+    Items, RxResult,
+};
+
+/// エフェメラルポートの範囲
+/// See also [RFC6335](https://tools.ietf.org/html/rfc6335#section-6)
+const EPHEMERAL_PORT_RANGE: std::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// `TcpListener::bind()` で使用するバックログ
+pub const DEFAULT_BACKLOG: usize = 128;
+
+/// LISTEN状態のポート
+#[derive(Debug)]
+pub struct Listener {
+    /// 確立済みで，まだアプリケーションに受け入れられていない接続
+    pub accept_queue: VecDeque<ConnectionId>,
+    /// 確立中及び受け入れ待ちの接続数の上限
+    pub backlog: usize,
+    /// `accept()` を待っているタスク
+    accept_waker: Option<Waker>,
+}
+
+/// 接続の状態変化を待っているタスク
+#[derive(Debug, Default)]
+struct ConnectionWakers {
+    read: Option<Waker>,
+    write: Option<Waker>,
+}
+
+/// プロトコルスタックが管理するTCPの接続一覧
+#[derive(Debug, Default)]
+pub struct ConnectionTable {
+    opt: TcpOption,
+    pub listeners: HashMap<u16, Listener>,
+    pub connections: HashMap<ConnectionId, Connection>,
+    isn_generator: IsnGenerator,
+    syn_cookie_generator: SynCookieGenerator,
+    wakers: HashMap<ConnectionId, ConnectionWakers>,
+}
+
+impl Listener {
+    pub fn new(backlog: usize) -> Self {
+        Self {
+            accept_queue: VecDeque::new(),
+            backlog: backlog.max(1),
+            accept_waker: None,
+        }
+    }
+}
+
+impl ConnectionTable {
+    pub fn new(opt: TcpOption) -> Self {
+        Self {
+            opt,
+            ..Default::default()
+        }
+    }
+
+    /// 受信したセグメントを該当する接続に渡し，送信すべきセグメントを返す
+    pub fn process_segment(
+        &mut self,
+        id: ConnectionId,
+        seg: &Segment,
+        now: Instant,
+    ) -> Vec<Segment> {
+        if let Some(conn) = self.connections.get_mut(&id) {
+            let before = conn.state();
+            conn.on_segment(seg, now);
+            let outgoing = conn.take_outgoing();
+
+            // 受動オープンした接続が確立したら，受け入れ待ちに加える
+            if before == State::SynReceived
+                && conn.is_passive()
+                && conn.state() == State::Established
+            {
+                self.push_accept_queue(id);
+            }
+            self.wake(&id);
+            self.remove_if_closed(&id);
+
+            return outgoing;
+        }
+
+        let flags = seg.header.flags;
+        if self.listeners.contains_key(&id.local_port) {
+            // See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.10.7.2)
+            if flags.contains(ControlFlags::RST) {
+                return Vec::new();
+            }
+            if flags.contains(ControlFlags::ACK) {
+                if let Some(outgoing) = self.accept_with_cookie(id, seg, now) {
+                    return outgoing;
+                }
+                return reset_for(seg).into_iter().collect();
+            }
+            if flags.contains(ControlFlags::SYN) {
+                let listener = &self.listeners[&id.local_port];
+                // 受け入れ待ちの接続が埋まっている場合はSYNを破棄し，相手の再送に任せる
+                if listener.accept_queue.len() >= listener.backlog {
+                    return Vec::new();
+                }
+                // SYNキューが溢れている場合は状態を持たずにSYN cookieで応答する
+                if self.pending_connections(id.local_port) >= listener.backlog
+                    || self.handshaking_connections(id.local_port) >= self.opt.syn_backlog
+                {
+                    if !self.opt.syn_cookies {
+                        return Vec::new();
+                    }
+                    return self.reply_with_cookie(id, seg, now);
+                }
+                let iss = self.isn_generator.generate(&id, now);
+                let mut conn = Connection::accept(id, &self.opt, seg, iss, now);
+                let outgoing = conn.take_outgoing();
+                self.connections.insert(id, conn);
+                return outgoing;
+            }
+            return Vec::new();
+        }
+
+        reset_for(seg).into_iter().collect()
+    }
+
+    /// 接続が送信したセグメントに対するICMPエラーを渡す
+    pub fn process_icmp_error(
+        &mut self,
+        id: ConnectionId,
+        seq: u32,
+        error: TransportProtocolError,
+    ) {
+        if let Some(conn) = self.connections.get_mut(&id) {
+            let before = (conn.state(), conn.error().is_some());
+            conn.on_icmp_error(seq, error);
+            if before != (conn.state(), conn.error().is_some()) {
+                self.wake(&id);
+            }
+            self.remove_if_closed(&id);
+        }
+    }
+
+    /// 閉じた接続を取り除く．
+    /// 異常終了した接続は，アプリケーションがエラーを受け取るまで残しておく
+    pub fn remove_if_closed(&mut self, id: &ConnectionId) {
+        if let Some(conn) = self.connections.get(id) {
+            let accepted = self
+                .listeners
+                .get(&id.local_port)
+                .is_none_or(|l| !l.accept_queue.contains(id));
+            if conn.state() == State::Closed && conn.error().is_none() && accepted {
+                self.connections.remove(id);
+            }
+        }
+    }
+
+    /// LISTENを解除し，受け入れられていない接続を強制終了する．
+    /// RSTは各接続の送信キューに残るので，呼び出し側かタイマ処理が送信する
+    pub fn remove_listener(&mut self, port: u16) -> Vec<ConnectionId> {
+        let accept_queue = match self.listeners.remove(&port) {
+            Some(listener) => listener.accept_queue,
+            None => return Vec::new(),
+        };
+
+        let pending: Vec<ConnectionId> = self
+            .connections
+            .iter()
+            .filter(|(id, conn)| id.local_port == port && conn.is_passive())
+            .filter(|(id, conn)| conn.state() == State::SynReceived || accept_queue.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in pending.iter() {
+            let errored = match self.connections.get_mut(id) {
+                Some(conn) => {
+                    conn.abort();
+                    conn.error().is_some()
+                }
+                None => false,
+            };
+            // 異常終了した接続はエラーを受け取るアプリケーションがいないので，ここで取り除く
+            if errored {
+                self.connections.remove(id);
+            }
+        }
+
+        pending
+    }
+
+    /// 確立中の接続と，受け入れ待ちの接続の数
+    fn pending_connections(&self, port: u16) -> usize {
+        let accept_queue_len = self
+            .listeners
+            .get(&port)
+            .map_or(0, |l| l.accept_queue.len());
+        accept_queue_len + self.handshaking_connections(port)
+    }
+
+    /// SYN-RECEIVED状態の接続の数
+    fn handshaking_connections(&self, port: u16) -> usize {
+        self.connections
+            .iter()
+            .filter(|(id, conn)| id.local_port == port && conn.is_passive())
+            .filter(|(_, conn)| conn.state() == State::SynReceived)
+            .count()
+    }
+
+    /// 受動オープンした接続を受け入れ待ちに加え，`accept()` を待っているタスクを起こす
+    fn push_accept_queue(&mut self, id: ConnectionId) {
+        if let Some(listener) = self.listeners.get_mut(&id.local_port) {
+            listener.accept_queue.push_back(id);
+            if let Some(waker) = listener.accept_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// SYN cookieで確立した接続はSYNのオプションを覚えていないので，MSS以外は使用しない
+    fn cookie_option(&self) -> TcpOption {
+        let mut opt = self.opt.clone();
+        opt.window_scaling = false;
+        opt.timestamps = false;
+        opt.sack = false;
+        opt
+    }
+
+    /// ISNにSYN cookieを用いたSYN/ACKを返す．接続は作成しない
+    /// See also [RFC4987](https://tools.ietf.org/html/rfc4987#section-3.6)
+    fn reply_with_cookie(&mut self, id: ConnectionId, syn: &Segment, now: Instant) -> Vec<Segment> {
+        let mss = syn
+            .header
+            .options
+            .iter()
+            .find_map(|option| match option {
+                SegmentOption::MaximumSegmentSize(mss) => Some(*mss),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_MSS as u16);
+        let cookie = self
+            .syn_cookie_generator
+            .generate(&id, syn.header.sequence_number, mss, now);
+
+        Connection::accept(id, &self.cookie_option(), syn, cookie, now).take_outgoing()
+    }
+
+    /// SYN cookieを返したACKであれば接続を確立し，受け入れ待ちに加える．
+    /// cookieとして正しくなければ `None` を返す
+    fn accept_with_cookie(
+        &mut self,
+        id: ConnectionId,
+        seg: &Segment,
+        now: Instant,
+    ) -> Option<Vec<Segment>> {
+        if !self.opt.syn_cookies || seg.header.flags.contains(ControlFlags::SYN) {
+            return None;
+        }
+        let peer_isn = seg.header.sequence_number.wrapping_sub(1);
+        let cookie = seg.header.acknowledgment_number.wrapping_sub(1);
+        let mss = self
+            .syn_cookie_generator
+            .validate(&id, peer_isn, cookie, now)?;
+
+        let listener = &self.listeners[&id.local_port];
+        if listener.accept_queue.len() >= listener.backlog {
+            return Some(Vec::new());
+        }
+
+        // 受け取ったはずのSYNを復元して，通常の受動オープンと同じ手順で確立させる
+        let mut header: SegmentHeader = Default::default();
+        header.src_port = id.remote_port;
+        header.dst_port = id.local_port;
+        header.sequence_number = peer_isn;
+        header.flags = ControlFlags::SYN;
+        header.window = seg.header.window;
+        header.options = vec![SegmentOption::MaximumSegmentSize(mss)];
+        let syn = Segment {
+            header,
+            payload: Vec::new(),
+        };
+
+        let mut conn = Connection::accept(id, &self.cookie_option(), &syn, cookie, now);
+        conn.take_outgoing();
+        conn.on_segment(seg, now);
+        if conn.state() != State::Established {
+            return None;
+        }
+        let outgoing = conn.take_outgoing();
+        self.connections.insert(id, conn);
+        self.push_accept_queue(id);
+
+        Some(outgoing)
+    }
+
+    /// 受け入れ待ちの接続を取り出す．
+    /// 無ければ `waker` を登録し，接続が確立した際に起こす
+    pub fn poll_accept(
+        &mut self,
+        port: u16,
+        waker: &Waker,
+    ) -> Result<Option<ConnectionId>, TransportProtocolError> {
+        let listener = self
+            .listeners
+            .get_mut(&port)
+            .ok_or(TransportProtocolError::SocketClosed)?;
+        match listener.accept_queue.pop_front() {
+            Some(id) => Ok(Some(id)),
+            None => {
+                listener.accept_waker = Some(waker.clone());
+                Ok(None)
+            }
+        }
+    }
+
+    /// データの到着や接続の終了を待つタスクを登録する
+    pub fn register_read_waker(&mut self, id: ConnectionId, waker: &Waker) {
+        self.wakers.entry(id).or_default().read = Some(waker.clone());
+    }
+
+    /// 送信バッファの空きや接続の確立を待つタスクを登録する
+    pub fn register_write_waker(&mut self, id: ConnectionId, waker: &Waker) {
+        self.wakers.entry(id).or_default().write = Some(waker.clone());
+    }
+
+    /// 接続を使用しなくなった際に，登録されたタスクを取り除く
+    pub fn remove_wakers(&mut self, id: &ConnectionId) {
+        self.wakers.remove(id);
+    }
+
+    /// 接続の状態変化を待っているタスクを起こす
+    fn wake(&mut self, id: &ConnectionId) {
+        if let Some(wakers) = self.wakers.get_mut(id) {
+            if let Some(waker) = wakers.read.take() {
+                waker.wake();
+            }
+            if let Some(waker) = wakers.write.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// 使用されていないエフェメラルポートを探す
+    fn find_ephemeral_port(&self, remote_addr: IpAddr, remote_port: u16) -> Option<u16> {
+        let range_len = (EPHEMERAL_PORT_RANGE.end() - EPHEMERAL_PORT_RANGE.start()) as u32 + 1;
+        let offset = rand::random::<u32>() % range_len;
+        (0..range_len)
+            .map(|i| EPHEMERAL_PORT_RANGE.start() + ((offset + i) % range_len) as u16)
+            .find(|port| {
+                !self.listeners.contains_key(port)
+                    && !self.connections.keys().any(|id| {
+                        id.local_port == *port
+                            && id.remote_addr == remote_addr
+                            && id.remote_port == remote_port
+                    })
+            })
+    }
+}
+
+pub async fn rx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    rx_result: RxResult,
+    buf: &[u8],
+) -> Result<(SegmentHeader, Vec<u8>), TransportProtocolError> {
+    let raw_segment = &buf[..rx_result.message_len.min(buf.len())];
+    let segment_hdr =
+        SegmentHeader::new_from_bytes(raw_segment, TransportProtocolError::CannotParseTCPSegment)?;
+
+    if calculate_checksum_with_ip_pseudo_header(
+        rx_result.src_ip_addr,
+        rx_result.dst_ip_addr,
+        TransportProtocol::TCP,
+        raw_segment,
+        TransportProtocolError::InvalidChecksum,
+    )? != 0
+    {
+        return Err(TransportProtocolError::InvalidChecksum);
+    }
+
+    if table.opt.debug {
+        eprintln!("++++++++ rx tcp segment ++++++++");
+        eprintln!("{}", segment_hdr);
+    }
+
+    // TCPはユニキャストのみを扱う
+    // See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.2.3.10)
+    let is_unicast = match rx_result.dst_ip_addr {
+        IpAddr::V4(dst) => dst == table.opt.ip_addr,
+        IpAddr::V6(dst) => !dst.is_multicast(),
+    };
+    if !is_unicast {
+        return Err(TransportProtocolError::Ignore);
+    }
+
+    let payload = raw_segment[segment_hdr.header_length()..].to_vec();
+    let segment = Segment {
+        header: segment_hdr.clone(),
+        payload,
+    };
+    let id = ConnectionId {
+        local_addr: rx_result.dst_ip_addr,
+        local_port: segment_hdr.dst_port,
+        remote_addr: rx_result.src_ip_addr,
+        remote_port: segment_hdr.src_port,
+    };
+
+    let outgoing = table
+        .tcp_table
+        .lock()
+        .unwrap()
+        .process_segment(id, &segment, Instant::now());
+    for seg in outgoing {
+        tx(table, &id, seg).await?;
+    }
+
+    Ok((segment_hdr, segment.payload))
+}
+
+/// 接続の自身のアドレスから相手のアドレスへ送信する
+pub async fn tx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    id: &ConnectionId,
+    mut segment: Segment,
+) -> Result<(), TransportProtocolError> {
+    segment.header.checksum = 0;
+    let mut raw_segment = segment
+        .header
+        .to_bytes(TransportProtocolError::CannotConstructTCPSegment)?;
+    raw_segment.extend_from_slice(&segment.payload);
+
+    segment.header.checksum = calculate_checksum_with_ip_pseudo_header(
+        id.local_addr,
+        id.remote_addr,
+        TransportProtocol::TCP,
+        &raw_segment,
+        TransportProtocolError::CannotConstructTCPSegment,
+    )?;
+    raw_segment[16..18].copy_from_slice(&segment.header.checksum.to_be_bytes());
+
+    if table.opt.debug {
+        eprintln!("++++++++ tx tcp segment ++++++++");
+        eprintln!("{}", segment.header);
+    }
+
+    let rx_result = RxResult {
+        src_ip_addr: id.remote_addr,
+        dst_ip_addr: id.local_addr,
+        ..Default::default()
+    };
+
+    internet::tx_with_ttl(
+        table,
+        TransportProtocol::TCP,
+        rx_result,
+        raw_segment,
+        internet::ip::DEFAULT_TTL,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// ポートをLISTEN状態にする
+pub fn listen<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    port: u16,
+    backlog: usize,
+) -> Result<(), TransportProtocolError> {
+    let mut tcp_table = table.tcp_table.lock().unwrap();
+    if tcp_table.listeners.contains_key(&port) {
+        return Err(TransportProtocolError::PortAlreadyInUse { port });
+    }
+    tcp_table.listeners.insert(port, Listener::new(backlog));
+
+    Ok(())
+}
+
+/// LISTEN状態を解除する．受け入れられていない接続はリセットする
+pub async fn unlisten<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    port: u16,
+) -> Result<(), TransportProtocolError> {
+    let outgoing = {
+        let mut tcp_table = table.tcp_table.lock().unwrap();
+        let mut outgoing = Vec::new();
+        for id in tcp_table.remove_listener(port) {
+            if let Some(conn) = tcp_table.connections.get_mut(&id) {
+                outgoing.extend(conn.take_outgoing().into_iter().map(|seg| (id, seg)));
+            }
+            tcp_table.remove_if_closed(&id);
+        }
+        outgoing
+    };
+
+    for (id, seg) in outgoing {
+        tx(table, &id, seg).await?;
+    }
+
+    Ok(())
+}
+
+/// 受信したICMPエラーを，原因となったセグメントを送信した接続に通知する
+pub fn on_icmp_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    received: &icmp::ReceivedError,
+) {
+    let (local_port, remote_port) = received.ports();
+    let id = ConnectionId {
+        local_addr: received.src_addr,
+        local_port,
+        remote_addr: received.dst_addr,
+        remote_port,
+    };
+    let d = &received.quoted_data;
+    let seq = u32::from_be_bytes([d[4], d[5], d[6], d[7]]);
+
+    table
+        .tcp_table
+        .lock()
+        .unwrap()
+        .process_icmp_error(id, seq, received.error);
+}
+
+/// 能動オープンを開始する．SYNを送信し，接続の識別子を返す
+pub async fn connect<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    remote_addr: IpAddr,
+    remote_port: u16,
+) -> Result<ConnectionId, TransportProtocolError> {
+    let local_addr = table.source_address(remote_addr);
+    let (id, outgoing) = {
+        let mut tcp_table = table.tcp_table.lock().unwrap();
+        let local_port = tcp_table
+            .find_ephemeral_port(remote_addr, remote_port)
+            .ok_or(TransportProtocolError::NoAvailablePort)?;
+        let id = ConnectionId {
+            local_addr,
+            local_port,
+            remote_addr,
+            remote_port,
+        };
+        let now = Instant::now();
+        let iss = tcp_table.isn_generator.generate(&id, now);
+        let mut conn = Connection::connect(id, &tcp_table.opt, iss, now);
+        let outgoing = conn.take_outgoing();
+        tcp_table.connections.insert(id, conn);
+        (id, outgoing)
+    };
+
+    for seg in outgoing {
+        tx(table, &id, seg).await?;
+    }
+
+    Ok(id)
+}
+
+/// 接続に対する操作を行い，その結果送信すべきセグメントを送る
+pub async fn with_connection<ND, F, T>(
+    table: &Items<ND>,
+    id: ConnectionId,
+    f: F,
+) -> Result<T, TransportProtocolError>
+where
+    ND: network_device::NetworkDevice,
+    F: FnOnce(&mut Connection) -> T,
+{
+    let (result, outgoing) = {
+        let mut tcp_table = table.tcp_table.lock().unwrap();
+        let conn = tcp_table
+            .connections
+            .get_mut(&id)
+            .ok_or(TransportProtocolError::ConnectionNotFound)?;
+        let result = f(conn);
+        let outgoing = conn.take_outgoing();
+        tcp_table.remove_if_closed(&id);
+        (result, outgoing)
+    };
+
+    for seg in outgoing {
+        tx(table, &id, seg).await?;
+    }
+
+    Ok(result)
+}
+
+/// 各接続のタイマを処理する
+pub async fn on_timer<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    now: Instant,
+) -> Result<(), TransportProtocolError> {
+    let outgoing = {
+        let mut tcp_table = table.tcp_table.lock().unwrap();
+        let ids: Vec<ConnectionId> = tcp_table.connections.keys().copied().collect();
+        let mut outgoing = Vec::new();
+        for id in ids {
+            if let Some(conn) = tcp_table.connections.get_mut(&id) {
+                let before = (conn.state(), conn.error().is_some());
+                conn.on_timer(now);
+                outgoing.extend(conn.take_outgoing().into_iter().map(|seg| (id, seg)));
+                if before != (conn.state(), conn.error().is_some()) {
+                    tcp_table.wake(&id);
+                }
+            }
+            tcp_table.remove_if_closed(&id);
+        }
+        outgoing
+    };
+
+    for (id, seg) in outgoing {
+        tx(table, &id, seg).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_id(port: u16) -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.1"),
+            local_port: port,
+            remote_addr: IpAddr::from("192.168.11.30"),
+            remote_port: 80,
+        }
+    }
+
+    fn server_id(port: u16) -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.30"),
+            local_port: 80,
+            remote_addr: IpAddr::from("192.168.11.1"),
+            remote_port: port,
+        }
+    }
+
+    #[test]
+    fn backlog_test() {
+        let now = Instant::now();
+        let opt = TcpOption {
+            syn_cookies: false,
+            ..Default::default()
+        };
+        let mut table = ConnectionTable::new(opt);
+        table.listeners.insert(80, Listener::new(1));
+
+        let mut first = Connection::connect(client_id(50000), &Default::default(), 1000, now);
+        let syn = first.take_outgoing().remove(0);
+        let syn_ack = table.process_segment(server_id(50000), &syn, now);
+        assert_eq!(1, syn_ack.len());
+
+        // 確立中の接続でバックログが埋まっているので，SYNを破棄する
+        let mut second = Connection::connect(client_id(50001), &Default::default(), 2000, now);
+        let syn = second.take_outgoing().remove(0);
+        assert!(table
+            .process_segment(server_id(50001), &syn, now)
+            .is_empty());
+        assert!(!table.connections.contains_key(&server_id(50001)));
+
+        // 確立して受け入れられれば，次のSYNを受け付ける
+        first.on_segment(&syn_ack[0], now);
+        for seg in first.take_outgoing() {
+            table.process_segment(server_id(50000), &seg, now);
+        }
+        assert_eq!(
+            Some(server_id(50000)),
+            table.listeners[&80].accept_queue.front().copied()
+        );
+        assert!(table
+            .process_segment(server_id(50001), &syn, now)
+            .is_empty());
+
+        table.listeners.get_mut(&80).unwrap().accept_queue.clear();
+        assert_eq!(1, table.process_segment(server_id(50001), &syn, now).len());
+    }
+
+    #[test]
+    fn remove_listener_resets_pending_connections_test() {
+        let now = Instant::now();
+        let mut table = ConnectionTable::new(Default::default());
+        table.listeners.insert(80, Listener::new(DEFAULT_BACKLOG));
+
+        let mut client = Connection::connect(client_id(50000), &Default::default(), 1000, now);
+        let syn = client.take_outgoing().remove(0);
+        table.process_segment(server_id(50000), &syn, now);
+
+        assert_eq!(vec![server_id(50000)], table.remove_listener(80));
+        assert!(!table.listeners.contains_key(&80));
+
+        let conn = table.connections.get_mut(&server_id(50000)).unwrap();
+        assert_eq!(State::Closed, conn.state());
+        let rst = conn.take_outgoing();
+        assert_eq!(1, rst.len());
+        assert!(rst[0].header.flags.contains(ControlFlags::RST));
+
+        table.remove_if_closed(&server_id(50000));
+        assert!(table.connections.is_empty());
+    }
+
+    #[test]
+    fn syn_cookie_handshake_test() {
+        let now = Instant::now();
+        let opt = TcpOption {
+            syn_backlog: 0,
+            ..Default::default()
+        };
+        let mut table = ConnectionTable::new(opt);
+        table.listeners.insert(80, Listener::new(DEFAULT_BACKLOG));
+
+        let mut client = Connection::connect(client_id(50000), &Default::default(), 1000, now);
+        let syn = client.take_outgoing().remove(0);
+        let syn_ack = table.process_segment(server_id(50000), &syn, now);
+        assert_eq!(1, syn_ack.len());
+        // SYN cookieで応答した場合は状態を持たない
+        assert!(table.connections.is_empty());
+        assert!(syn_ack[0].header.sack_blocks().is_empty());
+        assert_eq!(None, syn_ack[0].header.timestamps());
+
+        client.on_segment(&syn_ack[0], now);
+        assert_eq!(State::Established, client.state());
+        for seg in client.take_outgoing() {
+            table.process_segment(server_id(50000), &seg, now);
+        }
+        let server = &table.connections[&server_id(50000)];
+        assert_eq!(State::Established, server.state());
+        assert_eq!(
+            Some(server_id(50000)),
+            table.listeners[&80].accept_queue.front().copied()
+        );
+
+        // cookieとして正しくないACKにはRSTを返す
+        let mut forged = syn_ack[0].clone();
+        forged.header.src_port = 50001;
+        forged.header.dst_port = 80;
+        forged.header.flags = ControlFlags::ACK;
+        let rst = table.process_segment(server_id(50001), &forged, now);
+        assert!(rst[0].header.flags.contains(ControlFlags::RST));
+    }
+}
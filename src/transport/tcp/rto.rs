@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+/// RTT計測前のRTO
+/// See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-2)
+pub const INITIAL_RTO: Duration = Duration::from_secs(1);
+/// SYNの再送後に接続が確立した場合のRTO
+/// See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-5)
+pub const SYN_RETRANSMITTED_RTO: Duration = Duration::from_secs(3);
+pub const MIN_RTO: Duration = Duration::from_secs(1);
+pub const MAX_RTO: Duration = Duration::from_secs(60);
+/// タイマの粒度(G)．ネットワークデバイスを待つ間隔に合わせている
+pub const CLOCK_GRANULARITY: Duration = Duration::from_millis(100);
+
+/// 再送タイムアウト(RTO)の計算
+/// See also [RFC6298](https://tools.ietf.org/html/rfc6298)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttEstimator {
+    /// 平滑化されたRTT
+    srtt: Option<Duration>,
+    /// RTTの変動
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl RttEstimator {
+    pub fn new() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::from_secs(0),
+            rto: INITIAL_RTO,
+        }
+    }
+
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// RTTの計測値を反映する
+    /// See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-2)
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                // RTTVAR <- (1 - beta) * RTTVAR + beta * |SRTT - R'| (beta = 1/4)
+                // SRTT <- (1 - alpha) * SRTT + alpha * R' (alpha = 1/8)
+                let diff = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + diff / 4;
+                self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+            }
+        }
+
+        let srtt = self.srtt.unwrap();
+        let rto = srtt + CLOCK_GRANULARITY.max(self.rttvar * 4);
+        self.rto = rto.clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// タイムアウト時にRTOを倍にする
+    /// See also [RFC6298](https://tools.ietf.org/html/rfc6298#section-5)
+    pub fn backoff(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+    }
+
+    /// SYNを再送して確立した接続で，まだRTTを計測できていない場合に呼ぶ
+    pub fn on_syn_retransmitted(&mut self) {
+        if self.srtt.is_none() {
+            self.rto = SYN_RETRANSMITTED_RTO;
+        }
+    }
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rto_calculation_test() {
+        let mut estimator = RttEstimator::new();
+        assert_eq!(INITIAL_RTO, estimator.rto());
+
+        // 初回: SRTT = R, RTTVAR = R/2, RTO = SRTT + 4 * RTTVAR
+        estimator.sample(Duration::from_millis(800));
+        assert_eq!(Some(Duration::from_millis(800)), estimator.srtt());
+        assert_eq!(Duration::from_millis(2400), estimator.rto());
+
+        // RTTVAR = 3/4 * 400 + 1/4 * 400 = 400, SRTT = 7/8 * 800 + 1/8 * 400 = 750
+        estimator.sample(Duration::from_millis(400));
+        assert_eq!(Some(Duration::from_millis(750)), estimator.srtt());
+        assert_eq!(Duration::from_millis(2350), estimator.rto());
+
+        // 下限は1秒
+        let mut estimator = RttEstimator::new();
+        estimator.sample(Duration::from_millis(10));
+        assert_eq!(MIN_RTO, estimator.rto());
+    }
+
+    #[test]
+    fn backoff_test() {
+        let mut estimator = RttEstimator::new();
+        estimator.backoff();
+        assert_eq!(Duration::from_secs(2), estimator.rto());
+        for _ in 0..10 {
+            estimator.backoff();
+        }
+        assert_eq!(MAX_RTO, estimator.rto());
+
+        // 新しい計測値が得られれば元に戻る
+        estimator.sample(Duration::from_millis(100));
+        assert_eq!(MIN_RTO, estimator.rto());
+    }
+}
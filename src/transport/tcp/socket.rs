@@ -0,0 +1,669 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{
+    CongestionControlAlgorithm, Connection, ConnectionId, ConnectionTable, Keepalive, Segment,
+    State, DEFAULT_BACKLOG,
+};
+use crate::{
+    internet::SocketAddr,
+    network_device,
+    transport::{tcp, TransportProtocolError},
+    Items,
+};
+
+/// 送信中のセグメント列
+type Transmission = Pin<Box<dyn Future<Output = Result<(), TransportProtocolError>> + Send>>;
+
+/// LISTEN状態のポートを表すソケット
+///
+/// 受信したセグメントは `peachps::run` が処理するので，
+/// ソケットを使う場合は `run` を別タスクで動かしておく必要がある．
+///
+/// ```no_run
+/// # use peachps::{link, network_device, option, transport::tcp::TcpListener};
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let sock = network_device::setup_raw_socket("eth1".to_string())?;
+/// let items = peachps::Items::new(option::PeachPSOption::from_yaml("config.yaml"), sock);
+///
+/// let stack = items.clone();
+/// tokio::spawn(async move { peachps::run(&stack, link::LinkProtocol::Ethernet).await });
+///
+/// let listener = TcpListener::bind(&items, 7)?;
+/// loop {
+///     let (stream, _) = listener.accept().await?;
+///     tokio::spawn(async move {
+///         let (mut reader, mut writer) = tokio::io::split(stream);
+///         tokio::io::copy(&mut reader, &mut writer).await
+///     });
+/// }
+/// # }
+/// ```
+pub struct TcpListener<ND: network_device::NetworkDevice> {
+    items: Items<ND>,
+    local_port: u16,
+}
+
+impl<ND> TcpListener<ND>
+where
+    ND: network_device::NetworkDevice + Send + Sync + 'static,
+{
+    /// ポートをLISTEN状態にしたソケットを作成する
+    pub fn bind(items: &Items<ND>, port: u16) -> Result<Self, TransportProtocolError> {
+        Self::bind_with_backlog(items, port, DEFAULT_BACKLOG)
+    }
+
+    /// 確立中及び受け入れ待ちの接続数の上限を指定してソケットを作成する．
+    /// 上限に達している間に届いたSYNは破棄される
+    pub fn bind_with_backlog(
+        items: &Items<ND>,
+        port: u16,
+        backlog: usize,
+    ) -> Result<Self, TransportProtocolError> {
+        tcp::listen(items, port, backlog)?;
+
+        Ok(Self {
+            items: items.clone(),
+            local_port: port,
+        })
+    }
+
+    /// IPv4とIPv6の両方で待ち受けるが，アドレスはIPv4のものを返す
+    pub fn local_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.items.opt.ip_addr.into(), self.local_port)
+    }
+
+    /// 確立した接続を1つ受け入れる
+    pub async fn accept(&self) -> Result<(TcpStream<ND>, SocketAddr), TransportProtocolError> {
+        let id = std::future::poll_fn(|cx| {
+            let mut tcp_table = self.items.tcp_table.lock().unwrap();
+            match tcp_table.poll_accept(self.local_port, cx.waker()) {
+                Ok(Some(id)) => Poll::Ready(Ok(id)),
+                Ok(None) => Poll::Pending,
+                Err(e) => Poll::Ready(Err(e)),
+            }
+        })
+        .await?;
+
+        let stream = TcpStream::new(&self.items, id);
+        Ok((stream, SocketAddr::new(id.remote_addr, id.remote_port)))
+    }
+}
+
+impl<ND: network_device::NetworkDevice> Drop for TcpListener<ND> {
+    fn drop(&mut self) {
+        // RSTはタイマ処理の際に送信される
+        self.items
+            .tcp_table
+            .lock()
+            .unwrap()
+            .remove_listener(self.local_port);
+    }
+}
+
+/// 確立したTCP接続
+///
+/// `tokio::io::AsyncRead` 及び `AsyncWrite` を実装しているので，
+/// `tokio::io::copy` や `BufReader` 等と組み合わせて使用できる．
+/// `shutdown()` は送信側のみを閉じ(ハーフクローズ)，相手からのデータは引き続き読み出せる．
+/// ドロップした場合は未送信のデータを送り切った後にFINを送る
+pub struct TcpStream<ND: network_device::NetworkDevice> {
+    items: Items<ND>,
+    id: ConnectionId,
+    /// 送信中のセグメント．完了するまで次の送信を行わない
+    transmission: Option<Transmission>,
+}
+
+impl<ND> TcpStream<ND>
+where
+    ND: network_device::NetworkDevice + Send + Sync + 'static,
+{
+    fn new(items: &Items<ND>, id: ConnectionId) -> Self {
+        Self {
+            items: items.clone(),
+            id,
+            transmission: None,
+        }
+    }
+
+    /// 能動オープンを行い，接続が確立するまで待つ
+    pub async fn connect(
+        items: &Items<ND>,
+        addr: SocketAddr,
+    ) -> Result<Self, TransportProtocolError> {
+        let id = tcp::connect(items, addr.ip, addr.port).await?;
+        let stream = Self::new(items, id);
+
+        std::future::poll_fn(|cx| {
+            let mut tcp_table = items.tcp_table.lock().unwrap();
+            let conn = match tcp_table.connections.get(&id) {
+                Some(conn) => conn,
+                None => return Poll::Ready(Err(TransportProtocolError::ConnectionNotFound)),
+            };
+            if let Some(e) = conn.error() {
+                return Poll::Ready(Err(e));
+            }
+            match conn.state() {
+                State::SynSent | State::SynReceived => {
+                    tcp_table.register_write_waker(id, cx.waker());
+                    Poll::Pending
+                }
+                State::Closed => Poll::Ready(Err(TransportProtocolError::ConnectionNotFound)),
+                _ => Poll::Ready(Ok(())),
+            }
+        })
+        .await?;
+
+        Ok(stream)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.id.local_addr, self.id.local_port)
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.id.remote_addr, self.id.remote_port)
+    }
+
+    pub fn congestion_control(&self) -> Result<CongestionControlAlgorithm, TransportProtocolError> {
+        self.connection(|conn| conn.congestion_control())
+    }
+
+    /// この接続で使用する輻輳制御アルゴリズムを切り替える
+    pub fn set_congestion_control(
+        &self,
+        algorithm: CongestionControlAlgorithm,
+    ) -> Result<(), TransportProtocolError> {
+        self.connection(|conn| conn.set_congestion_control(algorithm))
+    }
+
+    pub fn nodelay(&self) -> Result<bool, TransportProtocolError> {
+        self.connection(|conn| conn.nodelay())
+    }
+
+    /// Nagleアルゴリズムを無効にするか(TCP_NODELAY)
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<(), TransportProtocolError> {
+        self.connection(|conn| conn.set_nodelay(nodelay, Instant::now()))
+    }
+
+    pub fn keepalive(&self) -> Result<Option<Keepalive>, TransportProtocolError> {
+        self.connection(|conn| conn.keepalive())
+    }
+
+    /// キープアライブを設定する．`None` の場合は無効になる
+    pub fn set_keepalive(
+        &self,
+        keepalive: Option<Keepalive>,
+    ) -> Result<(), TransportProtocolError> {
+        self.connection(|conn| conn.set_keepalive(keepalive))
+    }
+
+    pub fn delayed_ack(&self) -> Result<Option<Duration>, TransportProtocolError> {
+        self.connection(|conn| conn.delayed_ack())
+    }
+
+    /// 確認応答を遅延させる時間を設定する．`None` の場合は直ちに確認応答を返す
+    pub fn set_delayed_ack(&self, timeout: Option<Duration>) -> Result<(), TransportProtocolError> {
+        self.connection(|conn| conn.set_delayed_ack(timeout, Instant::now()))
+    }
+
+    /// 接続の設定を参照・変更する．
+    /// 変更によって生じたセグメントはタイマ処理の際に送信される
+    fn connection<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> T,
+    ) -> Result<T, TransportProtocolError> {
+        let mut tcp_table = self.items.tcp_table.lock().unwrap();
+        tcp_table
+            .connections
+            .get_mut(&self.id)
+            .map(f)
+            .ok_or(TransportProtocolError::ConnectionNotFound)
+    }
+
+    /// 送信中のセグメントがあれば，送信し終えるまで進める
+    fn poll_transmission(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(transmission) = self.transmission.as_mut() {
+            let result = match transmission.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.transmission = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_transmission(&mut self, segments: Vec<Segment>) {
+        if segments.is_empty() {
+            return;
+        }
+        let items = self.items.clone();
+        let id = self.id;
+        self.transmission = Some(Box::pin(async move {
+            for seg in segments {
+                tcp::tx(&items, &id, seg).await?;
+            }
+            Ok(())
+        }));
+    }
+
+    /// 接続に対する操作を行い，送信すべきセグメントがあれば送信を開始する．
+    /// 正常に閉じて取り除かれた接続の場合は `None` が渡される
+    fn with_connection<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        f: impl FnOnce(&mut ConnectionTable, Option<&mut Connection>, &Waker) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let (result, outgoing) = {
+            let mut tcp_table = self.items.tcp_table.lock().unwrap();
+            // テーブルと接続を同時に借用するため，一旦取り出す
+            let mut conn = tcp_table.connections.remove(&self.id);
+            let result = f(&mut tcp_table, conn.as_mut(), cx.waker());
+            let outgoing = match conn {
+                Some(mut conn) => {
+                    let outgoing = conn.take_outgoing();
+                    tcp_table.connections.insert(self.id, conn);
+                    tcp_table.remove_if_closed(&self.id);
+                    outgoing
+                }
+                None => Vec::new(),
+            };
+            (result, outgoing)
+        };
+
+        self.start_transmission(outgoing);
+        // 送信が完了しなくても，次の操作の際に続きを行う
+        if let Poll::Ready(Err(e)) = self.poll_transmission(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        result
+    }
+}
+
+impl<ND> AsyncRead for TcpStream<ND>
+where
+    ND: network_device::NetworkDevice + Send + Sync + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Poll::Ready(Err(e)) = this.poll_transmission(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        let id = this.id;
+        this.with_connection(cx, |tcp_table, conn, waker| {
+            let conn = match conn {
+                Some(conn) => conn,
+                None => return Poll::Ready(Ok(())),
+            };
+            if let Some(e) = conn.error() {
+                return Poll::Ready(Err(e.into()));
+            }
+            if conn.readable_len() > 0 {
+                let len = conn.recv(buf.initialize_unfilled(), Instant::now());
+                buf.advance(len);
+                return Poll::Ready(Ok(()));
+            }
+            // 相手がFINを送った後は，読み出しの終端(EOF)になる
+            if conn.is_read_closed() {
+                return Poll::Ready(Ok(()));
+            }
+
+            tcp_table.register_read_waker(id, waker);
+            Poll::Pending
+        })
+    }
+}
+
+impl<ND> AsyncWrite for TcpStream<ND>
+where
+    ND: network_device::NetworkDevice + Send + Sync + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.poll_transmission(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other.map_ok(|_| 0),
+        }
+
+        let id = this.id;
+        this.with_connection(cx, |tcp_table, conn, waker| {
+            let conn = match conn {
+                Some(conn) => conn,
+                None => return Poll::Ready(Err(TransportProtocolError::ConnectionNotFound.into())),
+            };
+            if let Some(e) = conn.error() {
+                return Poll::Ready(Err(e.into()));
+            }
+            if conn.state() != State::Closed && conn.send_buffer_space() == 0 {
+                tcp_table.register_write_waker(id, waker);
+                return Poll::Pending;
+            }
+            Poll::Ready(conn.send(buf, Instant::now()).map_err(Into::into))
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_transmission(cx)
+    }
+
+    /// FINを送信し，送信側を閉じる
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_transmission(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+
+        match this.with_connection(cx, |_, conn, _| {
+            if let Some(conn) = conn {
+                conn.close(Instant::now());
+            }
+            Poll::Ready(Ok(()))
+        }) {
+            Poll::Ready(Ok(())) => this.poll_transmission(cx),
+            other => other,
+        }
+    }
+}
+
+impl<ND: network_device::NetworkDevice> Drop for TcpStream<ND> {
+    fn drop(&mut self) {
+        let mut tcp_table = self.items.tcp_table.lock().unwrap();
+        tcp_table.remove_wakers(&self.id);
+
+        let errored = match tcp_table.connections.get_mut(&self.id) {
+            Some(conn) => {
+                // FINはタイマ処理の際に送信される
+                conn.close(Instant::now());
+                conn.error().is_some()
+            }
+            None => false,
+        };
+        if errored {
+            tcp_table.connections.remove(&self.id);
+        } else {
+            tcp_table.remove_if_closed(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        internet::{ip::IPv4Addr, IpAddr},
+        link::MacAddress,
+        network_device::FakeDevice,
+        option::{PeachPSOption, TcpOption},
+        transport::tcp::{ControlFlags, SegmentHeader},
+    };
+
+    const PEER_PORT: u16 = 50000;
+
+    fn fake_items(tcp: TcpOption) -> Items<FakeDevice> {
+        let opt = PeachPSOption {
+            ip_addr: IPv4Addr::from("192.168.11.30"),
+            network_mask: IPv4Addr::from("255.255.255.0"),
+            tcp,
+            ..Default::default()
+        };
+        let items = Items::new(opt, FakeDevice::default());
+        // 相手へ送信する際にアドレス解決を待たないようにする
+        items.arp_table.lock().unwrap().insert(
+            IPv4Addr::from("192.168.11.1"),
+            MacAddress([0x00, 0x15, 0x5d, 0x22, 0x1e, 0xff]),
+        );
+        items
+    }
+
+    fn server_id(port: u16) -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.30"),
+            local_port: 80,
+            remote_addr: IpAddr::from("192.168.11.1"),
+            remote_port: port,
+        }
+    }
+
+    fn client_id(port: u16) -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.1"),
+            local_port: port,
+            remote_addr: IpAddr::from("192.168.11.30"),
+            remote_port: 80,
+        }
+    }
+
+    /// デバイスから送信されたセグメントを取り出す
+    fn take_sent(items: &Items<FakeDevice>) -> Vec<Segment> {
+        let frames: Vec<Vec<u8>> = {
+            let dev = items.dev.lock().unwrap();
+            let mut frames = dev.frames.lock().unwrap();
+            frames.drain(..).collect()
+        };
+        frames
+            .iter()
+            .map(|frame| {
+                // Ethernetヘッダとオプションのないものとみなす
+                let ip_header_length = ((frame[14] & 0x0f) * 4) as usize;
+                let raw_segment = &frame[14 + ip_header_length..];
+                let header = SegmentHeader::new_from_bytes(
+                    raw_segment,
+                    TransportProtocolError::CannotParseTCPSegment,
+                )
+                .unwrap();
+                let payload = raw_segment[header.header_length()..].to_vec();
+                Segment { header, payload }
+            })
+            .collect()
+    }
+
+    /// 相手の接続とスタックの間で，送るべきセグメントがなくなるまでやり取りする
+    fn exchange(items: &Items<FakeDevice>, peer: &mut Connection, now: Instant) {
+        loop {
+            let outgoing = peer.take_outgoing();
+            let mut incoming = take_sent(items);
+            if outgoing.is_empty() && incoming.is_empty() {
+                break;
+            }
+            for seg in outgoing.iter() {
+                let id = server_id(seg.header.src_port);
+                incoming.extend(
+                    items
+                        .tcp_table
+                        .lock()
+                        .unwrap()
+                        .process_segment(id, seg, now),
+                );
+            }
+            for seg in incoming.iter() {
+                peer.on_segment(seg, now);
+            }
+        }
+    }
+
+    /// `PEER_PORT` から接続し，受け入れたストリームと相手の接続を返す
+    async fn accept(
+        items: &Items<FakeDevice>,
+        listener: &TcpListener<FakeDevice>,
+        now: Instant,
+    ) -> (TcpStream<FakeDevice>, Connection) {
+        let mut peer = Connection::connect(client_id(PEER_PORT), &Default::default(), 1000, now);
+        exchange(items, &mut peer, now);
+        assert_eq!(State::Established, peer.state());
+
+        let (stream, addr) = listener.accept().await.unwrap();
+        assert_eq!(
+            SocketAddr::new(IpAddr::from("192.168.11.1"), PEER_PORT),
+            addr
+        );
+        assert_eq!(listener.local_addr(), stream.local_addr());
+        (stream, peer)
+    }
+
+    #[tokio::test]
+    async fn stream_round_trip_test() {
+        let now = Instant::now();
+        let items = fake_items(Default::default());
+        let listener = TcpListener::bind(&items, 80).unwrap();
+        let (mut stream, mut peer) = accept(&items, &listener, now).await;
+
+        peer.send(b"hello", now).unwrap();
+        exchange(&items, &mut peer, now);
+        let mut buf = [0; 16];
+        assert_eq!(5, stream.read(&mut buf).await.unwrap());
+        assert_eq!(b"hello", &buf[..5]);
+
+        stream.write_all(b"world").await.unwrap();
+        exchange(&items, &mut peer, now);
+        assert_eq!(5, peer.recv(&mut buf, now));
+        assert_eq!(b"world", &buf[..5]);
+
+        // 送信側を閉じても，相手からのデータは読み出せる
+        stream.shutdown().await.unwrap();
+        exchange(&items, &mut peer, now);
+        assert_eq!(State::CloseWait, peer.state());
+        peer.send(b"bye", now).unwrap();
+        peer.close(now);
+        exchange(&items, &mut peer, now);
+        assert_eq!(3, stream.read(&mut buf).await.unwrap());
+        assert_eq!(b"bye", &buf[..3]);
+        assert_eq!(0, stream.read(&mut buf).await.unwrap());
+        assert_eq!(State::Closed, peer.state());
+    }
+
+    #[tokio::test]
+    async fn reset_is_reported_as_connection_reset_test() {
+        let now = Instant::now();
+        let items = fake_items(Default::default());
+        let listener = TcpListener::bind(&items, 80).unwrap();
+        let (mut stream, mut peer) = accept(&items, &listener, now).await;
+
+        peer.abort();
+        exchange(&items, &mut peer, now);
+
+        let mut buf = [0; 16];
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionReset, err.kind());
+        let err = stream.write(b"x").await.unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionReset, err.kind());
+
+        // エラーを受け取った接続はドロップで取り除かれる
+        drop(stream);
+        assert!(items.tcp_table.lock().unwrap().connections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn retransmission_timeout_is_reported_as_timed_out_test() {
+        let now = Instant::now();
+        let items = fake_items(TcpOption {
+            max_retransmissions: 1,
+            ..Default::default()
+        });
+        let listener = TcpListener::bind(&items, 80).unwrap();
+        let (mut stream, _peer) = accept(&items, &listener, now).await;
+
+        // 相手が応答しないまま再送の上限に達する
+        stream.write_all(b"hello").await.unwrap();
+        for i in 1..=10 {
+            tcp::on_timer(&items, now + Duration::from_secs(120 * i))
+                .await
+                .unwrap();
+        }
+        assert!(take_sent(&items).len() > 1);
+
+        let mut buf = [0; 16];
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[tokio::test]
+    async fn listener_backlog_test() {
+        let now = Instant::now();
+        let items = fake_items(TcpOption {
+            syn_cookies: false,
+            ..Default::default()
+        });
+        let _listener = TcpListener::bind_with_backlog(&items, 80, 1).unwrap();
+        assert!(matches!(
+            TcpListener::bind(&items, 80),
+            Err(TransportProtocolError::PortAlreadyInUse { port: 80 })
+        ));
+
+        let mut first = Connection::connect(client_id(PEER_PORT), &Default::default(), 1000, now);
+        exchange(&items, &mut first, now);
+        assert_eq!(State::Established, first.state());
+
+        // 受け入れ待ちの接続で埋まっているので，SYNに応答しない
+        let mut second =
+            Connection::connect(client_id(PEER_PORT + 1), &Default::default(), 2000, now);
+        exchange(&items, &mut second, now);
+        assert_eq!(State::SynSent, second.state());
+        assert!(!items
+            .tcp_table
+            .lock()
+            .unwrap()
+            .connections
+            .contains_key(&server_id(PEER_PORT + 1)));
+    }
+
+    #[tokio::test]
+    async fn drop_sends_fin_test() {
+        let now = Instant::now();
+        let items = fake_items(Default::default());
+        let listener = TcpListener::bind(&items, 80).unwrap();
+        let (mut stream, mut peer) = accept(&items, &listener, now).await;
+
+        stream.write_all(b"hello").await.unwrap();
+        drop(stream);
+        assert_eq!(
+            State::FinWait1,
+            items.tcp_table.lock().unwrap().connections[&server_id(PEER_PORT)].state()
+        );
+
+        // FINはタイマ処理で送信される
+        tcp::on_timer(&items, now).await.unwrap();
+        let sent = take_sent(&items);
+        assert!(sent
+            .iter()
+            .any(|seg| seg.header.flags.contains(ControlFlags::FIN)));
+        for seg in sent.iter() {
+            peer.on_segment(seg, now);
+        }
+        exchange(&items, &mut peer, now);
+        assert_eq!(State::CloseWait, peer.state());
+        let mut buf = [0; 16];
+        assert_eq!(5, peer.recv(&mut buf, now));
+
+        // LISTENを解除すると，新しい接続にはRSTを返す
+        drop(listener);
+        let mut other =
+            Connection::connect(client_id(PEER_PORT + 1), &Default::default(), 2000, now);
+        exchange(&items, &mut other, now);
+        assert_eq!(State::Closed, other.state());
+        assert!(matches!(
+            other.error(),
+            Some(TransportProtocolError::ConnectionRefused)
+        ));
+    }
+}
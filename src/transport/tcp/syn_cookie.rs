@@ -0,0 +1,138 @@
+use std::{
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use siphasher::sip::SipHasher24;
+
+use super::ConnectionId;
+
+/// カウンタtが1進む間隔
+const COUNTER_INTERVAL: Duration = Duration::from_secs(64);
+/// 何世代前のカウンタで生成したcookieまで受け入れるか
+const COUNTER_TOLERANCE: u32 = 1;
+/// cookieに3ビットで埋め込むMSSの候補．
+/// 先頭の536はIPv4のデフォルトMSSで，これより小さいMSSもこの値に切り上げる
+/// See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.2.2.6)
+const MSS_TABLE: [u16; 8] = [536, 1024, 1220, 1360, 1400, 1440, 1452, 1460];
+
+/// SYN cookieの生成と検証
+/// See also [RFC4987](https://tools.ietf.org/html/rfc4987#section-3.6)
+///
+/// SYNキューが溢れた際に，接続の状態を保持する代わりにSYN/ACKのISNへ情報を埋め込む．
+/// 上位5ビットがカウンタt(64秒毎に進む)，次の3ビットがMSSの番号，
+/// 下位24ビットがソケットペア・相手のISN・tに対する秘密鍵付きハッシュとなる
+#[derive(Debug, Clone)]
+pub struct SynCookieGenerator {
+    key: [u8; 16],
+    origin: Instant,
+}
+
+impl SynCookieGenerator {
+    pub fn new() -> Self {
+        Self {
+            key: rand::random(),
+            origin: Instant::now(),
+        }
+    }
+
+    /// SYN/ACKのISNとして使うcookieを生成する．
+    /// `mss` は相手が通知したMSSで，これを超えない最大の候補が埋め込まれる．
+    /// 最小の候補(536)を下回る場合は536が埋め込まれる
+    pub fn generate(&self, id: &ConnectionId, peer_isn: u32, mss: u16, now: Instant) -> u32 {
+        let t = self.counter(now);
+        let m = MSS_TABLE
+            .iter()
+            .rposition(|v| *v <= mss.max(MSS_TABLE[0]))
+            .unwrap_or_default() as u32;
+
+        (t % 32) << 27 | m << 24 | self.hash(id, peer_isn, t)
+    }
+
+    /// ACKで返ってきたcookieを検証し，埋め込まれたMSSを返す
+    pub fn validate(
+        &self,
+        id: &ConnectionId,
+        peer_isn: u32,
+        cookie: u32,
+        now: Instant,
+    ) -> Option<u16> {
+        let t_now = self.counter(now);
+        let t_bits = cookie >> 27;
+        let m = (cookie >> 24) & 0x7;
+
+        (0..=COUNTER_TOLERANCE)
+            .filter_map(|age| t_now.checked_sub(age))
+            .filter(|t| t % 32 == t_bits)
+            .any(|t| self.hash(id, peer_isn, t) == cookie & 0xff_ffff)
+            .then(|| MSS_TABLE[m as usize])
+    }
+
+    fn counter(&self, now: Instant) -> u32 {
+        (now.saturating_duration_since(self.origin).as_secs() / COUNTER_INTERVAL.as_secs()) as u32
+    }
+
+    fn hash(&self, id: &ConnectionId, peer_isn: u32, t: u32) -> u32 {
+        let mut hasher = SipHasher24::new_with_key(&self.key);
+        id.local_addr.hash(&mut hasher);
+        hasher.write_u16(id.local_port);
+        id.remote_addr.hash(&mut hasher);
+        hasher.write_u16(id.remote_port);
+        hasher.write_u32(peer_isn);
+        hasher.write_u32(t);
+        hasher.finish() as u32 & 0xff_ffff
+    }
+}
+
+impl Default for SynCookieGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internet::IpAddr;
+
+    use super::*;
+
+    fn id() -> ConnectionId {
+        ConnectionId {
+            local_addr: IpAddr::from("192.168.11.30"),
+            local_port: 80,
+            remote_addr: IpAddr::from("192.168.11.1"),
+            remote_port: 50000,
+        }
+    }
+
+    #[test]
+    fn syn_cookie_test() {
+        let generator = SynCookieGenerator::new();
+        let now = Instant::now();
+
+        let cookie = generator.generate(&id(), 1000, 1460, now);
+        assert_eq!(Some(1460), generator.validate(&id(), 1000, cookie, now));
+        // 候補にないMSSは，それを超えない最大の候補に丸められる
+        let cookie = generator.generate(&id(), 1000, 1300, now);
+        assert_eq!(Some(1220), generator.validate(&id(), 1000, cookie, now));
+        // 最小の候補を下回るMSSは，最小の候補に切り上げられる
+        let small = generator.generate(&id(), 1000, 100, now);
+        assert_eq!(Some(536), generator.validate(&id(), 1000, small, now));
+
+        // 次の世代までは受け入れ，それより古いものは拒否する
+        assert_eq!(
+            Some(1220),
+            generator.validate(&id(), 1000, cookie, now + COUNTER_INTERVAL)
+        );
+        assert_eq!(
+            None,
+            generator.validate(&id(), 1000, cookie, now + COUNTER_INTERVAL * 2)
+        );
+
+        // ISNやソケットペアが異なる場合は拒否する
+        assert_eq!(None, generator.validate(&id(), 1001, cookie, now));
+        let mut another = id();
+        another.remote_port = 50001;
+        assert_eq!(None, generator.validate(&another, 1000, cookie, now));
+    }
+}
@@ -0,0 +1,471 @@
+use std::io::Cursor;
+
+use crate::{byteorder_wrapper, transport::TransportHeader};
+
+/// data_offset領域のうちオフセットが該当する部分のマスク
+const DATA_OFFSET_MASK: u8 = 0xf0;
+
+/// TCPセグメントのヘッダ構造体
+/// See also [RFC9293](https://tools.ietf.org/html/rfc9293#section-3.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentHeader {
+    /// 送信元ポート番号
+    pub src_port: u16,
+    /// 宛先ポート番号
+    pub dst_port: u16,
+    /// このセグメントの先頭データのシーケンス番号．
+    /// SYNが立っている場合はISNを示す
+    pub sequence_number: u32,
+    /// ACKが立っている場合，次に受信を期待するシーケンス番号
+    pub acknowledgment_number: u32,
+    /// 上位4ビット: データオフセット(32bitワードの数), 下位4ビット: 予約
+    pub data_offset: u8,
+    /// 制御ビット
+    pub flags: ControlFlags,
+    /// 受信可能なデータ量
+    pub window: u16,
+    /// 疑似ヘッダを含めて計算するチェックサム
+    pub checksum: u16,
+    /// URGが立っている場合，緊急データの終端を示す
+    pub urgent_pointer: u16,
+    pub options: Vec<SegmentOption>,
+}
+
+/// 制御ビット
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ControlFlags(pub u8);
+
+/// TCPオプション
+/// See also [TCP Parameters](https://www.iana.org/assignments/tcp-parameters/tcp-parameters.xhtml)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentOption {
+    EndOfOptionList,
+    NoOperation,
+    MaximumSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    /// 受信済みのブロックを(左端, 右端)で表す
+    Sack(Vec<(u32, u32)>),
+    Timestamps {
+        value: u32,
+        echo_reply: u32,
+    },
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// ヘッダとペイロードをまとめたもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub header: SegmentHeader,
+    pub payload: Vec<u8>,
+}
+
+impl ControlFlags {
+    pub const FIN: Self = Self(0x01);
+    pub const SYN: Self = Self(0x02);
+    pub const RST: Self = Self(0x04);
+    pub const PSH: Self = Self(0x08);
+    pub const ACK: Self = Self(0x10);
+    pub const URG: Self = Self(0x20);
+    pub const ECE: Self = Self(0x40);
+    pub const CWR: Self = Self(0x80);
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for ControlFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for ControlFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl SegmentHeader {
+    /// オプションを含まないヘッダの長さ
+    pub const LEAST_LENGTH: usize = 20;
+    /// オプションを含むヘッダの最大長
+    pub const MAX_LENGTH: usize = 60;
+
+    pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut reader = Cursor::new(buf);
+        let mut segment_hdr = Self {
+            src_port: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            dst_port: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            sequence_number: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+            acknowledgment_number: byteorder_wrapper::read_u32_as_be(&mut reader, err)?,
+            data_offset: byteorder_wrapper::read_u8(&mut reader, err)?,
+            flags: ControlFlags(byteorder_wrapper::read_u8(&mut reader, err)?),
+            window: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            checksum: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            urgent_pointer: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            ..Default::default()
+        };
+
+        let header_length = segment_hdr.header_length();
+        if header_length < Self::LEAST_LENGTH || buf.len() < header_length {
+            return Err(err);
+        }
+
+        segment_hdr.options =
+            SegmentOption::parse_options(&buf[Self::LEAST_LENGTH..header_length], err)?;
+
+        Ok(segment_hdr)
+    }
+
+    /// ヘッダをバイト列に変換する．
+    /// data_offsetはオプションの長さから計算し直す
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut raw_options = Vec::new();
+        for option in self.options.iter() {
+            option.write_to(&mut raw_options, err)?;
+        }
+        // 32bit境界までパディングする
+        while raw_options.len() % 4 != 0 {
+            byteorder_wrapper::write_u8(&mut raw_options, 0, err)?;
+        }
+        if Self::LEAST_LENGTH + raw_options.len() > Self::MAX_LENGTH {
+            return Err(err);
+        }
+        let data_offset = ((Self::LEAST_LENGTH + raw_options.len()) / 4) as u8;
+
+        let mut buf = Vec::new();
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.src_port, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.dst_port, err)?;
+        byteorder_wrapper::write_u32_as_be(&mut buf, self.sequence_number, err)?;
+        byteorder_wrapper::write_u32_as_be(&mut buf, self.acknowledgment_number, err)?;
+        byteorder_wrapper::write_u8(&mut buf, data_offset.checked_shl(4).unwrap(), err)?;
+        byteorder_wrapper::write_u8(&mut buf, self.flags.0, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.window, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.checksum, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.urgent_pointer, err)?;
+        buf.append(&mut raw_options);
+
+        Ok(buf)
+    }
+
+    /// data_offset領域からヘッダ長を取り出す．
+    /// 32bitワードの数であるため，2ビット左シフトしてバイト単位に直す
+    pub fn header_length(&self) -> usize {
+        ((self.data_offset & DATA_OFFSET_MASK)
+            .checked_shr(4)
+            .unwrap() as usize)
+            .checked_shl(2)
+            .unwrap()
+    }
+
+    /// タイムスタンプオプションの(TSval, TSecr)
+    pub fn timestamps(&self) -> Option<(u32, u32)> {
+        self.options.iter().find_map(|option| match option {
+            SegmentOption::Timestamps { value, echo_reply } => Some((*value, *echo_reply)),
+            _ => None,
+        })
+    }
+
+    /// SACKオプションのブロック
+    pub fn sack_blocks(&self) -> &[(u32, u32)] {
+        self.options
+            .iter()
+            .find_map(|option| match option {
+                SegmentOption::Sack(blocks) => Some(blocks.as_slice()),
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+}
+
+impl SegmentOption {
+    const KIND_END_OF_OPTION_LIST: u8 = 0;
+    const KIND_NO_OPERATION: u8 = 1;
+    const KIND_MAXIMUM_SEGMENT_SIZE: u8 = 2;
+    const KIND_WINDOW_SCALE: u8 = 3;
+    const KIND_SACK_PERMITTED: u8 = 4;
+    const KIND_SACK: u8 = 5;
+    const KIND_TIMESTAMPS: u8 = 8;
+
+    /// オプション領域をパースする
+    pub fn parse_options<E>(buf: &[u8], err: E) -> Result<Vec<Self>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut options = Vec::new();
+        let mut reader = Cursor::new(buf);
+
+        while (reader.position() as usize) < buf.len() {
+            let kind = byteorder_wrapper::read_u8(&mut reader, err)?;
+            match kind {
+                Self::KIND_END_OF_OPTION_LIST => {
+                    options.push(SegmentOption::EndOfOptionList);
+                    break;
+                }
+                Self::KIND_NO_OPERATION => {
+                    options.push(SegmentOption::NoOperation);
+                    continue;
+                }
+                _ => {}
+            }
+
+            // kindとlengthを含めた長さ
+            let length = byteorder_wrapper::read_u8(&mut reader, err)? as usize;
+            if length < 2 || (reader.position() as usize) + length - 2 > buf.len() {
+                return Err(err);
+            }
+            let start = reader.position() as usize;
+            let data = &buf[start..start + length - 2];
+            reader.set_position((start + length - 2) as u64);
+
+            let mut data_reader = Cursor::new(data);
+            let option = match (kind, length) {
+                (Self::KIND_MAXIMUM_SEGMENT_SIZE, 4) => SegmentOption::MaximumSegmentSize(
+                    byteorder_wrapper::read_u16_as_be(&mut data_reader, err)?,
+                ),
+                (Self::KIND_WINDOW_SCALE, 3) => {
+                    SegmentOption::WindowScale(byteorder_wrapper::read_u8(&mut data_reader, err)?)
+                }
+                (Self::KIND_SACK_PERMITTED, 2) => SegmentOption::SackPermitted,
+                (Self::KIND_SACK, _) if (length - 2).is_multiple_of(8) => {
+                    let mut blocks = Vec::new();
+                    for _ in 0..(length - 2) / 8 {
+                        let left = byteorder_wrapper::read_u32_as_be(&mut data_reader, err)?;
+                        let right = byteorder_wrapper::read_u32_as_be(&mut data_reader, err)?;
+                        blocks.push((left, right));
+                    }
+                    SegmentOption::Sack(blocks)
+                }
+                (Self::KIND_TIMESTAMPS, 10) => SegmentOption::Timestamps {
+                    value: byteorder_wrapper::read_u32_as_be(&mut data_reader, err)?,
+                    echo_reply: byteorder_wrapper::read_u32_as_be(&mut data_reader, err)?,
+                },
+                // 既知のオプションでも長さが不正なものは未知のものとして扱う
+                _ => SegmentOption::Unknown {
+                    kind,
+                    data: data.to_vec(),
+                },
+            };
+            options.push(option);
+        }
+
+        Ok(options)
+    }
+
+    pub fn write_to<E>(&self, buf: &mut Vec<u8>, err: E) -> Result<(), E>
+    where
+        E: std::error::Error + Copy,
+    {
+        match self {
+            SegmentOption::EndOfOptionList => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_END_OF_OPTION_LIST, err)?;
+            }
+            SegmentOption::NoOperation => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_NO_OPERATION, err)?;
+            }
+            SegmentOption::MaximumSegmentSize(mss) => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_MAXIMUM_SEGMENT_SIZE, err)?;
+                byteorder_wrapper::write_u8(buf, 4, err)?;
+                byteorder_wrapper::write_u16_as_be(buf, *mss, err)?;
+            }
+            SegmentOption::WindowScale(shift) => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_WINDOW_SCALE, err)?;
+                byteorder_wrapper::write_u8(buf, 3, err)?;
+                byteorder_wrapper::write_u8(buf, *shift, err)?;
+            }
+            SegmentOption::SackPermitted => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_SACK_PERMITTED, err)?;
+                byteorder_wrapper::write_u8(buf, 2, err)?;
+            }
+            SegmentOption::Sack(blocks) => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_SACK, err)?;
+                byteorder_wrapper::write_u8(buf, (2 + blocks.len() * 8) as u8, err)?;
+                for (left, right) in blocks.iter() {
+                    byteorder_wrapper::write_u32_as_be(buf, *left, err)?;
+                    byteorder_wrapper::write_u32_as_be(buf, *right, err)?;
+                }
+            }
+            SegmentOption::Timestamps { value, echo_reply } => {
+                byteorder_wrapper::write_u8(buf, Self::KIND_TIMESTAMPS, err)?;
+                byteorder_wrapper::write_u8(buf, 10, err)?;
+                byteorder_wrapper::write_u32_as_be(buf, *value, err)?;
+                byteorder_wrapper::write_u32_as_be(buf, *echo_reply, err)?;
+            }
+            SegmentOption::Unknown { kind, data } => {
+                byteorder_wrapper::write_u8(buf, *kind, err)?;
+                byteorder_wrapper::write_u8(buf, (2 + data.len()) as u8, err)?;
+                buf.extend_from_slice(data);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Segment {
+    /// セグメントが消費するシーケンス番号の数(SEG.LEN)
+    /// SYNとFINはそれぞれ1つ分として数える
+    pub fn len(&self) -> u32 {
+        let mut len = self.payload.len() as u32;
+        if self.header.flags.contains(ControlFlags::SYN) {
+            len += 1;
+        }
+        if self.header.flags.contains(ControlFlags::FIN) {
+            len += 1;
+        }
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TransportHeader for SegmentHeader {}
+
+impl Default for SegmentHeader {
+    fn default() -> Self {
+        Self {
+            src_port: 0,
+            dst_port: 0,
+            sequence_number: 0,
+            acknowledgment_number: 0,
+            data_offset: ((Self::LEAST_LENGTH / 4) as u8).checked_shl(4).unwrap(),
+            flags: Default::default(),
+            window: 0,
+            checksum: 0,
+            urgent_pointer: 0,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for SegmentHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "src_port: {}", self.src_port)?;
+        writeln!(f, "dst_port: {}", self.dst_port)?;
+        writeln!(f, "sequence_number: {}", self.sequence_number)?;
+        writeln!(f, "acknowledgment_number: {}", self.acknowledgment_number)?;
+        writeln!(f, "header_length (bytes): {}", self.header_length())?;
+        writeln!(f, "flags: {}", self.flags)?;
+        writeln!(f, "window: {}", self.window)?;
+        writeln!(f, "checksum: {}", self.checksum)?;
+        writeln!(f, "urgent_pointer: {}", self.urgent_pointer)?;
+        writeln!(f, "options: {:?}", self.options)?;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ControlFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = [
+            (Self::CWR, "CWR"),
+            (Self::ECE, "ECE"),
+            (Self::URG, "URG"),
+            (Self::ACK, "ACK"),
+            (Self::PSH, "PSH"),
+            (Self::RST, "RST"),
+            (Self::SYN, "SYN"),
+            (Self::FIN, "FIN"),
+        ];
+        let flags_str = names
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect::<Vec<&str>>()
+            .join("|");
+        write!(f, "[{}]", flags_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::TransportProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn parse_tcp_segment_test() {
+        // SYN with MSS, SACK permitted, timestamps, NOP, window scale
+        let raw_segment = [
+            0xc3, 0x50, 0x00, 0x50, 0x8d, 0x3b, 0x2c, 0x11, 0x00, 0x00, 0x00, 0x00, 0xa0, 0x02,
+            0xfa, 0xf0, 0x12, 0x34, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a,
+            0x00, 0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x03, 0x07,
+        ];
+        let result = SegmentHeader::new_from_bytes(
+            &raw_segment,
+            TransportProtocolError::CannotParseTCPSegment,
+        );
+        assert!(result.is_ok());
+        let segment_hdr = result.unwrap();
+        assert_eq!(50000, segment_hdr.src_port);
+        assert_eq!(80, segment_hdr.dst_port);
+        assert_eq!(0x8d3b2c11, segment_hdr.sequence_number);
+        assert_eq!(0, segment_hdr.acknowledgment_number);
+        assert_eq!(40, segment_hdr.header_length());
+        assert_eq!(ControlFlags::SYN, segment_hdr.flags);
+        assert_eq!(64240, segment_hdr.window);
+        assert_eq!(0x1234, segment_hdr.checksum);
+        assert_eq!(
+            vec![
+                SegmentOption::MaximumSegmentSize(1460),
+                SegmentOption::SackPermitted,
+                SegmentOption::Timestamps {
+                    value: 0x00010203,
+                    echo_reply: 0
+                },
+                SegmentOption::NoOperation,
+                SegmentOption::WindowScale(7),
+            ],
+            segment_hdr.options
+        );
+
+        let raw = segment_hdr
+            .to_bytes(TransportProtocolError::CannotParseTCPSegment)
+            .unwrap();
+        assert_eq!(raw_segment.to_vec(), raw);
+    }
+
+    #[test]
+    fn parse_malformed_option_test() {
+        // length=1 のオプションは不正
+        let raw_segment = [
+            0xc3, 0x50, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x60, 0x02,
+            0xfa, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x00,
+        ];
+        assert!(SegmentHeader::new_from_bytes(
+            &raw_segment,
+            TransportProtocolError::CannotParseTCPSegment
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn sack_option_round_trip_test() {
+        let option = SegmentOption::Sack(vec![(100, 200), (300, 400)]);
+        let mut buf = Vec::new();
+        option
+            .write_to(&mut buf, TransportProtocolError::CannotParseTCPSegment)
+            .unwrap();
+        assert_eq!(18, buf.len());
+        assert_eq!(
+            vec![option],
+            SegmentOption::parse_options(&buf, TransportProtocolError::CannotParseTCPSegment)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,8 @@
+mod types;
+pub use types::*;
+
+mod protocol;
+pub use protocol::*;
+
+mod socket;
+pub use socket::*;
@@ -0,0 +1,246 @@
+use tokio::sync::mpsc;
+
+use super::{Datagram, DatagramError, DatagramHeader, DatagramReceiver};
+use crate::{
+    checksum::calculate_checksum_with_ip_pseudo_header,
+    internet::{self, IpAddr},
+    link, network_device,
+    transport::{icmp, icmpv6, TransportProtocol, TransportProtocolError},
+    Items, RxResult,
+};
+
+/// バインドされたポート毎に保持する受信キューの長さ
+pub const RECEIVE_QUEUE_LENGTH: usize = 64;
+/// エフェメラルポートの範囲
+/// See also [RFC6335](https://tools.ietf.org/html/rfc6335#section-6)
+const EPHEMERAL_PORT_RANGE: std::ops::RangeInclusive<u16> = 49152..=65535;
+/// IPヘッダとUDPヘッダを除いた，1データグラムで送信可能なデータ長
+const MAX_PAYLOAD_LENGTH: usize =
+    link::MTU - internet::ip::IPHeader::LEAST_LENGTH as usize - DatagramHeader::LENGTH;
+/// IPv6では送信元で断片化できるので，ペイロード長フィールドの上限まで送信できる
+const MAX_IPV6_PAYLOAD_LENGTH: usize = u16::MAX as usize - DatagramHeader::LENGTH;
+
+pub async fn rx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    rx_result: RxResult,
+    buf: &[u8],
+) -> Result<(DatagramHeader, Vec<u8>), TransportProtocolError> {
+    let raw_datagram = &buf[..rx_result.message_len.min(buf.len())];
+    let datagram_hdr = DatagramHeader::new_from_bytes(
+        raw_datagram,
+        TransportProtocolError::CannotParseUDPDatagram,
+    )?;
+
+    // ヘッダの長さフィールドが実際のデータより大きければ破棄する
+    let length = datagram_hdr.length as usize;
+    if length < DatagramHeader::LENGTH || raw_datagram.len() < length {
+        return Err(TransportProtocolError::CannotParseUDPDatagram);
+    }
+    let raw_datagram = &raw_datagram[..length];
+
+    // IPv6ではチェックサムが必須なので，0のデータグラムは破棄する
+    // See also [RFC8200](https://tools.ietf.org/html/rfc8200#section-8.1)
+    if datagram_hdr.checksum == 0 && rx_result.src_ip_addr.is_ipv6() {
+        return Err(TransportProtocolError::InvalidChecksum);
+    }
+
+    // チェックサムが0の場合，送信側は計算していない
+    if datagram_hdr.checksum != 0
+        && calculate_checksum_with_ip_pseudo_header(
+            rx_result.src_ip_addr,
+            rx_result.dst_ip_addr,
+            TransportProtocol::UDP,
+            raw_datagram,
+            TransportProtocolError::InvalidChecksum,
+        )? != 0
+    {
+        return Err(TransportProtocolError::InvalidChecksum);
+    }
+
+    if table.opt.debug {
+        eprintln!("++++++++ rx udp datagram ++++++++");
+        eprintln!("{}", datagram_hdr);
+    }
+
+    let payload = raw_datagram[DatagramHeader::LENGTH..].to_vec();
+
+    let sender = table
+        .udp_table
+        .lock()
+        .unwrap()
+        .get(&datagram_hdr.dst_port)
+        .cloned();
+
+    match sender {
+        Some(sender) => {
+            // 受信キューが溢れている場合は破棄する
+            let _ = sender.try_send(Ok(Datagram {
+                src_addr: rx_result.src_ip_addr,
+                src_port: datagram_hdr.src_port,
+                dst_addr: rx_result.dst_ip_addr,
+                dst_port: datagram_hdr.dst_port,
+                payload: payload.clone(),
+            }));
+        }
+        None => {
+            // ブロードキャスト/マルチキャスト宛てのデータグラムに対してはエラーを返さない
+            // See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.1.3.1)
+            match rx_result.src_ip_addr {
+                IpAddr::V4(_) => {
+                    icmp::tx_error(
+                        table,
+                        icmp::ErrorMessage::DestinationUnreachable(
+                            icmp::UnreachableCode::PortUnreachable,
+                        ),
+                        rx_result,
+                        raw_datagram,
+                    )
+                    .await?
+                }
+                IpAddr::V6(_) => {
+                    icmpv6::tx_error(
+                        table,
+                        icmpv6::ErrorMessage::DestinationUnreachable(
+                            icmpv6::UnreachableCode::PortUnreachable,
+                        ),
+                        rx_result,
+                        raw_datagram,
+                    )
+                    .await?
+                }
+            }
+        }
+    }
+
+    Ok((datagram_hdr, payload))
+}
+
+pub async fn tx<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    src_port: u16,
+    dst_addr: IpAddr,
+    dst_port: u16,
+    payload: &[u8],
+) -> Result<(), TransportProtocolError> {
+    tx_with_ttl(
+        table,
+        src_port,
+        dst_addr,
+        dst_port,
+        payload,
+        internet::ip::DEFAULT_TTL,
+    )
+    .await
+}
+
+/// TTL(IPv6ではホップリミット)を指定して送信する
+pub async fn tx_with_ttl<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    src_port: u16,
+    dst_addr: IpAddr,
+    dst_port: u16,
+    payload: &[u8],
+    ttl: u8,
+) -> Result<(), TransportProtocolError> {
+    // IPv4のフラグメンテーションは未実装なので，MTUに収まらないデータグラムは送信できない
+    let max_payload_length = match dst_addr {
+        IpAddr::V4(_) => MAX_PAYLOAD_LENGTH,
+        IpAddr::V6(_) => MAX_IPV6_PAYLOAD_LENGTH,
+    };
+    if payload.len() > max_payload_length {
+        return Err(TransportProtocolError::MessageTooLong);
+    }
+
+    let mut datagram_hdr = DatagramHeader {
+        src_port,
+        dst_port,
+        length: (DatagramHeader::LENGTH + payload.len()) as u16,
+        checksum: 0,
+    };
+
+    let mut raw_datagram =
+        datagram_hdr.to_bytes(TransportProtocolError::CannotConstructUDPDatagram)?;
+    raw_datagram.extend_from_slice(payload);
+
+    let src_addr = table.source_address(dst_addr);
+
+    // 計算結果が0の場合は，"チェックサムなし" と区別するためにすべて1で送信する
+    datagram_hdr.checksum = match calculate_checksum_with_ip_pseudo_header(
+        src_addr,
+        dst_addr,
+        TransportProtocol::UDP,
+        &raw_datagram,
+        TransportProtocolError::CannotConstructUDPDatagram,
+    )? {
+        0 => 0xffff,
+        cksum => cksum,
+    };
+    raw_datagram[6..8].copy_from_slice(&datagram_hdr.checksum.to_be_bytes());
+
+    if table.opt.debug {
+        eprintln!("++++++++ tx udp datagram ++++++++");
+        eprintln!("{}", datagram_hdr);
+    }
+
+    let rx_result = RxResult {
+        src_ip_addr: dst_addr,
+        dst_ip_addr: src_addr,
+        ..Default::default()
+    };
+
+    internet::tx_with_ttl(table, TransportProtocol::UDP, rx_result, raw_datagram, ttl).await?;
+
+    Ok(())
+}
+
+/// 受信したICMPエラーを，原因となったデータグラムを送信したポートに配送する
+pub fn on_icmp_error<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    received: &icmp::ReceivedError,
+) {
+    let (src_port, dst_port) = received.ports();
+    let sender = table.udp_table.lock().unwrap().get(&src_port).cloned();
+
+    if let Some(sender) = sender {
+        let _ = sender.try_send(Err(DatagramError {
+            reporter: received.reporter,
+            dst_addr: received.dst_addr,
+            dst_port,
+            error: received.error,
+        }));
+    }
+}
+
+/// ポートにバインドし，そのポート宛てのデータグラムとICMPエラーを受け取るキューを返す
+/// `port` に0を渡した場合はエフェメラルポートから割り当てる
+pub fn bind<ND: network_device::NetworkDevice>(
+    table: &Items<ND>,
+    port: u16,
+) -> Result<(u16, DatagramReceiver), TransportProtocolError> {
+    let mut udp_table = table.udp_table.lock().unwrap();
+
+    let port = if port == 0 {
+        let range_len = (EPHEMERAL_PORT_RANGE.end() - EPHEMERAL_PORT_RANGE.start()) as u32 + 1;
+        let offset = rand::random::<u32>() % range_len;
+        (0..range_len)
+            .map(|i| EPHEMERAL_PORT_RANGE.start() + ((offset + i) % range_len) as u16)
+            .find(|p| !udp_table.contains_key(p))
+            .ok_or(TransportProtocolError::NoAvailablePort)?
+    } else {
+        port
+    };
+
+    if udp_table.contains_key(&port) {
+        return Err(TransportProtocolError::PortAlreadyInUse { port });
+    }
+
+    let (sender, receiver) = mpsc::channel(RECEIVE_QUEUE_LENGTH);
+    udp_table.insert(port, sender);
+
+    Ok((port, receiver))
+}
+
+/// ポートのバインドを解除する
+pub fn unbind<ND: network_device::NetworkDevice>(table: &Items<ND>, port: u16) {
+    table.udp_table.lock().unwrap().remove(&port);
+}
@@ -0,0 +1,445 @@
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Mutex,
+    },
+};
+
+use super::{DatagramError, DatagramReceiver};
+use crate::{
+    internet::{self, ip::IPv4Addr, IpAddr, SocketAddr},
+    network_device,
+    transport::{igmp, udp, TransportProtocolError},
+    Items,
+};
+
+/// マルチキャストアドレスへ送信するデータグラムのTTLのデフォルト値．リンク外には届かない
+/// See also [RFC1112](https://tools.ietf.org/html/rfc1112#section-6.1)
+pub const DEFAULT_MULTICAST_TTL: u8 = 1;
+
+/// プロトコルスタック上で動作するUDPソケット
+///
+/// 受信したデータグラムは `peachps::run` がポート毎の受信キューに配送するので，
+/// ソケットを使う場合は `run` を別タスクで動かしておく必要がある．
+///
+/// ```no_run
+/// # use peachps::{link, network_device, option, transport::udp::UdpSocket};
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let sock = network_device::setup_raw_socket("eth1".to_string())?;
+/// let items = peachps::Items::new(option::PeachPSOption::from_yaml("config.yaml"), sock);
+///
+/// let stack = items.clone();
+/// tokio::spawn(async move { peachps::run(&stack, link::LinkProtocol::Ethernet).await });
+///
+/// let socket = UdpSocket::bind(&items, 7)?;
+/// let mut buf = [0; 1500];
+/// loop {
+///     let (len, addr) = socket.recv_from(&mut buf).await?;
+///     socket.send_to(&buf[..len], addr).await?;
+/// }
+/// # }
+/// ```
+pub struct UdpSocket<ND: network_device::NetworkDevice> {
+    items: Items<ND>,
+    local_port: u16,
+    /// `connect()` で設定された通信相手
+    peer: Mutex<Option<SocketAddr>>,
+    /// ブロードキャストアドレスへの送信を許可するか
+    broadcast: AtomicBool,
+    /// 送信するデータグラムのTTL
+    ttl: AtomicU8,
+    /// マルチキャストアドレスへ送信するデータグラムのTTL
+    multicast_ttl: AtomicU8,
+    /// このソケットが参加しているIPv4マルチキャストグループ．ソケットを閉じる際に離脱する
+    multicast_groups: Mutex<BTreeSet<IPv4Addr>>,
+    /// 最後に `recv()` がエラーとして返したICMPエラー
+    last_error: Mutex<Option<DatagramError>>,
+    receiver: tokio::sync::Mutex<DatagramReceiver>,
+}
+
+impl<ND: network_device::NetworkDevice> UdpSocket<ND> {
+    /// ポートにバインドしたソケットを作成する
+    /// `port` に0を渡した場合はエフェメラルポートが割り当てられる
+    pub fn bind(items: &Items<ND>, port: u16) -> Result<Self, TransportProtocolError> {
+        let (local_port, receiver) = udp::bind(items, port)?;
+
+        Ok(Self {
+            items: items.clone(),
+            local_port,
+            peer: Mutex::new(None),
+            broadcast: AtomicBool::new(false),
+            ttl: AtomicU8::new(internet::ip::DEFAULT_TTL),
+            multicast_ttl: AtomicU8::new(DEFAULT_MULTICAST_TTL),
+            multicast_groups: Mutex::new(BTreeSet::new()),
+            last_error: Mutex::new(None),
+            receiver: tokio::sync::Mutex::new(receiver),
+        })
+    }
+
+    /// 通信相手を固定する．
+    /// 以降はその相手からのデータグラムのみを受信し，`send()` の宛先として使用する
+    pub fn connect(&self, addr: SocketAddr) {
+        *self.peer.lock().unwrap() = Some(addr);
+    }
+
+    /// 接続済みであれば相手に対して使う自身のアドレスを，
+    /// 未接続であればIPv4アドレスを返す
+    pub fn local_addr(&self) -> SocketAddr {
+        let peer = *self.peer.lock().unwrap();
+        let ip = match peer {
+            Some(peer) => self.items.source_address(peer.ip),
+            None => self.items.opt.ip_addr.into(),
+        };
+        SocketAddr::new(ip, self.local_port)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, TransportProtocolError> {
+        self.peer
+            .lock()
+            .unwrap()
+            .ok_or(TransportProtocolError::NotConnected)
+    }
+
+    pub fn set_broadcast(&self, on: bool) {
+        self.broadcast.store(on, Ordering::Relaxed);
+    }
+
+    pub fn broadcast(&self) -> bool {
+        self.broadcast.load(Ordering::Relaxed)
+    }
+
+    pub fn set_ttl(&self, ttl: u8) {
+        self.ttl.store(ttl, Ordering::Relaxed);
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.ttl.load(Ordering::Relaxed)
+    }
+
+    pub fn set_multicast_ttl_v4(&self, ttl: u8) {
+        self.multicast_ttl.store(ttl, Ordering::Relaxed);
+    }
+
+    pub fn multicast_ttl_v4(&self) -> u8 {
+        self.multicast_ttl.load(Ordering::Relaxed)
+    }
+
+    /// IPv4マルチキャストグループに参加し，宛てられたデータグラムを受信できるようにする．
+    /// 同じグループに複数回参加しても，離脱は1回でよい
+    pub fn join_multicast_v4(&self, group: IPv4Addr) -> Result<(), TransportProtocolError> {
+        let mut groups = self.multicast_groups.lock().unwrap();
+        if groups.contains(&group) {
+            return Ok(());
+        }
+        igmp::join(&self.items, group)?;
+        groups.insert(group);
+        Ok(())
+    }
+
+    pub fn leave_multicast_v4(&self, group: IPv4Addr) -> Result<(), TransportProtocolError> {
+        if !self.multicast_groups.lock().unwrap().remove(&group) {
+            return Err(TransportProtocolError::NotJoinedMulticastGroup { addr: group });
+        }
+        igmp::leave(&self.items, group)
+    }
+
+    /// 最後に受信したICMPエラーを取り出す．
+    /// エラーを送信したホストなど，`recv()` が返すエラー以上の情報を得る場合に使う
+    pub fn take_error(&self) -> Option<DatagramError> {
+        self.last_error.lock().unwrap().take()
+    }
+
+    pub async fn send_to(
+        &self,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> Result<usize, TransportProtocolError> {
+        // IPv6にはブロードキャストが存在しない
+        if let IpAddr::V4(v4) = addr.ip {
+            let directed_broadcast = self
+                .items
+                .opt
+                .ip_addr
+                .to_broadcast(self.items.opt.network_mask);
+            if (v4 == IPv4Addr::BLOADCAST || v4 == directed_broadcast) && !self.broadcast() {
+                return Err(TransportProtocolError::BroadcastNotPermitted);
+            }
+        }
+
+        let ttl = if matches!(addr.ip, IpAddr::V4(v4) if v4.is_multicast()) {
+            self.multicast_ttl_v4()
+        } else {
+            self.ttl()
+        };
+        udp::tx_with_ttl(&self.items, self.local_port, addr.ip, addr.port, buf, ttl).await?;
+
+        Ok(buf.len())
+    }
+
+    /// `connect()` で設定した相手に送信する
+    pub async fn send(&self, buf: &[u8]) -> Result<usize, TransportProtocolError> {
+        let addr = self.peer_addr()?;
+        self.send_to(buf, addr).await
+    }
+
+    /// データグラムを1つ受信する．
+    /// `buf` に収まらない部分は切り捨てられる．
+    /// 接続済みのソケットでは，相手へ送信したデータグラムに対するICMPエラーも返す
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr), TransportProtocolError> {
+        let mut receiver = self.receiver.lock().await;
+
+        loop {
+            let received = receiver
+                .recv()
+                .await
+                .ok_or(TransportProtocolError::SocketClosed)?;
+            let peer = *self.peer.lock().unwrap();
+
+            // 未接続のソケットは，どの送信に対するエラーか区別できないので無視する
+            // See also [RFC1122](https://tools.ietf.org/html/rfc1122#section-4.1.3.3)
+            let datagram = match received {
+                Ok(datagram) => datagram,
+                Err(e) => {
+                    if peer == Some(SocketAddr::new(e.dst_addr, e.dst_port)) {
+                        *self.last_error.lock().unwrap() = Some(e);
+                        return Err(e.error);
+                    }
+                    continue;
+                }
+            };
+
+            // 接続済みのソケットは相手以外からのデータグラムを破棄する
+            if let Some(peer) = peer {
+                if peer != SocketAddr::new(datagram.src_addr, datagram.src_port) {
+                    continue;
+                }
+            }
+
+            let len = datagram.payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram.payload[..len]);
+
+            return Ok((len, SocketAddr::new(datagram.src_addr, datagram.src_port)));
+        }
+    }
+
+    /// `connect()` で設定した相手からのデータグラムを受信する
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize, TransportProtocolError> {
+        self.peer_addr()?;
+        let (len, _) = self.recv_from(buf).await?;
+        Ok(len)
+    }
+}
+
+impl<ND: network_device::NetworkDevice> Drop for UdpSocket<ND> {
+    fn drop(&mut self) {
+        for group in self.multicast_groups.lock().unwrap().iter() {
+            let _ = igmp::leave(&self.items, *group);
+        }
+        udp::unbind(&self.items, self.local_port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{network_device::FakeDevice, option, transport::icmp, RxResult};
+
+    const LOCAL_PORT: u16 = 5000;
+
+    fn fake_items() -> Items<FakeDevice> {
+        let opt = option::PeachPSOption {
+            ip_addr: IPv4Addr::from("192.168.11.30"),
+            network_mask: IPv4Addr::from("255.255.255.0"),
+            ..Default::default()
+        };
+        Items::new(opt, FakeDevice::default())
+    }
+
+    fn sent_frames(items: &Items<FakeDevice>) -> Vec<Vec<u8>> {
+        items.dev.lock().unwrap().frames.lock().unwrap().clone()
+    }
+
+    fn peer(addr: &str, port: u16) -> SocketAddr {
+        SocketAddr::new(IPv4Addr::from(addr).into(), port)
+    }
+
+    /// `src` から自身の `LOCAL_PORT` 宛てのデータグラムを受信させる．
+    /// チェックサムは省略する
+    async fn receive(items: &Items<FakeDevice>, src: SocketAddr, payload: &[u8]) {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&src.port.to_be_bytes());
+        datagram.extend_from_slice(&LOCAL_PORT.to_be_bytes());
+        datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(payload);
+
+        let rx_result = RxResult {
+            src_ip_addr: src.ip,
+            dst_ip_addr: items.opt.ip_addr.into(),
+            message_len: datagram.len(),
+            ..Default::default()
+        };
+        udp::rx(items, rx_result, &datagram).await.unwrap();
+    }
+
+    /// 自身の `LOCAL_PORT` から `dst` へ送ったデータグラムに対するICMPエラーを受信させる
+    fn receive_icmp_error(items: &Items<FakeDevice>, dst: SocketAddr) {
+        let mut quoted_data = Vec::new();
+        quoted_data.extend_from_slice(&LOCAL_PORT.to_be_bytes());
+        quoted_data.extend_from_slice(&dst.port.to_be_bytes());
+        quoted_data.extend_from_slice(&[0; 4]);
+
+        udp::on_icmp_error(
+            items,
+            &icmp::ReceivedError {
+                reporter: dst.ip,
+                src_addr: items.opt.ip_addr.into(),
+                dst_addr: dst.ip,
+                protocol: crate::transport::TransportProtocol::UDP,
+                quoted_data,
+                error: TransportProtocolError::ConnectionRefused,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn connected_socket_filters_peer_test() {
+        let items = fake_items();
+        let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+        let server = peer("192.168.11.1", 53);
+        socket.connect(server);
+        assert_eq!(server, socket.peer_addr().unwrap());
+
+        receive(&items, peer("192.168.11.2", 53), b"other").await;
+        receive(&items, peer("192.168.11.1", 54), b"port").await;
+        receive(&items, server, b"peer").await;
+
+        let mut buf = [0; 16];
+        assert_eq!(4, socket.recv(&mut buf).await.unwrap());
+        assert_eq!(b"peer", &buf[..4]);
+    }
+
+    #[tokio::test]
+    async fn unconnected_socket_receives_from_any_peer_test() {
+        let items = fake_items();
+        let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+        assert!(matches!(
+            socket.peer_addr(),
+            Err(TransportProtocolError::NotConnected)
+        ));
+
+        let src = peer("192.168.11.2", 40000);
+        receive(&items, src, b"hello").await;
+
+        // 収まらない部分は切り捨てる
+        let mut buf = [0; 4];
+        assert_eq!((4, src), socket.recv_from(&mut buf).await.unwrap());
+        assert_eq!(b"hell", &buf);
+    }
+
+    #[tokio::test]
+    async fn broadcast_not_permitted_test() {
+        let items = fake_items();
+        let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+
+        for addr in ["255.255.255.255", "192.168.11.255"].iter() {
+            assert!(matches!(
+                socket.send_to(b"x", peer(addr, 9)).await,
+                Err(TransportProtocolError::BroadcastNotPermitted)
+            ));
+        }
+        assert!(sent_frames(&items).is_empty());
+
+        socket.set_broadcast(true);
+        assert_eq!(
+            1,
+            socket
+                .send_to(b"x", peer("255.255.255.255", 9))
+                .await
+                .unwrap()
+        );
+        assert_eq!(1, sent_frames(&items).len());
+    }
+
+    #[tokio::test]
+    async fn icmp_error_is_delivered_to_connected_socket_test() {
+        let items = fake_items();
+        let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+        let server = peer("192.168.11.1", 53);
+        socket.connect(server);
+
+        // 相手以外へ送ったデータグラムに対するエラーは無視する
+        receive_icmp_error(&items, peer("192.168.11.2", 53));
+        receive_icmp_error(&items, server);
+
+        let mut buf = [0; 16];
+        assert!(matches!(
+            socket.recv(&mut buf).await,
+            Err(TransportProtocolError::ConnectionRefused)
+        ));
+        let error = socket.take_error().unwrap();
+        assert_eq!(server.ip, error.reporter);
+        assert_eq!(server.port, error.dst_port);
+        assert!(socket.take_error().is_none());
+    }
+
+    #[tokio::test]
+    async fn icmp_error_is_ignored_by_unconnected_socket_test() {
+        let items = fake_items();
+        let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+        let server = peer("192.168.11.1", 53);
+
+        receive_icmp_error(&items, server);
+        receive(&items, server, b"reply").await;
+
+        let mut buf = [0; 16];
+        assert_eq!((5, server), socket.recv_from(&mut buf).await.unwrap());
+        assert!(socket.take_error().is_none());
+    }
+
+    #[tokio::test]
+    async fn multicast_ttl_test() {
+        let items = fake_items();
+        let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+        assert_eq!(DEFAULT_MULTICAST_TTL, socket.multicast_ttl_v4());
+        socket.set_ttl(32);
+        socket.set_multicast_ttl_v4(4);
+        socket.set_broadcast(true);
+
+        socket.send_to(b"x", peer("239.1.2.3", 5353)).await.unwrap();
+        socket
+            .send_to(b"x", peer("255.255.255.255", 9))
+            .await
+            .unwrap();
+
+        // Ethernetヘッダの後ろ，IPヘッダの9オクテット目がTTL
+        let frames = sent_frames(&items);
+        assert_eq!(
+            vec![4, 32],
+            frames.iter().map(|f| f[22]).collect::<Vec<u8>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn drop_unbinds_and_leaves_groups_test() {
+        let items = fake_items();
+        let group = IPv4Addr::from("239.1.2.3");
+        {
+            let socket = UdpSocket::bind(&items, LOCAL_PORT).unwrap();
+            socket.join_multicast_v4(group).unwrap();
+            assert!(items.igmp_groups.lock().unwrap().is_member(group));
+            assert!(matches!(
+                UdpSocket::bind(&items, LOCAL_PORT),
+                Err(TransportProtocolError::PortAlreadyInUse { port: LOCAL_PORT })
+            ));
+        }
+
+        assert!(!items.igmp_groups.lock().unwrap().is_member(group));
+        assert!(!items.udp_table.lock().unwrap().contains_key(&LOCAL_PORT));
+        assert!(UdpSocket::bind(&items, LOCAL_PORT).is_ok());
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::Cursor;
+
+use tokio::sync::mpsc;
+
+use crate::{
+    byteorder_wrapper,
+    internet::IpAddr,
+    transport::{TransportHeader, TransportProtocolError},
+};
+
+/// UDPデータグラムのヘッダ構造体
+/// See also [RFC768](https://tools.ietf.org/html/rfc768)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DatagramHeader {
+    /// 送信元ポート番号
+    pub src_port: u16,
+    /// 宛先ポート番号
+    pub dst_port: u16,
+    /// ヘッダを含むデータグラムの全長
+    pub length: u16,
+    /// 疑似ヘッダを含めて計算するチェックサム．
+    /// 0の場合は送信側がチェックサムを計算していないことを示す
+    pub checksum: u16,
+}
+
+/// バインドされたポートに配送されるデータグラム
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub src_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub payload: Vec<u8>,
+}
+
+/// 送信したデータグラムに対してICMPエラーを受信した場合に，
+/// 送信元のポートに配送されるもの
+#[derive(Debug, Clone, Copy)]
+pub struct DatagramError {
+    /// エラーを送信したホスト
+    pub reporter: IpAddr,
+    /// 元のデータグラムの宛先
+    pub dst_addr: IpAddr,
+    pub dst_port: u16,
+    pub error: TransportProtocolError,
+}
+
+/// ポート毎の受信キュー
+pub type DatagramSender = mpsc::Sender<Result<Datagram, DatagramError>>;
+pub type DatagramReceiver = mpsc::Receiver<Result<Datagram, DatagramError>>;
+
+impl DatagramHeader {
+    pub const LENGTH: usize = 8;
+
+    pub fn new_from_bytes<E>(buf: &[u8], err: E) -> Result<Self, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut reader = Cursor::new(buf);
+        let datagram_hdr = Self {
+            src_port: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            dst_port: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            length: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+            checksum: byteorder_wrapper::read_u16_as_be(&mut reader, err)?,
+        };
+
+        Ok(datagram_hdr)
+    }
+
+    pub fn to_bytes<E>(&self, err: E) -> Result<Vec<u8>, E>
+    where
+        E: std::error::Error + Copy,
+    {
+        let mut buf = Vec::new();
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.src_port, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.dst_port, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.length, err)?;
+        byteorder_wrapper::write_u16_as_be(&mut buf, self.checksum, err)?;
+
+        Ok(buf)
+    }
+}
+
+impl TransportHeader for DatagramHeader {}
+
+impl Default for DatagramHeader {
+    fn default() -> Self {
+        Self {
+            src_port: 0,
+            dst_port: 0,
+            length: Self::LENGTH as u16,
+            checksum: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for DatagramHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "src_port: {}", self.src_port)?;
+        writeln!(f, "dst_port: {}", self.dst_port)?;
+        writeln!(f, "length (bytes): {}", self.length)?;
+        writeln!(f, "checksum: {}", self.checksum)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::TransportProtocolError;
+
+    use super::*;
+
+    #[test]
+    fn parse_udp_datagram_test() {
+        let raw_datagram = [0xd4, 0x31, 0x00, 0x35, 0x00, 0x0c, 0x8c, 0x2b];
+        let result = DatagramHeader::new_from_bytes(
+            &raw_datagram,
+            TransportProtocolError::CannotParseUDPDatagram,
+        );
+        assert!(result.is_ok());
+        let datagram_hdr = result.unwrap();
+        assert_eq!(54321, datagram_hdr.src_port);
+        assert_eq!(53, datagram_hdr.dst_port);
+        assert_eq!(12, datagram_hdr.length);
+        assert_eq!(0x8c2b, datagram_hdr.checksum);
+    }
+
+    #[test]
+    fn udp_datagram_round_trip_test() {
+        let datagram_hdr = DatagramHeader {
+            src_port: 68,
+            dst_port: 67,
+            length: 300,
+            checksum: 0x1234,
+        };
+        let raw = datagram_hdr
+            .to_bytes(TransportProtocolError::CannotConstructUDPDatagram)
+            .unwrap();
+        assert_eq!(DatagramHeader::LENGTH, raw.len());
+        assert_eq!(
+            datagram_hdr,
+            DatagramHeader::new_from_bytes(&raw, TransportProtocolError::CannotParseUDPDatagram)
+                .unwrap()
+        );
+    }
+}